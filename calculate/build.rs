@@ -0,0 +1,11 @@
+fn main() {
+    // The gRPC service is generated from its protobuf definition, with a
+    // vendored protoc so that no system install is needed
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("vendored protoc");
+        // SAFETY: the build script is single-threaded
+        unsafe { std::env::set_var("PROTOC", protoc) };
+        tonic_prost_build::compile_protos("proto/onebrc.proto").expect("valid protobuf definition");
+    }
+}
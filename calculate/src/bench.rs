@@ -0,0 +1,151 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use onebrc_core::is_stream;
+use onebrc_core::output::{Format, json_string};
+
+use crate::cli::{OutputArgs, RunArgs};
+use crate::{aggregate, input_size, open_output};
+
+/// Wall times of the timed runs plus the size of one pass.
+struct Report {
+    times: Vec<Duration>,
+    warmup: usize,
+    bytes: Option<u64>,
+    rows: u64,
+}
+
+impl Report {
+    fn min(&self) -> f64 {
+        self.secs().fold(f64::INFINITY, f64::min)
+    }
+
+    fn max(&self) -> f64 {
+        self.secs().fold(0.0, f64::max)
+    }
+
+    fn mean(&self) -> f64 {
+        self.secs().sum::<f64>() / self.times.len() as f64
+    }
+
+    /// Sample standard deviation; zero for a single run.
+    fn stddev(&self) -> f64 {
+        if self.times.len() < 2 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        let squares: f64 = self.secs().map(|t| (t - mean).powi(2)).sum();
+        (squares / (self.times.len() - 1) as f64).sqrt()
+    }
+
+    /// Bytes and rows per second of the fastest run.
+    fn throughput(&self) -> (Option<f64>, f64) {
+        let best = self.min();
+        (self.bytes.map(|b| b as f64 / best), self.rows as f64 / best)
+    }
+
+    fn secs(&self) -> impl Iterator<Item = f64> + '_ {
+        self.times.iter().map(Duration::as_secs_f64)
+    }
+}
+
+/// Aggregates the input `warmup + runs` times, discarding the results, and
+/// reports statistics over the wall times of the last `runs` passes.
+pub fn run(args: &RunArgs, output: &OutputArgs, runs: usize, warmup: usize) -> io::Result<()> {
+    if runs == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--bench needs at least one run",
+        ));
+    }
+    let paths = args.input_paths()?;
+    if paths.iter().any(is_stream) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--bench needs files; stdin, pipes and sockets can only be read once",
+        ));
+    }
+
+    for run in 1..=warmup {
+        eprintln!("warm-up {run}/{warmup}");
+        aggregate(args)?;
+    }
+    let mut times = Vec::with_capacity(runs);
+    let mut rows = 0;
+    for run in 1..=runs {
+        let start = Instant::now();
+        rows = aggregate(args)?.rows();
+        let elapsed = start.elapsed();
+        eprintln!("run {run}/{runs}: {elapsed:.2?}");
+        times.push(elapsed);
+    }
+
+    let report = Report {
+        times,
+        warmup,
+        bytes: input_size(&paths)?,
+        rows,
+    };
+    let mut out = open_output(output)?;
+    match output.output_format {
+        Format::Json => write_json(&mut out, &paths, &report)?,
+        _ => write_text(&mut out, &paths, &report)?,
+    }
+    out.flush()
+}
+
+fn write_text(out: &mut impl Write, inputs: &[PathBuf], report: &Report) -> io::Result<()> {
+    let label = match inputs {
+        [input] => input.display().to_string(),
+        inputs => format!("{} files", inputs.len()),
+    };
+    writeln!(
+        out,
+        "{label}: {} run(s), {} warm-up",
+        report.times.len(),
+        report.warmup
+    )?;
+    writeln!(out, "  min    {:>9.3}s", report.min())?;
+    writeln!(
+        out,
+        "  mean   {:>9.3}s ± {:.3}s",
+        report.mean(),
+        report.stddev()
+    )?;
+    writeln!(out, "  max    {:>9.3}s", report.max())?;
+    let (bytes_per_sec, rows_per_sec) = report.throughput();
+    if let Some(bytes_per_sec) = bytes_per_sec {
+        writeln!(out, "  best   {:>9.1} MB/s", bytes_per_sec / 1e6)?;
+    }
+    writeln!(out, "  best   {:>9.1}M rows/s", rows_per_sec / 1e6)
+}
+
+fn write_json(out: &mut impl Write, inputs: &[PathBuf], report: &Report) -> io::Result<()> {
+    let inputs: Vec<String> = inputs
+        .iter()
+        .map(|input| json_string(&input.to_string_lossy()))
+        .collect();
+    let times: Vec<String> = report.secs().map(|t| format!("{t:.6}")).collect();
+    let (bytes_per_sec, rows_per_sec) = report.throughput();
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+    writeln!(
+        out,
+        "{{\"inputs\": [{}], \"runs\": {}, \"warmup\": {}, \"bytes\": {}, \"rows\": {}, \
+         \"times_secs\": [{}], \"min_secs\": {:.6}, \"mean_secs\": {:.6}, \
+         \"stddev_secs\": {:.6}, \"max_secs\": {:.6}, \"bytes_per_sec\": {}, \
+         \"rows_per_sec\": {:.1}}}",
+        inputs.join(", "),
+        report.times.len(),
+        report.warmup,
+        optional(report.bytes.map(|b| b.to_string())),
+        report.rows,
+        times.join(", "),
+        report.min(),
+        report.mean(),
+        report.stddev(),
+        report.max(),
+        optional(bytes_per_sec.map(|b| format!("{b:.1}"))),
+        rows_per_sec
+    )
+}
@@ -1,158 +1,383 @@
 use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, Read};
-use std::thread;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
 use std::time::Instant;
-use crossbeam::channel;
-
-#[derive(Debug, Clone)]
-struct TempStats {
-    min: f64,
-    max: f64,
-    sum: f64,
-    count: usize,
-}
+use clap::Parser;
+use onebrc_core::output::{OutputOptions, write_partitioned, write_results};
+use onebrc_core::{
+    Aggregator, Compression, Options, Progress, Results, SkippedLines, aggregate_files,
+    aggregate_remote, aggregate_with_checkpoints, is_parquet, is_stream, is_url,
+};
 
-impl TempStats {
-    fn new(temp: f64) -> Self {
-        TempStats {
-            min: temp,
-            max: temp,
-            sum: temp,
-            count: 1,
-        }
-    }
+use cli::{Cli, Command, OutputArgs, RunArgs};
+use progress::ProgressBar;
 
-    fn update(&mut self, temp: f64) {
-        self.min = self.min.min(temp);
-        self.max = self.max.max(temp);
-        self.sum += temp;
-        self.count += 1;
-    }
+mod bench;
+mod cli;
+mod count;
+#[cfg(feature = "tui")]
+mod dashboard;
+mod follow;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod interrupt;
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "tracing")]
+mod logging;
+mod merge;
+#[cfg(feature = "plot")]
+mod plot;
+mod profile;
+mod progress;
+#[cfg(feature = "query")]
+mod query;
+mod repl;
+#[cfg(feature = "serve")]
+mod serve;
+mod validate;
+mod verify;
+mod worker;
+
+// mimalloc wins if both are enabled, as with `--all-features`
+#[cfg(feature = "alloc-mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+#[cfg(all(
+    feature = "alloc-jemalloc",
+    not(feature = "alloc-mimalloc"),
+    not(target_env = "msvc")
+))]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
-    fn mean(&self) -> f64 {
-        self.sum / self.count as f64
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    #[cfg(feature = "tracing")]
+    logging::init(&cli.log);
+    let outcome = match &cli.command {
+        None => match cli.bench {
+            Some(runs) => bench::run(&cli.run, &cli.output, runs, cli.warmup).map(|()| true),
+            None if cli.run.count_only => count::run(&cli.run, &cli.output).map(|()| true),
+            None if cli.profile => profile::run(&cli.run, &cli.output).map(|()| true),
+            None => run(&cli.run, &cli.output).map(|()| !interrupt::interrupted()),
+        },
+        Some(Command::Validate(args)) => validate::run(args),
+        Some(Command::VerifyData(args)) => verify::run(args),
+        Some(Command::Merge(args)) => merge::run(args).map(|()| true),
+        Some(Command::Worker(args)) => worker::run(args).map(|()| true),
+        #[cfg(feature = "grpc")]
+        Some(Command::Grpc(args)) => grpc::run(args).map(|()| true),
+        #[cfg(feature = "serve")]
+        Some(Command::Serve(args)) => serve::run(args).map(|()| true),
+        #[cfg(feature = "kafka")]
+        Some(Command::Kafka(args)) => kafka::run(args).map(|()| true),
+        #[cfg(feature = "query")]
+        Some(Command::Query(args)) => query::run(args).map(|()| true),
+    };
+    match outcome {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
     }
 }
 
-fn process_lines(lines: &[Vec<u8>]) -> BTreeMap<Vec<u8>, TempStats> {
-    let mut city_stats: BTreeMap<Vec<u8>, TempStats> = BTreeMap::new();
-
-    for line in lines {
-        if let Some(pos) = line.iter().position(|&b| b == b';') {
-            let (city, temp_bytes) = line.split_at(pos);
-            let temp_str = std::str::from_utf8(&temp_bytes[1..]); // skip ';'
-
-            if let Ok(temp_str) = temp_str {
-                if let Ok(temp) = temp_str.trim().parse::<f64>() {
-                    city_stats
-                        .entry(city.to_vec()) // only convert once
-                        .and_modify(|s| s.update(temp))
-                        .or_insert_with(|| TempStats::new(temp));
-                }
-            }
+fn run(args: &RunArgs, output: &OutputArgs) -> io::Result<()> {
+    let start = Instant::now();
+    interrupt::install()?;
+    if args.follow {
+        return follow::run(args, output);
+    }
+
+    let options = output.options(&args.percentiles)?;
+    #[cfg(feature = "plot")]
+    plot::check(args, output)?;
+    repl::check(args)?;
+    let (skipped, results) = if output.per_partition {
+        if args.checkpoint.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--checkpoint cannot be combined with --per-partition",
+            ));
         }
+        let (partitions, aggregator) = aggregate_partitions(args)?;
+        let skipped = aggregator.skipped().clone();
+        let results = aggregator.into_results();
+        save_state(output, &results)?;
+        #[cfg(feature = "plot")]
+        plot::plot(output, &results, &options)?;
+        let mut out = open_output(output)?;
+        write_partitioned(&mut out, &partitions, &results, &options)?;
+        out.flush()?;
+        (skipped, results)
+    } else {
+        let aggregator = aggregate(args)?;
+        let skipped = aggregator.skipped().clone();
+        let results = aggregator.into_results();
+        save_state(output, &results)?;
+        #[cfg(feature = "plot")]
+        plot::plot(output, &results, &options)?;
+        let mut out = open_output(output)?;
+        write_results(&mut out, &results, &options)?;
+        out.flush()?;
+        (skipped, results)
+    };
+
+    report_skipped(&skipped);
+    if let Some(min_count) = options.min_count {
+        report_sparse(&results, min_count);
+    }
+    if let Some(fraction) = args.sample {
+        report_sample(&results, fraction);
     }
 
-    city_stats
-}
+    let duration = start.elapsed();
+    eprintln!("Execution time: {:.2?}", duration);
 
+    if args.interactive {
+        repl::run(&results, &options)?;
+    }
+    Ok(())
+}
 
-fn main() -> io::Result<()> {
-    let start = Instant::now();
+/// Opens the `--output` file, or stdout without one.
+pub(crate) fn open_output(output: &OutputArgs) -> io::Result<io::BufWriter<Box<dyn Write>>> {
+    Ok(io::BufWriter::new(match &output.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    }))
+}
 
-    let file = File::open("../data/weather_stations.csv")?;
-    let reader = BufReader::new(file);
-
-    let num_threads = 8;
-    let batch_size = 100_000;
-
-    let (sender, receiver) = channel::unbounded();
-    let mut handles = vec![];
-    let mut buffer = Vec::with_capacity(batch_size);
-    let mut line_stream = reader.split(b'\n');
-
-    while let Some(line_result) = line_stream.next() {
-        if let Ok(line) = line_result {
-            buffer.push(line);
-            if buffer.len() >= batch_size {
-                let batch = std::mem::take(&mut buffer);
-                let thread_sender = sender.clone();
-                let handle = thread::spawn(move || {
-                    let result = process_lines(&batch);
-                    thread_sender.send(result).expect("Failed to send result");
-                });
-                handles.push(handle);
-            }
+/// Writes `results` to the --output file, or to stdout without one, and to
+/// the --save-state file, for commands that write them again and again as
+/// they go. Files are written next to their final name and renamed over
+/// it, so a reader never sees half of them.
+pub(crate) fn write_snapshot(
+    output: &OutputArgs,
+    results: &Results,
+    options: &OutputOptions,
+) -> io::Result<()> {
+    match &output.output {
+        Some(path) => replace(path, |partial| {
+            let mut out = io::BufWriter::new(File::create(partial)?);
+            write_results(&mut out, results, options)?;
+            out.flush()
+        })?,
+        None => {
+            let mut out = io::stdout().lock();
+            write_results(&mut out, results, options)?;
+            out.flush()?;
         }
     }
-
-    // Handle remaining lines
-    if !buffer.is_empty() {
-        let thread_sender = sender.clone();
-        let batch = std::mem::take(&mut buffer);
-        let handle = thread::spawn(move || {
-            let result = process_lines(&batch);
-            thread_sender.send(result).expect("Failed to send result");
-        });
-        handles.push(handle);
+    match &output.save_state {
+        Some(path) => replace(path, |partial| merge::write_state(partial, results)),
+        None => Ok(()),
     }
+}
 
-    drop(sender); // Close sender
+/// Runs `write` on a file beside `path`, then moves it to `path`.
+fn replace(path: &Path, write: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    write(&partial)?;
+    fs::rename(&partial, path)
+}
 
-    let partial_maps: Vec<_> = receiver.iter().collect();
-    let final_map = merge_maps(partial_maps);
+/// Writes the `--save-state` file, if one was asked for.
+fn save_state(output: &OutputArgs, results: &Results) -> io::Result<()> {
+    match &output.save_state {
+        Some(path) => merge::write_state(path, results),
+        None => Ok(()),
+    }
+}
 
-    for handle in handles {
-        handle.join().expect("Thread panicked");
+/// Runs the pipeline over all of `args.inputs`, with a progress line if
+/// requested, saving checkpoints along the way with --checkpoint.
+pub(crate) fn aggregate(args: &RunArgs) -> io::Result<Aggregator> {
+    if args.follow {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--follow only works for plain runs",
+        ));
     }
+    let paths = args.input_paths()?;
+    let Some(checkpoint) = args.checkpoint() else {
+        return with_progress(args, &paths, |options| aggregate_paths(args, &paths, options));
+    };
+    let [input] = paths.as_slice() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--checkpoint needs exactly one input file",
+        ));
+    };
+    with_progress(args, &paths, |options| {
+        aggregate_with_checkpoints(input, options, &checkpoint)
+    })
+}
 
-    let duration = start.elapsed();
-    println!("Execution time: {:.2?}", duration);
+/// Runs the pipeline once per Hive-style partition among the inputs, in
+/// partition order, and returns each partition's results along with the
+/// rollup of all of them.
+fn aggregate_partitions(args: &RunArgs) -> io::Result<(Vec<(String, Results)>, Aggregator)> {
+    let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for (path, partition) in args.partitioned_inputs()? {
+        groups.entry(partition).or_default().push(path);
+    }
+    let paths: Vec<_> = groups.values().flatten().cloned().collect();
+    with_progress(args, &paths, |options| {
+        let mut partitions = Vec::with_capacity(groups.len());
+        let mut total = options.aggregator();
+        for (partition, paths) in &groups {
+            let aggregator = aggregate_paths(args, paths, options)?;
+            partitions.push((partition.clone(), aggregator.clone().into_results()));
+            total.merge(aggregator);
+        }
+        Ok((partitions, total))
+    })
+}
 
-    Ok(())
+/// Aggregates `paths` on this machine, or on the --workers if given.
+fn aggregate_paths(args: &RunArgs, paths: &[PathBuf], options: &Options) -> io::Result<Aggregator> {
+    if args.workers.is_empty() {
+        aggregate_files(paths, options)
+    } else {
+        aggregate_remote(paths, &args.workers, options)
+    }
 }
 
+/// Calls `aggregate` with the pipeline options from `args`, drawing a
+/// progress line over `paths` while it runs if requested. If Ctrl+C stops
+/// the run, tells how much of the input the partial results cover.
+fn with_progress<T>(
+    args: &RunArgs,
+    paths: &[PathBuf],
+    aggregate: impl FnOnce(&Options) -> io::Result<T>,
+) -> io::Result<T> {
+    let mut options = args.options()?;
+    options.cancel = interrupt::flag();
+    #[cfg(feature = "tui")]
+    let watched = args.progress || args.tui;
+    #[cfg(not(feature = "tui"))]
+    let watched = args.progress;
+    if !watched && options.cancel.is_none() {
+        return aggregate(&options);
+    }
 
+    let progress = Arc::new(Progress::new());
+    options.progress = Some(Arc::clone(&progress));
+    let bar = if args.progress {
+        Some(ProgressBar::start(Arc::clone(&progress), input_size(paths)?))
+    } else {
+        None
+    };
+    #[cfg(feature = "tui")]
+    let dashboard = if args.tui {
+        let live = Arc::new(onebrc_core::LiveResults::new(dashboard::LIVE_EVERY));
+        options.live = Some(Arc::clone(&live));
+        Some(dashboard::Dashboard::start(
+            Arc::clone(&progress),
+            input_size(paths)?,
+            live,
+            options.cancel.clone(),
+        )?)
+    } else {
+        None
+    };
+    let outcome = aggregate(&options);
+    if let Some(bar) = bar {
+        bar.finish();
+    }
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = dashboard {
+        dashboard.finish()?;
+    }
+    if interrupt::interrupted() {
+        report_partial(&progress, input_size(paths).ok().flatten());
+    }
+    outcome
+}
 
-fn merge_maps(mut maps: Vec<BTreeMap<Vec<u8>, TempStats>>) -> BTreeMap<Vec<u8>, TempStats> {
-    while maps.len() > 1 {
-        let mut next_round = vec![];
-        let mut handles = vec![];
-
-        for chunk in maps.chunks(2) {
-            if chunk.len() == 2 {
-                let mut left = chunk[0].clone();
-                let right = chunk[1].clone();
-
-                let handle = thread::spawn(move || {
-                    for (city, stats) in right {
-                        left.entry(city)
-                            .and_modify(|s| {
-                                s.min = s.min.min(stats.min);
-                                s.max = s.max.max(stats.max);
-                                s.sum += stats.sum;
-                                s.count += stats.count;
-                            })
-                            .or_insert(stats);
-                    }
-                    left
-                });
-
-                handles.push(handle);
-            } else {
-                // Unpaired map (odd count), move to next round directly
-                next_round.push(chunk[0].clone());
-            }
-        }
+fn report_partial(progress: &Progress, total: Option<u64>) {
+    let bytes = progress.bytes();
+    match total.filter(|&total| total > 0) {
+        Some(total) => eprintln!(
+            "Partial results: {:.1} of {:.1} MB read ({:.1}% of the input), {} rows",
+            bytes as f64 / 1e6,
+            total as f64 / 1e6,
+            (bytes as f64 / total as f64 * 100.0).min(100.0),
+            progress.rows()
+        ),
+        None => eprintln!(
+            "Partial results: the first {:.1} MB of input, {} rows",
+            bytes as f64 / 1e6,
+            progress.rows()
+        ),
+    }
+}
 
-        // Collect merged results
-        for handle in handles {
-            next_round.push(handle.join().expect("Merge thread failed"));
+/// Number of bytes the pipeline will process, when that is known up front:
+/// not if a stream, a compressed file or a Parquet file is among the inputs.
+pub(crate) fn input_size(paths: &[PathBuf]) -> io::Result<Option<u64>> {
+    let mut total = 0;
+    for path in paths {
+        if is_stream(path) || is_url(path) {
+            return Ok(None);
         }
+        if Compression::detect(path)?.is_some() || is_parquet(path)? {
+            return Ok(None);
+        }
+        total += fs::metadata(path)?.len();
+    }
+    Ok(Some(total))
+}
 
-        maps = next_round;
+fn report_sample(results: &Results, fraction: f64) {
+    let readings: u64 = results.iter().map(|(_, stats)| stats.count()).sum();
+    eprintln!(
+        "Sampled {}% of the input, {readings} reading(s): the means are estimates, and the \
+         counts, minimums and maximums are the sample's",
+        (fraction * 1e4).round() / 100.0
+    );
+}
+
+fn report_sparse(results: &Results, min_count: u64) {
+    let sparse = results
+        .iter()
+        .filter(|(_, stats)| stats.count() < min_count)
+        .count();
+    if sparse > 0 {
+        eprintln!("Left out {sparse} station(s) with fewer than {min_count} readings");
     }
+}
 
-    maps.pop().unwrap_or_default()
+pub(crate) fn report_skipped(skipped: &SkippedLines) {
+    if skipped.is_empty() {
+        return;
+    }
+    let by_reason: Vec<String> = skipped
+        .by_reason()
+        .map(|(reason, count)| format!("{count} {reason}"))
+        .collect();
+    eprintln!(
+        "Skipped {} malformed line(s): {}",
+        skipped.count(),
+        by_reason.join(", ")
+    );
+    for sample in skipped.samples() {
+        eprintln!(
+            "  line {} (byte {}): {}: {}",
+            sample.line,
+            sample.offset,
+            sample.reason,
+            String::from_utf8_lossy(&sample.content)
+        );
+    }
 }
@@ -0,0 +1,929 @@
+use std::fs;
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::{Args, Parser, Subcommand};
+use onebrc_core::output::{Format, Metadata, OutputOptions, Rounding, Sort, Template, Top, Unit};
+use onebrc_core::{
+    Aliases, CheckpointOptions, Columns, Engine, InputFormat, IoBackend, Mode, Options,
+    ParseOptions, Percentile, Rank, SortKey, StationFilter, StationIndex, StatsOptions, discover,
+    is_url, partition_of,
+};
+
+#[cfg(feature = "plot")]
+use crate::plot::PlotKind;
+
+/// Aggregates min/mean/max temperature per station from a `station;temp` file.
+#[derive(Parser, Debug)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Time RUNS full passes over the input instead of printing results once;
+    /// the report is JSON with `-f json` and text otherwise
+    #[arg(long, value_name = "RUNS")]
+    pub bench: Option<usize>,
+
+    /// Untimed passes before a --bench, to warm the page cache
+    #[arg(long, value_name = "RUNS", default_value_t = 0, requires = "bench")]
+    pub warmup: usize,
+
+    /// Describe the input instead of printing results: its stations, rows
+    /// and bytes, line lengths, temperature range and rows per station; the
+    /// report is JSON with `-f json` and text otherwise
+    #[arg(long, conflicts_with = "bench")]
+    pub profile: bool,
+
+    #[cfg(feature = "tracing")]
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Aggregate the input and compare the result against a reference answer
+    Validate(Box<ValidateArgs>),
+    /// Check that every line of the input keeps to the challenge's rules:
+    /// a station name of 1 to 100 bytes of UTF-8, one `;` and a temperature
+    /// from -99.9 to 99.9 with one decimal
+    VerifyData(VerifyDataArgs),
+    /// Combine the partial results of runs made with --save-state, e.g. on
+    /// separate shards of a dataset, into one result
+    Merge(MergeArgs),
+    /// Serve aggregation jobs for coordinators started with --workers
+    Worker(WorkerArgs),
+    /// Serve the aggregation engine over gRPC (see `proto/onebrc.proto`)
+    #[cfg(feature = "grpc")]
+    Grpc(GrpcArgs),
+    /// Serve an HTTP API for submitting aggregation jobs and fetching their
+    /// progress and results
+    #[cfg(feature = "serve")]
+    Serve(ServeArgs),
+    /// Consume `station;temperature` messages from a Kafka topic until
+    /// stopped, writing a snapshot of the results every --snapshot-every
+    /// seconds and committing the offsets behind it; a --save-state file is
+    /// read back at start, so a restarted consumer goes on from its last
+    /// snapshot
+    #[cfg(feature = "kafka")]
+    Kafka(KafkaArgs),
+    /// Aggregate the input and run a SQL statement against the `results`
+    /// table (columns station, min, mean, max, count)
+    #[cfg(feature = "query")]
+    Query(Box<QueryArgs>),
+}
+
+/// Input and pipeline settings shared by every command that aggregates a file.
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// Measurements files, one `station;temperature` per line (`-` for
+    /// stdin, an `http://` or `https://` URL with the `http` feature, or an
+    /// `s3://`, `gs://` or `az://` URL with the `cloud` feature);
+    /// glob patterns such as `data/part-*.csv` are expanded and directories
+    /// are searched recursively
+    #[arg(default_value = "../data/weather_stations.csv")]
+    pub inputs: Vec<PathBuf>,
+
+    /// Number of worker threads (defaults to the number of CPUs)
+    #[arg(short, long)]
+    pub threads: Option<usize>,
+
+    /// Number of lines handed to a worker at a time
+    #[arg(short, long, default_value_t = 100_000)]
+    pub batch_size: usize,
+
+    /// Resize batches as the run goes, from how full the queue is and how
+    /// long workers take per batch; --batch-size is then the first size
+    #[arg(long)]
+    pub adaptive_batch: bool,
+
+    /// Maximum number of batches queued between the reader and the workers
+    /// (defaults to 2 per worker thread)
+    #[arg(long)]
+    pub queue_depth: Option<usize>,
+
+    /// Split the file into one newline-aligned byte range per worker instead
+    /// of reading lines on the main thread
+    #[arg(long, conflicts_with_all = ["mmap", "pipelined"])]
+    pub chunked: bool,
+
+    /// Memory-map the input and parse each worker's range in place (falls back
+    /// to --chunked reads if the file cannot be mapped)
+    #[arg(long, conflicts_with = "pipelined")]
+    pub mmap: bool,
+
+    /// Read the input on --io-threads threads that do nothing else, handing
+    /// newline-aligned stripes of it to the workers
+    #[arg(long)]
+    pub pipelined: bool,
+
+    /// With --pipelined, the number of reading threads
+    #[arg(long, default_value_t = 2, requires = "pipelined")]
+    pub io_threads: usize,
+
+    /// With --pipelined, how many stripes each reading thread may read
+    /// ahead of the workers
+    #[arg(long, default_value_t = 2, requires = "pipelined")]
+    pub read_queue_depth: usize,
+
+    /// With --mmap, ask for transparent huge pages behind the mapping, which
+    /// cuts TLB misses on large files (Linux; needs kernel support for huge
+    /// pages on file mappings)
+    #[arg(long, requires = "mmap")]
+    pub huge_pages: bool,
+
+    /// What runs the workers: `threads`, the pipeline's own; `rayon` for a
+    /// rayon fold/reduce over the memory-mapped file (built with `--features
+    /// rayon`); `custom-map`, which is --mmap with open-addressing tables
+    /// tuned for station names in place of hash maps; or `hash-only`, which
+    /// tells stations apart by a 64-bit hash of their names without ever
+    /// comparing them. All but `threads` ignore --chunked and --mmap
+    #[arg(long, value_enum, value_name = "ENGINE", default_value_t = Engine::Threads)]
+    pub engine: Engine,
+
+    /// With --engine hash-only, compare station names anyway and fail if
+    /// two different names share a hash, to check that the engine can be
+    /// trusted on this data
+    #[arg(long)]
+    pub verify_collisions: bool,
+
+    /// How plain text input is read: `std` reads, or `uring` to keep several
+    /// large reads in flight through io_uring (Linux, built with `--features
+    /// uring`); not used with --mmap
+    #[arg(long, value_enum, value_name = "BACKEND", default_value_t = IoBackend::Std)]
+    pub io_backend: IoBackend,
+
+    /// Read plain text input with O_DIRECT (Linux), bypassing the page cache,
+    /// e.g. to benchmark cold reads or to spare the cache on a shared machine
+    #[arg(long, conflicts_with = "mmap")]
+    pub direct_io: bool,
+
+    /// Pin each worker thread to a core of its own, and the reading and
+    /// merging thread to another (Linux; stays within the cores allowed by
+    /// taskset or cgroups)
+    #[arg(long)]
+    pub pin_threads: bool,
+
+    /// Keep the workers for consecutive parts of the input on the same NUMA
+    /// node, with their memory allocated there (Linux; combine with
+    /// --pin-threads for a core each)
+    #[arg(long)]
+    pub numa: bool,
+
+    /// Byte between station and temperature: a single ASCII character, or
+    /// `tab` (also `\t`) for tab-separated files [default: `;`, or what
+    /// `--input-format auto` finds]
+    #[arg(long, value_parser = parse_delimiter)]
+    pub delimiter: Option<u8>,
+
+    /// Layout of the input lines: `auto`, worked out from the first few KB of
+    /// each input (`delimited` with `;`, a tab or a comma and an optional
+    /// header, or `jsonl`); `delimited` (`station;temperature`); or `jsonl`,
+    /// one `{"station": "...", "temp": 12.3}` object per line. With
+    /// --delimiter, `auto` means `delimited`
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    pub input_format: InputFormat,
+
+    /// Also read temperatures with a decimal comma, `12,3`; `auto` turns
+    /// this on by itself for inputs that use one
+    #[arg(long)]
+    pub decimal_comma: bool,
+
+    /// Let fields be quoted as in RFC 4180, `"Washington, D.C.";4.3`, with
+    /// `""` for a quote inside one; reads more slowly, and not with --mmap
+    /// or --engine
+    #[arg(long)]
+    pub quoted: bool,
+
+    /// Drop the first line of every input as a header, and fail if it is a
+    /// reading; without it a first line is only dropped if its temperature
+    /// field is not a number
+    #[arg(long)]
+    pub skip_header: bool,
+
+    /// Field of a wider delimited line that holds the station, counting
+    /// from 1 like `cut -f` [default: 1 with --temp-col]
+    #[arg(long, value_name = "N")]
+    pub station_col: Option<NonZeroUsize>,
+
+    /// Field of a wider delimited line that holds the temperature, counting
+    /// from 1; columns after the later of the two are never looked at
+    /// [default: 2 with --station-col]
+    #[arg(long, value_name = "N")]
+    pub temp_col: Option<NonZeroUsize>,
+
+    /// Abort on the first malformed line instead of skipping it
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Number of skipped lines to show in the end-of-run report
+    #[arg(long, default_value_t = 5)]
+    pub skip_samples: usize,
+
+    /// Show throughput, percent complete and ETA on stderr while running
+    #[arg(long)]
+    pub progress: bool,
+
+    /// Show a full-screen dashboard on stderr while running: throughput,
+    /// how busy each worker is, memory use, skipped lines and the hottest
+    /// stations so far; `q` stops the run like Ctrl+C
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with_all = ["progress", "follow", "workers"])]
+    pub tui: bool,
+
+    /// Once the results are written, read commands such as `top 10 by max`,
+    /// `show Berlin` and `filter mean > 20` from stdin and answer them from
+    /// the results in memory, without another pass over the input
+    #[arg(long, conflicts_with = "follow")]
+    pub interactive: bool,
+
+    /// Approximate percentiles to report per station (e.g. p50,p95,p99),
+    /// shown in the JSON, CSV and table formats
+    #[arg(long, value_delimiter = ',', value_name = "LIST")]
+    pub percentiles: Vec<Percentile>,
+
+    /// Count readings per station in 0.5 degree buckets from -100 to 100,
+    /// exported in the JSON output
+    #[arg(long)]
+    pub histogram: bool,
+
+    /// Only aggregate stations whose name matches this regex (use `^...$`
+    /// for whole names)
+    #[arg(long, value_name = "REGEX")]
+    pub filter: Option<String>,
+
+    /// Only aggregate the stations listed in this file, one name per line
+    #[arg(long, value_name = "PATH")]
+    pub stations_file: Option<PathBuf>,
+
+    /// Merge stations spelled more than one way: CSV lines of an alias and
+    /// its canonical name, `St. Petersburg,Saint Petersburg`; --filter and
+    /// --stations-file still see the names as written
+    #[arg(long, value_name = "PATH")]
+    pub aliases: Option<PathBuf>,
+
+    /// The stations expected in the input, one name per line (anything
+    /// from a `;` on is ignored, so the generator's `name;mean` lists work as
+    /// they are): their names resolve through a perfect hash, other stations
+    /// are still aggregated; used with --mmap and --engine
+    #[arg(long, value_name = "PATH")]
+    pub known_stations: Option<PathBuf>,
+
+    /// Keep the workers' station maps under about this many megabytes
+    /// between them by spilling sorted runs to the temporary directory and
+    /// merging them at the end, for inputs with millions of stations
+    #[arg(
+        long,
+        value_name = "MB",
+        conflicts_with_all = ["mmap", "chunked", "checkpoint", "workers"]
+    )]
+    pub max_memory: Option<u64>,
+
+    /// Only aggregate this share of the input, such as `1%` or `0.01`, in
+    /// blocks spread evenly over every file, for a quick estimate: the means
+    /// come close, the counts, minimums and maximums are only the sample's
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        value_parser = parse_fraction,
+        conflicts_with_all = ["checkpoint", "workers", "follow"]
+    )]
+    pub sample: Option<f64>,
+
+    /// Only read and parse the input, without aggregating it, and report
+    /// the rows, the malformed ones and the throughput instead of results;
+    /// the report is JSON with `-f json` and text otherwise
+    #[arg(
+        long,
+        conflicts_with_all = ["workers", "follow", "interactive", "bench", "profile"]
+    )]
+    pub count_only: bool,
+
+    /// Range requests kept in flight for each URL input
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    pub connections: usize,
+
+    /// Also save the URL input to this file; if an earlier run left part of
+    /// it there, only the rest is downloaded
+    #[arg(long, value_name = "FILE")]
+    pub download_to: Option<PathBuf>,
+
+    /// Keep reading the input as it grows, like `tail -f`, and write the
+    /// results so far every --refresh milliseconds until Ctrl+C (a single
+    /// plain text input only); with --output, each write replaces the file
+    #[arg(long, conflicts_with_all = ["checkpoint", "workers", "progress"])]
+    pub follow: bool,
+
+    /// With --follow, milliseconds between two writes of the results
+    #[arg(long, value_name = "MS", default_value_t = 1000, requires = "follow")]
+    pub refresh: u64,
+
+    /// Save the partial results to this file as the run goes, so an
+    /// interrupted run can be picked up with --resume (a single plain text
+    /// input only; implies --chunked)
+    #[arg(long, value_name = "FILE", conflicts_with = "workers")]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Megabytes of input processed between two checkpoints
+    #[arg(
+        long,
+        value_name = "MB",
+        default_value_t = 1024,
+        requires = "checkpoint"
+    )]
+    pub checkpoint_every: u64,
+
+    /// Continue from the --checkpoint file if it exists
+    #[arg(long, requires = "checkpoint")]
+    pub resume: bool,
+
+    /// Coordinate a distributed run: hand byte ranges of the inputs to these
+    /// `calculate worker` processes and merge what they send back (the
+    /// workers must see the inputs at the same paths)
+    #[arg(long, value_delimiter = ',', value_name = "HOST:PORT,...")]
+    pub workers: Vec<String>,
+}
+
+impl RunArgs {
+    /// The input files with glob patterns expanded in sorted order and
+    /// directories replaced by the files under them. A pattern that matches
+    /// nothing is an error.
+    pub fn input_paths(&self) -> io::Result<Vec<PathBuf>> {
+        Ok(self
+            .partitioned_inputs()?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    /// Like [`RunArgs::input_paths`], with the Hive-style partition of every
+    /// file taken from the `key=value` directories in its path.
+    pub fn partitioned_inputs(&self) -> io::Result<Vec<(PathBuf, String)>> {
+        let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidInput, err);
+        let mut inputs = Vec::with_capacity(self.inputs.len());
+        for input in &self.inputs {
+            let pattern = input.to_string_lossy();
+            let paths = if is_url(input) || input.exists() || !pattern.contains(['*', '?', '[']) {
+                vec![input.clone()]
+            } else {
+                let matches = glob::glob(&pattern)
+                    .map_err(|err| invalid(format!("{pattern}: {err}")))?
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| invalid(err.to_string()))?;
+                if matches.is_empty() {
+                    return Err(invalid(format!("{pattern}: no files match")));
+                }
+                matches
+            };
+            for path in paths {
+                if path.is_dir() {
+                    inputs.extend(discover(&path)?);
+                } else {
+                    let partition = partition_of(Path::new(""), &path);
+                    inputs.push((path, partition));
+                }
+            }
+        }
+        Ok(inputs)
+    }
+
+    /// How input lines are read, before `--input-format auto` looks at them.
+    pub fn parse_options(&self) -> ParseOptions {
+        let format = match (self.input_format, self.delimiter) {
+            (InputFormat::Auto, Some(_)) => InputFormat::Delimited,
+            (format, _) => format,
+        };
+        ParseOptions {
+            delimiter: self.delimiter.unwrap_or(b';'),
+            strict: self.strict,
+            skip_samples: self.skip_samples,
+            format,
+            header: None,
+            decimal_comma: self.decimal_comma,
+            columns: self.columns(),
+            quoted: self.quoted,
+            skip_header: self.skip_header,
+        }
+    }
+
+    /// The `--station-col` and `--temp-col` fields, counted from 0.
+    fn columns(&self) -> Option<Columns> {
+        if self.station_col.is_none() && self.temp_col.is_none() {
+            return None;
+        }
+        let column =
+            |col: Option<NonZeroUsize>, default| col.map_or(default, NonZeroUsize::get) - 1;
+        Some(Columns {
+            station: column(self.station_col, 1),
+            temp: column(self.temp_col, 2),
+        })
+    }
+
+    pub fn options(&self) -> io::Result<Options> {
+        let defaults = Options::default();
+        if self.download_to.is_some()
+            && self.inputs.iter().filter(|input| is_url(input)).count() != 1
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--download-to needs exactly one URL input",
+            ));
+        }
+        if let Some(columns) = self.columns()
+            && columns.station == columns.temp
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--station-col and --temp-col must name different fields",
+            ));
+        }
+        if self.columns().is_some() && self.input_format == InputFormat::Jsonl {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--station-col and --temp-col pick fields of delimited lines, not JSON",
+            ));
+        }
+        Ok(Options {
+            threads: self.threads.unwrap_or(defaults.threads),
+            batch_size: self.batch_size,
+            adaptive_batch: self.adaptive_batch,
+            queue_depth: self.queue_depth,
+            mode: if self.mmap {
+                Mode::Mmap
+            } else if self.chunked {
+                Mode::Chunked
+            } else if self.pipelined {
+                Mode::Pipelined
+            } else {
+                Mode::Batched
+            },
+            io_threads: self.io_threads,
+            read_queue_depth: self.read_queue_depth,
+            engine: self.engine,
+            verify_collisions: self.verify_collisions,
+            io_backend: self.io_backend,
+            direct_io: self.direct_io,
+            huge_pages: self.huge_pages,
+            pin_threads: self.pin_threads,
+            numa: self.numa,
+            parse: self.parse_options(),
+            stats: StatsOptions {
+                percentiles: !self.percentiles.is_empty(),
+                histogram: self.histogram,
+            },
+            filter: self.station_filter()?.map(Arc::new),
+            aliases: self.aliases()?.map(Arc::new),
+            known_stations: self.known_stations()?.map(Arc::new),
+            max_memory: self.max_memory.map(|mb| mb.saturating_mul(1 << 20)),
+            sample: self.sample,
+            count_only: self.count_only,
+            connections: self.connections,
+            download_to: self.download_to.clone(),
+            progress: None,
+            live: None,
+            cancel: None,
+        })
+    }
+
+    /// The checkpoint settings, if --checkpoint was given.
+    pub fn checkpoint(&self) -> Option<CheckpointOptions> {
+        Some(CheckpointOptions {
+            path: self.checkpoint.clone()?,
+            every: self.checkpoint_every.saturating_mul(1 << 20),
+            resume: self.resume,
+        })
+    }
+
+    fn station_filter(&self) -> io::Result<Option<StationFilter>> {
+        if self.filter.is_none() && self.stations_file.is_none() {
+            return Ok(None);
+        }
+        let mut filter = StationFilter::new();
+        if let Some(pattern) = &self.filter {
+            filter = filter.with_pattern(pattern).map_err(|err| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("--filter: {err}"))
+            })?;
+        }
+        if let Some(path) = &self.stations_file {
+            let list = fs::read_to_string(path)?;
+            let names = list.lines().map(str::trim).filter(|name| !name.is_empty());
+            filter = filter.with_allowed(names);
+        }
+        Ok(Some(filter))
+    }
+
+    fn aliases(&self) -> io::Result<Option<Aliases>> {
+        let Some(path) = &self.aliases else {
+            return Ok(None);
+        };
+        let csv = fs::read_to_string(path)?;
+        let aliases = Aliases::parse(&csv).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("--aliases {}: {err}", path.display()),
+            )
+        })?;
+        Ok(Some(aliases))
+    }
+
+    fn known_stations(&self) -> io::Result<Option<StationIndex>> {
+        let Some(path) = &self.known_stations else {
+            return Ok(None);
+        };
+        let list = fs::read_to_string(path)?;
+        let names = list
+            .lines()
+            .map(|line| line.split(';').next().unwrap_or_default().trim())
+            .filter(|name| !name.is_empty() && !name.starts_with('#'));
+        Ok(Some(StationIndex::new(names)))
+    }
+}
+
+pub(crate) fn parse_delimiter(value: &str) -> Result<u8, String> {
+    match value {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ => match value.as_bytes() {
+            &[byte] if byte.is_ascii() && byte != b'\n' => Ok(byte),
+            _ => Err(format!(
+                "`{value}` is not a single ASCII character or `tab`"
+            )),
+        },
+    }
+}
+
+/// A share of the input, as a percentage such as `5%` or a fraction such as
+/// `0.05`.
+pub(crate) fn parse_fraction(value: &str) -> Result<f64, String> {
+    let fraction = match value.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(|percent| percent / 100.0),
+        None => value.parse::<f64>(),
+    };
+    match fraction {
+        Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(fraction),
+        _ => Err(format!(
+            "`{value}` is not a share above 0% and at most 100%"
+        )),
+    }
+}
+
+/// Where and how the results are written.
+#[derive(Args, Debug)]
+pub struct OutputArgs {
+    /// Format of the results
+    #[arg(short = 'f', long, value_enum, default_value_t = Format::Brc)]
+    pub output_format: Format,
+
+    /// Write every station as one line of this layout instead, e.g.
+    /// `"{station}: avg {mean} (min {min}, max {max}, n={count})"`. Fields
+    /// take Rust-style specs such as `{station:<20}` or `{mean:>8.2}`, and
+    /// `\n`, `\t` and `\e` are unescaped
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "output_format")]
+    pub template: Option<Template>,
+
+    /// Join station metadata onto the JSON, CSV and Arrow output, and place
+    /// the stations of -f geojson: a CSV file with a header line, the
+    /// station name first and then columns such as country, lat and lon
+    #[arg(long, value_name = "PATH")]
+    pub metadata: Option<PathBuf>,
+
+    /// Also write aggregates per value of this --metadata column, e.g.
+    /// `country`, after the stations
+    #[arg(
+        long,
+        value_name = "COLUMN",
+        requires = "metadata",
+        conflicts_with = "template"
+    )]
+    pub group_by: Option<String>,
+
+    /// Temperature unit of the results: Celsius, Fahrenheit or Kelvin
+    #[arg(long, value_enum, default_value_t = Unit::Celsius)]
+    pub unit: Unit,
+
+    /// Decimal places of the temperatures written, up to 15; an `f64` holds
+    /// no more than that reliably
+    #[arg(
+        long,
+        value_name = "DIGITS",
+        default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(0..=15)
+    )]
+    pub precision: usize,
+
+    /// How temperatures are rounded to --precision; the default matches the
+    /// challenge's reference implementation
+    #[arg(long, value_enum, default_value_t = Rounding::HalfUp)]
+    pub rounding: Rounding,
+
+    /// Write the results to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Also draw the --top stations, or the 20 with the highest means, as
+    /// a chart in this PNG file (or SVG, if it ends in `.svg`)
+    #[cfg(feature = "plot")]
+    #[arg(long, value_name = "FILE")]
+    pub plot: Option<PathBuf>,
+
+    /// What --plot draws
+    #[cfg(feature = "plot")]
+    #[arg(long, value_enum, default_value_t = PlotKind::Bars, requires = "plot")]
+    pub plot_kind: PlotKind,
+
+    /// Only write the K best-ranked stations, in rank order
+    #[arg(long, value_name = "K")]
+    pub top: Option<usize>,
+
+    /// Also write separate results for every Hive-style partition
+    /// (`key=value/` directories) among the inputs, before the rollup
+    #[arg(long)]
+    pub per_partition: bool,
+
+    /// Statistic that --top ranks by
+    #[arg(long, value_enum, default_value_t = Rank::Max, requires = "top")]
+    pub by: Rank,
+
+    /// Order the stations by this statistic, lowest first, instead of by
+    /// name; with --top, the K stations chosen are ordered this way
+    #[arg(long, value_enum, value_name = "KEY")]
+    pub sort: Option<SortKey>,
+
+    /// Sort highest first
+    #[arg(long, requires = "sort")]
+    pub desc: bool,
+
+    /// Leave out stations with fewer than N readings, noting how many were
+    /// left out on stderr
+    #[arg(long, value_name = "N")]
+    pub min_count: Option<u64>,
+
+    /// Also save the results with their raw accumulators to this file, for
+    /// `calculate merge`
+    #[arg(long, value_name = "FILE")]
+    pub save_state: Option<PathBuf>,
+}
+
+impl OutputArgs {
+    /// The output settings, with `percentiles` columns.
+    pub fn options(&self, percentiles: &[Percentile]) -> io::Result<OutputOptions> {
+        let metadata = self.metadata()?;
+        if let (Some(column), Some(metadata)) = (&self.group_by, &metadata)
+            && !metadata.columns().contains(column)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("--group-by: the metadata has no column `{column}`"),
+            ));
+        }
+        if self.output_format == Format::Geojson && metadata.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "-f geojson needs --metadata with the stations' coordinates",
+            ));
+        }
+        let ungrouped = match self.output_format {
+            Format::Geojson => Some("geojson"),
+            #[cfg(feature = "arrow")]
+            Format::Arrow => Some("arrow"),
+            _ => None,
+        };
+        if let (Some(_), Some(format)) = (&self.group_by, ungrouped) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("--group-by cannot be combined with -f {format}"),
+            ));
+        }
+        Ok(OutputOptions {
+            format: self.output_format,
+            percentiles: percentiles.to_vec(),
+            top: self.top.map(|k| Top { k, by: self.by }),
+            sort: self.sort.map(|by| Sort {
+                by,
+                descending: self.desc,
+            }),
+            min_count: self.min_count,
+            unit: self.unit,
+            precision: self.precision,
+            rounding: self.rounding,
+            template: self.template.clone(),
+            metadata: metadata.map(Arc::new),
+            group_by: self.group_by.clone(),
+        })
+    }
+
+    fn metadata(&self) -> io::Result<Option<Metadata>> {
+        let Some(path) = &self.metadata else {
+            return Ok(None);
+        };
+        let csv = fs::read_to_string(path)?;
+        let metadata = Metadata::parse(&csv).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("--metadata {}: {err}", path.display()),
+            )
+        })?;
+        Ok(Some(metadata))
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    /// Expected output in the challenge format; without it the input is
+    /// re-aggregated with the slow single-threaded reference implementation
+    #[arg(short, long)]
+    pub expected: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyDataArgs {
+    /// Measurements files (`-` for stdin; gzip and zstd files are
+    /// decompressed)
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Number of threads checking each plain file (defaults to the number
+    /// of CPUs)
+    #[arg(short, long)]
+    pub threads: Option<usize>,
+
+    /// Lines listed per file that break a rule; the rest are only counted
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    pub max_reported: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct MergeArgs {
+    /// State files written by `--save-state`
+    #[arg(required = true)]
+    pub states: Vec<PathBuf>,
+
+    /// Approximate percentiles to report per station; the runs must have
+    /// been made with --percentiles too
+    #[arg(long, value_delimiter = ',', value_name = "LIST")]
+    pub percentiles: Vec<Percentile>,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct WorkerArgs {
+    /// Address to accept coordinator connections on; only this machine can
+    /// connect unless it is changed, e.g. to 0.0.0.0:7070
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:7070")]
+    pub listen: String,
+
+    /// Number of threads per job (defaults to the number of CPUs)
+    #[arg(short, long)]
+    pub threads: Option<usize>,
+
+    /// Directory that the files of every job must be inside, once `..` and
+    /// symlinks are resolved
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    pub root: PathBuf,
+
+    /// Coordinators connected at once; further connections are closed
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    pub max_connections: usize,
+}
+
+#[cfg(feature = "grpc")]
+#[derive(Args, Debug)]
+pub struct GrpcArgs {
+    /// Address to serve on
+    #[arg(long, value_name = "ADDR", default_value = "0.0.0.0:50051")]
+    pub listen: String,
+
+    /// Number of threads per request (defaults to the number of CPUs)
+    #[arg(short, long)]
+    pub threads: Option<usize>,
+
+    /// Directory that the paths of `Aggregate` requests must be inside,
+    /// once `..` and symlinks are resolved; relative paths are taken from it
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    pub root: PathBuf,
+
+    /// `Aggregate` requests worked on at once; one beyond that fails with
+    /// RESOURCE_EXHAUSTED
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    pub max_jobs: usize,
+}
+
+#[cfg(feature = "serve")]
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Address to serve on
+    #[arg(long, value_name = "ADDR", default_value = "0.0.0.0:8080")]
+    pub listen: String,
+
+    /// Number of threads per job (defaults to the number of CPUs)
+    #[arg(short, long)]
+    pub threads: Option<usize>,
+
+    /// Milliseconds between the updates sent by `GET /jobs/:id/events`
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    pub refresh: u64,
+
+    /// Directory that the paths of posted jobs must be inside, once `..`
+    /// and symlinks are resolved; relative paths are taken from it
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    pub root: PathBuf,
+
+    /// Jobs running at once; one posted while this many run is turned away
+    /// with 503
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    pub max_jobs: usize,
+
+    /// Seconds the status and results of a finished job are kept for
+    #[arg(long, value_name = "SECS", default_value_t = 3600)]
+    pub keep_for: u64,
+
+    /// Finished jobs kept at most, the oldest dropped first
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    pub keep_jobs: usize,
+}
+
+#[cfg(feature = "kafka")]
+#[derive(Args, Debug)]
+pub struct KafkaArgs {
+    /// Topic whose messages hold one or more measurement lines each
+    #[arg(long)]
+    pub topic: String,
+
+    /// Kafka bootstrap servers, comma-separated
+    #[arg(long, value_name = "HOSTS", default_value = "localhost:9092")]
+    pub brokers: String,
+
+    /// Consumer group the offsets are committed for; a new group starts
+    /// from the beginning of the topic
+    #[arg(long, value_name = "ID", default_value = "onebrc")]
+    pub group: String,
+
+    /// Seconds between snapshots
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    pub snapshot_every: u64,
+
+    /// Byte between station and temperature: a single ASCII character, or
+    /// `tab` (also `\t`)
+    #[arg(long, default_value = ";", value_parser = parse_delimiter)]
+    pub delimiter: u8,
+
+    /// Layout of the lines in a message: `delimited` or `jsonl`
+    #[arg(long, value_enum, default_value_t = InputFormat::Delimited)]
+    pub input_format: InputFormat,
+
+    /// Stop at the first malformed line instead of skipping it
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Approximate percentiles to report per station (e.g. p50,p95,p99)
+    #[arg(long, value_delimiter = ',', value_name = "LIST")]
+    pub percentiles: Vec<Percentile>,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[cfg(feature = "query")]
+#[derive(Args, Debug)]
+pub struct QueryArgs {
+    /// The statement, e.g. `SELECT station, mean FROM results WHERE max > 40
+    /// ORDER BY mean DESC LIMIT 10`
+    pub sql: String,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[cfg(feature = "tracing")]
+#[derive(Args, Debug)]
+pub struct LogArgs {
+    /// Log spans and events of the pipeline at LEVEL and above to stderr:
+    /// off, error, warn, info (whole runs) or debug (workers and merges)
+    #[arg(long, value_name = "LEVEL", default_value = "off", global = true)]
+    pub log_level: tracing_subscriber::filter::LevelFilter,
+
+    /// How log lines are written
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true)]
+    pub log_format: LogFormat,
+}
+
+#[cfg(feature = "tracing")]
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line
+    Json,
+}
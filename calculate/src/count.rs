@@ -0,0 +1,88 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use onebrc_core::output::{Format, json_string};
+
+use crate::cli::{OutputArgs, RunArgs};
+use crate::{aggregate, input_size, open_output, report_skipped};
+
+/// What one pass that only parsed the lines found.
+struct Report {
+    time: Duration,
+    bytes: Option<u64>,
+    rows: u64,
+    malformed: u64,
+}
+
+impl Report {
+    /// Bytes and rows per second.
+    fn throughput(&self) -> (Option<f64>, f64) {
+        let secs = self.time.as_secs_f64();
+        (self.bytes.map(|b| b as f64 / secs), self.rows as f64 / secs)
+    }
+}
+
+/// Reads and parses the input in the usual pipeline without aggregating
+/// it, and reports how many lines there were, how many of them were
+/// malformed, and how fast they went by.
+pub fn run(args: &RunArgs, output: &OutputArgs) -> io::Result<()> {
+    let paths = args.input_paths()?;
+    let start = Instant::now();
+    let aggregator = aggregate(args)?;
+    let time = start.elapsed();
+    report_skipped(aggregator.skipped());
+
+    let report = Report {
+        time,
+        bytes: input_size(&paths)?,
+        rows: aggregator.rows(),
+        malformed: aggregator.skipped().count(),
+    };
+    let mut out = open_output(output)?;
+    match output.output_format {
+        Format::Json => write_json(&mut out, &paths, &report)?,
+        _ => write_text(&mut out, &paths, &report)?,
+    }
+    out.flush()
+}
+
+fn write_text(out: &mut impl Write, inputs: &[PathBuf], report: &Report) -> io::Result<()> {
+    let label = match inputs {
+        [input] => input.display().to_string(),
+        inputs => format!("{} files", inputs.len()),
+    };
+    writeln!(
+        out,
+        "{label}: {} row(s), {} malformed, in {:.3}s",
+        report.rows,
+        report.malformed,
+        report.time.as_secs_f64()
+    )?;
+    let (bytes_per_sec, rows_per_sec) = report.throughput();
+    if let Some(bytes_per_sec) = bytes_per_sec {
+        writeln!(out, "  {:>9.1} MB/s", bytes_per_sec / 1e6)?;
+    }
+    writeln!(out, "  {:>9.1}M rows/s", rows_per_sec / 1e6)
+}
+
+fn write_json(out: &mut impl Write, inputs: &[PathBuf], report: &Report) -> io::Result<()> {
+    let inputs: Vec<String> = inputs
+        .iter()
+        .map(|input| json_string(&input.to_string_lossy()))
+        .collect();
+    let (bytes_per_sec, rows_per_sec) = report.throughput();
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+    writeln!(
+        out,
+        "{{\"inputs\": [{}], \"bytes\": {}, \"rows\": {}, \"malformed\": {}, \
+         \"secs\": {:.6}, \"bytes_per_sec\": {}, \"rows_per_sec\": {:.1}}}",
+        inputs.join(", "),
+        optional(report.bytes.map(|b| b.to_string())),
+        report.rows,
+        report.malformed,
+        report.time.as_secs_f64(),
+        optional(bytes_per_sec.map(|b| format!("{b:.1}"))),
+        rows_per_sec
+    )
+}
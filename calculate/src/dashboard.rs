@@ -0,0 +1,292 @@
+//! `--tui`: a full-screen view of a run on stderr, in place of the
+//! `--progress` line.
+
+use std::io::{self, IsTerminal, Stderr};
+use std::panic;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use onebrc_core::{LiveResults, Progress, Rank};
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode,
+};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::text::Line;
+use ratatui::widgets::{Bar, BarChart, Block, Gauge, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+
+use crate::progress::clock;
+
+const REFRESH: Duration = Duration::from_millis(250);
+/// How often the workers publish their stations for the hottest list.
+pub const LIVE_EVERY: Duration = Duration::from_secs(1);
+const HOTTEST: usize = 10;
+
+type Screen = Terminal<CrosstermBackend<Stderr>>;
+
+/// The dashboard, redrawn on a background thread from the shared
+/// [`Progress`] counters and [`LiveResults`] until [`Dashboard::finish`].
+/// `q` or Ctrl+C stops the run the way Ctrl+C does without it.
+pub struct Dashboard {
+    handle: JoinHandle<io::Result<()>>,
+    done: Arc<AtomicBool>,
+}
+
+impl Dashboard {
+    /// Takes over the terminal. `total` is the input size in bytes, if
+    /// known; `cancel` is set when the run is stopped from the keyboard.
+    pub fn start(
+        progress: Arc<Progress>,
+        total: Option<u64>,
+        live: Arc<LiveResults>,
+        cancel: Option<Arc<AtomicBool>>,
+    ) -> io::Result<Self> {
+        if !io::stderr().is_terminal() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--tui needs a terminal on stderr",
+            ));
+        }
+        let mut screen = enter()?;
+        let done = Arc::new(AtomicBool::new(false));
+        let finished = Arc::clone(&done);
+        let handle = thread::spawn(move || {
+            let mut view = View::new(total);
+            let drawn = (|| {
+                while !finished.load(Ordering::Relaxed) {
+                    view.update(&progress, &live);
+                    screen.draw(|frame| view.render(frame))?;
+                    if event::poll(REFRESH)?
+                        && let Event::Key(key) = event::read()?
+                        && key.kind == KeyEventKind::Press
+                        && (key.code == KeyCode::Char('q')
+                            || key.code == KeyCode::Char('c')
+                                && key.modifiers.contains(KeyModifiers::CONTROL))
+                    {
+                        stop(cancel.as_deref());
+                    }
+                }
+                Ok(())
+            })();
+            leave()?;
+            drawn
+        });
+        Ok(Dashboard { handle, done })
+    }
+
+    /// Gives the terminal back.
+    pub fn finish(self) -> io::Result<()> {
+        self.done.store(true, Ordering::Relaxed);
+        self.handle.join().expect("Dashboard thread panicked")
+    }
+}
+
+/// Switches stderr to the alternate screen in raw mode, and makes sure a
+/// panic switches it back before the message is printed.
+fn enter() -> io::Result<Screen> {
+    enable_raw_mode()?;
+    execute!(io::stderr(), EnterAlternateScreen)?;
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let _ = leave();
+        hook(info);
+    }));
+    Terminal::new(CrosstermBackend::new(io::stderr()))
+}
+
+fn leave() -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(io::stderr(), LeaveAlternateScreen)
+}
+
+/// Raw mode turns Ctrl+C into a key press, so the interrupt handler's
+/// second-press exit is repeated here.
+fn stop(cancel: Option<&AtomicBool>) {
+    match cancel {
+        Some(cancel) if !cancel.swap(true, Ordering::Relaxed) => {}
+        _ => {
+            let _ = leave();
+            process::exit(130);
+        }
+    }
+}
+
+/// What the dashboard shows, worked out from the counters once per redraw.
+struct View {
+    total: Option<u64>,
+    start: Instant,
+    last: Instant,
+    /// Parse time per worker at the previous redraw.
+    last_times: Vec<Duration>,
+    /// Share of the time since the previous redraw each worker spent
+    /// parsing.
+    utilization: Vec<f64>,
+    bytes: u64,
+    rows: u64,
+    skipped: u64,
+    memory: Option<u64>,
+    /// Station, max, mean and count of the hottest stations.
+    hottest: Vec<[String; 4]>,
+    hottest_at: Option<Instant>,
+}
+
+impl View {
+    fn new(total: Option<u64>) -> Self {
+        let now = Instant::now();
+        View {
+            total,
+            start: now,
+            last: now,
+            last_times: Vec::new(),
+            utilization: Vec::new(),
+            bytes: 0,
+            rows: 0,
+            skipped: 0,
+            memory: None,
+            hottest: Vec::new(),
+            hottest_at: None,
+        }
+    }
+
+    fn update(&mut self, progress: &Progress, live: &LiveResults) {
+        let now = Instant::now();
+        let times = progress.worker_times();
+        let wall = now.duration_since(self.last).as_secs_f64().max(1e-3);
+        self.utilization = times
+            .iter()
+            .enumerate()
+            .map(|(worker, time)| {
+                let before = self.last_times.get(worker).copied().unwrap_or_default();
+                (time.saturating_sub(before).as_secs_f64() / wall).min(1.0)
+            })
+            .collect();
+        self.last_times = times;
+        self.last = now;
+        self.bytes = progress.bytes();
+        self.rows = progress.rows();
+        self.skipped = progress.skipped();
+        self.memory = resident_memory();
+        // Merging every worker's stations is the costly part, and they only
+        // publish every LIVE_EVERY anyway
+        if self.hottest_at.is_none_or(|at| at.elapsed() >= LIVE_EVERY) {
+            let results = live.results();
+            self.hottest = results
+                .top(HOTTEST, Rank::Max)
+                .into_iter()
+                .map(|(city, stats)| {
+                    [
+                        city.to_owned(),
+                        format!("{:.1}", stats.max()),
+                        format!("{:.1}", stats.mean()),
+                        stats.count().to_string(),
+                    ]
+                })
+                .collect();
+            self.hottest_at = Some(now);
+        }
+    }
+
+    fn render(&self, frame: &mut Frame) {
+        let [gauge, counters, body] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(4),
+            Constraint::Min(0),
+        ])
+        .areas(frame.area());
+        let [workers, hottest] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(body);
+        self.render_gauge(frame, gauge);
+        self.render_counters(frame, counters);
+        self.render_workers(frame, workers);
+        self.render_hottest(frame, hottest);
+    }
+
+    fn secs(&self) -> f64 {
+        self.start.elapsed().as_secs_f64().max(1e-3)
+    }
+
+    fn render_gauge(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(" calculate · q to stop ");
+        let elapsed = format!("{} elapsed", clock(self.secs()));
+        let Some(total) = self.total.filter(|&total| total > 0) else {
+            frame.render_widget(Paragraph::new(elapsed).block(block), area);
+            return;
+        };
+        let fraction = (self.bytes as f64 / total as f64).min(1.0);
+        let bytes_per_sec = self.bytes as f64 / self.secs();
+        let mut label = format!("{:.1}%, {elapsed}", fraction * 100.0);
+        if bytes_per_sec > 0.0 {
+            let remaining = total.saturating_sub(self.bytes) as f64 / bytes_per_sec;
+            label += &format!(", ETA {}", clock(remaining));
+        }
+        let gauge = Gauge::default().block(block).ratio(fraction).label(label);
+        frame.render_widget(gauge, area);
+    }
+
+    fn render_counters(&self, frame: &mut Frame, area: Rect) {
+        let secs = self.secs();
+        let memory = match self.memory {
+            Some(bytes) => format!("{:.1} MB", bytes as f64 / 1e6),
+            None => "n/a".to_owned(),
+        };
+        let lines = vec![
+            Line::from(format!(
+                "{:.1} MB/s, {:.1}M rows/s ({:.1} MB, {} rows)",
+                self.bytes as f64 / secs / 1e6,
+                self.rows as f64 / secs / 1e6,
+                self.bytes as f64 / 1e6,
+                self.rows
+            )),
+            Line::from(format!("{} skipped lines, {memory} resident", self.skipped)),
+        ];
+        let counters = Paragraph::new(lines).block(Block::bordered().title(" Throughput "));
+        frame.render_widget(counters, area);
+    }
+
+    fn render_workers(&self, frame: &mut Frame, area: Rect) {
+        let bars: Vec<Bar> = self
+            .utilization
+            .iter()
+            .enumerate()
+            .map(|(worker, &share)| {
+                let percent = (share * 100.0).round() as u64;
+                Bar::with_label(format!("{:>3}", worker + 1), percent)
+                    .text_value(format!("{percent}%"))
+            })
+            .collect();
+        let chart = BarChart::horizontal(bars)
+            .max(100)
+            .bar_gap(0)
+            .block(Block::bordered().title(" Workers busy parsing "));
+        frame.render_widget(chart, area);
+    }
+
+    fn render_hottest(&self, frame: &mut Frame, area: Rect) {
+        let rows = self.hottest.iter().map(|row| Row::new(row.clone()));
+        let widths = [
+            Constraint::Min(12),
+            Constraint::Length(6),
+            Constraint::Length(6),
+            Constraint::Length(10),
+        ];
+        let table = Table::new(rows, widths)
+            .header(Row::new(vec!["station", "max", "mean", "count"]))
+            .block(Block::bordered().title(" Hottest so far "));
+        frame.render_widget(table, area);
+    }
+}
+
+/// Resident set size of this process, where the OS tells it.
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
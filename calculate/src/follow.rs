@@ -0,0 +1,65 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use onebrc_core::{LiveResults, Options, follow_file};
+
+use crate::cli::{OutputArgs, RunArgs};
+use crate::{interrupt, report_skipped, write_snapshot};
+
+/// How often the followed file is looked at for new lines.
+const POLL: Duration = Duration::from_millis(100);
+
+/// Follows the one input of `args` until Ctrl+C, writing the results so far
+/// every --refresh milliseconds and the final ones once it stops.
+pub fn run(args: &RunArgs, output: &OutputArgs) -> io::Result<()> {
+    if output.per_partition {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--follow cannot be combined with --per-partition",
+        ));
+    }
+    let paths = args.input_paths()?;
+    let [input] = paths.as_slice() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--follow needs exactly one input file",
+        ));
+    };
+    // Workers publish after every block, so lines appended between two
+    // writes are always in the next one
+    let live = Arc::new(LiveResults::new(Duration::ZERO));
+    let options = Options {
+        live: Some(Arc::clone(&live)),
+        cancel: interrupt::flag(),
+        ..args.options()?
+    };
+    let output_options = output.options(&args.percentiles)?;
+    let every = Duration::from_millis(args.refresh.max(1));
+
+    let (followed, written) = thread::scope(|scope| {
+        let run = scope.spawn(|| follow_file(input, &options, POLL));
+        let mut last = Instant::now();
+        let mut written = Ok(());
+        while !run.is_finished() && written.is_ok() {
+            thread::sleep(POLL);
+            if last.elapsed() >= every {
+                written = write_snapshot(output, &live.results(), &output_options);
+                last = Instant::now();
+            }
+        }
+        if written.is_err()
+            && let Some(cancel) = &options.cancel
+        {
+            // Nowhere to write to any more, so stop reading too
+            cancel.store(true, Ordering::Relaxed);
+        }
+        (run.join().expect("follow thread panicked"), written)
+    });
+    written?;
+    let aggregator = followed?;
+    report_skipped(aggregator.skipped());
+    write_snapshot(output, &aggregator.into_results(), &output_options)
+}
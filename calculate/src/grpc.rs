@@ -0,0 +1,179 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use onebrc_core::{Aggregator, Options, ParseOptions, SkipReason, aggregate_files, confined_files};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::cli::GrpcArgs;
+
+use proto::aggregation_server::{Aggregation, AggregationServer};
+
+mod proto {
+    tonic::include_proto!("onebrc");
+}
+
+/// Serves the `onebrc.Aggregation` service until the process is stopped.
+pub fn run(args: &GrpcArgs) -> io::Result<()> {
+    let defaults = Options::default();
+    let root = fs::canonicalize(&args.root)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", args.root.display())))?;
+    let service = Service {
+        options: Options {
+            threads: args.threads.unwrap_or(defaults.threads),
+            ..defaults
+        },
+        root: Arc::from(root.as_path()),
+        max_jobs: args.max_jobs,
+        running: Arc::default(),
+    };
+    let addr = args
+        .listen
+        .parse()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, format!("--listen: {err}")))?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    eprintln!("Listening on {addr}, serving files in {}", root.display());
+    runtime
+        .block_on(
+            Server::builder()
+                .add_service(AggregationServer::new(service))
+                .serve(addr),
+        )
+        .map_err(io::Error::other)
+}
+
+struct Service {
+    options: Options,
+    /// Directory the paths of `Aggregate` requests must be inside.
+    root: Arc<Path>,
+    max_jobs: usize,
+    /// `Aggregate` requests being worked on.
+    running: Arc<AtomicUsize>,
+}
+
+/// One of [`Service::running`], given back when dropped: when the
+/// aggregation ends, not when the client stops waiting for it.
+struct Running(Arc<AtomicUsize>);
+
+impl Running {
+    /// Counts in another aggregation, unless `max` already run.
+    fn start(running: &Arc<AtomicUsize>, max: usize) -> Option<Running> {
+        running
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Running(Arc::clone(running)))
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[tonic::async_trait]
+impl Aggregation for Service {
+    async fn aggregate(
+        &self,
+        request: Request<proto::AggregateRequest>,
+    ) -> Result<Response<proto::Results>, Status> {
+        let request = request.into_inner();
+        let delimiter = match request.delimiter.as_bytes() {
+            [] => ParseOptions::default().delimiter,
+            &[byte] => byte,
+            _ => return Err(Status::invalid_argument("delimiter must be a single byte")),
+        };
+        let options = Options {
+            parse: ParseOptions {
+                delimiter,
+                strict: request.strict,
+                ..ParseOptions::default()
+            },
+            ..self.options.clone()
+        };
+        let running = Running::start(&self.running, self.max_jobs).ok_or_else(|| {
+            Status::resource_exhausted(format!(
+                "{} aggregation(s) running already, as many as --max-jobs allows",
+                self.max_jobs
+            ))
+        })?;
+        let root = Arc::clone(&self.root);
+        // Aggregation blocks a thread per worker; keep it off the runtime
+        let aggregator = tokio::task::spawn_blocking(move || -> io::Result<Aggregator> {
+            let _running = running;
+            let mut paths = Vec::with_capacity(request.paths.len());
+            for path in request.paths.into_iter().map(PathBuf::from) {
+                paths.extend(confined_files(&root, &path)?);
+            }
+            aggregate_files(&paths, &options)
+        })
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?
+        .map_err(status)?;
+        Ok(Response::new(results(aggregator)))
+    }
+
+    async fn submit_rows(
+        &self,
+        request: Request<Streaming<proto::RowBatch>>,
+    ) -> Result<Response<proto::Results>, Status> {
+        let mut batches = request.into_inner();
+        let mut aggregator = self.options.aggregator();
+        while let Some(batch) = batches.message().await? {
+            aggregator
+                .process_chunk(&batch.lines)
+                .map_err(|err| status(err.into()))?;
+            for reading in batch.readings {
+                let tenths = (reading.temperature * 10.0).round();
+                if tenths.abs() <= i32::MAX as f64 {
+                    aggregator.add(reading.station.as_bytes(), tenths as i32);
+                } else {
+                    aggregator
+                        .reject(reading.station.as_bytes(), SkipReason::OutOfRange)
+                        .map_err(|err| status(err.into()))?;
+                }
+            }
+        }
+        Ok(Response::new(results(aggregator)))
+    }
+}
+
+fn results(aggregator: Aggregator) -> proto::Results {
+    let rows = aggregator.rows();
+    let skipped = aggregator.skipped().count();
+    let stations = aggregator
+        .into_results()
+        .iter()
+        .map(|(station, stats)| proto::StationStats {
+            station: station.to_owned(),
+            min: stats.min(),
+            mean: stats.mean(),
+            max: stats.max(),
+            count: stats.count(),
+        })
+        .collect();
+    proto::Results {
+        stations,
+        rows,
+        skipped,
+    }
+}
+
+/// The gRPC status for a failed aggregation.
+fn status(err: io::Error) -> Status {
+    match err.kind() {
+        io::ErrorKind::NotFound => Status::not_found(err.to_string()),
+        io::ErrorKind::PermissionDenied => Status::permission_denied(err.to_string()),
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => {
+            Status::invalid_argument(err.to_string())
+        }
+        _ => Status::internal(err.to_string()),
+    }
+}
@@ -0,0 +1,37 @@
+//! Ctrl+C during a run: the first press stops the reader and lets the
+//! pipeline finish with what it has read, a second one exits at once.
+
+use std::io;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// Installs the handler. From then on [`flag`] returns the flag it sets.
+pub fn install() -> io::Result<()> {
+    let flag = Arc::new(AtomicBool::new(false));
+    let set = Arc::clone(&flag);
+    ctrlc::set_handler(move || {
+        if set.swap(true, Ordering::Relaxed) {
+            process::exit(130);
+        }
+        eprintln!("\nInterrupted, finishing what has been read (press Ctrl+C again to quit)");
+    })
+    .map_err(io::Error::other)?;
+    let _ = INTERRUPTED.set(flag);
+    Ok(())
+}
+
+/// The flag for [`Options::cancel`](onebrc_core::Options::cancel), if the
+/// handler is installed.
+pub fn flag() -> Option<Arc<AtomicBool>> {
+    INTERRUPTED.get().cloned()
+}
+
+/// Whether Ctrl+C has been pressed.
+pub fn interrupted() -> bool {
+    INTERRUPTED
+        .get()
+        .is_some_and(|flag| flag.load(Ordering::Relaxed))
+}
@@ -0,0 +1,117 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use onebrc_core::{Options, ParseOptions, Results, StatsOptions};
+use rdkafka::Message;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::error::KafkaError;
+
+use crate::cli::KafkaArgs;
+use crate::merge::load_state;
+use crate::{interrupt, report_skipped, write_snapshot};
+
+/// How long one poll waits for a message before the clock and Ctrl+C are
+/// looked at again.
+const POLL_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Consumes `args.topic` until Ctrl+C, then writes a last snapshot.
+///
+/// Offsets are only committed once the snapshot holding their messages is
+/// written, so a consumer that dies in between reads them again; with
+/// --save-state it also starts again from that snapshot, and every message
+/// is counted once.
+pub fn run(args: &KafkaArgs) -> io::Result<()> {
+    let output = &args.output;
+    if output.per_partition {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "messages carry no partitions; --per-partition needs input files",
+        ));
+    }
+    interrupt::install()?;
+
+    let options = Options {
+        parse: ParseOptions {
+            delimiter: args.delimiter,
+            strict: args.strict,
+            format: args.input_format,
+            ..ParseOptions::default()
+        },
+        stats: StatsOptions {
+            percentiles: !args.percentiles.is_empty(),
+            ..StatsOptions::default()
+        },
+        ..Options::default()
+    };
+    let output_options = output.options(&args.percentiles)?;
+    let mut total = match &output.save_state {
+        Some(path) if path.exists() => load_state(path)?,
+        _ => Results::default(),
+    };
+
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &args.brokers)
+        .set("group.id", &args.group)
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .map_err(kafka_error)?;
+    consumer.subscribe(&[&args.topic]).map_err(kafka_error)?;
+    eprintln!("Consuming {} from {}", args.topic, args.brokers);
+
+    let every = Duration::from_secs(args.snapshot_every.max(1));
+    let mut aggregator = options.aggregator();
+    let mut lines = 0;
+    let mut uncommitted = false;
+    let mut last = Instant::now();
+    loop {
+        let stop = interrupt::interrupted();
+        if !stop {
+            match consumer.poll(POLL_TIMEOUT) {
+                Some(Ok(message)) => {
+                    if let Some(payload) = message.payload() {
+                        aggregator.set_next_line(lines + 1);
+                        aggregator.process_chunk(payload)?;
+                        lines += line_count(payload);
+                    }
+                    uncommitted = true;
+                }
+                // Most errors are passing, such as a broker going away, and
+                // librdkafka keeps retrying; only a fatal one ends the run
+                Some(Err(err)) => match consumer.client().fatal_error() {
+                    Some((_, reason)) => return Err(io::Error::other(format!("kafka: {reason}"))),
+                    None => eprintln!("kafka: {err}"),
+                },
+                None => {}
+            }
+        }
+        if stop || last.elapsed() >= every {
+            let part = std::mem::replace(&mut aggregator, options.aggregator());
+            report_skipped(part.skipped());
+            total = total.into_iter().chain(part.into_results()).collect();
+            write_snapshot(output, &total, &output_options)?;
+            if uncommitted {
+                consumer
+                    .commit_consumer_state(CommitMode::Sync)
+                    .map_err(kafka_error)?;
+                uncommitted = false;
+            }
+            last = Instant::now();
+        }
+        if stop {
+            return Ok(());
+        }
+    }
+}
+
+/// Lines in one message, the last one with or without its newline.
+fn line_count(payload: &[u8]) -> u64 {
+    let newlines = payload.iter().filter(|&&byte| byte == b'\n').count();
+    let unterminated = !payload.is_empty() && !payload.ends_with(b"\n");
+    (newlines + usize::from(unterminated)) as u64
+}
+
+fn kafka_error(err: KafkaError) -> io::Error {
+    io::Error::other(format!("kafka: {err}"))
+}
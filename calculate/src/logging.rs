@@ -0,0 +1,20 @@
+use std::io::{self, IsTerminal};
+
+use tracing_subscriber::fmt::format::FmtSpan;
+
+use crate::cli::{LogArgs, LogFormat};
+
+/// Logs to stderr as `args` ask. Spans are logged when they close, with the
+/// time spent in them, so every worker and merge reports how long it took.
+pub fn init(args: &LogArgs) {
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(args.log_level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_thread_ids(true)
+        .with_ansi(io::stderr().is_terminal())
+        .with_writer(io::stderr);
+    match args.log_format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
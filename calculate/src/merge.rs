@@ -0,0 +1,53 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use onebrc_core::Results;
+use onebrc_core::output::write_results;
+
+use crate::cli::MergeArgs;
+use crate::open_output;
+
+/// Combines the partial results saved by `--save-state` runs and writes them
+/// as one result.
+pub fn run(args: &MergeArgs) -> io::Result<()> {
+    let output = &args.output;
+    if output.per_partition {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "state files carry no partitions; --per-partition needs the inputs",
+        ));
+    }
+
+    let mut parts = Vec::with_capacity(args.states.len());
+    for path in &args.states {
+        parts.push(load_state(path)?);
+    }
+    let results: Results = parts.into_iter().flatten().collect();
+
+    if let Some(path) = &output.save_state {
+        write_state(path, &results)?;
+    }
+    let mut out = open_output(output)?;
+    write_results(&mut out, &results, &output.options(&args.percentiles)?)?;
+    out.flush()
+}
+
+/// Reads a state file written by [`write_state`].
+pub(crate) fn load_state(path: &Path) -> io::Result<Results> {
+    let reader = BufReader::new(File::open(path)?);
+    serde_json::from_reader(reader).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: not a state file: {err}", path.display()),
+        )
+    })
+}
+
+/// Writes `results` with their raw accumulators as JSON, so that
+/// [`load_state`] gets back exactly the same statistics.
+pub(crate) fn write_state(path: &Path, results: &Results) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut out, results)?;
+    out.flush()
+}
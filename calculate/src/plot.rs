@@ -0,0 +1,255 @@
+//! `--plot`: the results drawn as a chart, for a quick look without
+//! exporting them to another tool.
+
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use clap::ValueEnum;
+use onebrc_core::output::{OutputOptions, Unit};
+use onebrc_core::{HISTOGRAM_BUCKET_WIDTH, HISTOGRAM_MIN, Rank, Results, TempStats};
+use plotters::coord::Shift;
+use plotters::coord::ranged1d::SegmentValue;
+use plotters::prelude::*;
+
+use crate::cli::{OutputArgs, RunArgs};
+
+/// Stations drawn without --top.
+const STATIONS: usize = 20;
+/// Pixels per station, plus room for the caption and the axes.
+const ROW_HEIGHT: u32 = 24;
+const MARGINS: u32 = 120;
+const WIDTH: u32 = 1000;
+
+/// What --plot draws.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PlotKind {
+    /// A bar per station at its mean, with a line from its min to its max
+    #[default]
+    Bars,
+    /// Stations against temperature, shaded by how many of each station's
+    /// readings fall in every 0.5 degree bucket (needs --histogram)
+    Heatmap,
+}
+
+/// Fails before the run if --plot cannot draw what it was asked to.
+pub fn check(args: &RunArgs, output: &OutputArgs) -> io::Result<()> {
+    if output.plot.is_some() && output.plot_kind == PlotKind::Heatmap && !args.histogram {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--plot-kind heatmap needs --histogram",
+        ));
+    }
+    Ok(())
+}
+
+/// Draws the --plot chart of `results`, if one was asked for: the --top
+/// stations, or the 20 with the highest means, as a PNG, or as an SVG if
+/// the file name ends in `.svg`.
+pub fn plot(output: &OutputArgs, results: &Results, options: &OutputOptions) -> io::Result<()> {
+    let Some(path) = &output.plot else {
+        return Ok(());
+    };
+    let rows = match options.top {
+        Some(top) => results.top(top.k, top.by),
+        None => results.top(STATIONS, Rank::Mean),
+    };
+    if rows.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "--plot: there are no stations to draw",
+        ));
+    }
+    let chart = Chart {
+        rows: &rows,
+        unit: options.unit,
+        kind: output.plot_kind,
+    };
+    let size = (WIDTH, MARGINS + ROW_HEIGHT * rows.len().max(1) as u32);
+    let svg = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"));
+    if svg {
+        let area = SVGBackend::new(path, size).into_drawing_area();
+        chart
+            .draw(&area)
+            .and_then(|()| area.present())
+            .map_err(|err| failed(path, err))
+    } else {
+        let area = BitMapBackend::new(path, size).into_drawing_area();
+        chart
+            .draw(&area)
+            .and_then(|()| area.present())
+            .map_err(|err| failed(path, err))
+    }
+}
+
+fn failed(path: &Path, err: impl fmt::Display) -> io::Error {
+    io::Error::other(format!("--plot {}: {err}", path.display()))
+}
+
+struct Chart<'a> {
+    /// Top row first.
+    rows: &'a [(&'a str, &'a TempStats)],
+    unit: Unit,
+    kind: PlotKind,
+}
+
+type Drawn<DB> = Result<(), DrawingAreaErrorKind<<DB as DrawingBackend>::ErrorType>>;
+
+impl Chart<'_> {
+    fn draw<DB: DrawingBackend>(&self, area: &DrawingArea<DB, Shift>) -> Drawn<DB> {
+        area.fill(&WHITE)?;
+        match self.kind {
+            PlotKind::Bars => self.bars(area),
+            PlotKind::Heatmap => self.heatmap(area),
+        }
+    }
+
+    /// The segment of the row at `rank`, counted from the top.
+    fn segment(&self, rank: usize) -> (SegmentValue<i32>, SegmentValue<i32>) {
+        let i = (self.rows.len() - 1 - rank) as i32;
+        (SegmentValue::Exact(i), SegmentValue::Exact(i + 1))
+    }
+
+    /// The station name for a label of the vertical axis.
+    fn label(&self, value: &SegmentValue<i32>) -> String {
+        match *value {
+            SegmentValue::CenterOf(i) if (0..self.rows.len() as i32).contains(&i) => {
+                self.rows[self.rows.len() - 1 - i as usize].0.to_owned()
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn bars<DB: DrawingBackend>(&self, area: &DrawingArea<DB, Shift>) -> Drawn<DB> {
+        let temps = self.temp_range(
+            self.rows
+                .iter()
+                .flat_map(|(_, stats)| [stats.min(), stats.max(), 0.0]),
+        );
+        let mut chart = ChartBuilder::on(area)
+            .caption("Mean temperature, min to max", ("sans-serif", 22))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(160)
+            .build_cartesian_2d(temps, (0..self.rows.len() as i32).into_segmented())?;
+        chart
+            .configure_mesh()
+            .disable_y_mesh()
+            .y_labels(self.rows.len())
+            .y_label_formatter(&|value| self.label(value))
+            .x_desc(self.unit_label())
+            .draw()?;
+        let zero = self
+            .unit
+            .convert(0.0)
+            .clamp(chart.x_range().start, chart.x_range().end);
+        chart.draw_series(self.rows.iter().enumerate().map(|(rank, (_, stats))| {
+            let (bottom, top) = self.segment(rank);
+            let mean = self.unit.convert(stats.mean());
+            let mut bar = Rectangle::new([(zero, bottom), (mean, top)], BLUE.mix(0.6).filled());
+            bar.set_margin(3, 3, 0, 0);
+            bar
+        }))?;
+        chart.draw_series(self.rows.iter().enumerate().map(|(rank, (_, stats))| {
+            let i = (self.rows.len() - 1 - rank) as i32;
+            let (min, max) = (
+                self.unit.convert(stats.min()),
+                self.unit.convert(stats.max()),
+            );
+            PathElement::new(
+                [
+                    (min, SegmentValue::CenterOf(i)),
+                    (max, SegmentValue::CenterOf(i)),
+                ],
+                BLACK.stroke_width(2),
+            )
+        }))?;
+        Ok(())
+    }
+
+    fn heatmap<DB: DrawingBackend>(&self, area: &DrawingArea<DB, Shift>) -> Drawn<DB> {
+        // Only the buckets some station has readings in
+        let buckets = self
+            .rows
+            .iter()
+            .filter_map(|(_, stats)| stats.histogram())
+            .flat_map(|histogram| {
+                let counts = histogram.counts();
+                let first = counts.iter().position(|&count| count > 0);
+                let last = counts.iter().rposition(|&count| count > 0);
+                first.zip(last).map(|(first, last)| [first, last + 1])
+            })
+            .flatten();
+        let (first, end) = buckets.fold((usize::MAX, 0), |(first, end), bucket| {
+            (first.min(bucket), end.max(bucket))
+        });
+        let edge = |bucket: usize| {
+            let tenths = HISTOGRAM_MIN + bucket as i32 * HISTOGRAM_BUCKET_WIDTH;
+            self.unit.convert(f64::from(tenths) / 10.0)
+        };
+        let temps = match first < end {
+            true => edge(first)..edge(end),
+            false => self.temp_range([0.0]),
+        };
+        let mut chart = ChartBuilder::on(area)
+            .caption("Readings by temperature", ("sans-serif", 22))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(160)
+            .build_cartesian_2d(temps, (0..self.rows.len() as i32).into_segmented())?;
+        chart
+            .configure_mesh()
+            .disable_mesh()
+            .y_labels(self.rows.len())
+            .y_label_formatter(&|value| self.label(value))
+            .x_desc(self.unit_label())
+            .draw()?;
+        for (rank, (_, stats)) in self.rows.iter().enumerate() {
+            let Some(histogram) = stats.histogram() else {
+                continue;
+            };
+            let (bottom, top) = self.segment(rank);
+            let busiest = histogram.counts().iter().copied().max().unwrap_or(0).max(1);
+            chart.draw_series(histogram.counts().iter().enumerate().filter_map(
+                |(bucket, &count)| {
+                    if count == 0 {
+                        return None;
+                    }
+                    // Blue for a few readings, through green, to red for the busiest bucket
+                    let share = count as f64 / busiest as f64;
+                    let color = HSLColor(0.66 * (1.0 - share), 0.9, 0.5);
+                    let cell = [
+                        (edge(bucket), bottom.clone()),
+                        (edge(bucket + 1), top.clone()),
+                    ];
+                    Some(Rectangle::new(cell, color.filled()))
+                },
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// The span of the temperatures `celsius`, in the output unit, with a
+    /// little room on either side.
+    fn temp_range(&self, celsius: impl IntoIterator<Item = f64>) -> Range<f64> {
+        let (low, high) = celsius
+            .into_iter()
+            .map(|celsius| self.unit.convert(celsius))
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), temp| {
+                (low.min(temp), high.max(temp))
+            });
+        let pad = ((high - low) * 0.05).max(1.0);
+        low - pad..high + pad
+    }
+
+    fn unit_label(&self) -> &'static str {
+        match self.unit {
+            Unit::Celsius => "°C",
+            Unit::Fahrenheit => "°F",
+            Unit::Kelvin => "K",
+        }
+    }
+}
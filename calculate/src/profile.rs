@@ -0,0 +1,198 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use onebrc_core::output::{Format, json_string};
+use onebrc_core::{LineLengths, Results, is_parquet, is_stream, is_url, open_input};
+
+use crate::cli::{OutputArgs, RunArgs};
+use crate::{aggregate, open_output, report_skipped};
+
+/// The shape of a dataset, gathered from one aggregation and one scan of
+/// its lines.
+struct Report {
+    lines: LineLengths,
+    /// Readings aggregated, which leaves out headers and skipped lines.
+    rows: u64,
+    skipped: u64,
+    /// Stations by readings, fewest first.
+    stations: Vec<(String, u64)>,
+    coldest: Option<(String, f64)>,
+    hottest: Option<(String, f64)>,
+}
+
+impl Report {
+    fn new(lines: LineLengths, results: &Results, skipped: u64) -> Self {
+        let mut stations: Vec<_> = results
+            .iter()
+            .map(|(name, stats)| (name.to_owned(), stats.count()))
+            .collect();
+        stations.sort_by_key(|&(_, count)| count);
+        let coldest = results
+            .iter()
+            .min_by(|(_, a), (_, b)| a.min().total_cmp(&b.min()))
+            .map(|(name, stats)| (name.to_owned(), stats.min()));
+        let hottest = results
+            .iter()
+            .max_by(|(_, a), (_, b)| a.max().total_cmp(&b.max()))
+            .map(|(name, stats)| (name.to_owned(), stats.max()));
+        Report {
+            lines,
+            rows: stations.iter().map(|&(_, count)| count).sum(),
+            skipped,
+            stations,
+            coldest,
+            hottest,
+        }
+    }
+
+    fn fewest(&self) -> Option<&(String, u64)> {
+        self.stations.first()
+    }
+
+    fn most(&self) -> Option<&(String, u64)> {
+        self.stations.last()
+    }
+
+    fn median(&self) -> Option<u64> {
+        (!self.stations.is_empty()).then(|| self.stations[self.stations.len() / 2].1)
+    }
+
+    /// How many times the median station's readings the busiest one has;
+    /// 1 for evenly spread data such as the generator's.
+    fn skew(&self) -> Option<f64> {
+        Some(self.most()?.1 as f64 / self.median()?.max(1) as f64)
+    }
+}
+
+/// Aggregates the input and scans its lines once more, then writes how many
+/// stations, rows and bytes it has, how long its lines are, the range of
+/// its temperatures and how unevenly its rows spread over stations.
+pub fn run(args: &RunArgs, output: &OutputArgs) -> io::Result<()> {
+    let paths = args.input_paths()?;
+    for path in &paths {
+        if is_stream(path) || is_url(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--profile needs files; stdin, pipes, sockets and URLs are only read once",
+            ));
+        }
+        if is_parquet(path)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: --profile reads lines of text", path.display()),
+            ));
+        }
+    }
+
+    let aggregator = aggregate(args)?;
+    report_skipped(aggregator.skipped());
+    let skipped = aggregator.skipped().count();
+    let results = aggregator.into_results();
+    let mut lines = LineLengths::new();
+    for path in &paths {
+        lines.scan(open_input(path)?)?;
+    }
+
+    let report = Report::new(lines, &results, skipped);
+    let mut out = open_output(output)?;
+    match output.output_format {
+        Format::Json => write_json(&mut out, &paths, &report)?,
+        _ => write_text(&mut out, &paths, &report)?,
+    }
+    out.flush()
+}
+
+fn write_text(out: &mut impl Write, inputs: &[PathBuf], report: &Report) -> io::Result<()> {
+    let label = match inputs {
+        [input] => input.display().to_string(),
+        inputs => format!("{} files", inputs.len()),
+    };
+    let lines = &report.lines;
+    writeln!(out, "{label}:")?;
+    writeln!(out, "  bytes        {}", lines.bytes())?;
+    writeln!(
+        out,
+        "  rows         {} ({} line(s), {} skipped)",
+        report.rows,
+        lines.lines(),
+        report.skipped
+    )?;
+    writeln!(out, "  stations     {}", report.stations.len())?;
+    if let (Some(shortest), Some(mean), Some(longest)) =
+        (lines.shortest(), lines.mean(), lines.longest())
+    {
+        writeln!(
+            out,
+            "  line bytes   min {shortest}, mean {mean:.1}, p50 {}, p99 {}, max {longest}",
+            lines.quantile(0.5).unwrap_or_default(),
+            lines.quantile(0.99).unwrap_or_default()
+        )?;
+    }
+    if let (Some((coldest, min)), Some((hottest, max))) = (&report.coldest, &report.hottest) {
+        writeln!(
+            out,
+            "  temperature  {min:.1} ({coldest}) to {max:.1} ({hottest})"
+        )?;
+    }
+    if let (Some((fewest, low)), Some(median), Some((most, high)), Some(skew)) = (
+        report.fewest(),
+        report.median(),
+        report.most(),
+        report.skew(),
+    ) {
+        writeln!(
+            out,
+            "  rows/station min {low} ({fewest}), median {median}, max {high} ({most}); \
+             max/median {skew:.2}"
+        )?;
+    }
+    Ok(())
+}
+
+fn write_json(out: &mut impl Write, inputs: &[PathBuf], report: &Report) -> io::Result<()> {
+    let inputs: Vec<String> = inputs
+        .iter()
+        .map(|input| json_string(&input.to_string_lossy()))
+        .collect();
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+    let integer = |value: Option<u64>| optional(value.map(|value| value.to_string()));
+    let station = |station: Option<&(String, u64)>| {
+        optional(station.map(|(name, count)| {
+            format!("{{\"station\": {}, \"rows\": {count}}}", json_string(name))
+        }))
+    };
+    let reading = |reading: &Option<(String, f64)>| {
+        optional(reading.as_ref().map(|(name, temperature)| {
+            format!(
+                "{{\"station\": {}, \"temperature\": {temperature:.1}}}",
+                json_string(name)
+            )
+        }))
+    };
+    let lines = &report.lines;
+    writeln!(
+        out,
+        "{{\"inputs\": [{}], \"bytes\": {}, \"lines\": {}, \"rows\": {}, \"skipped\": {}, \
+         \"stations\": {}, \"line_bytes\": {{\"min\": {}, \"mean\": {}, \"p50\": {}, \
+         \"p99\": {}, \"max\": {}}}, \"coldest\": {}, \"hottest\": {}, \
+         \"rows_per_station\": {{\"min\": {}, \"median\": {}, \"max\": {}, \
+         \"max_over_median\": {}}}}}",
+        inputs.join(", "),
+        lines.bytes(),
+        lines.lines(),
+        report.rows,
+        report.skipped,
+        report.stations.len(),
+        integer(lines.shortest()),
+        optional(lines.mean().map(|mean| format!("{mean:.2}"))),
+        integer(lines.quantile(0.5)),
+        integer(lines.quantile(0.99)),
+        integer(lines.longest()),
+        reading(&report.coldest),
+        reading(&report.hottest),
+        station(report.fewest()),
+        integer(report.median()),
+        station(report.most()),
+        optional(report.skew().map(|skew| format!("{skew:.3}")))
+    )
+}
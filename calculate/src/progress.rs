@@ -0,0 +1,83 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use onebrc_core::Progress;
+
+const REFRESH: Duration = Duration::from_millis(250);
+const BAR_WIDTH: usize = 30;
+
+/// A stderr status line redrawn from the shared [`Progress`] counters on a
+/// background thread until [`ProgressBar::finish`] is called.
+pub struct ProgressBar {
+    handle: JoinHandle<()>,
+    done: Arc<AtomicBool>,
+}
+
+impl ProgressBar {
+    /// `total` is the input size in bytes, if known; without it only the
+    /// throughput is shown.
+    pub fn start(progress: Arc<Progress>, total: Option<u64>) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let finished = Arc::clone(&done);
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            while !finished.load(Ordering::Relaxed) {
+                draw(&progress, total, start.elapsed());
+                thread::park_timeout(REFRESH);
+            }
+            draw(&progress, total, start.elapsed());
+            eprintln!();
+        });
+        ProgressBar { handle, done }
+    }
+
+    /// Draws the final state and ends the status line.
+    pub fn finish(self) {
+        self.done.store(true, Ordering::Relaxed);
+        self.handle.thread().unpark();
+        self.handle.join().expect("Progress thread panicked");
+    }
+}
+
+fn draw(progress: &Progress, total: Option<u64>, elapsed: Duration) {
+    let bytes = progress.bytes();
+    let rows = progress.rows();
+    let secs = elapsed.as_secs_f64().max(1e-3);
+    let bytes_per_sec = bytes as f64 / secs;
+
+    let mut line = String::new();
+    if let Some(total) = total.filter(|&total| total > 0) {
+        let fraction = (bytes as f64 / total as f64).min(1.0);
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        line += &format!(
+            "[{}{}] {:5.1}% ",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            fraction * 100.0
+        );
+        if bytes_per_sec > 0.0 {
+            let remaining = total.saturating_sub(bytes) as f64 / bytes_per_sec;
+            line += &format!("ETA {} ", clock(remaining));
+        }
+    }
+    line += &format!(
+        "{:.1} MB/s, {:.1}M rows/s, {} elapsed",
+        bytes_per_sec / 1e6,
+        rows as f64 / secs / 1e6,
+        clock(secs)
+    );
+
+    let mut stderr = io::stderr().lock();
+    // Clear the rest of the previous line in case it was longer
+    let _ = write!(stderr, "\r{line}\x1b[K");
+    let _ = stderr.flush();
+}
+
+/// Formats seconds as `m:ss`.
+pub(crate) fn clock(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
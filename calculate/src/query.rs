@@ -0,0 +1,23 @@
+use std::io;
+
+use datafusion::prelude::SessionContext;
+
+use crate::aggregate;
+use crate::cli::QueryArgs;
+
+/// Name the results are registered under.
+const TABLE: &str = "results";
+
+/// Aggregates the input, registers the results as the `results` table and
+/// prints the result set of the SQL statement.
+pub fn run(args: &QueryArgs) -> io::Result<()> {
+    let results = aggregate(&args.run)?.into_results();
+    let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+    runtime
+        .block_on(async {
+            let ctx = SessionContext::new();
+            ctx.register_batch(TABLE, results.to_record_batch())?;
+            ctx.sql(&args.sql).await?.show().await
+        })
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
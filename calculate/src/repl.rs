@@ -0,0 +1,214 @@
+//! `--interactive`: a prompt for looking through the results once the run
+//! is done, without scanning the input again.
+
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use clap::ValueEnum;
+use onebrc_core::output::{OutputOptions, Top, write_results};
+use onebrc_core::{Rank, Results, TempStats};
+
+use crate::cli::RunArgs;
+
+const HELP: &str = "\
+commands:
+  top N [by max|min|mean|count]   the N best-ranked stations
+  show STATION                    one station
+  filter STAT OP VALUE            keep the stations where, e.g., mean > 20
+                                  (STAT is min, mean, max or count; OP is
+                                  <, <=, =, !=, >= or >)
+  list                            every station kept
+  reset                           undo the filters
+  help                            this list
+  quit                            leave, as does Ctrl+D";
+
+/// Fails before the run if the prompt would compete with the input for
+/// stdin.
+pub fn check(args: &RunArgs) -> io::Result<()> {
+    if args.interactive && args.inputs.iter().any(|input| input == Path::new("-")) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--interactive reads its commands from stdin, which cannot be an input as well",
+        ));
+    }
+    Ok(())
+}
+
+/// Reads commands from stdin until `quit` or the end of it, writing what
+/// they select from `results` to stdout with `options`; the prompt and
+/// mistakes go to stderr.
+pub fn run(results: &Results, options: &OutputOptions) -> io::Result<()> {
+    let mut view = results.clone();
+    let top_by = options.top.map_or(Rank::Max, |top| top.by);
+    let listing = OutputOptions {
+        top: None,
+        ..options.clone()
+    };
+    eprintln!("{} stations; `help` lists the commands", view.len());
+    let mut stdin = io::stdin().lock();
+    let mut line = String::new();
+    loop {
+        eprint!("> ");
+        line.clear();
+        if stdin.read_line(&mut line)? == 0 {
+            eprintln!();
+            return Ok(());
+        }
+        let command = match Command::parse(&line, top_by) {
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(err) => {
+                eprintln!("{err}");
+                continue;
+            }
+        };
+        let mut out = io::stdout().lock();
+        match command {
+            Command::Top(top) => {
+                let options = OutputOptions {
+                    top: Some(top),
+                    ..listing.clone()
+                };
+                write_results(&mut out, &view, &options)?;
+            }
+            Command::Show(station) => match view.get(&station) {
+                Some(stats) => {
+                    let one = [(station, stats.clone())].into_iter().collect();
+                    write_results(&mut out, &one, &listing)?;
+                }
+                None if results.get(&station).is_some() => {
+                    eprintln!("`{station}` is filtered out; `reset` brings it back");
+                }
+                None => eprintln!("there is no station `{station}`"),
+            },
+            Command::Filter(filter) => {
+                view = view
+                    .iter()
+                    .filter(|(_, stats)| filter.keeps(stats, options))
+                    .map(|(station, stats)| (station.to_owned(), stats.clone()))
+                    .collect();
+                eprintln!("{} stations kept", view.len());
+            }
+            Command::List => write_results(&mut out, &view, &listing)?,
+            Command::Reset => {
+                view = results.clone();
+                eprintln!("{} stations", view.len());
+            }
+            Command::Help => eprintln!("{HELP}"),
+            Command::Quit => return Ok(()),
+        }
+        out.flush()?;
+    }
+}
+
+enum Command {
+    Top(Top),
+    Show(String),
+    Filter(Filter),
+    List,
+    Reset,
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// The command on `line`, `None` if it is blank; `top` ranks by `top_by`
+    /// unless told otherwise.
+    fn parse(line: &str, top_by: Rank) -> Result<Option<Self>, String> {
+        let line = line.trim();
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        let command = match (name, words.as_slice()) {
+            ("", _) => return Ok(None),
+            ("top", [k]) => Command::Top(Top {
+                k: count(k)?,
+                by: top_by,
+            }),
+            ("top", [k, "by", by]) => Command::Top(Top {
+                k: count(k)?,
+                by: stat(by)?,
+            }),
+            ("show", [_, ..]) => Command::Show(rest.to_owned()),
+            ("filter", [by, op, value]) => Command::Filter(Filter {
+                by: stat(by)?,
+                op: Op::parse(op)?,
+                value: value
+                    .parse()
+                    .map_err(|_| format!("`{value}` is not a number"))?,
+            }),
+            ("list", []) => Command::List,
+            ("reset", []) => Command::Reset,
+            ("help", []) => Command::Help,
+            ("quit" | "exit", []) => Command::Quit,
+            _ => return Err(format!("not a command: `{line}`; try `help`")),
+        };
+        Ok(Some(command))
+    }
+}
+
+fn count(k: &str) -> Result<usize, String> {
+    k.parse()
+        .map_err(|_| format!("`{k}` is not a number of stations"))
+}
+
+fn stat(name: &str) -> Result<Rank, String> {
+    Rank::from_str(name, true)
+        .map_err(|_| format!("`{name}` is not one of min, mean, max and count"))
+}
+
+/// `filter`: a statistic compared with a value. Temperatures are compared
+/// as they are written, in the output unit and at its precision, so
+/// `filter max = 45.3` finds the stations listed with that maximum.
+struct Filter {
+    by: Rank,
+    op: Op,
+    value: f64,
+}
+
+impl Filter {
+    fn keeps(&self, stats: &TempStats, options: &OutputOptions) -> bool {
+        let temp = |celsius| {
+            options
+                .rounding
+                .round(options.unit.convert(celsius), options.precision)
+        };
+        let stat = match self.by {
+            Rank::Min => temp(stats.min()),
+            Rank::Mean => temp(stats.mean()),
+            Rank::Max => temp(stats.max()),
+            Rank::Count => stats.count() as f64,
+        };
+        match self.op {
+            Op::Less => stat < self.value,
+            Op::AtMost => stat <= self.value,
+            Op::Equal => stat == self.value,
+            Op::NotEqual => stat != self.value,
+            Op::AtLeast => stat >= self.value,
+            Op::Greater => stat > self.value,
+        }
+    }
+}
+
+enum Op {
+    Less,
+    AtMost,
+    Equal,
+    NotEqual,
+    AtLeast,
+    Greater,
+}
+
+impl Op {
+    fn parse(op: &str) -> Result<Self, String> {
+        Ok(match op {
+            "<" => Op::Less,
+            "<=" => Op::AtMost,
+            "=" | "==" => Op::Equal,
+            "!=" => Op::NotEqual,
+            ">=" => Op::AtLeast,
+            ">" => Op::Greater,
+            _ => return Err(format!("`{op}` is not one of <, <=, =, !=, >= and >")),
+        })
+    }
+}
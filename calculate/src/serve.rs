@@ -0,0 +1,571 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::{self, Cursor};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use std::{fs, thread};
+
+use onebrc_core::output::{Format, OutputOptions, write_results};
+use onebrc_core::{
+    LiveResults, Options, ParseOptions, Percentile, Progress, Results, Stage, StationFilter,
+    StatsOptions, aggregate_files, confined_files,
+};
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::cli::{ServeArgs, parse_delimiter};
+use crate::input_size;
+
+/// A job as posted to `POST /jobs`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobRequest {
+    /// Files on the server; directories are searched recursively
+    paths: Vec<PathBuf>,
+    /// As `--delimiter`
+    #[serde(default)]
+    delimiter: Option<String>,
+    #[serde(default)]
+    strict: bool,
+    /// As `--percentiles`, e.g. `["p50", "p99"]`
+    #[serde(default)]
+    percentiles: Vec<String>,
+    #[serde(default)]
+    histogram: bool,
+    /// As `--filter`
+    #[serde(default)]
+    filter: Option<String>,
+}
+
+struct Job {
+    progress: Arc<Progress>,
+    /// The stations so far, for `GET /jobs/:id/events`.
+    live: Arc<LiveResults>,
+    total_bytes: Option<u64>,
+    threads: usize,
+    started: Instant,
+    percentiles: Vec<Percentile>,
+    /// `None` while the job runs.
+    outcome: Option<Result<Results, String>>,
+    finished: Option<Instant>,
+}
+
+/// What `GET /jobs/:id` answers.
+#[derive(Serialize)]
+struct JobStatus<'a> {
+    id: usize,
+    status: &'static str,
+    bytes: u64,
+    rows: u64,
+    /// Input size, when that is known up front (see `input_size`).
+    total_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// The jobs posted so far by id, but for the finished ones
+/// [`JobTable::evict`] dropped.
+#[derive(Default)]
+struct JobTable {
+    jobs: BTreeMap<usize, Job>,
+    next_id: usize,
+    /// What the dropped jobs add to the counters of `GET /metrics`.
+    retired: Retired,
+}
+
+#[derive(Default)]
+struct Retired {
+    bytes: u64,
+    rows: u64,
+    skipped: u64,
+    /// By [`Stage::ALL`].
+    stages: [Duration; Stage::ALL.len()],
+}
+
+impl JobTable {
+    fn running(&self) -> usize {
+        self.jobs
+            .values()
+            .filter(|job| job.outcome.is_none())
+            .count()
+    }
+
+    /// Drops the jobs that finished over `keep_for` ago, then the oldest
+    /// finished ones beyond the `keep` newest.
+    fn evict(&mut self, keep_for: Duration, keep: usize) {
+        let mut finished: Vec<(Instant, usize)> = self
+            .jobs
+            .iter()
+            .filter_map(|(&id, job)| Some((job.finished?, id)))
+            .collect();
+        finished.sort_unstable();
+        let excess = finished.len().saturating_sub(keep);
+        for (i, (at, id)) in finished.into_iter().enumerate() {
+            if i < excess || at.elapsed() > keep_for {
+                let job = self.jobs.remove(&id).expect("listed above");
+                let retired = &mut self.retired;
+                retired.bytes += job.progress.bytes();
+                retired.rows += job.progress.rows();
+                retired.skipped += job.progress.skipped();
+                for (seconds, stage) in retired.stages.iter_mut().zip(Stage::ALL) {
+                    *seconds += job.progress.stage_time(stage);
+                }
+            }
+        }
+    }
+}
+
+type Jobs = Arc<Mutex<JobTable>>;
+
+/// Locks the jobs. Nothing panics halfway through changing the table, so
+/// one left poisoned by a panic elsewhere is used as it is.
+fn lock(jobs: &Jobs) -> MutexGuard<'_, JobTable> {
+    jobs.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Serves the jobs API until the process is stopped. Requests are answered
+/// one at a time, but for event streams, which get a thread each; every
+/// job aggregates on threads of its own.
+pub fn run(args: &ServeArgs) -> io::Result<()> {
+    let root = fs::canonicalize(&args.root)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", args.root.display())))?;
+    let server = Server::http(&args.listen).map_err(io::Error::other)?;
+    eprintln!(
+        "Listening on {}, serving files in {}",
+        args.listen,
+        root.display()
+    );
+    let jobs = Jobs::default();
+    let every = refresh(args);
+    let keep_for = Duration::from_secs(args.keep_for);
+    for mut request in server.incoming_requests() {
+        lock(&jobs).evict(keep_for, args.keep_jobs);
+        if let Some(id) = events_of(&request, &jobs) {
+            let jobs = Arc::clone(&jobs);
+            thread::spawn(move || stream_events(request, id, &jobs, every));
+            continue;
+        }
+        let response = route(&mut request, args, &root, &jobs);
+        if let Err(err) = request.respond(response) {
+            eprintln!("error: {err}");
+        }
+    }
+    Ok(())
+}
+
+/// The response to `request`.
+fn route(
+    request: &mut Request,
+    args: &ServeArgs,
+    root: &Path,
+    jobs: &Jobs,
+) -> Response<Cursor<Vec<u8>>> {
+    let url = request.url().to_owned();
+    let path = url.split('?').next().unwrap_or_default();
+    let path: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let (status, body) = match (request.method(), path.as_slice()) {
+        (Method::Get, ["metrics"]) => {
+            let body = metrics(&lock(jobs));
+            return response(200, "text/plain; version=0.0.4", body);
+        }
+        (Method::Post, ["jobs"]) => submit(request, args, root, jobs),
+        (Method::Get, ["jobs", id]) => with_job(jobs, id, |id, job| (200, status(id, job))),
+        (Method::Get, ["jobs", id, "results"]) => {
+            with_job(jobs, id, |id, job| match &job.outcome {
+                None => (409, error(&format!("job {id} is still running"))),
+                Some(Err(err)) => (500, error(err)),
+                Some(Ok(results)) => (200, results_json(results, &job.percentiles)),
+            })
+        }
+        // Events of jobs that exist are streamed by `run`
+        (Method::Get, ["jobs", id, "events"]) => (404, error(&format!("no job {id}"))),
+        (_, ["jobs"] | ["jobs", _] | ["jobs", _, "results" | "events"] | ["metrics"]) => {
+            (405, error("method not allowed"))
+        }
+        _ => (404, error("not found")),
+    };
+    response(status, "application/json", body)
+}
+
+fn response(status: u16, content_type: &str, body: String) -> Response<Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes("Content-Type", content_type).expect("valid header");
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+/// Starts the posted job and answers with its id, or with 503 if
+/// `--max-jobs` jobs are running already.
+fn submit(request: &mut Request, args: &ServeArgs, root: &Path, jobs: &Jobs) -> (u16, String) {
+    let posted: JobRequest = match serde_json::from_reader(request.as_reader()) {
+        Ok(posted) => posted,
+        Err(err) => return (400, error(&format!("invalid job: {err}"))),
+    };
+    let (options, paths, percentiles) = match setup(posted, args, root) {
+        Ok(setup) => setup,
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            return (403, error(&err.to_string()));
+        }
+        Err(err) => return (400, error(&err.to_string())),
+    };
+    let total_bytes = input_size(&paths).ok().flatten();
+    let progress = Arc::new(Progress::new());
+    let live = Arc::new(LiveResults::new(refresh(args)));
+    let id = {
+        let mut table = lock(jobs);
+        if table.running() >= args.max_jobs {
+            let message = format!(
+                "{} job(s) running already, as many as --max-jobs allows; try again later",
+                table.running()
+            );
+            return (503, error(&message));
+        }
+        let id = table.next_id;
+        table.next_id += 1;
+        table.jobs.insert(
+            id,
+            Job {
+                progress: Arc::clone(&progress),
+                live: Arc::clone(&live),
+                total_bytes,
+                threads: options.threads,
+                started: Instant::now(),
+                percentiles,
+                outcome: None,
+                finished: None,
+            },
+        );
+        id
+    };
+
+    let jobs = Arc::clone(jobs);
+    thread::spawn(move || {
+        let options = Options {
+            progress: Some(progress),
+            live: Some(live),
+            ..options
+        };
+        // A panic fails the job rather than leaving it running for good
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| aggregate_files(&paths, &options)))
+            .unwrap_or_else(|panic| {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown error");
+                Err(io::Error::other(format!("job panicked: {message}")))
+            })
+            .map(|aggregator| aggregator.into_results())
+            .map_err(|err| err.to_string());
+        if let Some(job) = lock(&jobs).jobs.get_mut(&id) {
+            job.outcome = Some(outcome);
+            job.finished = Some(Instant::now());
+        }
+    });
+    (202, serde_json::json!({ "id": id }).to_string())
+}
+
+/// The pipeline options, input files and percentiles of a posted job.
+fn setup(
+    posted: JobRequest,
+    args: &ServeArgs,
+    root: &Path,
+) -> io::Result<(Options, Vec<PathBuf>, Vec<Percentile>)> {
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidInput, err);
+    let delimiter = match &posted.delimiter {
+        Some(delimiter) => parse_delimiter(delimiter).map_err(invalid)?,
+        None => ParseOptions::default().delimiter,
+    };
+    let percentiles = posted
+        .percentiles
+        .iter()
+        .map(|p| p.parse::<Percentile>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+    let filter = match &posted.filter {
+        Some(pattern) => Some(Arc::new(
+            StationFilter::new()
+                .with_pattern(pattern)
+                .map_err(|err| invalid(format!("filter: {err}")))?,
+        )),
+        None => None,
+    };
+
+    let mut paths = Vec::with_capacity(posted.paths.len());
+    for path in posted.paths {
+        paths.extend(confined_files(root, &path)?);
+    }
+
+    let defaults = Options::default();
+    let options = Options {
+        threads: args.threads.unwrap_or(defaults.threads),
+        parse: ParseOptions {
+            delimiter,
+            strict: posted.strict,
+            ..ParseOptions::default()
+        },
+        stats: StatsOptions {
+            percentiles: !percentiles.is_empty(),
+            histogram: posted.histogram,
+        },
+        filter,
+        ..defaults
+    };
+    Ok((options, paths, percentiles))
+}
+
+/// Calls `answer` with the job `id` names, or answers 404.
+fn with_job(
+    jobs: &Jobs,
+    id: &str,
+    answer: impl FnOnce(usize, &Job) -> (u16, String),
+) -> (u16, String) {
+    let table = lock(jobs);
+    match id
+        .parse()
+        .ok()
+        .and_then(|id: usize| Some((id, table.jobs.get(&id)?)))
+    {
+        Some((id, job)) => answer(id, job),
+        None => (404, error(&format!("no job {id}"))),
+    }
+}
+
+fn status(id: usize, job: &Job) -> String {
+    let (status, error) = match &job.outcome {
+        None => ("running", None),
+        Some(Ok(_)) => ("done", None),
+        Some(Err(err)) => ("failed", Some(err.as_str())),
+    };
+    let status = JobStatus {
+        id,
+        status,
+        bytes: job.progress.bytes(),
+        rows: job.progress.rows(),
+        total_bytes: job.total_bytes,
+        error,
+    };
+    serde_json::to_string(&status).expect("status serializes")
+}
+
+fn refresh(args: &ServeArgs) -> Duration {
+    Duration::from_millis(args.refresh.max(1))
+}
+
+/// The job whose events `request` asks for, if it is a `GET
+/// /jobs/:id/events` for a job that exists.
+fn events_of(request: &Request, jobs: &Jobs) -> Option<usize> {
+    if request.method() != &Method::Get {
+        return None;
+    }
+    let path = request.url().split('?').next().unwrap_or_default();
+    let path: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let ["jobs", id, "events"] = path.as_slice() else {
+        return None;
+    };
+    let id = id.parse().ok()?;
+    lock(jobs).jobs.contains_key(&id).then_some(id)
+}
+
+/// Answers `GET /jobs/:id/events` with Server-Sent Events: every `every`, a
+/// `status` event with what `GET /jobs/:id` answers and a `results` event
+/// with the stations so far in the `-f json` format, until the job is over;
+/// then the final pair and a `done` event. Stops early if the client goes
+/// away.
+fn stream_events(request: Request, id: usize, jobs: &Jobs, every: Duration) {
+    let mut out = request.into_writer();
+    if let Err(err) = send_events(&mut out, id, jobs, every)
+        && err.kind() != io::ErrorKind::BrokenPipe
+        && err.kind() != io::ErrorKind::ConnectionReset
+    {
+        eprintln!("error: {err}");
+    }
+}
+
+fn send_events(
+    out: &mut impl io::Write,
+    id: usize,
+    jobs: &Jobs,
+    every: Duration,
+) -> io::Result<()> {
+    // Chunked, so the client sees where the stream ends while the server
+    // keeps the connection
+    out.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+          Transfer-Encoding: chunked\r\n\r\n",
+    )?;
+    loop {
+        let (status, results, over) = {
+            let table = lock(jobs);
+            // Dropped while the stream slept, which only happens to jobs
+            // that are over
+            let Some(job) = table.jobs.get(&id) else {
+                send_event(out, "done", "{}")?;
+                out.write_all(b"0\r\n\r\n")?;
+                return out.flush();
+            };
+            // The final results, with any spilled stations, once there are
+            let results = match &job.outcome {
+                None => Some(results_json(&job.live.results(), &job.percentiles)),
+                Some(Ok(results)) => Some(results_json(results, &job.percentiles)),
+                Some(Err(_)) => None,
+            };
+            (status(id, job), results, job.outcome.is_some())
+        };
+        send_event(out, "status", &status)?;
+        if let Some(results) = results {
+            send_event(out, "results", &results)?;
+        }
+        if over {
+            send_event(out, "done", "{}")?;
+            out.write_all(b"0\r\n\r\n")?;
+            return out.flush();
+        }
+        thread::sleep(every);
+    }
+}
+
+/// Writes one Server-Sent Event, a `data:` line per line of `data`, as one
+/// chunk.
+fn send_event(out: &mut impl io::Write, name: &str, data: &str) -> io::Result<()> {
+    let mut event = format!("event: {name}\n");
+    for line in data.lines() {
+        let _ = writeln!(event, "data: {line}");
+    }
+    event.push('\n');
+    write!(out, "{:x}\r\n{event}\r\n", event.len())?;
+    out.flush()
+}
+
+/// Totals over all jobs in the Prometheus text format; the counters go on
+/// counting the jobs that were dropped.
+fn metrics(table: &JobTable) -> String {
+    let jobs: Vec<&Job> = table.jobs.values().collect();
+    let running: Vec<_> = jobs.iter().filter(|job| job.outcome.is_none()).collect();
+    let total = |value: fn(&Progress) -> u64, retired: u64| -> f64 {
+        (retired + jobs.iter().map(|job| value(&job.progress)).sum::<u64>()) as f64
+    };
+    // Time spent parsing over the time the workers were there for
+    let busy: f64 = running
+        .iter()
+        .map(|job| job.progress.stage_time(Stage::Parse).as_secs_f64())
+        .sum();
+    let available: f64 = running
+        .iter()
+        .map(|job| job.started.elapsed().as_secs_f64() * job.threads as f64)
+        .sum();
+    let stages: Vec<_> = Stage::ALL
+        .into_iter()
+        .zip(table.retired.stages)
+        .map(|(stage, retired)| {
+            let seconds = jobs.iter().map(|job| job.progress.stage_time(stage));
+            (
+                format!("{{stage=\"{}\"}}", stage.name()),
+                (retired + seconds.sum::<Duration>()).as_secs_f64(),
+            )
+        })
+        .collect();
+    let finished = |ok: bool| {
+        jobs.iter()
+            .filter(|job| {
+                job.outcome
+                    .as_ref()
+                    .is_some_and(|outcome| outcome.is_ok() == ok)
+            })
+            .count() as f64
+    };
+
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, f64)]| {
+        let _ = writeln!(out, "# HELP onebrc_{name} {help}");
+        let _ = writeln!(out, "# TYPE onebrc_{name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(out, "onebrc_{name}{labels} {value}");
+        }
+    };
+    metric(
+        "bytes_read_total",
+        "counter",
+        "Input bytes processed.",
+        &[("", total(Progress::bytes, table.retired.bytes))],
+    );
+    metric(
+        "rows_parsed_total",
+        "counter",
+        "Lines processed, malformed ones included.",
+        &[("", total(Progress::rows, table.retired.rows))],
+    );
+    metric(
+        "rows_skipped_total",
+        "counter",
+        "Malformed lines skipped.",
+        &[("", total(Progress::skipped, table.retired.skipped))],
+    );
+    metric(
+        "batches_in_flight",
+        "gauge",
+        "Batches read but not yet picked up by a worker.",
+        &[("", total(Progress::in_flight, 0))],
+    );
+    let stages: Vec<_> = stages
+        .iter()
+        .map(|(labels, seconds)| (labels.as_str(), *seconds))
+        .collect();
+    metric(
+        "stage_seconds_total",
+        "counter",
+        "Time spent per pipeline stage, summed over threads.",
+        &stages,
+    );
+    metric(
+        "workers",
+        "gauge",
+        "Worker threads of the running jobs.",
+        &[(
+            "",
+            running.iter().map(|job| job.threads).sum::<usize>() as f64,
+        )],
+    );
+    metric(
+        "worker_utilization",
+        "gauge",
+        "Share of the running jobs' worker time spent parsing.",
+        &[(
+            "",
+            if available > 0.0 {
+                busy / available
+            } else {
+                0.0
+            },
+        )],
+    );
+    metric(
+        "jobs",
+        "gauge",
+        "Jobs by status.",
+        &[
+            ("{status=\"running\"}", running.len() as f64),
+            ("{status=\"done\"}", finished(true)),
+            ("{status=\"failed\"}", finished(false)),
+        ],
+    );
+    out
+}
+
+/// The results in the `-f json` format.
+fn results_json(results: &Results, percentiles: &[Percentile]) -> String {
+    let options = OutputOptions {
+        format: Format::Json,
+        percentiles: percentiles.to_vec(),
+        ..OutputOptions::default()
+    };
+    let mut out = Vec::new();
+    write_results(&mut out, results, &options).expect("writing to memory cannot fail");
+    String::from_utf8(out).expect("JSON output is UTF-8")
+}
+
+fn error(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
@@ -0,0 +1,90 @@
+use std::fs;
+use std::io::{self, BufReader, Read};
+
+use onebrc_core::validate::{
+    ROUNDED_MEAN_TOLERANCE, UNROUNDED_MEAN_TOLERANCE, compare, parse_brc, reference_results,
+    summarize,
+};
+use onebrc_core::{InputFormat, is_parquet, is_stream, open_input, sniff_file};
+
+use crate::aggregate;
+use crate::cli::ValidateArgs;
+
+/// Returns whether the results matched.
+pub fn run(args: &ValidateArgs) -> io::Result<bool> {
+    let results = aggregate(&args.run)?.into_results();
+    let actual = summarize(&results);
+
+    let (expected, mean_tolerance) = match &args.expected {
+        Some(path) => {
+            let text = fs::read_to_string(path)?;
+            let expected = parse_brc(&text).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {err}", path.display()),
+                )
+            })?;
+            (expected, ROUNDED_MEAN_TOLERANCE)
+        }
+        None => {
+            // A newline after every file keeps a missing final newline from
+            // gluing two files' lines together
+            let paths = args.run.input_paths()?;
+            let mut parse = args.run.parse_options();
+            if let Some(first) = paths.iter().find(|path| !is_stream(path)) {
+                parse = sniff_file(first, parse)?;
+            }
+            if parse.format == InputFormat::Jsonl {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the reference implementation only reads delimited lines; pass --expected",
+                ));
+            }
+            if parse.quoted {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the reference implementation does not unquote fields; pass --expected",
+                ));
+            }
+            if parse.columns.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the reference implementation only reads two-field lines; pass --expected",
+                ));
+            }
+            if parse.decimal_comma {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the reference implementation only reads decimal points; pass --expected",
+                ));
+            }
+            let mut inputs: Box<dyn Read> = Box::new(io::empty());
+            for path in paths {
+                if is_parquet(&path)? {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "{}: the reference implementation only reads text; pass --expected",
+                            path.display()
+                        ),
+                    ));
+                }
+                inputs = Box::new(inputs.chain(open_input(path)?).chain(&b"\n"[..]));
+            }
+            let expected = reference_results(BufReader::new(inputs), parse.delimiter)?;
+            (expected, UNROUNDED_MEAN_TOLERANCE)
+        }
+    };
+
+    let mismatches = compare(&actual, &expected, mean_tolerance);
+    if mismatches.is_empty() {
+        println!("OK: all {} stations match", expected.len());
+        return Ok(true);
+    }
+
+    println!("{} station(s) differ:", mismatches.len());
+    for mismatch in &mismatches {
+        println!("  {mismatch}");
+    }
+    Ok(false)
+}
@@ -0,0 +1,46 @@
+use std::io;
+
+use onebrc_core::Options;
+use onebrc_core::spec::verify_file;
+
+use crate::cli::VerifyDataArgs;
+
+/// Returns whether every line of every input keeps to the spec.
+pub fn run(args: &VerifyDataArgs) -> io::Result<bool> {
+    let threads = args.threads.unwrap_or_else(|| Options::default().threads);
+    let mut ok = true;
+    for path in &args.inputs {
+        let verification = verify_file(path, threads, args.max_reported)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+        if verification.is_ok() {
+            println!(
+                "{}: OK, all {} line(s) keep to the spec",
+                path.display(),
+                verification.lines()
+            );
+            continue;
+        }
+        ok = false;
+        let by_violation: Vec<String> = verification
+            .by_violation()
+            .map(|(violation, count)| format!("{count} {violation}"))
+            .collect();
+        println!(
+            "{}: {} of {} line(s) break the spec: {}",
+            path.display(),
+            verification.violations(),
+            verification.lines(),
+            by_violation.join(", ")
+        );
+        for sample in verification.samples() {
+            println!(
+                "  line {} (byte {}): {}: {}",
+                sample.line,
+                sample.offset,
+                sample.violation,
+                String::from_utf8_lossy(&sample.content)
+            );
+        }
+    }
+    Ok(ok)
+}
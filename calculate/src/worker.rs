@@ -0,0 +1,25 @@
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+
+use onebrc_core::{Options, serve_worker};
+
+use crate::cli::WorkerArgs;
+
+/// Serves coordinators until the process is stopped.
+pub fn run(args: &WorkerArgs) -> io::Result<()> {
+    let defaults = Options::default();
+    let options = Options {
+        threads: args.threads.unwrap_or(defaults.threads),
+        ..defaults
+    };
+    let root = fs::canonicalize(&args.root)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", args.root.display())))?;
+    let listener = TcpListener::bind(&args.listen)?;
+    eprintln!(
+        "Listening on {}, serving files in {}",
+        listener.local_addr()?,
+        root.display()
+    );
+    serve_worker(listener, &options, &root, args.max_connections)
+}
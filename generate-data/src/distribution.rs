@@ -0,0 +1,90 @@
+use clap::ValueEnum;
+use rand::Rng;
+use rand::distr::Uniform;
+use rand_distr::{Distribution, Normal, SkewNormal, Zipf};
+
+/// Standard deviation of every station's readings around its mean.
+const STD_DEV: f64 = 10.0;
+
+/// Shape parameter of the skewed distribution; positive skews towards heat.
+const SKEW_SHAPE: f64 = 4.0;
+
+/// Shape of the temperature readings drawn for each station.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shape {
+    /// Normal distribution around the station mean
+    Gaussian,
+    /// Flat distribution with the same spread as the Gaussian
+    Uniform,
+    /// Skew-normal distribution with a long warm tail
+    Skewed,
+}
+
+/// Temperature distribution of one station.
+#[derive(Debug, Clone, Copy)]
+pub enum Readings {
+    Gaussian(Normal<f64>),
+    Uniform(Uniform<f64>),
+    Skewed(SkewNormal<f64>),
+}
+
+impl Readings {
+    pub fn new(shape: Shape, mean: f64) -> Self {
+        match shape {
+            Shape::Gaussian => {
+                Readings::Gaussian(Normal::new(mean, STD_DEV).expect("valid standard deviation"))
+            }
+            Shape::Uniform => {
+                // A uniform on [m - a, m + a] has standard deviation a / sqrt(3)
+                let half_width = STD_DEV * 3f64.sqrt();
+                Readings::Uniform(
+                    Uniform::new_inclusive(mean - half_width, mean + half_width)
+                        .expect("valid range"),
+                )
+            }
+            Shape::Skewed => Readings::Skewed(
+                SkewNormal::new(mean, STD_DEV, SKEW_SHAPE).expect("valid skew-normal parameters"),
+            ),
+        }
+    }
+}
+
+impl Distribution<f64> for Readings {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        match self {
+            Readings::Gaussian(d) => d.sample(rng),
+            Readings::Uniform(d) => d.sample(rng),
+            Readings::Skewed(d) => d.sample(rng),
+        }
+    }
+}
+
+/// Chooses which station each row belongs to.
+#[derive(Debug, Clone, Copy)]
+pub enum StationPicker {
+    /// Every station equally likely.
+    Uniform(usize),
+    /// Station `k` (0-based) drawn with probability proportional to
+    /// `1 / (k + 1)^s`, for studying skewed key frequencies.
+    Zipf(Zipf<f64>),
+}
+
+impl StationPicker {
+    pub fn new(stations: usize, zipf_exponent: Option<f64>) -> Result<Self, String> {
+        match zipf_exponent {
+            None => Ok(StationPicker::Uniform(stations)),
+            Some(s) => Zipf::new(stations as f64, s)
+                .map(StationPicker::Zipf)
+                .map_err(|err| format!("invalid Zipf exponent {s}: {err}")),
+        }
+    }
+}
+
+impl Distribution<usize> for StationPicker {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> usize {
+        match self {
+            StationPicker::Uniform(n) => rng.random_range(0..*n),
+            StationPicker::Zipf(d) => d.sample(rng) as usize - 1,
+        }
+    }
+}
@@ -1,37 +1,254 @@
+use std::collections::BTreeMap;
 use std::fs::{self, File};
-use std::io::{BufWriter, Write};
-use std::path::Path;
-
-fn main() {
-    println!("Hello, world!");
-    let cities = [
-        "Hyderabad",
-        "Mumbai",
-        "Banglore",
-        "Chennai",
-        "Munnar",
-        "Kochi",
-        "Udipi",
-        "Goa",
-    ];
-
-    let temperatures = [
-        -5.0, 0.0, 5.0, 10.0, 15.0, 20.0, 25.0, 30.0, 35.0, 40.0, -4.5, 1.0, 6.5, 11.0, 16.0, 21.5,
-        26.0, 31.0, 36.0, 41.5,
-    ];
-
-    let dir_path = Path::new("../data");
-    if !dir_path.exists() {
-        fs::create_dir_all(dir_path).unwrap();
-    }
-
-    let file = File::create(dir_path.join("weather_stations.csv")).unwrap();
-    let mut writer = BufWriter::new(file);
-
-    let num_entries = 50_000_000;
-    for i in 0..num_entries {
-        let city = cities[i % cities.len()];
-        let temperature = temperatures[i % temperatures.len()];
-        writeln!(writer, "{};{:.1}", city, temperature).unwrap();
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use clap::Parser;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use rand_distr::Distribution;
+
+use distribution::{Readings, Shape, StationPicker};
+
+mod distribution;
+mod stations;
+
+/// Default seed, so the same arguments always yield the same file.
+const DEFAULT_SEED: u64 = 0x1b_2c;
+
+/// Rows formatted by a worker in one go. Each block has its own RNG stream,
+/// so the output depends only on the seed and options, never on the number of
+/// threads.
+const ROWS_PER_BLOCK: u64 = 1 << 20;
+
+/// The stations rows are drawn from, with the readings of each and how often
+/// each comes up.
+struct Draw {
+    stations: Vec<stations::Station>,
+    distributions: Vec<Readings>,
+    picker: StationPicker,
+}
+
+/// Writes a synthetic `station;temperature` measurements file.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Number of rows to write (up to 1,000,000,000 for the full challenge)
+    #[arg(short = 'n', long, default_value_t = 50_000_000)]
+    rows: u64,
+
+    /// Output file
+    #[arg(short, long, default_value = "../data/weather_stations.csv")]
+    output: PathBuf,
+
+    /// Number of formatting threads (defaults to the number of CPUs)
+    #[arg(short, long)]
+    threads: Option<usize>,
+
+    /// RNG seed; identical seeds and options produce identical files
+    #[arg(long, default_value_t = DEFAULT_SEED)]
+    seed: u64,
+
+    /// Station list to draw from, one `name;mean temperature` per line
+    /// (defaults to the 413 stations of the original challenge)
+    #[arg(long)]
+    stations: Option<PathBuf>,
+
+    /// Shape of each station's temperature distribution
+    #[arg(long, value_enum, default_value_t = Shape::Gaussian)]
+    distribution: Shape,
+
+    /// Draw stations with Zipfian frequencies of this exponent instead of
+    /// uniformly (e.g. 1.0; larger means more skew)
+    #[arg(long, value_name = "EXPONENT")]
+    zipf: Option<f64>,
+}
+
+fn main() -> io::Result<()> {
+    let args = Args::parse();
+
+    if let Some(dir_path) = args.output.parent()
+        && !dir_path.as_os_str().is_empty()
+        && !dir_path.exists()
+    {
+        fs::create_dir_all(dir_path)?;
+    }
+
+    let stations = match &args.stations {
+        Some(path) => stations::parse(&fs::read_to_string(path)?).map_err(invalid_input)?,
+        None => stations::bundled(),
+    };
+    if stations.is_empty() {
+        return Err(invalid_input("station list is empty".to_string()));
+    }
+    let distributions: Vec<_> = stations
+        .iter()
+        .map(|s| Readings::new(args.distribution, s.mean))
+        .collect();
+    let picker = StationPicker::new(stations.len(), args.zipf).map_err(invalid_input)?;
+    let num_threads = args
+        .threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .max(1);
+
+    let draw = Draw {
+        stations,
+        distributions,
+        picker,
+    };
+
+    let file = File::create(&args.output)?;
+    let mut writer = BufWriter::with_capacity(1 << 20, file);
+    write_rows(
+        &mut writer,
+        &draw,
+        args.rows,
+        args.seed,
+        num_threads,
+        ROWS_PER_BLOCK,
+    )?;
+    writer.flush()
+}
+
+/// Writes `rows` rows in blocks of `rows_per_block`, formatted on
+/// `num_threads` threads and written in order.
+fn write_rows(
+    writer: &mut impl Write,
+    draw: &Draw,
+    rows: u64,
+    seed: u64,
+    num_threads: usize,
+    rows_per_block: u64,
+) -> io::Result<()> {
+    let num_blocks = rows.div_ceil(rows_per_block);
+    let next_block = AtomicU64::new(0);
+    // Bounded so fast workers cannot run arbitrarily far ahead of the disk
+    let (sender, receiver) = mpsc::sync_channel::<(u64, Vec<u8>)>(num_threads * 2);
+
+    thread::scope(|scope| -> io::Result<()> {
+        for _ in 0..num_threads {
+            let sender = sender.clone();
+            let next_block = &next_block;
+            scope.spawn(move || {
+                loop {
+                    let block = next_block.fetch_add(1, Ordering::Relaxed);
+                    if block >= num_blocks {
+                        break;
+                    }
+                    let rows = rows_per_block.min(rows - block * rows_per_block);
+                    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                    rng.set_stream(block);
+                    let buffer = generate_block(&mut rng, rows, draw);
+                    if sender.send((block, buffer)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // Blocks finish out of order; hold early ones back until their turn
+        let mut pending = BTreeMap::new();
+        let mut next_to_write = 0;
+        for (block, buffer) in receiver {
+            pending.insert(block, buffer);
+            while let Some(buffer) = pending.remove(&next_to_write) {
+                writer.write_all(&buffer)?;
+                next_to_write += 1;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Formats `rows` rows into a fresh buffer.
+fn generate_block(rng: &mut ChaCha8Rng, rows: u64, draw: &Draw) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(rows as usize * 16);
+    for _ in 0..rows {
+        let i = draw.picker.sample(rng);
+        let temperature = draw.distributions[i].sample(rng);
+        buffer.extend_from_slice(draw.stations[i].name.as_bytes());
+        buffer.push(b';');
+        write_tenths(&mut buffer, to_tenths(temperature));
+        buffer.push(b'\n');
+    }
+    buffer
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Rounds to one decimal and clamps into the challenge's `[-99.9, 99.9]`.
+fn to_tenths(temperature: f64) -> i32 {
+    ((temperature * 10.0).round() as i32).clamp(-999, 999)
+}
+
+/// Appends tenths of a degree as `-12.3` without going through float formatting.
+fn write_tenths(buffer: &mut Vec<u8>, tenths: i32) {
+    if tenths < 0 {
+        buffer.push(b'-');
+    }
+    let abs = tenths.unsigned_abs();
+    if abs >= 100 {
+        buffer.push(b'0' + (abs / 100) as u8);
+    }
+    buffer.push(b'0' + (abs / 10 % 10) as u8);
+    buffer.push(b'.');
+    buffer.push(b'0' + (abs % 10) as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(shape: Shape, zipf: Option<f64>) -> Draw {
+        let stations = stations::bundled();
+        Draw {
+            distributions: stations
+                .iter()
+                .map(|station| Readings::new(shape, station.mean))
+                .collect(),
+            picker: StationPicker::new(stations.len(), zipf).unwrap(),
+            stations,
+        }
+    }
+
+    fn rows(draw: &Draw, num_threads: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_rows(&mut out, draw, 10_500, DEFAULT_SEED, num_threads, 1000).unwrap();
+        out
+    }
+
+    #[test]
+    fn output_does_not_depend_on_the_threads() {
+        for draw in [
+            draw(Shape::Gaussian, None),
+            draw(Shape::Uniform, None),
+            draw(Shape::Skewed, Some(1.0)),
+        ] {
+            let one = rows(&draw, 1);
+            assert_eq!(one.iter().filter(|&&byte| byte == b'\n').count(), 10_500);
+            for num_threads in [2, 3, 8] {
+                assert!(rows(&draw, num_threads) == one, "{num_threads} threads");
+            }
+        }
+    }
+
+    #[test]
+    fn tenths_are_rounded_clamped_and_formatted() {
+        let format = |temperature| {
+            let mut buffer = Vec::new();
+            write_tenths(&mut buffer, to_tenths(temperature));
+            String::from_utf8(buffer).unwrap()
+        };
+        assert_eq!(format(0.04), "0.0");
+        assert_eq!(format(-0.06), "-0.1");
+        assert_eq!(format(12.34), "12.3");
+        assert_eq!(format(150.0), "99.9");
+        assert_eq!(format(-150.0), "-99.9");
     }
 }
@@ -1 +0,0 @@
-mod generate_data;
@@ -0,0 +1,80 @@
+/// A weather station and the mean temperature its readings are drawn around.
+#[derive(Debug, Clone)]
+pub struct Station {
+    pub name: String,
+    pub mean: f64,
+}
+
+/// The 413 stations of the original challenge, one `name;mean` per line.
+const BUNDLED: &str = include_str!("stations.txt");
+
+pub fn bundled() -> Vec<Station> {
+    parse(BUNDLED).expect("bundled station list is valid")
+}
+
+/// The longest station name the challenge allows, in bytes.
+const MAX_NAME_BYTES: usize = 100;
+
+/// Parses a `name;mean temperature` list, ignoring blank lines. Names must
+/// be 1 to 100 bytes without a `;`, so every row written keeps to the spec,
+/// and means finite numbers.
+pub fn parse(list: &str) -> Result<Vec<Station>, String> {
+    list.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let (name, mean) = line
+                .rsplit_once(';')
+                .ok_or_else(|| format!("line {}: expected `name;mean`", i + 1))?;
+            let mean: f64 = mean
+                .trim()
+                .parse()
+                .map_err(|err| format!("line {}: bad mean `{mean}`: {err}", i + 1))?;
+            if !mean.is_finite() {
+                return Err(format!(
+                    "line {}: mean `{mean}` is not a finite number",
+                    i + 1
+                ));
+            }
+            if name.is_empty() || name.len() > MAX_NAME_BYTES || name.contains(';') {
+                return Err(format!(
+                    "line {}: station names are 1 to {MAX_NAME_BYTES} bytes without a `;`",
+                    i + 1
+                ));
+            }
+            Ok(Station {
+                name: name.to_string(),
+                mean,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_names_and_means() {
+        let stations = parse("Oslo;5.7\n\nSt. John's;-2\n").unwrap();
+        let parsed: Vec<_> = stations.iter().map(|s| (s.name.as_str(), s.mean)).collect();
+        assert_eq!(parsed, [("Oslo", 5.7), ("St. John's", -2.0)]);
+        assert_eq!(bundled().len(), 413);
+    }
+
+    #[test]
+    fn rejects_non_finite_means() {
+        for mean in ["inf", "-inf", "NaN", "infinity"] {
+            let err = parse(&format!("Oslo;1.0\nA;{mean}\n")).unwrap_err();
+            assert!(err.starts_with("line 2: "), "{err}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_names() {
+        assert!(parse(";1.0").unwrap_err().starts_with("line 1: "));
+        let long = format!("{};1.0", "x".repeat(MAX_NAME_BYTES + 1));
+        assert!(parse(&long).is_err());
+        assert!(parse("no mean").is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! Micro-benchmarks for the hot-path pieces of the engine, run with
+//! `cargo bench -p onebrc-core`.
+
+use std::hint::black_box;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use onebrc_core::{Aggregator, TempStats, parse_line, parse_temp};
+
+const STATIONS: &[&str] = &[
+    "Abha",
+    "Abidjan",
+    "Accra",
+    "Addis Ababa",
+    "Adelaide",
+    "Aden",
+    "Ahvaz",
+    "Albuquerque",
+    "Alexandra",
+    "Alexandria",
+    "Algiers",
+    "Alice Springs",
+    "Almaty",
+    "Amsterdam",
+    "Anadyr",
+    "Anchorage",
+    "Andorra la Vella",
+    "Ankara",
+    "Antananarivo",
+    "Antsiranana",
+];
+
+/// Deterministic `station;temp` lines covering one- and two-digit,
+/// positive and negative readings.
+fn sample_lines(count: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    for i in 0..count {
+        let station = STATIONS[i % STATIONS.len()];
+        let tenths = (i as i32 * 7919) % 1999 - 999;
+        let sign = if tenths < 0 { "-" } else { "" };
+        let tenths = tenths.abs();
+        data.extend_from_slice(
+            format!("{station};{sign}{}.{}\n", tenths / 10, tenths % 10).as_bytes(),
+        );
+    }
+    data
+}
+
+fn parsing(c: &mut Criterion) {
+    let data = sample_lines(10_000);
+    let lines: Vec<&[u8]> = data
+        .split(|&b| b == b'\n')
+        .filter(|l| !l.is_empty())
+        .collect();
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("parse_line", |b| {
+        b.iter(|| {
+            for line in &lines {
+                black_box(parse_line(black_box(line), b';'));
+            }
+        })
+    });
+    group.bench_function("parse_temp", |b| {
+        b.iter(|| {
+            for temp in [&b"-99.9"[..], b"0.0", b"12.3", b"-4.5"] {
+                black_box(parse_temp(black_box(temp)));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn merging(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merge");
+    group.bench_function("TempStats::merge", |b| {
+        let other = TempStats::new(123);
+        let mut stats = TempStats::new(-45);
+        b.iter(|| stats.merge(black_box(&other)))
+    });
+
+    let data = sample_lines(100_000);
+    let mut partial = Aggregator::new();
+    partial.process_chunk(&data).unwrap();
+    group.bench_function("Aggregator::merge", |b| {
+        b.iter_batched(
+            || (partial.clone(), partial.clone()),
+            |(mut left, right)| left.merge(right),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn aggregating(c: &mut Criterion) {
+    let data = sample_lines(100_000);
+    let mut group = c.benchmark_group("aggregate");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("Aggregator::process_chunk", |b| {
+        b.iter(|| {
+            let mut aggregator = Aggregator::new();
+            aggregator.process_chunk(black_box(&data)).unwrap();
+            aggregator
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parsing, merging, aggregating);
+criterion_main!(benches);
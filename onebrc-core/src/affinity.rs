@@ -0,0 +1,182 @@
+//! Pinning threads to cores and NUMA nodes for [`Options::pin_threads`] and
+//! [`Options::numa`].
+//!
+//! [`Options::pin_threads`]: crate::Options::pin_threads
+//! [`Options::numa`]: crate::Options::numa
+
+use std::io;
+
+/// The CPUs a run may place its threads on, grouped by NUMA node.
+#[derive(Debug)]
+pub(crate) struct Cores {
+    /// Never empty, and neither is any node.
+    nodes: Vec<Vec<usize>>,
+    /// Pin every thread to a single core rather than to its node.
+    per_core: bool,
+}
+
+impl Cores {
+    /// The CPUs the calling thread is allowed on, which takes `taskset` and
+    /// cgroup CPU sets into account. With `numa`, they are grouped by the
+    /// node they belong to; otherwise, or if the machine reports no nodes,
+    /// they form a single group.
+    pub(crate) fn allowed(per_core: bool, numa: bool) -> io::Result<Cores> {
+        let cpus = get_affinity()?;
+        if cpus.is_empty() {
+            return Err(io::Error::other("no CPUs to pin threads to"));
+        }
+        let mut nodes = if numa { numa_nodes(&cpus) } else { Vec::new() };
+        if nodes.is_empty() {
+            nodes.push(cpus);
+        }
+        Ok(Cores { nodes, per_core })
+    }
+
+    /// Pins the calling thread, worker `index` of `workers`, which take
+    /// consecutive parts of the input. Consecutive workers share a node, in
+    /// proportion to the nodes' sizes, so each part of the input is read
+    /// and aggregated into memory on one node; since memory is placed on the
+    /// node of the thread that first touches it, the worker's own maps end
+    /// up there too.
+    ///
+    /// Per core, the first core is left to the reading and merging thread
+    /// unless there are no more cores than that; with more workers than
+    /// cores they share them round-robin. Pinning is only a hint for speed,
+    /// so failures are ignored.
+    pub(crate) fn pin_worker(&self, index: usize, workers: usize) {
+        let (node, local) = self.place(index, workers);
+        let cpus = &self.nodes[node];
+        if !self.per_core {
+            let _ = set_affinity(cpus);
+            return;
+        }
+        let cpu = if node == 0 && cpus.len() > 1 {
+            cpus[1 + local % (cpus.len() - 1)]
+        } else {
+            cpus[local % cpus.len()]
+        };
+        let _ = set_affinity(&[cpu]);
+    }
+
+    /// The node of worker `index` of `workers`, and its index among the
+    /// workers on that node.
+    fn place(&self, index: usize, workers: usize) -> (usize, usize) {
+        let total: usize = self.nodes.iter().map(Vec::len).sum();
+        let mut first = 0;
+        for (node, cpus) in self.nodes.iter().enumerate() {
+            let end = first + (workers * cpus.len()).div_ceil(total);
+            if index < end || node == self.nodes.len() - 1 {
+                return (node, index - first);
+            }
+            first = end;
+        }
+        unreachable!("there is always a node")
+    }
+
+    /// With per-core pinning, pins the calling thread, which reads and
+    /// merges for the workers, to the first core until the returned guard is
+    /// dropped.
+    pub(crate) fn pin_coordinator(&self) -> Pinned {
+        if !self.per_core {
+            return Pinned {
+                previous: Vec::new(),
+            };
+        }
+        let previous = get_affinity().unwrap_or_default();
+        let _ = set_affinity(&self.nodes[0][..1]);
+        Pinned { previous }
+    }
+}
+
+/// Puts the thread back on the CPUs it was allowed on before
+/// [`Cores::pin_coordinator`].
+pub(crate) struct Pinned {
+    previous: Vec<usize>,
+}
+
+impl Drop for Pinned {
+    fn drop(&mut self) {
+        if !self.previous.is_empty() {
+            let _ = set_affinity(&self.previous);
+        }
+    }
+}
+
+/// The CPUs of every NUMA node that has some of `allowed`, as listed under
+/// `/sys/devices/system/node`. Empty if that cannot be read.
+fn numa_nodes(allowed: &[usize]) -> Vec<Vec<usize>> {
+    let Ok(entries) = std::fs::read_dir("/sys/devices/system/node") else {
+        return Vec::new();
+    };
+    let mut nodes: Vec<(usize, Vec<usize>)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name();
+            let node = name.to_str()?.strip_prefix("node")?.parse().ok()?;
+            let list = std::fs::read_to_string(entry.path().join("cpulist")).ok()?;
+            let cpus: Vec<usize> = parse_cpu_list(&list)?
+                .into_iter()
+                .filter(|cpu| allowed.contains(cpu))
+                .collect();
+            (!cpus.is_empty()).then_some((node, cpus))
+        })
+        .collect();
+    nodes.sort_unstable();
+    nodes.into_iter().map(|(_, cpus)| cpus).collect()
+}
+
+/// Parses a kernel CPU list such as `0-3,8-11`.
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in list.trim().split(',').filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let first: usize = first.parse().ok()?;
+                cpus.extend(first..=last.parse().ok()?);
+            }
+            None => cpus.push(part.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+#[cfg(target_os = "linux")]
+fn get_affinity() -> io::Result<Vec<usize>> {
+    // SAFETY: `set` is a plain bit set that the call fills in.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let cpus = (0..libc::CPU_SETSIZE as usize).filter(|&cpu| libc::CPU_ISSET(cpu, &set));
+        Ok(cpus.collect())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+    // SAFETY: as in `get_affinity`; pid 0 is the calling thread.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_affinity() -> io::Result<Vec<usize>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pinning threads is only available on Linux",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_: &[usize]) -> io::Result<()> {
+    get_affinity().map(drop)
+}
@@ -0,0 +1,827 @@
+use std::borrow::Cow;
+use std::io::{self, BufRead};
+use std::sync::Arc;
+use std::thread;
+
+use memchr::memchr;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::aliases::Aliases;
+use crate::filter::StationFilter;
+use crate::json::{for_each_json, parse_json};
+use crate::key::StationKey;
+use crate::parse::{InputFormat, ParseOptions, parse_record, strip_bom, strip_cr};
+use crate::perfect::{KnownStats, StationIndex};
+use crate::progress::Counts;
+use crate::quoted::{for_each_quoted, parse_quoted};
+use crate::results::Results;
+use crate::simd::for_each_record;
+use crate::skipped::{LineLog, MalformedLine, SkipReason, SkippedLines};
+use crate::stats::{StatsOptions, TempStats};
+use crate::table::{KeyCheck, StationTable};
+
+/// Per-station accumulator used on the hot path. Hashing the raw station
+/// bytes with FxHash is much cheaper than BTreeMap's byte comparisons; the
+/// result is only sorted once, in [`Aggregator::into_results`]. Keys are
+/// [`StationKey`]s, so names copied out of the input are rarely allocated.
+pub(crate) type StationMap = FxHashMap<StationKey, TempStats>;
+
+/// An aggregator's stations and line bookkeeping, in a form that can be
+/// saved to disk or sent to another process.
+#[cfg(any(feature = "checkpoint", feature = "remote"))]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Snapshot {
+    pub(crate) rows: u64,
+    pub(crate) results: Results,
+    pub(crate) skipped: SkippedLines,
+}
+
+/// Accumulates per-station statistics from `station;temperature` lines.
+///
+/// One aggregator is meant to be owned by one thread; partial aggregators
+/// are combined with [`Aggregator::merge`] or [`Aggregator::merge_all`].
+#[derive(Debug, Clone)]
+pub struct Aggregator {
+    stations: StationMap,
+    log: LineLog,
+    stats: StatsOptions,
+    filter: Option<Arc<StationFilter>>,
+    /// Stations the filter turned down, so it runs once per name.
+    rejected: FxHashSet<StationKey>,
+    aliases: Option<Arc<Aliases>>,
+    count_only: bool,
+    /// Heap bytes of the names in `stations` and `rejected` too long to be
+    /// stored inline, for [`Aggregator::map_bytes`].
+    #[cfg(feature = "spill")]
+    long_names: usize,
+}
+
+impl Default for Aggregator {
+    fn default() -> Self {
+        Self::with_options(ParseOptions::default())
+    }
+}
+
+impl Aggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(options: ParseOptions) -> Self {
+        Aggregator {
+            stations: StationMap::default(),
+            log: LineLog::new(options),
+            stats: StatsOptions::default(),
+            filter: None,
+            rejected: FxHashSet::default(),
+            aliases: None,
+            count_only: false,
+            #[cfg(feature = "spill")]
+            long_names: 0,
+        }
+    }
+
+    /// Sets the optional statistics gathered for stations seen from now on.
+    pub fn with_stats(mut self, stats: StatsOptions) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Only parses and counts the lines from now on, keeping no stations,
+    /// which times reading and parsing without the map.
+    pub fn with_count_only(mut self, count_only: bool) -> Self {
+        self.count_only = count_only;
+        self
+    }
+
+    /// Drops rows of stations that `filter` does not match. Rows are still
+    /// counted in [`Aggregator::rows`].
+    pub fn with_filter(mut self, filter: Option<Arc<StationFilter>>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Renames the stations in `aliases` to their canonical names, merging
+    /// them with any station of that name, when the results are taken out.
+    /// Until then [`Aggregator::len`] counts every spelling.
+    pub fn with_aliases(mut self, aliases: Option<Arc<Aliases>>) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    /// Number of distinct stations seen so far.
+    pub fn len(&self) -> usize {
+        self.stations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stations.is_empty()
+    }
+
+    /// Number of lines processed, including skipped ones.
+    pub fn rows(&self) -> u64 {
+        self.log.rows()
+    }
+
+    /// Malformed lines dropped so far (always empty in strict mode).
+    pub fn skipped(&self) -> &SkippedLines {
+        self.log.skipped()
+    }
+
+    /// Sets the number reported for the next line, for callers that feed
+    /// lines from the middle of a file.
+    pub fn set_next_line(&mut self, line: u64) {
+        self.log.set_next_line(line);
+    }
+
+    /// Sets the byte offset reported for the next line, the same way.
+    pub fn set_next_offset(&mut self, offset: u64) {
+        self.log.set_next_offset(offset);
+    }
+
+    /// How lines are laid out.
+    pub(crate) fn parse_options(&self) -> ParseOptions {
+        self.log.options
+    }
+
+    /// Switches to how the next input is laid out, for workers that go from
+    /// one file to another.
+    pub(crate) fn set_parse(&mut self, options: ParseOptions) {
+        self.log.options = options;
+    }
+
+    /// Adds a single line (without its trailing newline). Lines that are not
+    /// `station;temperature`, or JSON objects with [`InputFormat::Jsonl`],
+    /// are skipped, or returned as an error in strict mode.
+    pub fn process_line(&mut self, line: &[u8]) -> Result<(), MalformedLine> {
+        self.log.enter_chunk(line, line.len() + 1);
+        match self.log.options.format {
+            InputFormat::Delimited | InputFormat::Auto if self.log.options.quoted => {
+                self.process_parsed(line, parse_quoted(line, &self.log.options))
+            }
+            InputFormat::Delimited | InputFormat::Auto => {
+                self.process_record(line, memchr(self.log.options.delimiter, line))
+            }
+            InputFormat::Jsonl => self.process_parsed(line, parse_json(line)),
+        }
+    }
+
+    /// [`Aggregator::process_line`], with the position of the delimiter
+    /// already found, or known to be missing.
+    #[inline]
+    fn process_record(
+        &mut self,
+        line: &[u8],
+        delimiter: Option<usize>,
+    ) -> Result<(), MalformedLine> {
+        let Some((city, temp)) = parse_record(line, delimiter, &self.log.options) else {
+            let reason = SkipReason::classify(line, &self.log.options);
+            return self.log.reject(line, reason);
+        };
+        self.add(city, temp);
+        Ok(())
+    }
+
+    /// Adds one line that [`for_each_json`] or [`for_each_quoted`] took
+    /// apart.
+    #[inline]
+    fn process_parsed(
+        &mut self,
+        line: &[u8],
+        record: Result<(Cow<'_, [u8]>, i32), SkipReason>,
+    ) -> Result<(), MalformedLine> {
+        match record {
+            Ok((city, temp)) => {
+                self.add(&city, temp);
+                Ok(())
+            }
+            Err(reason) => self.log.reject(line, reason),
+        }
+    }
+
+    /// Adds one reading that was already split into station and temperature
+    /// (in tenths of a degree), counting it as a line.
+    #[inline]
+    pub fn add(&mut self, city: &[u8], temp: i32) {
+        self.log.accept();
+        if self.count_only {
+            return;
+        }
+        if let Some(stats) = self.stations.get_mut(city) {
+            stats.update(temp);
+        } else if self.filter.is_some() && self.rejected.contains(city) {
+            // Turned down before; stored once is enough
+        } else if let Some(filter) = &self.filter
+            && !filter.matches(city)
+        {
+            let key = StationKey::from(city);
+            self.count_long_name(key.heap_bytes());
+            self.rejected.insert(key);
+        } else {
+            let key = StationKey::from(city); // only convert once
+            self.count_long_name(key.heap_bytes());
+            let stats = TempStats::with_options(temp, self.stats);
+            self.stations.insert(key, stats);
+        }
+    }
+
+    /// Adds the heap bytes of a newly kept name to those
+    /// [`Aggregator::map_bytes`] counts.
+    #[inline]
+    fn count_long_name(&mut self, _bytes: usize) {
+        #[cfg(feature = "spill")]
+        {
+            self.long_names += _bytes;
+        }
+    }
+
+    /// Counts the names too long to be stored inline afresh, after the
+    /// map or `rejected` were filled wholesale.
+    #[cfg(feature = "spill")]
+    fn recount_long_names(&mut self) {
+        let keys = self.stations.keys().chain(&self.rejected);
+        self.long_names = keys.map(|key| key.heap_bytes()).sum();
+    }
+
+    /// Counts a record that holds no usable reading as a malformed line:
+    /// skipped, or returned as an error in strict mode.
+    pub fn reject(&mut self, content: &[u8], reason: SkipReason) -> Result<(), MalformedLine> {
+        self.log.reject(content, reason)
+    }
+
+    /// Aggregates every line of an in-memory chunk.
+    pub fn process_chunk(&mut self, chunk: &[u8]) -> Result<(), MalformedLine> {
+        self.log.enter_chunk(chunk, chunk.len());
+        let delimiter = self.log.options.delimiter;
+        match self.log.options.format {
+            InputFormat::Delimited | InputFormat::Auto if self.log.options.quoted => {
+                let options = self.log.options;
+                for_each_quoted(chunk, &options, |line, record| {
+                    self.process_parsed(line, record)
+                })
+            }
+            InputFormat::Delimited | InputFormat::Auto => {
+                for_each_record(chunk, delimiter, |line, pos| self.process_record(line, pos))
+            }
+            InputFormat::Jsonl => {
+                for_each_json(chunk, |line, record| self.process_parsed(line, record))
+            }
+        }
+    }
+
+    /// Reads `reader` to the end on the calling thread, reusing one line
+    /// buffer throughout. A leading byte order mark is skipped.
+    pub fn process_reader<R: BufRead>(&mut self, mut reader: R) -> io::Result<()> {
+        let mut line = Vec::new();
+        let mut first = true;
+        let mut offset = 0;
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            let read = line.len() as u64;
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            let content = if first { strip_bom(&line) } else { &line };
+            self.set_next_offset(offset + (line.len() - content.len()) as u64);
+            self.process_line(strip_cr(content))?;
+            offset += read;
+            first = false;
+            line.clear();
+        }
+        Ok(())
+    }
+
+    /// Folds `other` into `self`.
+    pub fn merge(&mut self, mut other: Aggregator) {
+        // Fold the smaller map into the larger one
+        if self.stations.len() < other.stations.len() {
+            std::mem::swap(&mut self.stations, &mut other.stations);
+        }
+        self.log.merge(other.log);
+        // At most: names both sides kept count twice
+        #[cfg(feature = "spill")]
+        {
+            self.long_names += other.long_names;
+        }
+        self.rejected.extend(other.rejected);
+        self.aliases = self.aliases.take().or(other.aliases);
+        for (city, stats) in other.stations {
+            if let Some(s) = self.stations.get_mut(&city) {
+                s.merge(&stats);
+            } else {
+                self.stations.insert(city, stats);
+            }
+        }
+    }
+
+    /// Merges many partial aggregators pairwise, one thread per pair and
+    /// round, taking ownership so nothing is cloned.
+    pub fn merge_all(mut parts: Vec<Aggregator>) -> Aggregator {
+        while parts.len() > 1 {
+            let mut next_round = Vec::with_capacity(parts.len().div_ceil(2));
+            let mut handles = vec![];
+
+            let mut drained = parts.drain(..);
+            while let Some(mut left) = drained.next() {
+                let Some(right) = drained.next() else {
+                    // Unpaired map (odd count), move to next round directly
+                    next_round.push(left);
+                    break;
+                };
+
+                handles.push(thread::spawn(move || {
+                    left.merge(right);
+                    left
+                }));
+            }
+            drop(drained);
+
+            // Collect merged results
+            for handle in handles {
+                next_round.push(handle.join().expect("Merge thread failed"));
+            }
+
+            parts = next_round;
+        }
+
+        parts.pop().unwrap_or_default()
+    }
+
+    pub(crate) fn shift_line_numbers(&mut self, base: u64) {
+        self.log.shift(base);
+    }
+
+    /// Moves the stations a worker kept apart for [`StationIndex`] into the
+    /// map.
+    fn take_known(&mut self, known: Option<KnownStats>) {
+        for (city, stats) in known.into_iter().flat_map(KnownStats::into_entries) {
+            self.count_long_name(city.heap_bytes());
+            match stats {
+                Some(stats) => {
+                    self.stations.insert(city, stats);
+                }
+                None => {
+                    self.rejected.insert(city);
+                }
+            }
+        }
+    }
+
+    /// Everything aggregated so far, leaving the aggregator as it is.
+    #[cfg(any(feature = "checkpoint", feature = "remote"))]
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            rows: self.rows(),
+            results: self.to_results(),
+            skipped: self.skipped().clone(),
+        }
+    }
+
+    /// Continues from a [`Snapshot`] taken elsewhere, replacing whatever
+    /// was aggregated so far.
+    #[cfg(any(feature = "checkpoint", feature = "remote"))]
+    pub(crate) fn restore(&mut self, snapshot: Snapshot) {
+        self.stations = snapshot
+            .results
+            .into_iter()
+            .map(|(city, stats)| (city.into_bytes().into(), stats))
+            .collect();
+        #[cfg(feature = "spill")]
+        self.recount_long_names();
+        self.log.restore(snapshot.rows, snapshot.skipped);
+    }
+
+    /// Drops the stations `filter` does not match, for results that were
+    /// aggregated without it.
+    #[cfg(feature = "remote")]
+    pub(crate) fn retain_matching(&mut self, filter: &StationFilter) {
+        self.stations.retain(|city, _| filter.matches(city));
+    }
+
+    /// About what the stations take in memory: the tables of the map and
+    /// of `rejected`, the names too long to be stored inline and the most
+    /// the percentile digests and histograms can hold. Allocator overhead is
+    /// left out, so a limit built on it is approximate.
+    #[cfg(feature = "spill")]
+    pub(crate) fn map_bytes(&self) -> usize {
+        let entry = std::mem::size_of::<(StationKey, TempStats)>() + 1;
+        let rejected = std::mem::size_of::<StationKey>() + 1;
+        self.stations.capacity() * entry
+            + self.stations.len() * self.stats.heap_bytes()
+            + self.rejected.capacity() * rejected
+            + self.long_names
+    }
+
+    /// Takes the stations out, sorted by name, leaving an empty map and the
+    /// line bookkeeping behind.
+    #[cfg(feature = "spill")]
+    pub(crate) fn take_sorted(&mut self) -> Vec<(String, TempStats)> {
+        let mut stations: Vec<_> = std::mem::take(&mut self.stations)
+            .into_iter()
+            .map(|(city, stats)| (String::from_utf8_lossy(&city).into_owned(), stats))
+            .collect();
+        stations.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        self.recount_long_names();
+        stations
+    }
+
+    /// Folds in the statistics of one station gathered elsewhere.
+    #[cfg(feature = "spill")]
+    pub(crate) fn insert_merged(&mut self, city: String, stats: TempStats) {
+        if let Some(ours) = self.stations.get_mut(city.as_bytes()) {
+            ours.merge(&stats);
+        } else {
+            let key = StationKey::from(city.into_bytes());
+            self.count_long_name(key.heap_bytes());
+            self.stations.insert(key, stats);
+        }
+    }
+
+    /// The stations so far as owned results, leaving the aggregator as it
+    /// is.
+    pub(crate) fn to_results(&self) -> Results {
+        let stations = self
+            .stations
+            .iter()
+            .map(|(city, stats)| (String::from_utf8_lossy(city).into_owned(), stats.clone()));
+        match &self.aliases {
+            Some(aliases) => aliases.rename(stations).collect(),
+            None => stations.collect(),
+        }
+    }
+
+    /// Sorts the stations by name and converts them into owned results,
+    /// under their canonical names with [`Aggregator::with_aliases`].
+    pub fn into_results(self) -> Results {
+        let stations = self
+            .stations
+            .into_iter()
+            .map(|(city, stats)| (String::from_utf8_lossy(&city).into_owned(), stats));
+        match &self.aliases {
+            Some(aliases) => aliases.rename(stations).collect(),
+            None => stations.collect(),
+        }
+    }
+}
+
+impl Counts for Aggregator {
+    fn counts(&self) -> (u64, u64) {
+        (self.log.rows(), self.log.skipped().count())
+    }
+}
+
+/// Aggregator whose keys borrow the station bytes straight from the input
+/// buffer (typically a memory map), so no key is allocated while parsing.
+/// Owned keys are only materialised by [`BorrowedAggregator::into_owned`].
+#[derive(Debug)]
+pub struct BorrowedAggregator<'a> {
+    stations: FxHashMap<&'a [u8], TempStats>,
+    log: LineLog,
+    stats: StatsOptions,
+    filter: Option<Arc<StationFilter>>,
+    rejected: FxHashSet<&'a [u8]>,
+    known: Option<KnownStats>,
+    aliases: Option<Arc<Aliases>>,
+    count_only: bool,
+}
+
+impl Default for BorrowedAggregator<'_> {
+    fn default() -> Self {
+        Self::with_options(ParseOptions::default())
+    }
+}
+
+impl<'a> BorrowedAggregator<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(options: ParseOptions) -> Self {
+        BorrowedAggregator {
+            stations: FxHashMap::default(),
+            log: LineLog::new(options),
+            stats: StatsOptions::default(),
+            filter: None,
+            rejected: FxHashSet::default(),
+            known: None,
+            aliases: None,
+            count_only: false,
+        }
+    }
+
+    /// Sets the optional statistics gathered for stations seen from now on.
+    pub fn with_stats(mut self, stats: StatsOptions) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// See [`Aggregator::with_filter`].
+    pub fn with_filter(mut self, filter: Option<Arc<StationFilter>>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// See [`Aggregator::with_aliases`].
+    pub fn with_aliases(mut self, aliases: Option<Arc<Aliases>>) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    /// See [`Aggregator::with_count_only`].
+    pub fn with_count_only(mut self, count_only: bool) -> Self {
+        self.count_only = count_only;
+        self
+    }
+
+    /// Keeps the stations of `index` in an array it resolves names to,
+    /// rather than in the map; other stations still go to the map. Set this
+    /// before the first line.
+    pub fn with_known_stations(mut self, index: Option<Arc<StationIndex>>) -> Self {
+        self.known = index.map(KnownStats::new);
+        self
+    }
+
+    /// Number of lines processed, including skipped ones.
+    pub fn rows(&self) -> u64 {
+        self.log.rows()
+    }
+
+    pub fn process_line(&mut self, line: &'a [u8]) -> Result<(), MalformedLine> {
+        self.log.enter_chunk(line, line.len() + 1);
+        self.process_record(line, memchr(self.log.options.delimiter, line))
+    }
+
+    /// [`BorrowedAggregator::process_line`], with the position of the delimiter
+    /// already found, or known to be missing.
+    #[inline]
+    fn process_record(
+        &mut self,
+        line: &'a [u8],
+        delimiter: Option<usize>,
+    ) -> Result<(), MalformedLine> {
+        let Some((city, temp)) = parse_record(line, delimiter, &self.log.options) else {
+            let reason = SkipReason::classify(line, &self.log.options);
+            return self.log.reject(line, reason);
+        };
+        self.log.accept();
+        if self.count_only {
+            return Ok(());
+        }
+        if let Some(known) = &mut self.known
+            && known.add(city, temp, self.stats, self.filter.as_deref())
+        {
+            return Ok(());
+        }
+        if let Some(stats) = self.stations.get_mut(city) {
+            stats.update(temp);
+        } else if self.filter.is_some() && self.rejected.contains(city) {
+            // Turned down before
+        } else if let Some(filter) = &self.filter
+            && !filter.matches(city)
+        {
+            self.rejected.insert(city);
+        } else {
+            self.stations
+                .insert(city, TempStats::with_options(temp, self.stats));
+        }
+        Ok(())
+    }
+
+    pub fn process_chunk(&mut self, chunk: &'a [u8]) -> Result<(), MalformedLine> {
+        self.log.enter_chunk(chunk, chunk.len());
+        let delimiter = self.log.options.delimiter;
+        for_each_record(chunk, delimiter, |line, pos| self.process_record(line, pos))
+    }
+
+    pub(crate) fn shift_line_numbers(&mut self, base: u64) {
+        self.log.shift(base);
+    }
+
+    pub(crate) fn set_next_offset(&mut self, offset: u64) {
+        self.log.set_next_offset(offset);
+    }
+
+    pub fn merge(&mut self, mut other: BorrowedAggregator<'a>) {
+        if self.stations.len() < other.stations.len() {
+            std::mem::swap(&mut self.stations, &mut other.stations);
+        }
+        self.log.merge(other.log);
+        self.rejected.extend(other.rejected);
+        self.aliases = self.aliases.take().or(other.aliases);
+        merge_known(&mut self.known, other.known);
+        for (city, stats) in other.stations {
+            self.stations
+                .entry(city)
+                .and_modify(|s| s.merge(&stats))
+                .or_insert(stats);
+        }
+    }
+
+    /// Copies the keys out of the borrowed buffer.
+    pub fn into_owned(self) -> Aggregator {
+        let mut aggregator = Aggregator {
+            stations: self
+                .stations
+                .into_iter()
+                .map(|(city, stats)| (city.into(), stats))
+                .collect(),
+            log: self.log,
+            stats: self.stats,
+            filter: self.filter,
+            rejected: self.rejected.into_iter().map(StationKey::from).collect(),
+            aliases: self.aliases,
+            count_only: self.count_only,
+            #[cfg(feature = "spill")]
+            long_names: 0,
+        };
+        #[cfg(feature = "spill")]
+        aggregator.recount_long_names();
+        aggregator.take_known(self.known);
+        aggregator
+    }
+}
+
+impl Counts for BorrowedAggregator<'_> {
+    fn counts(&self) -> (u64, u64) {
+        (self.log.rows(), self.log.skipped().count())
+    }
+}
+
+/// Aggregator for [`Engine::CustomMap`] and [`Engine::HashOnly`]: a
+/// [`BorrowedAggregator`] whose stations live in a [`StationTable`] instead
+/// of a general-purpose map.
+///
+/// [`Engine::CustomMap`]: crate::Engine::CustomMap
+/// [`Engine::HashOnly`]: crate::Engine::HashOnly
+#[derive(Debug)]
+pub(crate) struct TableAggregator<'a> {
+    stations: StationTable<'a>,
+    log: LineLog,
+    stats: StatsOptions,
+    filter: Option<Arc<StationFilter>>,
+    known: Option<KnownStats>,
+    aliases: Option<Arc<Aliases>>,
+}
+
+impl<'a> TableAggregator<'a> {
+    pub(crate) fn new(
+        options: ParseOptions,
+        stats: StatsOptions,
+        filter: Option<Arc<StationFilter>>,
+        known: Option<Arc<StationIndex>>,
+        aliases: Option<Arc<Aliases>>,
+        check: KeyCheck,
+    ) -> Self {
+        TableAggregator {
+            stations: StationTable::new(check),
+            log: LineLog::new(options),
+            stats,
+            filter,
+            known: known.map(KnownStats::new),
+            aliases,
+        }
+    }
+
+    pub(crate) fn rows(&self) -> u64 {
+        self.log.rows()
+    }
+
+    /// Adds one line, whose delimiter is at `delimiter` if it has one.
+    #[inline]
+    fn process_record(
+        &mut self,
+        line: &'a [u8],
+        delimiter: Option<usize>,
+    ) -> Result<(), MalformedLine> {
+        let Some((city, temp)) = parse_record(line, delimiter, &self.log.options) else {
+            let reason = SkipReason::classify(line, &self.log.options);
+            return self.log.reject(line, reason);
+        };
+        self.log.accept();
+        if let Some(known) = &mut self.known
+            && known.add(city, temp, self.stats, self.filter.as_deref())
+        {
+            return Ok(());
+        }
+        let hash = StationTable::hash(city);
+        match self.stations.get_mut(hash, city) {
+            Some(Some(stats)) => stats.update(temp),
+            Some(None) => {}
+            None => {
+                let wanted = match &self.filter {
+                    Some(filter) => filter.matches(city),
+                    None => true,
+                };
+                let stats = wanted.then(|| TempStats::with_options(temp, self.stats));
+                self.stations.insert(hash, city, stats);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn process_chunk(&mut self, chunk: &'a [u8]) -> Result<(), MalformedLine> {
+        self.log.enter_chunk(chunk, chunk.len());
+        let delimiter = self.log.options.delimiter;
+        for_each_record(chunk, delimiter, |line, pos| self.process_record(line, pos))
+    }
+
+    pub(crate) fn shift_line_numbers(&mut self, base: u64) {
+        self.log.shift(base);
+    }
+
+    pub(crate) fn set_next_offset(&mut self, offset: u64) {
+        self.log.set_next_offset(offset);
+    }
+
+    pub(crate) fn merge(&mut self, other: TableAggregator<'a>) {
+        self.log.merge(other.log);
+        self.aliases = self.aliases.take().or(other.aliases);
+        merge_known(&mut self.known, other.known);
+        self.stations.merge(other.stations);
+    }
+
+    /// See [`StationTable::verify`].
+    pub(crate) fn verify(&self) -> io::Result<()> {
+        self.stations.verify()
+    }
+
+    /// Copies the keys out of the borrowed buffer.
+    pub(crate) fn into_owned(self) -> Aggregator {
+        let mut stations = StationMap::default();
+        stations.reserve(self.stations.len());
+        let mut rejected = FxHashSet::default();
+        for (city, stats) in self.stations.into_entries() {
+            match stats {
+                Some(stats) => {
+                    stations.insert(city.into(), stats);
+                }
+                None => {
+                    rejected.insert(city.into());
+                }
+            }
+        }
+        let mut aggregator = Aggregator {
+            stations,
+            log: self.log,
+            stats: self.stats,
+            filter: self.filter,
+            rejected,
+            aliases: self.aliases,
+            count_only: false,
+            #[cfg(feature = "spill")]
+            long_names: 0,
+        };
+        #[cfg(feature = "spill")]
+        aggregator.recount_long_names();
+        aggregator.take_known(self.known);
+        aggregator
+    }
+}
+
+impl Counts for TableAggregator<'_> {
+    fn counts(&self) -> (u64, u64) {
+        (self.log.rows(), self.log.skipped().count())
+    }
+}
+
+fn merge_known(ours: &mut Option<KnownStats>, theirs: Option<KnownStats>) {
+    match (ours.as_mut(), theirs) {
+        (Some(ours), Some(theirs)) => ours.merge(theirs),
+        (None, theirs) => *ours = theirs,
+        (Some(_), None) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter() -> Option<Arc<StationFilter>> {
+        Some(Arc::new(StationFilter::new().with_allowed(["Oslo"])))
+    }
+
+    #[test]
+    fn stations_turned_down_are_stored_once() {
+        let long = "a station name well past the inline length".as_bytes();
+        let mut aggregator = Aggregator::new().with_filter(filter());
+        for temp in 0..3 {
+            aggregator.add(b"Lima", temp);
+            aggregator.add(long, temp);
+            aggregator.add(b"Oslo", temp);
+        }
+        assert_eq!(aggregator.rows(), 9);
+        assert_eq!(aggregator.rejected.len(), 2);
+        assert_eq!(aggregator.stations.len(), 1);
+        #[cfg(feature = "spill")]
+        assert_eq!(aggregator.long_names, long.len());
+    }
+
+    #[test]
+    fn borrowed_stations_turned_down_are_stored_once() {
+        let input = b"Lima;1.0\nOslo;2.0\nLima;3.0\nRiga;4.0\nLima;5.0\n";
+        let mut aggregator = BorrowedAggregator::new().with_filter(filter());
+        aggregator.process_chunk(input).unwrap();
+        assert_eq!(aggregator.rejected.len(), 2);
+        let results = aggregator.into_owned().into_results();
+        assert_eq!(results.len(), 1);
+    }
+}
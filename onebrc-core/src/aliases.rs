@@ -0,0 +1,94 @@
+use rustc_hash::FxHashMap;
+
+use crate::key::StationKey;
+use crate::quoted::field;
+
+/// Canonical names for stations spelled more than one way, such as
+/// `St. Petersburg` for `Saint Petersburg`.
+///
+/// Aggregators keep every spelling apart while parsing, so the hot path
+/// never looks names up here; they are renamed, and their statistics
+/// merged, when the results are taken out. Filters see names as written in
+/// the input.
+#[derive(Debug, Clone, Default)]
+pub struct Aliases {
+    canonical: FxHashMap<StationKey, String>,
+}
+
+impl Aliases {
+    /// No aliases: every station keeps its name.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Renames `alias` to `canonical`.
+    pub fn with_alias(mut self, alias: impl Into<Vec<u8>>, canonical: impl Into<String>) -> Self {
+        self.canonical
+            .insert(StationKey::from(alias.into()), canonical.into());
+        self
+    }
+
+    /// Aliases from CSV lines of an alias and its canonical name,
+    /// `St. Petersburg,Saint Petersburg`, quoted as in RFC 4180 if a name
+    /// holds a comma. Blank lines and lines starting with `#` are ignored. A
+    /// canonical name cannot be an alias of another one, so renaming twice
+    /// changes nothing.
+    pub fn parse(csv: &str) -> Result<Self, String> {
+        let mut aliases = Aliases::new();
+        for (number, line) in csv.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = || format!("line {}: `{line}` is not `alias,canonical`", number + 1);
+            let (alias, rest) = field(line.as_bytes(), b',').map_err(|_| bad())?;
+            let (canonical, rest) = field(rest.ok_or_else(bad)?, b',').map_err(|_| bad())?;
+            if rest.is_some() {
+                return Err(bad());
+            }
+            let canonical = String::from_utf8(canonical.into_owned()).map_err(|_| bad())?;
+            aliases = aliases.with_alias(alias.into_owned(), canonical);
+        }
+        if let Some(chained) = aliases.canonical.values().find(|name| {
+            aliases
+                .canonical
+                .get(name.as_bytes())
+                .is_some_and(|other| other != *name)
+        }) {
+            return Err(format!("`{chained}` is both a canonical name and an alias"));
+        }
+        Ok(aliases)
+    }
+
+    pub fn len(&self) -> usize {
+        self.canonical.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.canonical.is_empty()
+    }
+
+    /// The canonical name of `station`, which is its own unless it is an
+    /// alias.
+    pub fn canonical<'a>(&'a self, station: &'a [u8]) -> &'a [u8] {
+        self.canonical
+            .get(station)
+            .map_or(station, |canonical| canonical.as_bytes())
+    }
+
+    /// `stations` under their canonical names; collecting them into
+    /// [`Results`] merges the spellings of one station.
+    ///
+    /// [`Results`]: crate::Results
+    pub(crate) fn rename<S>(
+        &self,
+        stations: impl IntoIterator<Item = (String, S)>,
+    ) -> impl Iterator<Item = (String, S)> {
+        stations
+            .into_iter()
+            .map(|(name, stats)| match self.canonical.get(name.as_bytes()) {
+                Some(canonical) => (canonical.clone(), stats),
+                None => (name, stats),
+            })
+    }
+}
@@ -0,0 +1,106 @@
+//! Results as Arrow record batches, and the Arrow IPC file output format.
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt64Array};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+
+use crate::output::OutputOptions;
+use crate::results::Results;
+use crate::stats::TempStats;
+
+impl Results {
+    /// One row per station, alphabetically, with `station`, `min`, `mean`,
+    /// `max` and `count` columns; temperatures are in Celsius and unrounded.
+    pub fn to_record_batch(&self) -> RecordBatch {
+        let rows: Vec<_> = self.iter().map(|row| (None, row)).collect();
+        record_batch(&rows, &OutputOptions::default()).expect("columns match the schema")
+    }
+}
+
+/// A station, its statistics and the partition it belongs to, if any.
+pub(crate) type LabelledRow<'a> = (Option<&'a str>, (&'a str, &'a TempStats));
+
+/// Builds a record batch of `rows` with the columns `options` asks for: a
+/// leading `partition` column if the rows have labels, then the same
+/// columns as the CSV format, converted to `options.unit` but not rounded.
+pub(crate) fn record_batch(
+    rows: &[LabelledRow],
+    options: &OutputOptions,
+) -> Result<RecordBatch, ArrowError> {
+    let unit = options.unit;
+    let float = |value: fn(&TempStats) -> f64| -> ArrayRef {
+        Arc::new(Float64Array::from_iter_values(
+            rows.iter()
+                .map(|(_, (_, stats))| unit.convert(value(stats))),
+        ))
+    };
+
+    let mut fields = Vec::new();
+    let mut columns: Vec<ArrayRef> = Vec::new();
+    if rows.iter().any(|(partition, _)| partition.is_some()) {
+        fields.push(Field::new("partition", DataType::Utf8, false));
+        columns.push(Arc::new(StringArray::from_iter_values(
+            rows.iter()
+                .map(|(partition, _)| partition.unwrap_or_default()),
+        )));
+    }
+    fields.push(Field::new("station", DataType::Utf8, false));
+    columns.push(Arc::new(StringArray::from_iter_values(
+        rows.iter().map(|(_, (station, _))| station),
+    )));
+    if let Some(metadata) = &options.metadata {
+        for (i, column) in metadata.columns().iter().enumerate() {
+            fields.push(Field::new(column, DataType::Utf8, true));
+            columns.push(Arc::new(StringArray::from_iter(rows.iter().map(
+                |(_, (station, _))| metadata.get(station).map(|values| values[i].as_str()),
+            ))));
+        }
+    }
+    for (name, value) in [
+        ("min", TempStats::min as fn(&TempStats) -> f64),
+        ("mean", TempStats::mean),
+        ("max", TempStats::max),
+    ] {
+        fields.push(Field::new(name, DataType::Float64, false));
+        columns.push(float(value));
+    }
+    fields.push(Field::new("count", DataType::UInt64, false));
+    columns.push(Arc::new(UInt64Array::from_iter_values(
+        rows.iter().map(|(_, (_, stats))| stats.count()),
+    )));
+    #[cfg(feature = "variance")]
+    {
+        fields.push(Field::new("stddev", DataType::Float64, false));
+        columns.push(Arc::new(Float64Array::from_iter_values(
+            rows.iter()
+                .map(|(_, (_, stats))| unit.scale(stats.std_dev())),
+        )));
+    }
+    for percentile in &options.percentiles {
+        fields.push(Field::new(percentile.to_string(), DataType::Float64, true));
+        columns.push(Arc::new(Float64Array::from_iter(rows.iter().map(
+            |(_, (_, stats))| {
+                stats
+                    .quantile(percentile.fraction())
+                    .map(|q| unit.convert(q))
+            },
+        ))));
+    }
+
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+}
+
+/// Writes `rows` as an Arrow IPC file holding a single record batch.
+pub(crate) fn write_arrow(
+    out: &mut impl Write,
+    rows: &[LabelledRow],
+    options: &OutputOptions,
+) -> io::Result<()> {
+    let batch = record_batch(rows, options).map_err(io::Error::other)?;
+    let mut writer = FileWriter::try_new(out, &batch.schema()).map_err(io::Error::other)?;
+    writer.write(&batch).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
@@ -0,0 +1,95 @@
+//! Batch sizes that follow the workers, for [`Options::adaptive_batch`].
+//!
+//! [`Options::adaptive_batch`]: crate::Options::adaptive_batch
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// How long a worker should take over one batch: long enough that handing
+/// it over costs next to nothing, short enough that workers get work often.
+const TARGET_BATCH_TIME: Duration = Duration::from_millis(10);
+
+/// Bounds on an adaptive batch, in lines.
+const MIN_BATCH: usize = 10_000;
+const MAX_BATCH: usize = 1_000_000;
+
+/// What the reader of a worker pool can see of the workers: how full their
+/// queue was at the last hand-over, and how fast the last batch went.
+#[derive(Debug)]
+pub(crate) struct Feedback {
+    capacity: usize,
+    queued: AtomicUsize,
+    /// Picoseconds per line of the batch a worker finished last; zero
+    /// until one has.
+    picos_per_row: AtomicU64,
+}
+
+impl Feedback {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Feedback {
+            capacity,
+            queued: AtomicUsize::new(0),
+            picos_per_row: AtomicU64::new(0),
+        }
+    }
+
+    /// Records the number of items waiting when the reader handed over one
+    /// more.
+    pub(crate) fn set_queued(&self, queued: usize) {
+        self.queued.store(queued, Ordering::Relaxed);
+    }
+
+    /// Records that a worker took `elapsed` over a batch of `rows` lines.
+    pub(crate) fn finished(&self, rows: u64, elapsed: Duration) {
+        if rows > 0 {
+            let picos = elapsed.as_nanos() * 1000 / u128::from(rows);
+            let picos = u64::try_from(picos).unwrap_or(u64::MAX).max(1);
+            self.picos_per_row.store(picos, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Picks the size of each batch from the [`Feedback`] of the previous ones.
+///
+/// The size moves towards the number of lines a worker gets through in
+/// [`TARGET_BATCH_TIME`], at most doubling or halving from one batch to the
+/// next. An empty queue means the workers are waiting for the reader, so
+/// batches are halved to reach them sooner; a full one means they are
+/// behind, and larger batches would only hold more of the input in memory,
+/// so they do not grow.
+#[derive(Debug)]
+pub(crate) struct BatchSizer {
+    size: usize,
+}
+
+impl BatchSizer {
+    pub(crate) fn new(initial: usize) -> Self {
+        BatchSizer {
+            size: initial.clamp(MIN_BATCH, MAX_BATCH),
+        }
+    }
+
+    /// Lines in the next batch.
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Updates the size after a batch was handed over.
+    pub(crate) fn adjust(&mut self, feedback: &Feedback) {
+        let queued = feedback.queued.load(Ordering::Relaxed);
+        let picos = feedback.picos_per_row.load(Ordering::Relaxed);
+        let mut next = if picos == 0 {
+            self.size
+        } else {
+            let target = TARGET_BATCH_TIME.as_nanos() * 1000 / u128::from(picos);
+            usize::try_from(target).unwrap_or(usize::MAX)
+        };
+        next = next.clamp(self.size / 2, self.size.saturating_mul(2));
+        if queued == 0 {
+            next = next.min(self.size / 2);
+        } else if queued >= feedback.capacity {
+            next = next.min(self.size);
+        }
+        self.size = next.clamp(MIN_BATCH, MAX_BATCH);
+    }
+}
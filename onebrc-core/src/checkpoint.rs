@@ -0,0 +1,270 @@
+//! Long runs over a single file that can be interrupted and resumed: the
+//! file is processed in segments, and after each one the byte offset reached
+//! and the partial results so far are written to a checkpoint file.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::aggregator::{Aggregator, Snapshot};
+use crate::chunk::split_range;
+use crate::columnar::is_parquet;
+use crate::compression::{Compression, is_stream};
+use crate::pipeline::{Options, process_ranges, shift_malformed};
+use crate::sniff::sniff_file;
+use crate::trace::{event, span};
+
+/// Where and how often [`aggregate_with_checkpoints`] saves its progress.
+#[derive(Debug, Clone)]
+pub struct CheckpointOptions {
+    /// The checkpoint file. It is replaced after every segment and removed
+    /// once the run completes.
+    pub path: PathBuf,
+    /// Input bytes per segment, i.e. between two checkpoints.
+    pub every: u64,
+    /// Continue from the checkpoint at `path` if there is one, rather than
+    /// starting over.
+    pub resume: bool,
+}
+
+impl Default for CheckpointOptions {
+    fn default() -> Self {
+        CheckpointOptions {
+            path: PathBuf::from("onebrc.checkpoint"),
+            every: 1 << 30,
+            resume: false,
+        }
+    }
+}
+
+/// What a checkpoint file holds.
+#[derive(Serialize, Deserialize)]
+struct State {
+    input: PathBuf,
+    input_len: u64,
+    /// Bytes of the input already folded into `results`; always a line start.
+    offset: u64,
+    aggregated: Snapshot,
+}
+
+/// Aggregates the plain text file `input` like [`aggregate_file`] in
+/// [`Mode::Chunked`], saving a checkpoint every `checkpoint.every` bytes.
+/// With `checkpoint.resume`, a run picks up where the saved one stopped; the
+/// checkpoint must have been written for the same file, at the same size.
+///
+/// Stdin, compressed and Parquet input cannot be resumed and are rejected.
+/// A run stopped through [`Options::cancel`] returns its partial result and
+/// leaves the last checkpoint in place, so it can still be resumed.
+///
+/// [`aggregate_file`]: crate::aggregate_file
+/// [`Mode::Chunked`]: crate::Mode::Chunked
+pub fn aggregate_with_checkpoints(
+    input: impl AsRef<Path>,
+    options: &Options,
+    checkpoint: &CheckpointOptions,
+) -> io::Result<Aggregator> {
+    let input = input.as_ref();
+    if is_stream(input) || Compression::detect(input)?.is_some() || is_parquet(input)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "checkpoints need an uncompressed text file as input",
+        ));
+    }
+    let options = &Options {
+        parse: sniff_file(input, options.parse)?,
+        ..options.clone()
+    };
+    let input_len = fs::metadata(input)?.len();
+    let canonical = fs::canonicalize(input)?;
+
+    let mut total = options.aggregator();
+    let mut offset = 0;
+    if checkpoint.resume
+        && let Some(state) = load(&checkpoint.path)?
+    {
+        if state.input != canonical || state.input_len != input_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "checkpoint {} was written for {} ({} bytes), not {} ({input_len} bytes)",
+                    checkpoint.path.display(),
+                    state.input.display(),
+                    state.input_len,
+                    input.display(),
+                ),
+            ));
+        }
+        offset = state.offset;
+        event!(INFO, offset, rows = state.aggregated.rows, "resuming");
+        if let Some(progress) = &options.progress {
+            progress.record(offset, state.aggregated.rows);
+            progress.record_skipped(state.aggregated.skipped.count());
+        }
+        total.restore(state.aggregated);
+    }
+
+    let segments = (input_len - offset).div_ceil(checkpoint.every.max(1));
+    for (start, end) in split_range(input, offset, input_len, segments as usize)? {
+        let _span = span!(INFO, "segment", start, end);
+        let ranges = split_range(input, start, end, options.threads.max(1))?;
+        let mut segment = process_ranges(input, ranges, options)
+            .map_err(|err| shift_malformed(err, total.rows()))?;
+        segment.shift_line_numbers(total.rows());
+        total.merge(segment);
+        if options.cancelled() {
+            // The segment may be incomplete, so it must not be checkpointed
+            return Ok(total);
+        }
+
+        if end < input_len {
+            save(
+                &checkpoint.path,
+                &State {
+                    input: canonical.clone(),
+                    input_len,
+                    offset: end,
+                    aggregated: total.snapshot(),
+                },
+            )?;
+            event!(INFO, offset = end, "checkpoint saved");
+        }
+    }
+
+    match fs::remove_file(&checkpoint.path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(total),
+    }
+}
+
+/// Reads the checkpoint at `path`, if there is one.
+fn load(path: &Path) -> io::Result<Option<State>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let state = serde_json::from_reader(BufReader::new(file)).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unreadable checkpoint {}: {err}", path.display()),
+        )
+    })?;
+    Ok(Some(state))
+}
+
+/// Writes the checkpoint next to `path` and renames it into place, so an
+/// interruption never leaves a half-written checkpoint behind.
+fn save(path: &Path, state: &State) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let mut out = BufWriter::new(File::create(&temp)?);
+    serde_json::to_writer(&mut out, state)?;
+    out.into_inner()?.sync_all()?;
+    fs::rename(temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{Mode, aggregate_file};
+    use crate::results::Results;
+    use crate::skipped::MalformedLine;
+    use crate::testing::TempFile;
+
+    /// Readings with a malformed line every so often, CRLF ones among them.
+    fn input() -> Vec<u8> {
+        let mut input = Vec::new();
+        for line in 0..3000 {
+            let row = match line % 500 {
+                123 => "bad line\n".to_string(),
+                n if n % 3 == 0 => format!("St{};{}.{}\r\n", line % 17, line % 40, line % 10),
+                _ => format!("St{};-{}.{}\n", line % 17, line % 40, line % 10),
+            };
+            input.extend_from_slice(row.as_bytes());
+        }
+        input
+    }
+
+    fn options() -> Options {
+        let mut options = Options {
+            threads: 2,
+            ..Options::default()
+        };
+        options.parse.skip_samples = 10;
+        options
+    }
+
+    fn outcome(aggregator: Aggregator) -> (u64, u64, Vec<MalformedLine>, Results) {
+        let skipped = aggregator.skipped();
+        let (count, samples) = (skipped.count(), skipped.samples().cloned().collect());
+        (aggregator.rows(), count, samples, aggregator.into_results())
+    }
+
+    fn uninterrupted(input: &Path) -> (u64, u64, Vec<MalformedLine>, Results) {
+        let options = Options {
+            mode: Mode::Chunked,
+            ..options()
+        };
+        outcome(aggregate_file(input, &options).unwrap())
+    }
+
+    #[test]
+    fn segments_add_up_to_the_whole_file() {
+        let input = TempFile::new("checkpoint-segments", &input());
+        let path = TempFile::new("checkpoint-segments.checkpoint", b"");
+        let checkpoint = CheckpointOptions {
+            path: path.0.clone(),
+            every: 4096,
+            resume: false,
+        };
+        let aggregator = aggregate_with_checkpoints(&input.0, &options(), &checkpoint).unwrap();
+        assert_eq!(outcome(aggregator), uninterrupted(&input.0));
+        assert!(!path.0.exists(), "the checkpoint is removed at the end");
+    }
+
+    #[test]
+    fn a_resumed_run_gives_the_result_of_an_uninterrupted_one() {
+        let data = input();
+        let input = TempFile::new("checkpoint-resume", &data);
+        let path = TempFile::new("checkpoint-resume.checkpoint", b"");
+        // Stopped after the line that crosses the middle, past a bad line
+        let offset = data.len() / 2 + memchr::memchr(b'\n', &data[data.len() / 2..]).unwrap() + 1;
+        let mut before = options().aggregator();
+        before.process_chunk(&data[..offset]).unwrap();
+        let state = |input_len| State {
+            input: fs::canonicalize(&input.0).unwrap(),
+            input_len,
+            offset: offset as u64,
+            aggregated: before.snapshot(),
+        };
+        let checkpoint = CheckpointOptions {
+            path: path.0.clone(),
+            every: 4096,
+            resume: true,
+        };
+
+        save(&path.0, &state(data.len() as u64 + 1)).unwrap();
+        let err = aggregate_with_checkpoints(&input.0, &options(), &checkpoint).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        save(&path.0, &state(data.len() as u64)).unwrap();
+        let aggregator = aggregate_with_checkpoints(&input.0, &options(), &checkpoint).unwrap();
+        assert_eq!(outcome(aggregator), uninterrupted(&input.0));
+        assert!(!path.0.exists());
+    }
+
+    #[test]
+    fn an_unreadable_checkpoint_is_an_error() {
+        let input = TempFile::new("checkpoint-unreadable", &input());
+        let path = TempFile::new("checkpoint-unreadable.checkpoint", b"{\"offset\":");
+        let checkpoint = CheckpointOptions {
+            path: path.0.clone(),
+            every: 4096,
+            resume: true,
+        };
+        let err = aggregate_with_checkpoints(&input.0, &options(), &checkpoint).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
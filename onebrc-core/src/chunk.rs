@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::Path;
+
+use memchr::memchr;
+use memmap2::Mmap;
+#[cfg(unix)]
+use memmap2::Advice;
+
+/// Maps `path` read-only into memory.
+pub fn map_file(path: impl AsRef<Path>) -> io::Result<Mmap> {
+    let file = File::open(path)?;
+    // SAFETY: the mapping is read-only; we assume the file is not truncated
+    // or modified by another process while we are reading it.
+    unsafe { Mmap::map(&file) }
+}
+
+/// Tells the kernel that `chunk`, a slice of `mmap`, is about to be read
+/// front to back: read ahead aggressively, and start now. Only a hint, so
+/// failures are ignored. Windows has no sequential hint, but is asked to
+/// prefetch the whole chunk.
+pub(crate) fn advise_sequential(mmap: &Mmap, chunk: &[u8]) {
+    #[cfg(unix)]
+    {
+        let offset = chunk.as_ptr() as usize - mmap.as_ptr() as usize;
+        let _ = mmap.advise_range(Advice::Sequential, offset, chunk.len());
+        let _ = mmap.advise_range(Advice::WillNeed, offset, chunk.len());
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::Memory::{PrefetchVirtualMemory, WIN32_MEMORY_RANGE_ENTRY};
+        use windows_sys::Win32::System::Threading::GetCurrentProcess;
+
+        let _ = mmap;
+        let range = WIN32_MEMORY_RANGE_ENTRY {
+            VirtualAddress: chunk.as_ptr().cast_mut().cast(),
+            NumberOfBytes: chunk.len(),
+        };
+        // SAFETY: the range lies within a live mapping of this process
+        unsafe { PrefetchVirtualMemory(GetCurrentProcess(), 1, &range, 0) };
+    }
+    #[cfg(not(any(unix, windows)))]
+    let _ = (mmap, chunk);
+}
+
+/// Asks for `mmap` to be backed by transparent huge pages, which cuts TLB
+/// misses on large files. Only a hint: it takes a kernel that supports huge
+/// pages for file mappings, and does nothing outside Linux.
+pub(crate) fn advise_huge_pages(mmap: &Mmap) {
+    #[cfg(target_os = "linux")]
+    let _ = mmap.advise(Advice::HugePage);
+    #[cfg(not(target_os = "linux"))]
+    let _ = mmap;
+}
+
+/// In-memory counterpart of [`chunk_ranges`]: splits `data` into at most `n`
+/// slices that each end just after a newline (or at the end of the data).
+pub fn slice_chunks(data: &[u8], n: usize) -> Vec<&[u8]> {
+    let mut chunks = Vec::with_capacity(n);
+    let mut rest = data;
+    for i in 0..n {
+        if rest.is_empty() {
+            break;
+        }
+        let remaining = n - i;
+        let target = rest.len() / remaining;
+        let end = match memchr(b'\n', &rest[target..]) {
+            Some(pos) if remaining > 1 => target + pos + 1,
+            _ => rest.len(),
+        };
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+/// Divides the file into at most `n` `(start, end)` byte ranges. Every boundary
+/// is moved forward to just past the next newline so no line is split.
+pub fn chunk_ranges(path: impl AsRef<Path>, n: usize) -> io::Result<Vec<(u64, u64)>> {
+    let len = File::open(&path)?.metadata()?.len();
+    split_range(path, 0, len, n)
+}
+
+/// Like [`chunk_ranges`] for the bytes from `from` to `to` only, which must
+/// both sit at line starts (or the end of the file).
+pub(crate) fn split_range(
+    path: impl AsRef<Path>,
+    from: u64,
+    to: u64,
+    n: usize,
+) -> io::Result<Vec<(u64, u64)>> {
+    let mut file = File::open(path)?;
+    let len = to - from;
+
+    let mut ranges = Vec::with_capacity(n);
+    let mut start = from;
+    let mut skipped = Vec::new();
+    for i in 1..=n as u64 {
+        let mut end = from + len * i / n as u64;
+        if end <= start {
+            continue;
+        }
+        if end < to {
+            file.seek(SeekFrom::Start(end))?;
+            skipped.clear();
+            end += BufReader::new(&mut file).read_until(b'\n', &mut skipped)? as u64;
+        }
+        let end = end.min(to);
+        ranges.push((start, end));
+        start = end;
+        if start >= to {
+            break;
+        }
+    }
+
+    Ok(ranges)
+}
@@ -0,0 +1,97 @@
+//! Input files in S3, Google Cloud Storage or Azure Blob Storage, read
+//! through `object_store` several byte ranges at a time.
+
+use std::io;
+
+use object_store::aws::AmazonS3Builder;
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path;
+use object_store::{ObjectStore, ObjectStoreScheme};
+use tokio::runtime::{self, Runtime};
+use url::Url;
+
+use crate::aggregator::Aggregator;
+use crate::pipeline::Options;
+use crate::pool::{Buffer, BufferPool};
+use crate::ranged::{Source, aggregate_ranges};
+use crate::trace::span;
+
+/// Aggregates the plain text object at `url`, such as `s3://bucket/key`,
+/// `gs://bucket/key` or `az://container/blob`, through [`aggregate_ranges`]
+/// with `options.connections` ranged GETs in flight.
+///
+/// Credentials and settings come from the environment the way each store's
+/// own tools read them: `AWS_ACCESS_KEY_ID`, `AWS_REGION`, `AWS_ENDPOINT`
+/// and the like for S3, `GOOGLE_SERVICE_ACCOUNT` or
+/// `GOOGLE_APPLICATION_CREDENTIALS` for GCS, `AZURE_STORAGE_ACCOUNT_NAME`
+/// and `AZURE_STORAGE_ACCOUNT_KEY` for Azure.
+pub(crate) fn aggregate_object(url: &str, options: &Options) -> io::Result<Aggregator> {
+    let _span = span!(INFO, "aggregate_object", url);
+    let invalid = |err| io::Error::new(io::ErrorKind::InvalidInput, err);
+    let parsed = Url::parse(url).map_err(|err| invalid(err.to_string()))?;
+    let (scheme, path) =
+        ObjectStoreScheme::parse(&parsed).map_err(|err| invalid(err.to_string()))?;
+    let store: Box<dyn ObjectStore> = match scheme {
+        ObjectStoreScheme::AmazonS3 => Box::new(
+            AmazonS3Builder::from_env()
+                .with_url(url)
+                .build()
+                .map_err(io::Error::from)?,
+        ),
+        ObjectStoreScheme::GoogleCloudStorage => Box::new(
+            GoogleCloudStorageBuilder::from_env()
+                .with_url(url)
+                .build()
+                .map_err(io::Error::from)?,
+        ),
+        ObjectStoreScheme::MicrosoftAzure => Box::new(
+            MicrosoftAzureBuilder::from_env()
+                .with_url(url)
+                .build()
+                .map_err(io::Error::from)?,
+        ),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not an S3, GCS or Azure URL",
+            ));
+        }
+    };
+    // The connection threads each block on their own request; the runtime
+    // only drives the sockets
+    let runtime = runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()?;
+    let object = Object {
+        store,
+        path,
+        runtime,
+    };
+    let total = object
+        .runtime
+        .block_on(object.store.head(&object.path))
+        .map_err(io::Error::from)?
+        .size;
+    aggregate_ranges(&object, total, options)
+}
+
+/// One object and the store it is in.
+struct Object {
+    store: Box<dyn ObjectStore>,
+    path: Path,
+    runtime: Runtime,
+}
+
+impl Source for Object {
+    fn fetch_range(&self, start: u64, end: u64, pool: &BufferPool) -> io::Result<Buffer> {
+        let range = self
+            .runtime
+            .block_on(self.store.get_range(&self.path, start..end))
+            .map_err(io::Error::from)?;
+        let mut bytes = pool.get();
+        bytes.extend_from_slice(&range);
+        Ok(bytes)
+    }
+}
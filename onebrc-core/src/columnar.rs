@@ -0,0 +1,263 @@
+//! Parquet input: record batches of a station column and a temperature
+//! column are fed to the same aggregators as text lines.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::aggregator::Aggregator;
+use crate::compression::is_stream;
+use crate::pipeline::Options;
+use crate::progress::Progress;
+
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+
+/// Whether `path` starts with the Parquet magic bytes.
+pub fn is_parquet(path: impl AsRef<Path>) -> io::Result<bool> {
+    if is_stream(&path) {
+        return Ok(false);
+    }
+    let mut magic = Vec::with_capacity(PARQUET_MAGIC.len());
+    File::open(path)?
+        .take(PARQUET_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    Ok(magic == PARQUET_MAGIC)
+}
+
+/// Aggregates a Parquet file, spreading its row groups across
+/// `options.threads` workers. The columns used are `station` and
+/// `temperature` if the file has them and the first two otherwise; the
+/// station must be a string and the temperature a float or a decimal string,
+/// which is rounded to tenths. Rows with a null or unreadable value count as
+/// malformed lines, numbered by row.
+#[cfg(feature = "parquet")]
+pub fn aggregate_parquet(path: impl AsRef<Path>, options: &Options) -> io::Result<Aggregator> {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+
+    use crate::trace::{current_span, span};
+
+    let path = path.as_ref();
+    let metadata = reader::open(path)?.metadata().clone();
+    let row_groups = metadata.num_row_groups();
+    let _span = span!(INFO, "aggregate_parquet", path = %path.display(), row_groups);
+    let first_rows: Vec<u64> = (0..row_groups)
+        .scan(0, |rows, i| {
+            let first = *rows;
+            *rows += metadata.row_group(i).num_rows() as u64;
+            Some(first)
+        })
+        .collect();
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let cores = options.cores()?;
+    let _pinned = cores.as_ref().map(|cores| cores.pin_coordinator());
+    let workers = options.threads.clamp(1, row_groups.max(1));
+    let partials = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|index| {
+                let mut aggregator = options.aggregator();
+                let (next, failed, first_rows) = (&next, &failed, &first_rows);
+                let progress = options.progress.as_deref();
+                let cores = cores.as_deref();
+                let parent = current_span!();
+                scope.spawn(move || -> io::Result<Aggregator> {
+                    let _span = span!(parent: &parent, DEBUG, "worker");
+                    if let Some(cores) = cores {
+                        cores.pin_worker(index, workers);
+                    }
+                    while !failed.load(Ordering::Relaxed) && !options.cancelled() {
+                        let group = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(&first_row) = first_rows.get(group) else {
+                            break;
+                        };
+                        let _group = span!(DEBUG, "row_group", group);
+                        aggregator.set_next_line(first_row + 1);
+                        let read = reader::read(&mut aggregator, path, Some(group), progress);
+                        if let Err(err) = read {
+                            failed.store(true, Ordering::Relaxed);
+                            return Err(err);
+                        }
+                    }
+                    Ok(aggregator)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Thread panicked"))
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+
+    Ok(crate::pipeline::merge_all(partials, options.progress.as_deref()))
+}
+
+/// Fails with [`io::ErrorKind::Unsupported`]: Parquet support was not
+/// compiled in.
+#[cfg(not(feature = "parquet"))]
+#[allow(unused_variables)]
+pub fn aggregate_parquet(path: impl AsRef<Path>, options: &Options) -> io::Result<Aggregator> {
+    Err(unsupported())
+}
+
+/// Folds a whole Parquet file into `aggregator` on the calling thread.
+#[cfg_attr(not(feature = "parquet"), allow(unused_variables))]
+pub(crate) fn read_parquet(
+    aggregator: &mut Aggregator,
+    path: &Path,
+    progress: Option<&Progress>,
+) -> io::Result<()> {
+    #[cfg(feature = "parquet")]
+    return reader::read(aggregator, path, None, progress);
+    #[cfg(not(feature = "parquet"))]
+    Err(unsupported())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Parquet input needs the `parquet` feature",
+    )
+}
+
+#[cfg(feature = "parquet")]
+mod reader {
+    use std::fs::File;
+    use std::io;
+    use std::path::Path;
+
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float32Type, Float64Type};
+    use arrow_array::{Array, RecordBatch};
+    use arrow_schema::DataType;
+    use parquet::arrow::ProjectionMask;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use crate::aggregator::Aggregator;
+    use crate::parse::parse_temp;
+    use crate::progress::{Progress, Stage, timed, track};
+    use crate::skipped::SkipReason;
+
+    const STATION_COLUMN: &str = "station";
+    const TEMPERATURE_COLUMN: &str = "temperature";
+
+    /// Rows per record batch.
+    const BATCH_SIZE: usize = 64 * 1024;
+
+    pub(super) fn open(path: &Path) -> io::Result<ParquetRecordBatchReaderBuilder<File>> {
+        ParquetRecordBatchReaderBuilder::try_new(File::open(path)?).map_err(invalid)
+    }
+
+    /// Folds one row group of `path`, or all of them, into `aggregator`.
+    pub(super) fn read(
+        aggregator: &mut Aggregator,
+        path: &Path,
+        row_group: Option<usize>,
+        progress: Option<&Progress>,
+    ) -> io::Result<()> {
+        let mut builder = open(path)?;
+        let fields = builder.schema().fields();
+        let index = |name: &str| fields.iter().position(|field| field.name() == name);
+        let columns = match (index(STATION_COLUMN), index(TEMPERATURE_COLUMN)) {
+            (Some(station), Some(temperature)) => [station, temperature],
+            _ if fields.len() >= 2 => [0, 1],
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Parquet input needs a station and a temperature column",
+                ));
+            }
+        };
+        let names = columns.map(|i| fields[i].name().clone());
+        let mask = ProjectionMask::roots(builder.parquet_schema(), columns);
+        builder = builder.with_projection(mask).with_batch_size(BATCH_SIZE);
+        if let Some(row_group) = row_group {
+            builder = builder.with_row_groups(vec![row_group]);
+        }
+
+        let mut batches = builder.build().map_err(invalid)?;
+        while let Some(batch) = timed(progress, Stage::Read, || batches.next()) {
+            let batch = batch.map_err(invalid)?;
+            let bytes = batch.get_array_memory_size();
+            track(progress, aggregator, bytes, |aggregator| {
+                add_batch(aggregator, &batch, &names)
+            })?;
+        }
+        Ok(())
+    }
+
+    fn add_batch(
+        aggregator: &mut Aggregator,
+        batch: &RecordBatch,
+        [station, temperature]: &[String; 2],
+    ) -> io::Result<()> {
+        let column = |name: &str| batch.column_by_name(name).expect("projected column");
+        let stations = Strings::new(column(station).as_ref())?;
+        let temps = column(temperature);
+        let temps = temps.as_ref();
+        for row in 0..batch.num_rows() {
+            let Some(city) = stations.get(row) else {
+                aggregator.reject(b"", SkipReason::Missing)?;
+                continue;
+            };
+            match temperature_at(temps, row)? {
+                Some(temp) => aggregator.add(city, temp),
+                None if temps.is_null(row) => aggregator.reject(city, SkipReason::Missing)?,
+                None => aggregator.reject(city, SkipReason::BadTemperature)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// The temperature in row `row`, in tenths of a degree.
+    fn temperature_at(temps: &dyn Array, row: usize) -> io::Result<Option<i32>> {
+        if temps.is_null(row) {
+            return Ok(None);
+        }
+        let degrees = match temps.data_type() {
+            DataType::Float64 => temps.as_primitive::<Float64Type>().value(row),
+            DataType::Float32 => f64::from(temps.as_primitive::<Float32Type>().value(row)),
+            _ => return Ok(Strings::new(temps)?.get(row).and_then(parse_temp)),
+        };
+        let tenths = (degrees * 10.0).round();
+        Ok((tenths.abs() <= i32::MAX as f64).then_some(tenths as i32))
+    }
+
+    /// A string column in any of Arrow's string layouts.
+    enum Strings<'a> {
+        Utf8(&'a arrow_array::StringArray),
+        LargeUtf8(&'a arrow_array::LargeStringArray),
+        Utf8View(&'a arrow_array::StringViewArray),
+    }
+
+    impl<'a> Strings<'a> {
+        fn new(array: &'a dyn Array) -> io::Result<Self> {
+            Ok(match array.data_type() {
+                DataType::Utf8 => Strings::Utf8(array.as_string()),
+                DataType::LargeUtf8 => Strings::LargeUtf8(array.as_string()),
+                DataType::Utf8View => Strings::Utf8View(array.as_string_view()),
+                other => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unsupported Parquet column type {other}"),
+                    ));
+                }
+            })
+        }
+
+        fn get(&self, row: usize) -> Option<&'a [u8]> {
+            let value = match self {
+                Strings::Utf8(array) => array.is_valid(row).then(|| array.value(row)),
+                Strings::LargeUtf8(array) => array.is_valid(row).then(|| array.value(row)),
+                Strings::Utf8View(array) => array.is_valid(row).then(|| array.value(row)),
+            };
+            value.map(str::as_bytes)
+        }
+    }
+
+    fn invalid(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
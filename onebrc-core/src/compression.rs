@@ -0,0 +1,120 @@
+use std::fs::File;
+use std::io::{self, Read};
+#[cfg(unix)]
+use std::os::unix::{fs::FileTypeExt, net::UnixStream};
+use std::path::Path;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression formats recognised from the first bytes of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Sniffs the magic bytes at the start of `path`. Returns `None` for plain
+    /// text, which is everything that is not gzip or zstd.
+    /// Streams, see [`is_stream`], are not sniffed, as that would eat the
+    /// bytes read, and count as plain text.
+    pub fn detect(path: impl AsRef<Path>) -> io::Result<Option<Compression>> {
+        if is_stream(&path) {
+            return Ok(None);
+        }
+        let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+        File::open(path)?
+            .take(ZSTD_MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+        Ok(if magic.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+/// Whether `path` can only be read once, front to back: `-` for standard
+/// input, on Unix a named pipe, a Unix domain socket or a character device,
+/// and on Windows a named pipe, `\\.\pipe\name`. Streams are read as
+/// plain text by [`open_stream`], never split, sniffed or reopened.
+pub fn is_stream(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    if path == Path::new("-") {
+        return true;
+    }
+    #[cfg(unix)]
+    if let Ok(metadata) = std::fs::metadata(path) {
+        let file_type = metadata.file_type();
+        return file_type.is_fifo() || file_type.is_socket() || file_type.is_char_device();
+    }
+    #[cfg(windows)]
+    if let Some(path) = path.to_str() {
+        let prefix = path.get(..9).unwrap_or_default();
+        return prefix.eq_ignore_ascii_case(r"\\.\pipe\")
+            || prefix.eq_ignore_ascii_case(r"\\?\pipe\");
+    }
+    false
+}
+
+/// Opens the stream at `path`: standard input for `-`, a connection for a
+/// Unix domain socket, whose peer is expected to write the measurements and
+/// close it, and the file itself for a named pipe or a device.
+pub fn open_stream(path: impl AsRef<Path>) -> io::Result<Box<dyn Read>> {
+    let path = path.as_ref();
+    if path == Path::new("-") {
+        return Ok(Box::new(io::stdin()));
+    }
+    #[cfg(unix)]
+    if std::fs::metadata(path)?.file_type().is_socket() {
+        return Ok(Box::new(UnixStream::connect(path)?));
+    }
+    Ok(Box::new(File::open(path)?))
+}
+
+/// Opens `path` for sequential reading: as a stream if it is one, through a
+/// decoder if the file is compressed, and as a plain file otherwise.
+pub fn open_input(path: impl AsRef<Path>) -> io::Result<Box<dyn Read>> {
+    let path = path.as_ref();
+    if is_stream(path) {
+        return open_stream(path);
+    }
+    match Compression::detect(path)? {
+        Some(compression) => open_decoder(path, compression),
+        None => Ok(Box::new(File::open(path)?)),
+    }
+}
+
+/// Opens `path` and wraps it in a decoder for `compression`. Fails with
+/// [`io::ErrorKind::Unsupported`] if support for it was not compiled in.
+#[cfg_attr(not(any(feature = "gzip", feature = "zstd")), allow(unused_variables))]
+pub fn open_decoder(path: impl AsRef<Path>, compression: Compression) -> io::Result<Box<dyn Read>> {
+    match compression {
+        #[cfg(feature = "gzip")]
+        // Concatenated members are common when large files are compressed in pieces
+        Compression::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(
+            io::BufReader::new(File::open(path)?),
+        ))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(Box::new(zstd::Decoder::new(File::open(path)?)?)),
+        #[allow(unreachable_patterns)]
+        other => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "input is {}-compressed but onebrc-core was built without the `{}` feature",
+                other.name(),
+                other.name()
+            ),
+        )),
+    }
+}
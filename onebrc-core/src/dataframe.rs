@@ -0,0 +1,31 @@
+//! Results as a Polars `DataFrame`.
+
+use polars::prelude::{Column, DataFrame, PolarsResult};
+
+use crate::results::Results;
+use crate::stats::TempStats;
+
+impl Results {
+    /// One row per station, alphabetically, with `station`, `min`, `mean`,
+    /// `max` and `count` columns (and `stddev` with the `variance` feature);
+    /// temperatures are in Celsius and unrounded.
+    pub fn into_dataframe(self) -> PolarsResult<DataFrame> {
+        let stats: Vec<_> = self.iter().map(|(_, stats)| stats).collect();
+        let float = |name: &str, value: fn(&TempStats) -> f64| {
+            let values: Vec<f64> = stats.iter().map(|&stats| value(stats)).collect();
+            Column::new(name.into(), values)
+        };
+        let stations: Vec<&str> = self.iter().map(|(station, _)| station).collect();
+        let counts: Vec<u64> = stats.iter().map(|stats| stats.count()).collect();
+        let columns = vec![
+            Column::new("station".into(), stations),
+            float("min", |stats| stats.min()),
+            float("mean", |stats| stats.mean()),
+            float("max", |stats| stats.max()),
+            Column::new("count".into(), counts),
+            #[cfg(feature = "variance")]
+            float("stddev", |stats| stats.std_dev()),
+        ];
+        DataFrame::new(self.len(), columns)
+    }
+}
@@ -0,0 +1,255 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+/// Controls the size/accuracy trade-off: a digest keeps on the order of
+/// `COMPRESSION` centroids, with the smallest ones near the tails.
+const COMPRESSION: f64 = 100.0;
+
+/// Readings buffered before they are folded into the centroids.
+const BUFFER_SIZE: usize = 5 * COMPRESSION as usize;
+
+/// About the most a boxed digest takes in memory: itself, its buffer and the
+/// centroids, which compressing keeps to some `2 * COMPRESSION`.
+#[cfg(feature = "spill")]
+pub(crate) const DIGEST_BYTES: usize = std::mem::size_of::<TDigest>()
+    + (BUFFER_SIZE + 2 * COMPRESSION as usize) * std::mem::size_of::<Centroid>();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+/// A merging t-digest (Dunning & Ertl): a mergeable sketch that answers
+/// quantile queries in bounded memory, most accurately near the tails.
+///
+/// Values are whatever the caller adds; [`TempStats`](crate::TempStats)
+/// feeds it tenths of a degree.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TDigest {
+    centroids: Vec<Centroid>,
+    buffer: Vec<Centroid>,
+    min: f64,
+    max: f64,
+}
+
+impl Default for TDigest {
+    fn default() -> Self {
+        TDigest {
+            centroids: Vec::new(),
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl TDigest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.buffer.push(Centroid {
+            mean: value,
+            weight: 1.0,
+        });
+        if self.buffer.len() >= BUFFER_SIZE {
+            self.compress();
+        }
+    }
+
+    /// Folds in a digest built elsewhere (another thread, another file).
+    pub fn merge(&mut self, other: &TDigest) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.buffer.extend_from_slice(&other.centroids);
+        self.buffer.extend_from_slice(&other.buffer);
+        self.compress();
+    }
+
+    /// Estimates the value below which a fraction `q` (0 to 1) of the added
+    /// values fall. `NaN` for an empty digest.
+    pub fn quantile(&self, q: f64) -> f64 {
+        if !self.buffer.is_empty() {
+            let mut compressed = self.clone();
+            compressed.compress();
+            return compressed.quantile(q);
+        }
+        let Some(last) = self.centroids.last() else {
+            return f64::NAN;
+        };
+        if q <= 0.0 {
+            return self.min;
+        }
+        if q >= 1.0 {
+            return self.max;
+        }
+
+        // Treat each centroid's mean as sitting at the middle of its weight
+        // and interpolate linearly between neighbouring centres; the exact
+        // min and max anchor both ends.
+        let total = self.total_weight();
+        let target = q * total;
+        let mut before = 0.0;
+        let (mut prev_mean, mut prev_center) = (self.min, 0.0);
+        for centroid in &self.centroids {
+            let center = before + centroid.weight / 2.0;
+            if target < center {
+                let t = (target - prev_center) / (center - prev_center);
+                return prev_mean + t * (centroid.mean - prev_mean);
+            }
+            (prev_mean, prev_center) = (centroid.mean, center);
+            before += centroid.weight;
+        }
+        let t = (target - prev_center) / (total - prev_center);
+        last.mean + t * (self.max - last.mean)
+    }
+
+    fn total_weight(&self) -> f64 {
+        self.centroids.iter().map(|c| c.weight).sum()
+    }
+
+    /// Sorts the buffered values in with the centroids and greedily merges
+    /// neighbours while the result stays within one unit of the k1 scale
+    /// function, which keeps centroids small where `q` is near 0 or 1.
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut items = std::mem::take(&mut self.centroids);
+        items.append(&mut self.buffer);
+        items.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+
+        let total: f64 = items.iter().map(|c| c.weight).sum();
+        let scale = |q: f64| COMPRESSION / (2.0 * PI) * (2.0 * q - 1.0).asin();
+
+        let mut merged = Vec::with_capacity(COMPRESSION as usize * 2);
+        let mut items = items.into_iter();
+        let mut current = items.next().expect("buffer was not empty");
+        let mut before = 0.0;
+        let mut k_left = scale(0.0);
+        for item in items {
+            let q_right = (before + current.weight + item.weight) / total;
+            if scale(q_right) - k_left <= 1.0 {
+                let weight = current.weight + item.weight;
+                current.mean += (item.mean - current.mean) * item.weight / weight;
+                current.weight = weight;
+            } else {
+                before += current.weight;
+                k_left = scale(before / total);
+                merged.push(current);
+                current = item;
+            }
+        }
+        merged.push(current);
+        self.centroids = merged;
+    }
+}
+
+/// A requested percentile such as `p99`, parsed from `p99`, `99` or `p99.9`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Percentile(f64);
+
+impl Percentile {
+    /// The percentile as a fraction between 0 and 1.
+    pub fn fraction(self) -> f64 {
+        self.0 / 100.0
+    }
+}
+
+impl fmt::Display for Percentile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "p{}", self.0)
+    }
+}
+
+impl FromStr for Percentile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = s.strip_prefix('p').unwrap_or(s);
+        match number.parse::<f64>() {
+            Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(Percentile(percent)),
+            _ => Err(format!("`{s}` is not a percentile between p0 and p100")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 0 to 9999 in a scrambled order.
+    fn values() -> impl Iterator<Item = f64> {
+        (0..10_000u64).map(|i| (i * 7919 % 10_000) as f64)
+    }
+
+    #[test]
+    fn merged_parts_agree_with_one_digest() {
+        let mut whole = TDigest::new();
+        let mut parts = vec![TDigest::new(); 4];
+        for (i, value) in values().enumerate() {
+            whole.add(value);
+            parts[i % 3 + usize::from(value > 9000.0)].add(value);
+        }
+        let mut merged = TDigest::new();
+        for part in &parts {
+            merged.merge(part);
+        }
+        assert_eq!(merged.total_weight(), 10_000.0);
+        assert_eq!((merged.min, merged.max), (0.0, 9999.0));
+        for q in [0.001, 0.01, 0.25, 0.5, 0.75, 0.99, 0.999] {
+            let exact = q * 9999.0;
+            for digest in [&whole, &merged] {
+                let estimate = digest.quantile(q);
+                assert!(
+                    (estimate - exact).abs() < 10.0,
+                    "q {q}: {estimate} for {exact}"
+                );
+            }
+        }
+        assert_eq!(merged.quantile(0.0), 0.0);
+        assert_eq!(merged.quantile(1.0), 9999.0);
+    }
+
+    #[test]
+    fn merging_an_empty_digest_changes_nothing() {
+        let mut digest = TDigest::new();
+        values().for_each(|value| digest.add(value));
+        let median = digest.quantile(0.5);
+        digest.merge(&TDigest::new());
+        assert_eq!(digest.quantile(0.5), median);
+
+        let mut empty = TDigest::new();
+        assert!(empty.quantile(0.5).is_nan());
+        empty.merge(&digest);
+        assert_eq!(empty.quantile(0.5), median);
+    }
+
+    #[test]
+    fn centroids_stay_bounded() {
+        let mut digest = TDigest::new();
+        for round in 0..20 {
+            values().for_each(|value| digest.add(value + round as f64));
+        }
+        digest.compress();
+        assert!(digest.centroids.len() <= 2 * COMPRESSION as usize);
+        // The tails are kept finer than the middle
+        let middle = digest.centroids[digest.centroids.len() / 2].weight;
+        assert!(digest.centroids[0].weight * 10.0 < middle);
+    }
+
+    #[test]
+    fn percentiles() {
+        assert_eq!("p99".parse::<Percentile>().unwrap().fraction(), 0.99);
+        assert_eq!("99.9".parse::<Percentile>().unwrap().to_string(), "p99.9");
+        assert!("p101".parse::<Percentile>().is_err());
+        assert!("median".parse::<Percentile>().is_err());
+    }
+}
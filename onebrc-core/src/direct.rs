@@ -0,0 +1,225 @@
+//! Reads that bypass the page cache, for [`Options::direct_io`]. With
+//! `O_DIRECT` the buffer, the file offset and the length of every read must be
+//! multiples of the device's block size, so ranges are read from the block
+//! before their start and trimmed afterwards.
+//!
+//! [`Options::direct_io`]: crate::Options::direct_io
+
+use std::alloc::{self, Layout};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::ops::{Deref, DerefMut};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+use std::ptr::NonNull;
+
+/// Alignment of direct reads; a multiple of every common block size.
+pub(crate) const ALIGN: u64 = 4096;
+
+/// Bytes asked for by each read of a [`DirectReader`].
+const READ_SIZE: usize = 4 << 20;
+
+/// Opens `path` for reading with `O_DIRECT`.
+pub(crate) fn open_direct(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+        .map_err(|err| io::Error::new(err.kind(), format!("direct I/O: {err}")))
+}
+
+/// A zeroed heap buffer aligned to [`ALIGN`].
+pub(crate) struct AlignedBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl AlignedBuf {
+    pub(crate) fn new(len: usize) -> Self {
+        let layout = Self::layout(len);
+        // SAFETY: the layout has a non-zero size
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        AlignedBuf { ptr, len }
+    }
+
+    fn layout(len: usize) -> Layout {
+        Layout::from_size_align(len.max(1), ALIGN as usize).expect("buffer size fits a layout")
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` initialized bytes owned by `self`
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as in `deref`, and `&mut self` makes the access unique
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        // SAFETY: allocated in `new` with the same layout
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), Self::layout(self.len)) }
+    }
+}
+
+// SAFETY: the buffer is plain owned memory, like a `Box<[u8]>`
+unsafe impl Send for AlignedBuf {}
+
+/// The part of a read of `filled` bytes at `offset` that falls inside
+/// `start..end`, as positions in the buffer.
+pub(crate) fn trim(offset: u64, filled: usize, start: u64, end: u64) -> (usize, usize) {
+    let from = start.saturating_sub(offset).min(filled as u64) as usize;
+    let to = (end.saturating_sub(offset).min(filled as u64) as usize).max(from);
+    (from, to)
+}
+
+/// Reads the bytes `start..end` of a file opened with `O_DIRECT`, in
+/// aligned reads of [`READ_SIZE`] bytes.
+pub(crate) struct DirectReader {
+    file: File,
+    buf: AlignedBuf,
+    /// The bytes of `buf` still to hand out.
+    pos: usize,
+    filled: usize,
+    /// Aligned offset of the next read.
+    next_offset: u64,
+    start: u64,
+    end: u64,
+}
+
+impl DirectReader {
+    pub(crate) fn open(path: &Path, start: u64, end: u64) -> io::Result<Self> {
+        Ok(DirectReader {
+            file: open_direct(path)?,
+            buf: AlignedBuf::new(READ_SIZE),
+            pos: 0,
+            filled: 0,
+            next_offset: start / ALIGN * ALIGN,
+            start,
+            end,
+        })
+    }
+}
+
+impl Read for DirectReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.filled {
+            if self.next_offset >= self.end {
+                return Ok(0);
+            }
+            let offset = self.next_offset;
+            let wanted = (self.end - offset)
+                .next_multiple_of(ALIGN)
+                .min(READ_SIZE as u64);
+            let read = loop {
+                match self.file.read_at(&mut self.buf[..wanted as usize], offset) {
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    read => break read?,
+                }
+            };
+            // A short read means the end of the file
+            self.next_offset = if read < wanted as usize {
+                self.end
+            } else {
+                offset + wanted
+            };
+            (self.pos, self.filled) = trim(offset, read, self.start, self.end);
+        }
+        let n = out.len().min(self.filled - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFile;
+
+    /// `start..end` of `path` through a [`DirectReader`], or `None` where
+    /// the filesystem rejects `O_DIRECT`.
+    fn read_direct(path: &Path, start: u64, end: u64) -> Option<Vec<u8>> {
+        let mut reader = match DirectReader::open(path, start, end) {
+            Ok(reader) => reader,
+            Err(err) if err.kind() == io::ErrorKind::InvalidInput => {
+                eprintln!("skipped, no O_DIRECT here: {err}");
+                return None;
+            }
+            Err(err) => panic!("{err}"),
+        };
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        Some(read)
+    }
+
+    /// Compares direct reads of every range in `ranges` with the bytes of a
+    /// `len`-byte file.
+    fn assert_direct_reads(name: &str, len: usize, ranges: &[(u64, u64)]) {
+        let data: Vec<u8> = (0..len).map(|i| (i * 13 + i / 4093) as u8).collect();
+        let file = TempFile::new(name, &data);
+        for &(start, end) in ranges {
+            let Some(read) = read_direct(&file.0, start, end) else {
+                return;
+            };
+            let expected = &data[start as usize..(end as usize).min(len)];
+            assert!(read == expected, "{start}..{end} of {len} bytes");
+        }
+    }
+
+    #[test]
+    fn aligned_buffers_are_aligned_and_zeroed() {
+        for len in [0, 1, 4095, 4096, 3 * 4096 + 5] {
+            let buf = AlignedBuf::new(len);
+            assert_eq!(buf.len(), len);
+            assert_eq!(buf.as_ptr() as usize % ALIGN as usize, 0);
+            assert!(buf.iter().all(|&byte| byte == 0));
+        }
+    }
+
+    #[test]
+    fn trim_keeps_the_part_in_range() {
+        // A read from the block before an unaligned start
+        assert_eq!(trim(4096, 8192, 5000, 9000), (904, 4904));
+        // Cut short by the end of the file
+        assert_eq!(trim(4096, 100, 5000, 9000), (100, 100));
+        assert_eq!(trim(0, 4096, 10, 4096), (10, 4096));
+        // A read past the end of the range
+        assert_eq!(trim(8192, 4096, 0, 8000), (0, 0));
+    }
+
+    #[test]
+    fn unaligned_starts_and_ends() {
+        let len = 3 * READ_SIZE + 777;
+        let ranges = [
+            (1, 4095),
+            (4095, 4097),
+            (100, READ_SIZE as u64 + 3),
+            (READ_SIZE as u64 - 1, 2 * READ_SIZE as u64 + 1),
+            (12_345, len as u64),
+            (5, 5),
+        ];
+        assert_direct_reads("direct-unaligned", len, &ranges);
+    }
+
+    #[test]
+    fn file_shorter_than_a_block() {
+        assert_direct_reads("direct-short", 1000, &[(0, 1000), (3, 999), (999, 1000)]);
+    }
+
+    #[test]
+    fn file_a_multiple_of_the_alignment() {
+        let len = 4 * ALIGN;
+        let ranges = [(0, len), (ALIGN, 3 * ALIGN), (ALIGN - 1, len), (len, len)];
+        assert_direct_reads("direct-multiple", len as usize, &ranges);
+    }
+}
@@ -0,0 +1,46 @@
+use regex::bytes::Regex;
+use rustc_hash::FxHashSet;
+
+use crate::key::StationKey;
+
+/// Restricts aggregation to some stations: those matching a regex, those on
+/// an allow-list, or (with both) those passing both tests.
+///
+/// Aggregators consult the filter only the first time they meet a station
+/// and remember rejected names, so filtered-out rows cost one hash lookup
+/// and never create an entry in the results.
+#[derive(Debug, Clone, Default)]
+pub struct StationFilter {
+    pattern: Option<Regex>,
+    allowed: Option<FxHashSet<StationKey>>,
+}
+
+impl StationFilter {
+    /// A filter that lets every station through.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps only stations whose name contains a match for `pattern`;
+    /// anchor it with `^...$` to match whole names.
+    pub fn with_pattern(mut self, pattern: &str) -> Result<Self, regex::Error> {
+        self.pattern = Some(Regex::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Keeps only stations named exactly as one of `names`.
+    pub fn with_allowed<N: Into<Vec<u8>>>(mut self, names: impl IntoIterator<Item = N>) -> Self {
+        self.allowed
+            .get_or_insert_with(FxHashSet::default)
+            .extend(names.into_iter().map(|name| StationKey::from(name.into())));
+        self
+    }
+
+    pub fn matches(&self, station: &[u8]) -> bool {
+        self.pattern.as_ref().is_none_or(|re| re.is_match(station))
+            && self
+                .allowed
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(station))
+    }
+}
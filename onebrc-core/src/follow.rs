@@ -0,0 +1,101 @@
+//! [`follow_file`]: a file that is still being written, read the way
+//! `tail -f` reads it.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use memchr::{memchr_iter, memrchr};
+
+use crate::aggregator::Aggregator;
+use crate::columnar::is_parquet;
+use crate::compression::{Compression, is_stream};
+use crate::parse::BOM;
+use crate::pipeline::{Options, STREAM_BLOCK_SIZE, run_pool};
+use crate::progress::TimedReader;
+use crate::sniff::sniff_file;
+use crate::trace::{event, span};
+
+/// Aggregates the plain text file at `path` like [`aggregate_reader`], but
+/// does not stop at its end: every `poll`, whatever was appended since is
+/// read, and its complete lines go to the workers. A line still being
+/// written waits for its newline.
+///
+/// Only [`Options::cancel`] ends the run, which then returns what was
+/// aggregated; watch it meanwhile through [`Options::live`]. A file that
+/// shrinks, e.g. because it was truncated for rotation, is an error, as
+/// its lines would otherwise be counted twice.
+///
+/// [`aggregate_reader`]: crate::aggregate_reader
+/// [`Options::cancel`]: crate::Options::cancel
+/// [`Options::live`]: crate::Options::live
+pub fn follow_file(
+    path: impl AsRef<Path>,
+    options: &Options,
+    poll: Duration,
+) -> io::Result<Aggregator> {
+    let path = path.as_ref();
+    let _span = span!(INFO, "follow_file", path = %path.display());
+    if is_stream(path) || Compression::detect(path)?.is_some() || is_parquet(path)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only an uncompressed text file can be followed",
+        ));
+    }
+    let options = &Options {
+        parse: sniff_file(path, options.parse)?,
+        ..options.clone()
+    };
+    let file = File::open(path)?;
+    run_pool(options, |send, _, pool| {
+        let mut file = TimedReader::new(file, options.progress.as_deref());
+        let mut next_line = 1;
+        let mut offset = 0;
+        let mut sent = 0;
+        let mut carry = Vec::new();
+        while !options.cancelled() {
+            let mut block = pool.get();
+            block.append(&mut carry);
+            let kept = block.len();
+            let read = (&mut file)
+                .take(STREAM_BLOCK_SIZE as u64)
+                .read_to_end(&mut block)?;
+            offset += read as u64;
+            if read == 0 {
+                if fs::metadata(path)?.len() < offset {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "the file shrank while it was followed",
+                    ));
+                }
+                carry.extend_from_slice(&block);
+                thread::sleep(poll);
+                continue;
+            }
+
+            // Lines are cut at the last newline; a line longer than a block
+            // keeps growing in `carry` until it ends
+            let cut = memrchr(b'\n', &block[kept..]).map_or(0, |pos| kept + pos + 1);
+            carry.extend_from_slice(&block[cut..]);
+            block.truncate(cut);
+            let mut at = sent;
+            sent += block.len() as u64;
+            if next_line == 1 && block.starts_with(BOM) {
+                block.drain(..BOM.len());
+                at += BOM.len() as u64;
+            }
+            if block.is_empty() {
+                continue;
+            }
+            let first_line = next_line;
+            next_line += memchr_iter(b'\n', &block).count() as u64;
+            if !send(first_line, at, block) {
+                break;
+            }
+        }
+        event!(INFO, lines = next_line - 1, "stopped following");
+        Ok(())
+    })
+}
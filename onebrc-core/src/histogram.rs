@@ -0,0 +1,96 @@
+/// Lower edge of the first bucket, in tenths of a degree.
+pub const HISTOGRAM_MIN: i32 = -1000;
+/// Width of every bucket, in tenths of a degree.
+pub const HISTOGRAM_BUCKET_WIDTH: i32 = 5;
+/// Buckets between [`HISTOGRAM_MIN`] and +100.0 degrees.
+pub const HISTOGRAM_BUCKETS: usize = 400;
+
+/// Fixed-bucket counts of readings: 0.5 degree buckets from -100.0 to
+/// +100.0, plus one count each for readings below and above that range.
+/// Bucket `i` covers `[min + i * width, min + (i + 1) * width)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Histogram {
+    #[cfg_attr(feature = "serde", serde(with = "bucket_counts"))]
+    counts: [u64; HISTOGRAM_BUCKETS],
+    underflow: u64,
+    overflow: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: [0; HISTOGRAM_BUCKETS],
+            underflow: 0,
+            overflow: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts one reading, in tenths of a degree.
+    pub fn add(&mut self, temp: i32) {
+        let offset = temp - HISTOGRAM_MIN;
+        if offset < 0 {
+            self.underflow += 1;
+            return;
+        }
+        match self.counts.get_mut((offset / HISTOGRAM_BUCKET_WIDTH) as usize) {
+            Some(count) => *count += 1,
+            None => self.overflow += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+        self.underflow += other.underflow;
+        self.overflow += other.overflow;
+    }
+
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Readings below the first bucket.
+    pub fn underflow(&self) -> u64 {
+        self.underflow
+    }
+
+    /// Readings at or above the end of the last bucket.
+    pub fn overflow(&self) -> u64 {
+        self.overflow
+    }
+}
+
+/// serde only implements arrays of up to 32 elements, so the counts go
+/// through a sequence of exactly [`HISTOGRAM_BUCKETS`].
+#[cfg(feature = "serde")]
+mod bucket_counts {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::HISTOGRAM_BUCKETS;
+
+    pub(super) fn serialize<S: Serializer>(
+        counts: &[u64; HISTOGRAM_BUCKETS],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        counts.as_slice().serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u64; HISTOGRAM_BUCKETS], D::Error> {
+        let counts = Vec::<u64>::deserialize(deserializer)?;
+        let len = counts.len();
+        counts
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &"one count per histogram bucket"))
+    }
+}
@@ -0,0 +1,102 @@
+//! Input files fetched over HTTP(S), several byte ranges at a time.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+use ureq::Agent;
+use ureq::http::StatusCode;
+
+use crate::aggregator::Aggregator;
+use crate::pipeline::{Options, aggregate_reader};
+use crate::pool::{Buffer, BufferPool};
+use crate::ranged::{Source, aggregate_ranges};
+use crate::trace::{event, span};
+
+/// Aggregates the plain text file at `url`.
+///
+/// If the server answers range requests, the file goes through
+/// [`aggregate_ranges`] with `options.connections` requests in flight.
+/// Otherwise the response is parsed as one stream, and with
+/// `options.download_to` saved as it goes.
+pub(crate) fn aggregate_url(url: &str, options: &Options) -> io::Result<Aggregator> {
+    let _span = span!(INFO, "aggregate_url", url);
+    let agent = Agent::new_with_defaults();
+    let probe = agent
+        .get(url)
+        .header("Range", "bytes=0-0")
+        .call()
+        .map_err(ureq::Error::into_io)?;
+    let status = probe.status();
+    let total = match status {
+        StatusCode::PARTIAL_CONTENT => probe
+            .headers()
+            .get("Content-Range")
+            .and_then(|range| range.to_str().ok())
+            .and_then(|range| range.rsplit_once('/'))
+            .and_then(|(_, total)| total.parse::<u64>().ok()),
+        _ => None,
+    };
+    let Some(total) = total else {
+        event!(INFO, "no range requests, reading one stream");
+        // A range without a known total still needs the whole file
+        let response = match status {
+            StatusCode::PARTIAL_CONTENT => agent.get(url).call().map_err(ureq::Error::into_io)?,
+            _ => probe,
+        };
+        let body = response.into_body().into_reader();
+        let copy = match &options.download_to {
+            Some(path) => Some(File::create(path)?),
+            None => None,
+        };
+        return aggregate_reader(Copying { inner: body, copy }, options);
+    };
+    aggregate_ranges(&Http { agent, url }, total, options)
+}
+
+/// A file on a server that answers range requests.
+struct Http<'a> {
+    agent: Agent,
+    url: &'a str,
+}
+
+impl Source for Http<'_> {
+    fn fetch_range(&self, start: u64, end: u64, pool: &BufferPool) -> io::Result<Buffer> {
+        let mut response = self
+            .agent
+            .get(self.url)
+            .header("Range", format!("bytes={start}-{}", end - 1))
+            .call()
+            .map_err(ureq::Error::into_io)?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(io::Error::other(format!(
+                "range {start}-{end} answered with {}",
+                response.status()
+            )));
+        }
+        let mut bytes = pool.get();
+        response.body_mut().as_reader().read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != end - start {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("range {start}-{end} came back with {} bytes", bytes.len()),
+            ));
+        }
+        Ok(bytes)
+    }
+}
+
+/// A reader whose bytes are also written to `copy`.
+struct Copying<R> {
+    inner: R,
+    copy: Option<File>,
+}
+
+impl<R: Read> Read for Copying<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(copy) = &mut self.copy {
+            copy.write_all(&buf[..read])?;
+        }
+        Ok(read)
+    }
+}
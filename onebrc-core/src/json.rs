@@ -0,0 +1,306 @@
+//! [`InputFormat::Jsonl`] lines, `{"station": "Hamburg", "temp": 12.0}`,
+//! taken apart by hand: only the two fields are looked for, and the station
+//! is borrowed from the line unless it has escapes.
+//!
+//! [`InputFormat::Jsonl`]: crate::InputFormat::Jsonl
+
+use std::borrow::Cow;
+
+use memchr::{memchr_iter, memchr2};
+
+use crate::parse::{parse_temp, strip_cr};
+use crate::skipped::SkipReason;
+
+/// Calls `f` for every line of `chunk` with what [`parse_json`] made of it,
+/// stopping at the first error. Lines ending in CRLF lose the `\r` too.
+#[inline]
+pub(crate) fn for_each_json<'a, E>(
+    chunk: &'a [u8],
+    mut f: impl FnMut(&'a [u8], Result<(Cow<'a, [u8]>, i32), SkipReason>) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut record = |line: &'a [u8]| {
+        let line = strip_cr(line);
+        f(line, parse_json(line))
+    };
+    let mut start = 0;
+    for end in memchr_iter(b'\n', chunk) {
+        record(&chunk[start..end])?;
+        start = end + 1;
+    }
+    if start < chunk.len() {
+        record(&chunk[start..])?;
+    }
+    Ok(())
+}
+
+/// The station and the temperature, in tenths of a degree, of a JSON
+/// object with a `station` string and a `temp` number. Other fields are
+/// skipped, and the rest of the line is not looked at once both were found.
+/// A temperature with other than one decimal place is rounded to tenths.
+pub(crate) fn parse_json(line: &[u8]) -> Result<(Cow<'_, [u8]>, i32), SkipReason> {
+    let mut rest = line.trim_ascii_start();
+    rest = rest.strip_prefix(b"{").ok_or(SkipReason::BadJson)?;
+    let mut station = None;
+    let mut temp = None;
+    loop {
+        rest = rest.trim_ascii_start();
+        if rest.first() == Some(&b'}') {
+            break;
+        }
+        let (key, _) = string(&mut rest)?;
+        rest = rest.trim_ascii_start();
+        rest = rest.strip_prefix(b":").ok_or(SkipReason::BadJson)?;
+        rest = rest.trim_ascii_start();
+        match key {
+            b"station" if rest.starts_with(b"null") => return Err(SkipReason::Missing),
+            b"station" => station = Some(string(&mut rest)?),
+            b"temp" if rest.starts_with(b"null") => return Err(SkipReason::Missing),
+            b"temp" => temp = Some(number(&mut rest)?),
+            _ => skip_value(&mut rest)?,
+        }
+        if let (Some((station, escaped)), Some(temp)) = (station, temp) {
+            let station = match escaped {
+                false => Cow::Borrowed(station),
+                true => Cow::Owned(unescape(station).ok_or(SkipReason::BadJson)?),
+            };
+            return Ok((station, temp));
+        }
+        rest = rest.trim_ascii_start();
+        match rest.split_first() {
+            Some((b',', after)) => rest = after,
+            Some((b'}', _)) => break,
+            _ => return Err(SkipReason::BadJson),
+        }
+    }
+    Err(SkipReason::Missing)
+}
+
+/// Takes the string at the start of `rest`: its raw contents, and whether
+/// they hold escapes.
+#[inline]
+fn string<'a>(rest: &mut &'a [u8]) -> Result<(&'a [u8], bool), SkipReason> {
+    let body = rest.strip_prefix(b"\"").ok_or(SkipReason::BadJson)?;
+    let mut escaped = false;
+    let mut pos = 0;
+    loop {
+        pos += memchr2(b'"', b'\\', &body[pos..]).ok_or(SkipReason::BadJson)?;
+        if body[pos] == b'"' {
+            *rest = &body[pos + 1..];
+            return Ok((&body[..pos], escaped));
+        }
+        // Whatever follows the backslash is never the closing quote
+        escaped = true;
+        pos += 2;
+        if pos > body.len() {
+            return Err(SkipReason::BadJson);
+        }
+    }
+}
+
+/// Takes the number at the start of `rest`, in tenths.
+#[inline]
+fn number(rest: &mut &[u8]) -> Result<i32, SkipReason> {
+    let end = rest
+        .iter()
+        .position(|&b| matches!(b, b',' | b'}' | b' ' | b'\t'))
+        .unwrap_or(rest.len());
+    let (digits, after) = rest.split_at(end);
+    *rest = after;
+    parse_temp(digits).map_or_else(|| round_number(digits), Ok)
+}
+
+/// A JSON number in any other form, e.g. `12`, `12.25` or `1.2e1`.
+#[cold]
+fn round_number(digits: &[u8]) -> Result<i32, SkipReason> {
+    // Rust also takes `inf`, `NaN` and a leading `+`, which JSON does not
+    if !matches!(digits.first(), Some(b'-' | b'0'..=b'9')) {
+        return Err(SkipReason::BadTemperature);
+    }
+    let degrees: f64 = std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .ok_or(SkipReason::BadTemperature)?;
+    let tenths = (degrees * 10.0).round();
+    if tenths.abs() > i32::MAX as f64 {
+        return Err(SkipReason::OutOfRange);
+    }
+    Ok(tenths as i32)
+}
+
+/// Skips the value at the start of `rest`, nested objects and arrays
+/// included.
+fn skip_value(rest: &mut &[u8]) -> Result<(), SkipReason> {
+    let mut depth = 0usize;
+    loop {
+        match rest.first().ok_or(SkipReason::BadJson)? {
+            b'"' => {
+                string(rest)?;
+            }
+            b'{' | b'[' => {
+                depth += 1;
+                *rest = &rest[1..];
+            }
+            b'}' | b']' if depth > 0 => {
+                depth -= 1;
+                *rest = &rest[1..];
+            }
+            b',' | b'}' | b']' if depth == 0 => return Ok(()),
+            _ => *rest = &rest[1..],
+        }
+        if depth == 0 && matches!(rest.first(), Some(b',' | b'}')) {
+            return Ok(());
+        }
+    }
+}
+
+/// The bytes a JSON string body stands for, or `None` for a broken escape.
+#[cold]
+fn unescape(body: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(body.len());
+    let mut iter = body.iter().copied();
+    while let Some(byte) = iter.next() {
+        if byte != b'\\' {
+            out.push(byte);
+            continue;
+        }
+        let unescaped = match iter.next()? {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let high = hex_unit(&mut iter)?;
+                let units = match high {
+                    // A surrogate pair is written as two escapes
+                    0xD800..=0xDBFF => {
+                        if (iter.next(), iter.next()) != (Some(b'\\'), Some(b'u')) {
+                            return None;
+                        }
+                        vec![high, hex_unit(&mut iter)?]
+                    }
+                    _ => vec![high],
+                };
+                char::decode_utf16(units).next()?.ok()?
+            }
+            _ => return None,
+        };
+        out.extend_from_slice(unescaped.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+    Some(out)
+}
+
+/// The four hex digits of a `\u` escape.
+fn hex_unit(iter: &mut impl Iterator<Item = u8>) -> Option<u16> {
+    let hex: Vec<u8> = iter.take(4).collect();
+    u16::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn station(line: &[u8]) -> Vec<u8> {
+        parse_json(line).unwrap().0.into_owned()
+    }
+
+    #[test]
+    fn fields_in_any_order_among_others() {
+        let (station, temp) = parse_json(br#"{"station": "Hamburg", "temp": 12.0}"#).unwrap();
+        assert!(matches!(station, Cow::Borrowed(b"Hamburg")));
+        assert_eq!(temp, 120);
+
+        let line = br#" { "id": 7, "meta": {"a": [1, {"b": "}]"}], "c": null},
+            "temp":-3.4 , "tags": ["x,y"], "station":"Oslo", "rest": {"#;
+        assert_eq!(parse_json(line).unwrap(), (Cow::from(&b"Oslo"[..]), -34));
+    }
+
+    #[test]
+    fn escaped_stations() {
+        let line = br#"{"station": "K\u00f8ge \"N\" \\ \/", "temp": 1.0}"#;
+        assert!(matches!(parse_json(line).unwrap().0, Cow::Owned(_)));
+        assert_eq!(station(line), "Køge \"N\" \\ /".as_bytes());
+        let line = br#"{"station": "\ud83c\udf0d\t", "temp": 1.0}"#;
+        assert_eq!(station(line), "🌍\t".as_bytes());
+
+        for broken in [
+            &br#"{"station": "\x", "temp": 1.0}"#[..],
+            br#"{"station": "\ud83c", "temp": 1.0}"#,
+            br#"{"station": "\uzzzz", "temp": 1.0}"#,
+        ] {
+            assert_eq!(parse_json(broken), Err(SkipReason::BadJson));
+        }
+    }
+
+    #[test]
+    fn other_numbers_are_rounded_to_tenths() {
+        let temp = |number: &str| {
+            let line = format!(r#"{{"temp": {number}, "station": "Oslo"}}"#);
+            parse_json(line.as_bytes()).map(|(_, temp)| temp)
+        };
+        assert_eq!(temp("12"), Ok(120));
+        assert_eq!(temp("12.25"), Ok(123));
+        assert_eq!(temp("-0.04"), Ok(0));
+        assert_eq!(temp("1.2e1"), Ok(120));
+        assert_eq!(temp("+1.0"), Err(SkipReason::BadTemperature));
+        assert_eq!(temp("NaN"), Err(SkipReason::BadTemperature));
+        assert_eq!(temp("\"1.0\""), Err(SkipReason::BadTemperature));
+        assert_eq!(temp("1e10"), Err(SkipReason::OutOfRange));
+    }
+
+    #[test]
+    fn broken_and_incomplete_objects() {
+        assert_eq!(parse_json(b"Oslo;1.0"), Err(SkipReason::BadJson));
+        assert_eq!(
+            parse_json(br#"{"station" "Oslo"}"#),
+            Err(SkipReason::BadJson)
+        );
+        assert_eq!(
+            parse_json(br#"{"station": "Oslo"#),
+            Err(SkipReason::BadJson)
+        );
+        assert_eq!(
+            parse_json(br#"{"station": "Oslo" "temp": 1.0}"#),
+            Err(SkipReason::BadJson)
+        );
+        assert_eq!(
+            parse_json(br#"{"station": "Oslo"}"#),
+            Err(SkipReason::Missing)
+        );
+        assert_eq!(parse_json(br#"{}"#), Err(SkipReason::Missing));
+        assert_eq!(
+            parse_json(br#"{"station": null, "temp": 1.0}"#),
+            Err(SkipReason::Missing)
+        );
+        assert_eq!(
+            parse_json(br#"{"station": "Oslo", "temp": null}"#),
+            Err(SkipReason::Missing)
+        );
+    }
+
+    #[test]
+    fn for_each_json_strips_cr_and_takes_a_last_line_without_newline() {
+        let mut lines = Vec::new();
+        let chunk =
+            b"{\"station\": \"A\", \"temp\": 1.0}\r\n{}\n{\"temp\": 2.0, \"station\": \"B\"}";
+        for_each_json(chunk, |line, record| {
+            lines.push((
+                line.last().copied(),
+                record.map(|(station, temp)| (station.to_vec(), temp)),
+            ));
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+        assert_eq!(
+            lines,
+            [
+                (Some(b'}'), Ok((b"A".to_vec(), 10))),
+                (Some(b'}'), Err(SkipReason::Missing)),
+                (Some(b'}'), Ok((b"B".to_vec(), 20))),
+            ]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Owned station names that keep short names inline.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+
+/// Longest name kept inline; with the length and the variant tag, an inline
+/// key takes 32 bytes, half a cache line.
+const INLINE: usize = 30;
+
+/// An owned station name, used as the key of the owned maps and sets.
+///
+/// Almost every station name fits in [`INLINE`] bytes, so copying a name
+/// out of the input allocates nothing and the bytes sit next to the rest of
+/// the key in the map instead of behind a pointer; longer names spill to
+/// the heap. It hashes and compares like the `[u8]` it derefs to, so maps
+/// keyed by it are looked up with plain byte slices.
+#[derive(Clone)]
+pub(crate) enum StationKey {
+    Inline { len: u8, bytes: [u8; INLINE] },
+    Heap(Box<[u8]>),
+}
+
+impl StationKey {
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        match self {
+            StationKey::Inline { len, bytes } => &bytes[..*len as usize],
+            StationKey::Heap(bytes) => bytes,
+        }
+    }
+
+    /// Bytes the name takes on the heap: none if it is stored inline.
+    pub(crate) fn heap_bytes(&self) -> usize {
+        match self {
+            StationKey::Inline { .. } => 0,
+            StationKey::Heap(bytes) => bytes.len(),
+        }
+    }
+}
+
+impl From<&[u8]> for StationKey {
+    #[inline]
+    fn from(name: &[u8]) -> Self {
+        if name.len() > INLINE {
+            return StationKey::Heap(name.into());
+        }
+        let mut bytes = [0; INLINE];
+        bytes[..name.len()].copy_from_slice(name);
+        StationKey::Inline {
+            len: name.len() as u8,
+            bytes,
+        }
+    }
+}
+
+impl From<Vec<u8>> for StationKey {
+    fn from(name: Vec<u8>) -> Self {
+        if name.len() > INLINE {
+            StationKey::Heap(name.into_boxed_slice())
+        } else {
+            StationKey::from(name.as_slice())
+        }
+    }
+}
+
+impl Deref for StationKey {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl Borrow<[u8]> for StationKey {
+    fn borrow(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl PartialEq for StationKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for StationKey {}
+
+impl Hash for StationKey {
+    // Must hash like `[u8]` for lookups through `Borrow`
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state);
+    }
+}
+
+impl fmt::Debug for StationKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(self))
+    }
+}
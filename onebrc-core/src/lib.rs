@@ -0,0 +1,105 @@
+//! Aggregation engine for the one billion rows challenge.
+//!
+//! Input is a sequence of `station;temperature` lines. The engine keeps
+//! min/mean/max per station and can be driven either line by line through an
+//! [`Aggregator`] or over a whole file with [`aggregate_file`], which splits
+//! the work across threads.
+//!
+//! ```no_run
+//! use onebrc_core::{aggregate_file, Options};
+//!
+//! let results = aggregate_file("measurements.txt", &Options::default())?
+//!     .into_results();
+//! println!("{results}");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+mod affinity;
+mod aggregator;
+mod aliases;
+mod batching;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod chunk;
+#[cfg(feature = "checkpoint")]
+mod checkpoint;
+#[cfg(feature = "cloud")]
+mod cloud;
+mod columnar;
+mod compression;
+#[cfg(feature = "polars")]
+mod dataframe;
+mod digest;
+#[cfg(target_os = "linux")]
+mod direct;
+mod filter;
+mod follow;
+mod histogram;
+#[cfg(feature = "http")]
+mod http;
+mod json;
+mod key;
+mod live;
+pub mod output;
+mod parse;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod partition;
+mod perfect;
+mod pipeline;
+mod pool;
+mod profile;
+mod progress;
+mod quoted;
+#[cfg(any(feature = "cloud", feature = "http"))]
+mod ranged;
+#[cfg(feature = "remote")]
+mod remote;
+mod results;
+mod ring;
+mod sample;
+mod simd;
+mod skipped;
+mod sniff;
+pub mod spec;
+#[cfg(feature = "spill")]
+mod spill;
+mod staged;
+mod stats;
+mod streaming;
+mod table;
+#[cfg(test)]
+mod testing;
+mod trace;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring;
+pub mod validate;
+
+pub use aggregator::{Aggregator, BorrowedAggregator};
+pub use aliases::Aliases;
+pub use chunk::{chunk_ranges, map_file, slice_chunks};
+#[cfg(feature = "checkpoint")]
+pub use checkpoint::{CheckpointOptions, aggregate_with_checkpoints};
+pub use columnar::{aggregate_parquet, is_parquet};
+pub use compression::{Compression, is_stream, open_decoder, open_input, open_stream};
+pub use digest::{Percentile, TDigest};
+pub use filter::StationFilter;
+pub use follow::follow_file;
+pub use histogram::{HISTOGRAM_BUCKET_WIDTH, HISTOGRAM_BUCKETS, HISTOGRAM_MIN, Histogram};
+pub use parse::{Columns, Header, InputFormat, ParseOptions, parse_line, parse_temp};
+pub use partition::{ROOT_PARTITION, confine, confined_files, discover, partition_of};
+pub use live::LiveResults;
+pub use perfect::StationIndex;
+pub use pipeline::{
+    Engine, IoBackend, Mode, Options, QUEUE_DEPTH_PER_WORKER, STREAM_BLOCK_SIZE, aggregate_file,
+    aggregate_files, aggregate_reader, is_url,
+};
+pub use profile::{LONG_LINE, LineLengths};
+pub use progress::{Progress, Stage};
+#[cfg(feature = "remote")]
+pub use remote::{aggregate_remote, serve_worker};
+pub use results::{Rank, Results, SortKey};
+pub use skipped::{MalformedLine, SkipReason, SkippedLines};
+pub use sniff::{sniff_file, sniff_sample};
+pub use stats::{StatsOptions, TempStats};
+pub use streaming::ChunkAggregator;
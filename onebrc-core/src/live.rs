@@ -0,0 +1,87 @@
+//! Results of a run that is still going, for dashboards and the like.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::aggregator::Aggregator;
+use crate::results::Results;
+
+/// The stations a run has aggregated so far, as its workers last
+/// published them. Set [`Options::live`] to one and read
+/// [`LiveResults::results`] from any thread while the run goes.
+///
+/// Workers of [`Mode::Batched`], [`Mode::Pipelined`] and [`Mode::Chunked`],
+/// which also read streams, compressed files and URLs, and the workers that
+/// take whole files in [`aggregate_files`] publish a copy of their stations
+/// every `every` and once more when they are done, so the results are at
+/// most about `every` behind and complete once the run is. Other modes and
+/// engines do not publish. Stations spilled with
+/// [`Options::max_memory`] only show up in the final results.
+///
+/// [`aggregate_files`]: crate::aggregate_files
+/// [`Options::live`]: crate::Options::live
+/// [`Options::max_memory`]: crate::Options::max_memory
+/// [`Mode::Batched`]: crate::Mode::Batched
+/// [`Mode::Pipelined`]: crate::Mode::Pipelined
+/// [`Mode::Chunked`]: crate::Mode::Chunked
+#[derive(Debug)]
+pub struct LiveResults {
+    every: Duration,
+    /// What every worker of the run had when it last published, each
+    /// worker of every file in a slot of its own.
+    workers: Mutex<Vec<Results>>,
+}
+
+impl LiveResults {
+    pub fn new(every: Duration) -> Self {
+        LiveResults {
+            every,
+            workers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Everything published so far, merged.
+    pub fn results(&self) -> Results {
+        let workers = self.workers.lock().expect("live results lock");
+        workers
+            .iter()
+            .flat_map(|results| results.iter())
+            .map(|(city, stats)| (city.to_owned(), stats.clone()))
+            .collect()
+    }
+
+    /// A slot for one more worker.
+    pub(crate) fn worker(&self) -> LiveWorker<'_> {
+        let mut workers = self.workers.lock().expect("live results lock");
+        workers.push(Results::default());
+        LiveWorker {
+            live: self,
+            slot: workers.len() - 1,
+            last: Instant::now(),
+        }
+    }
+}
+
+/// One worker's slot in [`LiveResults`].
+pub(crate) struct LiveWorker<'a> {
+    live: &'a LiveResults,
+    slot: usize,
+    last: Instant,
+}
+
+impl LiveWorker<'_> {
+    /// Publishes the stations of `aggregator` if the last time was long
+    /// enough ago.
+    pub(crate) fn offer(&mut self, aggregator: &Aggregator) {
+        if self.last.elapsed() >= self.live.every {
+            self.publish(aggregator);
+        }
+    }
+
+    pub(crate) fn publish(&mut self, aggregator: &Aggregator) {
+        // Copied before taking the lock, which readers hold while merging
+        let results = aggregator.to_results();
+        self.live.workers.lock().expect("live results lock")[self.slot] = results;
+        self.last = Instant::now();
+    }
+}
@@ -0,0 +1,805 @@
+//! Rendering [`Results`] in the supported output formats.
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::digest::Percentile;
+use crate::histogram::{HISTOGRAM_BUCKET_WIDTH, HISTOGRAM_MIN, Histogram};
+use crate::results::{Rank, Results, SortKey};
+use crate::stats::TempStats;
+
+mod geojson;
+mod metadata;
+mod template;
+
+pub use metadata::Metadata;
+pub use template::Template;
+
+/// Output format for [`write_results`]. With the `variance` feature the JSON,
+/// CSV and table formats gain a `stddev` column.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Format {
+    /// The challenge format: `{Abha=-23.0/18.0/59.2, ...}`
+    #[default]
+    Brc,
+    /// `{"stations": [{"station": ..., "min": ..., "mean": ..., "max": ..., "count": ...}]}`
+    Json,
+    /// `station,min,mean,max,count` with a header row
+    Csv,
+    /// Aligned columns for reading in a terminal
+    Table,
+    /// A GeoJSON `FeatureCollection` with a point per station, placed by the
+    /// latitude and longitude columns of the station metadata
+    Geojson,
+    /// An Arrow IPC file with the CSV columns, unrounded
+    #[cfg(feature = "arrow")]
+    Arrow,
+}
+
+/// Temperature scale the results are written in. Aggregation always runs in
+/// Celsius; the conversion happens only when formatting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Unit {
+    #[default]
+    #[cfg_attr(feature = "clap", value(name = "c", alias = "celsius"))]
+    Celsius,
+    #[cfg_attr(feature = "clap", value(name = "f", alias = "fahrenheit"))]
+    Fahrenheit,
+    #[cfg_attr(feature = "clap", value(name = "k", alias = "kelvin"))]
+    Kelvin,
+}
+
+impl Unit {
+    /// Converts a temperature from Celsius.
+    pub fn convert(self, celsius: f64) -> f64 {
+        match self {
+            Unit::Celsius => celsius,
+            Unit::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+            Unit::Kelvin => celsius + 273.15,
+        }
+    }
+
+    /// Converts a temperature difference, such as a standard deviation or a
+    /// bucket width, from Celsius degrees.
+    pub fn scale(self, degrees: f64) -> f64 {
+        match self {
+            Unit::Celsius | Unit::Kelvin => degrees,
+            Unit::Fahrenheit => degrees * 9.0 / 5.0,
+        }
+    }
+}
+
+/// How values are rounded to [`OutputOptions::precision`] decimals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Rounding {
+    /// Halves toward positive infinity, like the challenge's reference
+    /// implementation (`Math.round(value * 10.0) / 10.0`)
+    #[default]
+    HalfUp,
+    /// Halves to the even neighbour
+    HalfEven,
+    /// Always toward positive infinity
+    Ceil,
+    /// Always toward zero
+    Trunc,
+}
+
+impl Rounding {
+    /// Rounds `value` to `decimals` decimal places. Plain `{:.1}` would round
+    /// the binary value instead, which is seldom exactly the decimal it
+    /// prints as: the mean 4.35 is stored as 4.3499999…, so anything within
+    /// representation error of a rounding boundary counts as on it. The
+    /// result is never `-0.0`. A value too large to scale to `decimals`
+    /// places, past some 300 of them, is given back as it is.
+    pub fn round(self, value: f64, decimals: usize) -> f64 {
+        let factor = 10f64.powi(i32::try_from(decimals).unwrap_or(i32::MAX));
+        let scaled = value * factor;
+        if !scaled.is_finite() {
+            return value + 0.0;
+        }
+        // A few units in the last place of `scaled`, but no less than 1e-9
+        let tolerance = (scaled.abs() * 8.0 * f64::EPSILON).max(1e-9);
+        let nearest = scaled.round();
+        let floor = scaled.floor();
+        let on_half = (scaled - floor - 0.5).abs() <= tolerance;
+        let rounded = match self {
+            Rounding::Ceil | Rounding::Trunc if (scaled - nearest).abs() <= tolerance => nearest,
+            Rounding::Ceil => scaled.ceil(),
+            Rounding::Trunc => scaled.trunc(),
+            Rounding::HalfUp if on_half => floor + 1.0,
+            Rounding::HalfEven if on_half && floor.rem_euclid(2.0) == 0.0 => floor,
+            Rounding::HalfEven if on_half => floor + 1.0,
+            Rounding::HalfUp | Rounding::HalfEven => nearest,
+        };
+        // `+ 0.0` turns a `-0.0` left by rounding (-0.5, 0) into `0.0`
+        rounded / factor + 0.0
+    }
+}
+
+/// Settings for [`write_results`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputOptions {
+    pub format: Format,
+    /// Extra percentile columns for the JSON, CSV and table formats. Stations
+    /// aggregated without [`StatsOptions::percentiles`](crate::StatsOptions)
+    /// show them as empty.
+    pub percentiles: Vec<Percentile>,
+    /// Only write the best-ranked stations, in rank order, instead of all of
+    /// them alphabetically.
+    pub top: Option<Top>,
+    /// Writes the stations, or the [`OutputOptions::top`] ones, in this
+    /// order instead.
+    pub sort: Option<Sort>,
+    /// Leaves out stations, and groups, with fewer readings than this,
+    /// whose statistics say more about chance than about the station.
+    pub min_count: Option<u64>,
+    pub unit: Unit,
+    /// Decimal places of every temperature written; 1 in the challenge.
+    pub precision: usize,
+    pub rounding: Rounding,
+    /// Writes every station as a line of this layout instead of in
+    /// [`OutputOptions::format`].
+    pub template: Option<Template>,
+    /// Columns joined onto every station by name in the JSON, CSV and Arrow
+    /// formats; the coordinates of the GeoJSON one.
+    pub metadata: Option<Arc<Metadata>>,
+    /// Also write aggregates per value of this [`OutputOptions::metadata`]
+    /// column, after the stations: their statistics merged once more, so
+    /// a group costs no more than the stations in it. Stations without a
+    /// value are in no group.
+    pub group_by: Option<String>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            format: Format::default(),
+            percentiles: Vec::new(),
+            top: None,
+            sort: None,
+            min_count: None,
+            unit: Unit::default(),
+            precision: 1,
+            rounding: Rounding::default(),
+            template: None,
+            metadata: None,
+            group_by: None,
+        }
+    }
+}
+
+/// Selects the `k` best stations by `by`; see [`Results::top`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Top {
+    pub k: usize,
+    pub by: Rank,
+}
+
+/// Orders the stations by `by`, highest first if `descending`; see
+/// [`SortKey::sort`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sort {
+    pub by: SortKey,
+    pub descending: bool,
+}
+
+/// A station and its statistics, in the order they are written.
+type Row<'a> = (&'a str, &'a TempStats);
+
+/// Writes `results` to `out` as `options` asks, followed by a newline, or
+/// with an [`OutputOptions::template`] as one line per station. With
+/// [`OutputOptions::group_by`], the challenge format writes the groups on a
+/// line of their own, JSON beside the stations as `groups`, CSV as rows
+/// with an empty station and the table format as a second table; Arrow
+/// and GeoJSON have no groups.
+pub fn write_results(
+    out: &mut impl Write,
+    results: &Results,
+    options: &OutputOptions,
+) -> io::Result<()> {
+    let rows = options.rows(results);
+    if let Some(template) = &options.template {
+        return template.write_rows(out, &rows, options, None);
+    }
+    match options.format {
+        Format::Brc => write_brc(out, results, options),
+        Format::Json => write_json(out, results, options),
+        Format::Csv => {
+            write_csv_header(out, options, false)?;
+            write_csv_rows(out, results, options, None)
+        }
+        Format::Table => write_table(out, results, options),
+        Format::Geojson => {
+            let rows: Vec<_> = rows.into_iter().map(|row| (None, row)).collect();
+            geojson::write_geojson(out, &rows, options)
+        }
+        #[cfg(feature = "arrow")]
+        Format::Arrow => {
+            let rows: Vec<_> = rows.into_iter().map(|row| (None, row)).collect();
+            crate::arrow::write_arrow(out, &rows, options)
+        }
+    }
+}
+
+/// Label of the rollup over all partitions in [`write_partitioned`]. Real
+/// partition labels always contain `=` (or are `.`), so it cannot clash.
+pub const TOTAL_LABEL: &str = "total";
+
+/// Writes one set of results per partition followed by their rollup. The
+/// CSV and Arrow formats gain a leading `partition` column and GeoJSON a
+/// `partition` property; JSON nests every set under `partitions` with the
+/// rollup under `total`; the other formats write a labelled block per
+/// partition. A template's lines follow one another, with the label as
+/// their `{partition}`.
+pub fn write_partitioned(
+    out: &mut impl Write,
+    partitions: &[(String, Results)],
+    total: &Results,
+    options: &OutputOptions,
+) -> io::Result<()> {
+    let labelled = partitions
+        .iter()
+        .map(|(label, results)| (label.as_str(), results))
+        .chain([(TOTAL_LABEL, total)]);
+    if let Some(template) = &options.template {
+        for (label, results) in labelled {
+            template.write_rows(out, &options.rows(results), options, Some(label))?;
+        }
+        return Ok(());
+    }
+    match options.format {
+        Format::Brc => {
+            for (label, results) in labelled {
+                write!(out, "{label}: ")?;
+                write_brc(out, results, options)?;
+            }
+            Ok(())
+        }
+        Format::Json => {
+            writeln!(out, "{{\"partitions\": [")?;
+            for (i, (label, results)) in partitions.iter().enumerate() {
+                write!(out, "{{\"partition\": {}, ", json_string(label))?;
+                write_json_stations(out, results, options)?;
+                let separator = if i + 1 < partitions.len() { "," } else { "" };
+                writeln!(out, "}}{separator}")?;
+            }
+            write!(out, "], \"total\": {{")?;
+            write_json_stations(out, total, options)?;
+            writeln!(out, "}}}}")
+        }
+        Format::Csv => {
+            write_csv_header(out, options, true)?;
+            for (label, results) in labelled {
+                write_csv_rows(out, results, options, Some(label))?;
+            }
+            Ok(())
+        }
+        Format::Table => {
+            for (i, (label, results)) in labelled.enumerate() {
+                if i > 0 {
+                    writeln!(out)?;
+                }
+                writeln!(out, "[{label}]")?;
+                write_table(out, results, options)?;
+            }
+            Ok(())
+        }
+        Format::Geojson => {
+            let rows: Vec<_> = labelled
+                .flat_map(|(label, results)| {
+                    let rows = options.rows(results);
+                    rows.into_iter().map(move |row| (Some(label), row))
+                })
+                .collect();
+            geojson::write_geojson(out, &rows, options)
+        }
+        #[cfg(feature = "arrow")]
+        Format::Arrow => {
+            let rows: Vec<_> = labelled
+                .flat_map(|(label, results)| {
+                    let rows = options.rows(results);
+                    rows.into_iter().map(move |row| (Some(label), row))
+                })
+                .collect();
+            crate::arrow::write_arrow(out, &rows, options)
+        }
+    }
+}
+
+impl OutputOptions {
+    /// The rows of `results` to write, in order.
+    fn rows<'a>(&self, results: &'a Results) -> Vec<Row<'a>> {
+        let mut rows: Vec<_> = results
+            .iter()
+            .filter(|(_, stats)| self.min_count.is_none_or(|min| stats.count() >= min))
+            .collect();
+        if let Some(Top { k, by }) = self.top {
+            by.rank(&mut rows, k);
+        }
+        if let Some(Sort { by, descending }) = self.sort {
+            by.sort(&mut rows, descending);
+        }
+        rows
+    }
+
+    /// The [`OutputOptions::group_by`] column and the groups of `results`
+    /// by it, if there are any.
+    fn groups(&self, results: &Results) -> Option<(&str, Results)> {
+        let column = self.group_by.as_deref()?;
+        Some((column, self.metadata.as_ref()?.group_by(column, results)?))
+    }
+
+    /// Formats a temperature given in Celsius.
+    fn temp(&self, celsius: f64) -> String {
+        self.number(self.unit.convert(celsius))
+    }
+
+    /// Formats a temperature difference given in Celsius degrees.
+    fn spread(&self, degrees: f64) -> String {
+        self.number(self.unit.scale(degrees))
+    }
+
+    fn number(&self, value: f64) -> String {
+        self.number_to(value, self.precision)
+    }
+
+    fn number_to(&self, value: f64, precision: usize) -> String {
+        format!("{:.precision$}", self.rounding.round(value, precision))
+    }
+}
+
+/// `results` in the challenge format, and then their groups as
+/// `country: {DE=..., FR=...}`.
+fn write_brc(out: &mut impl Write, results: &Results, options: &OutputOptions) -> io::Result<()> {
+    write_brc_rows(out, &options.rows(results), options)?;
+    if let Some((column, groups)) = options.groups(results) {
+        write!(out, "{column}: ")?;
+        write_brc_rows(out, &options.rows(&groups), options)?;
+    }
+    Ok(())
+}
+
+/// Same layout as the `Display` impl of [`Results`], for any row order.
+fn write_brc_rows(out: &mut impl Write, rows: &[Row], options: &OutputOptions) -> io::Result<()> {
+    write!(out, "{{")?;
+    for (i, (station, stats)) in rows.iter().enumerate() {
+        if i > 0 {
+            write!(out, ", ")?;
+        }
+        write!(
+            out,
+            "{}={}/{}/{}",
+            station,
+            options.temp(stats.min()),
+            options.temp(stats.mean()),
+            options.temp(stats.max())
+        )?;
+    }
+    writeln!(out, "}}")
+}
+
+fn write_json(out: &mut impl Write, results: &Results, options: &OutputOptions) -> io::Result<()> {
+    write!(out, "{{")?;
+    write_json_stations(out, results, options)?;
+    writeln!(out, "}}")
+}
+
+/// Writes the `"stations": [...]` member of a JSON object, and with
+/// [`OutputOptions::group_by`] a `"groups": [...]` one after it.
+fn write_json_stations(
+    out: &mut impl Write,
+    results: &Results,
+    options: &OutputOptions,
+) -> io::Result<()> {
+    writeln!(out, "\"stations\": [")?;
+    let metadata = options.metadata.as_deref();
+    write_json_rows(out, "station", &options.rows(results), metadata, options)?;
+    write!(out, "]")?;
+    if let Some((column, groups)) = options.groups(results) {
+        writeln!(out, ", \"groups\": [")?;
+        write_json_rows(out, column, &options.rows(&groups), None, options)?;
+        write!(out, "]")?;
+    }
+    Ok(())
+}
+
+/// Writes one JSON object per row, named by its `key` member and followed
+/// by the `metadata` columns if there are any.
+fn write_json_rows(
+    out: &mut impl Write,
+    key: &str,
+    rows: &[Row],
+    metadata: Option<&Metadata>,
+    options: &OutputOptions,
+) -> io::Result<()> {
+    for (i, (name, stats)) in rows.iter().enumerate() {
+        write!(out, "  {{{}: {}", json_string(key), json_string(name))?;
+        if let Some(metadata) = metadata {
+            for (column, value) in metadata.columns().iter().zip(metadata.values(name)) {
+                let value = metadata::json_value(value);
+                write!(out, ", {}: {value}", json_string(column))?;
+            }
+        }
+        write!(
+            out,
+            ", \"min\": {}, \"mean\": {}, \"max\": {}, \"count\": {}",
+            options.temp(stats.min()),
+            options.temp(stats.mean()),
+            options.temp(stats.max()),
+            stats.count()
+        )?;
+        #[cfg(feature = "variance")]
+        write!(out, ", \"stddev\": {}", options.spread(stats.std_dev()))?;
+        for &percentile in &options.percentiles {
+            match stats
+                .quantile(percentile.fraction())
+                .map(|q| options.temp(q))
+            {
+                Some(value) => write!(out, ", \"{percentile}\": {value}")?,
+                None => write!(out, ", \"{percentile}\": null")?,
+            }
+        }
+        if let Some(histogram) = stats.histogram() {
+            write!(
+                out,
+                ", \"histogram\": {}",
+                json_histogram(histogram, options)
+            )?;
+        }
+        let separator = if i + 1 < rows.len() { "," } else { "" };
+        writeln!(out, "}}{separator}")?;
+    }
+    Ok(())
+}
+
+/// The bucket bounds are converted to the output unit; the counts stay per
+/// 0.5 degree Celsius bucket.
+fn json_histogram(histogram: &Histogram, options: &OutputOptions) -> String {
+    let counts: Vec<String> = histogram.counts().iter().map(u64::to_string).collect();
+    format!(
+        "{{\"min\": {}, \"width\": {}, \"underflow\": {}, \"overflow\": {}, \"counts\": [{}]}}",
+        options.temp(HISTOGRAM_MIN as f64 / 10.0),
+        options.spread(HISTOGRAM_BUCKET_WIDTH as f64 / 10.0),
+        histogram.underflow(),
+        histogram.overflow(),
+        counts.join(", ")
+    )
+}
+
+/// Quotes `s` as a JSON string literal.
+pub fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn write_csv_header(
+    out: &mut impl Write,
+    options: &OutputOptions,
+    partition_column: bool,
+) -> io::Result<()> {
+    if partition_column {
+        write!(out, "partition,")?;
+    }
+    write!(out, "station")?;
+    if let Some(metadata) = &options.metadata {
+        for column in metadata.columns() {
+            write!(out, ",{}", csv_field(column))?;
+        }
+    }
+    write!(out, ",min,mean,max,count")?;
+    #[cfg(feature = "variance")]
+    write!(out, ",stddev")?;
+    for percentile in &options.percentiles {
+        write!(out, ",{percentile}")?;
+    }
+    writeln!(out)
+}
+
+/// Writes the stations of `results`, then with [`OutputOptions::group_by`]
+/// their groups, as rows whose station is empty and whose metadata is only
+/// the group's value.
+fn write_csv_rows(
+    out: &mut impl Write,
+    results: &Results,
+    options: &OutputOptions,
+    partition: Option<&str>,
+) -> io::Result<()> {
+    for row in options.rows(results) {
+        write_csv_row(out, row, None, options, partition)?;
+    }
+    if let Some((column, groups)) = options.groups(results) {
+        for row in options.rows(&groups) {
+            write_csv_row(out, row, Some(column), options, partition)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a station's row, or a group's if `group` names its column.
+fn write_csv_row(
+    out: &mut impl Write,
+    (name, stats): Row,
+    group: Option<&str>,
+    options: &OutputOptions,
+    partition: Option<&str>,
+) -> io::Result<()> {
+    if let Some(partition) = partition {
+        write!(out, "{},", csv_field(partition))?;
+    }
+    let station = if group.is_some() { "" } else { name };
+    write!(out, "{}", csv_field(station))?;
+    if let Some(metadata) = &options.metadata {
+        match group {
+            Some(group) => {
+                for column in metadata.columns() {
+                    let value = if column == group { name } else { "" };
+                    write!(out, ",{}", csv_field(value))?;
+                }
+            }
+            None => {
+                for value in metadata.values(name) {
+                    write!(out, ",{}", csv_field(value))?;
+                }
+            }
+        }
+    }
+    write!(
+        out,
+        ",{},{},{},{}",
+        options.temp(stats.min()),
+        options.temp(stats.mean()),
+        options.temp(stats.max()),
+        stats.count()
+    )?;
+    #[cfg(feature = "variance")]
+    write!(out, ",{}", options.spread(stats.std_dev()))?;
+    for &percentile in &options.percentiles {
+        write!(out, ",")?;
+        if let Some(value) = stats
+            .quantile(percentile.fraction())
+            .map(|q| options.temp(q))
+        {
+            write!(out, "{value}")?;
+        }
+    }
+    writeln!(out)
+}
+
+/// Quotes a field per RFC 4180 when it contains a comma, quote or newline.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// The stations of `results` as a table, and then their groups as another
+/// one headed by the group column.
+fn write_table(out: &mut impl Write, results: &Results, options: &OutputOptions) -> io::Result<()> {
+    write_table_rows(out, "station", &options.rows(results), options)?;
+    if let Some((column, groups)) = options.groups(results) {
+        writeln!(out)?;
+        write_table_rows(out, column, &options.rows(&groups), options)?;
+    }
+    Ok(())
+}
+
+/// Aligned columns of `rows`, the first headed `key`.
+fn write_table_rows(
+    out: &mut impl Write,
+    key: &str,
+    rows: &[Row],
+    options: &OutputOptions,
+) -> io::Result<()> {
+    let percentiles = &options.percentiles;
+    // Value columns fit `-100.0` at one decimal; widen them for more
+    let column = 6 + options.precision.saturating_sub(1);
+    let width = rows
+        .iter()
+        .map(|(station, _)| station.chars().count())
+        .chain([key.chars().count()])
+        .max()
+        .unwrap_or(0);
+
+    let extra_columns = percentiles.len() + usize::from(cfg!(feature = "variance"));
+    let rule = width + 16 + (2 + column) * (3 + extra_columns);
+    write!(
+        out,
+        "{:<width$}  {:>column$}  {:>column$}  {:>column$}  {:>12}",
+        key, "min", "mean", "max", "count"
+    )?;
+    #[cfg(feature = "variance")]
+    write!(out, "  {:>column$}", "stddev")?;
+    for percentile in percentiles {
+        write!(out, "  {:>column$}", percentile.to_string())?;
+    }
+    writeln!(out)?;
+    writeln!(out, "{}", "-".repeat(rule))?;
+    for (station, stats) in rows {
+        write!(
+            out,
+            "{:<width$}  {:>column$}  {:>column$}  {:>column$}  {:>12}",
+            station,
+            options.temp(stats.min()),
+            options.temp(stats.mean()),
+            options.temp(stats.max()),
+            stats.count()
+        )?;
+        #[cfg(feature = "variance")]
+        write!(out, "  {:>column$}", options.spread(stats.std_dev()))?;
+        for &percentile in percentiles {
+            match stats
+                .quantile(percentile.fraction())
+                .map(|q| options.temp(q))
+            {
+                Some(value) => write!(out, "  {value:>column$}")?,
+                None => write!(out, "  {:>column$}", "-")?,
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::pipeline::Options;
+
+    fn half_up(value: f64) -> f64 {
+        Rounding::HalfUp.round(value, 1)
+    }
+
+    /// `value` and its sign, so that `0.0` and `-0.0` compare unequal.
+    fn signed(value: f64) -> (f64, bool) {
+        (value, value.is_sign_negative())
+    }
+
+    #[test]
+    fn half_up_rounds_halves_toward_positive_infinity_like_math_round() {
+        assert_eq!(half_up(-0.75), -0.7);
+        assert_eq!(half_up(4.35), 4.4);
+        assert_eq!(half_up(-4.35), -4.3);
+        assert_eq!(half_up(0.05), 0.1);
+        assert_eq!(half_up(-1.25), -1.2);
+        assert_eq!(half_up(-10.05), -10.0);
+        assert_eq!(half_up(-99.95), -99.9);
+        assert_eq!(Rounding::HalfUp.round(-2.5, 0), -2.0);
+        assert_eq!(Rounding::HalfUp.round(2.5, 0), 3.0);
+    }
+
+    #[test]
+    fn rounding_never_gives_negative_zero() {
+        for rounding in [
+            Rounding::HalfUp,
+            Rounding::HalfEven,
+            Rounding::Ceil,
+            Rounding::Trunc,
+        ] {
+            for value in [-0.05, -0.04, -0.0, -0.01] {
+                assert_eq!(
+                    signed(rounding.round(value, 1)),
+                    (0.0, false),
+                    "{rounding:?} of {value}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn means_within_representation_error_of_a_half_count_as_on_it() {
+        // Means as the stats compute them: integer tenths over a count
+        let mean = |sum: i64, count: u64| sum as f64 / count as f64 / 10.0;
+        assert_eq!(half_up(mean(87, 2)), 4.4);
+        assert_eq!(half_up(mean(-15, 2)), -0.7);
+        assert_eq!(half_up(mean(-1, 2)), 0.0);
+        assert_eq!(half_up(mean(3, 6)), 0.1);
+        // 1.005 is stored a little below the decimal it prints as, so that
+        // `1.005 * 100.0` is 100.49999999999999
+        assert_eq!(Rounding::HalfUp.round(1.005, 2), 1.01);
+        assert_eq!(Rounding::HalfUp.round(-1.005, 2), -1.0);
+        assert_eq!(Rounding::HalfEven.round(1.015, 2), 1.02);
+    }
+
+    #[test]
+    fn values_beyond_the_tolerance_round_by_their_value() {
+        assert_eq!(half_up(4.35 - 1e-6), 4.3);
+        assert_eq!(half_up(4.35 + 1e-6), 4.4);
+        assert_eq!(half_up(-0.75 - 1e-6), -0.8);
+        assert_eq!(Rounding::Ceil.round(4.3 + 1e-6, 1), 4.4);
+        assert_eq!(Rounding::Ceil.round(4.3 + 1e-12, 1), 4.3);
+        assert_eq!(Rounding::Trunc.round(4.3 - 1e-12, 1), 4.3);
+        assert_eq!(Rounding::Trunc.round(4.3 - 1e-6, 1), 4.2);
+    }
+
+    #[test]
+    fn large_values_round_by_their_value() {
+        // Readings at the `i32` limits of tenths
+        assert_eq!(half_up(214748364.7), 214748364.7);
+        assert_eq!(half_up(-214748364.8), -214748364.8);
+        assert_eq!(Rounding::Ceil.round(214748364.7, 1), 214748364.7);
+        assert_eq!(Rounding::HalfUp.round(2147483647.5, 0), 2147483648.0);
+        assert_eq!(Rounding::HalfUp.round(2147483647.25, 0), 2147483647.0);
+    }
+
+    #[test]
+    fn more_decimals_than_an_f64_holds_leave_the_value() {
+        for decimals in [308, 309, 400, usize::MAX] {
+            let rounded = Rounding::HalfUp.round(4.35, decimals);
+            assert_eq!(rounded, 4.35, "{decimals}");
+        }
+        assert_eq!(signed(Rounding::Trunc.round(-0.0, 400)), (0.0, false));
+    }
+
+    #[test]
+    fn half_even_rounds_halves_to_the_even_neighbour() {
+        let half_even = |value| Rounding::HalfEven.round(value, 1);
+        assert_eq!(half_even(0.25), 0.2);
+        assert_eq!(half_even(0.35), 0.4);
+        assert_eq!(half_even(-0.25), -0.2);
+        assert_eq!(half_even(-0.35), -0.4);
+        assert_eq!(half_even(4.36), 4.4);
+    }
+
+    #[test]
+    fn ceil_and_trunc_round_in_one_direction() {
+        assert_eq!(Rounding::Ceil.round(4.31, 1), 4.4);
+        assert_eq!(Rounding::Ceil.round(-4.39, 1), -4.3);
+        assert_eq!(Rounding::Trunc.round(4.39, 1), 4.3);
+        assert_eq!(Rounding::Trunc.round(-4.39, 1), -4.3);
+    }
+
+    /// Every `samples/*.txt` aggregates to the `.out` beside it, written by
+    /// the challenge's reference implementation.
+    #[test]
+    fn results_match_the_reference_outputs() {
+        let samples = Path::new(env!("CARGO_MANIFEST_DIR")).join("../samples");
+        let mut checked = 0;
+        for entry in fs::read_dir(&samples).unwrap() {
+            let input = entry.unwrap().path();
+            if input.extension().is_none_or(|ext| ext != "txt") {
+                continue;
+            }
+            let mut aggregator = Options::default().aggregator();
+            aggregator
+                .process_chunk(&fs::read(&input).unwrap())
+                .unwrap();
+            let mut out = Vec::new();
+            write_results(
+                &mut out,
+                &aggregator.into_results(),
+                &OutputOptions::default(),
+            )
+            .unwrap();
+            let expected = fs::read_to_string(input.with_extension("out")).unwrap();
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                expected,
+                "{}",
+                input.display()
+            );
+            checked += 1;
+        }
+        assert!(checked > 0, "no samples in {}", samples.display());
+    }
+}
@@ -0,0 +1,95 @@
+//! [`Format::Geojson`](super::Format::Geojson): the results as points on a
+//! map.
+
+use std::io::{self, Write};
+
+use super::metadata::{Metadata, is_json_number, json_value};
+use super::{OutputOptions, Row, json_string};
+
+/// Column names taken for a station's latitude and longitude, in any case.
+const LATITUDE: [&str; 2] = ["lat", "latitude"];
+const LONGITUDE: [&str; 4] = ["lon", "lng", "long", "longitude"];
+
+/// Writes `rows` as a GeoJSON `FeatureCollection`, one `Point` feature per
+/// station with its statistics, partition and other metadata columns as
+/// properties. Stations without coordinates get a `null` geometry, which
+/// map viewers leave out.
+pub(super) fn write_geojson(
+    out: &mut impl Write,
+    rows: &[(Option<&str>, Row)],
+    options: &OutputOptions,
+) -> io::Result<()> {
+    let Some((metadata, lat, lon)) = options.metadata.as_deref().and_then(|metadata| {
+        let lat = metadata.column_named(&LATITUDE)?;
+        let lon = metadata.column_named(&LONGITUDE)?;
+        Some((metadata, lat, lon))
+    }) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "GeoJSON output needs metadata with latitude and longitude columns",
+        ));
+    };
+    writeln!(out, "{{\"type\": \"FeatureCollection\", \"features\": [")?;
+    for (i, (partition, (station, stats))) in rows.iter().enumerate() {
+        let values = metadata.get(station);
+        let coordinate = |column: usize| {
+            values
+                .map(|values| values[column].trim())
+                .filter(|value| is_json_number(value))
+        };
+        let geometry = match (coordinate(lon), coordinate(lat)) {
+            (Some(lon), Some(lat)) => {
+                format!("{{\"type\": \"Point\", \"coordinates\": [{lon}, {lat}]}}")
+            }
+            _ => "null".to_owned(),
+        };
+        write!(
+            out,
+            "  {{\"type\": \"Feature\", \"geometry\": {geometry}, \"properties\": {{\"station\": {}",
+            json_string(station)
+        )?;
+        if let Some(partition) = partition {
+            write!(out, ", \"partition\": {}", json_string(partition))?;
+        }
+        write_other_columns(out, metadata, values, [lat, lon])?;
+        write!(
+            out,
+            ", \"min\": {}, \"mean\": {}, \"max\": {}, \"count\": {}",
+            options.temp(stats.min()),
+            options.temp(stats.mean()),
+            options.temp(stats.max()),
+            stats.count()
+        )?;
+        #[cfg(feature = "variance")]
+        write!(out, ", \"stddev\": {}", options.spread(stats.std_dev()))?;
+        for &percentile in &options.percentiles {
+            match stats
+                .quantile(percentile.fraction())
+                .map(|q| options.temp(q))
+            {
+                Some(value) => write!(out, ", \"{percentile}\": {value}")?,
+                None => write!(out, ", \"{percentile}\": null")?,
+            }
+        }
+        let separator = if i + 1 < rows.len() { "," } else { "" };
+        writeln!(out, "}}}}{separator}")?;
+    }
+    writeln!(out, "]}}")
+}
+
+/// The metadata columns of a station other than its coordinates.
+fn write_other_columns(
+    out: &mut impl Write,
+    metadata: &Metadata,
+    values: Option<&[String]>,
+    coordinates: [usize; 2],
+) -> io::Result<()> {
+    for (i, column) in metadata.columns().iter().enumerate() {
+        if coordinates.contains(&i) {
+            continue;
+        }
+        let value = values.map_or("", |values| values[i].as_str());
+        write!(out, ", {}: {}", json_string(column), json_value(value))?;
+    }
+    Ok(())
+}
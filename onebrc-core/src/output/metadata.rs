@@ -0,0 +1,157 @@
+//! [`Metadata`]: columns of a station list joined onto the results.
+
+use rustc_hash::FxHashMap;
+
+use crate::digest::Percentile;
+use crate::quoted::field;
+use crate::results::Results;
+
+/// Names the written results already use for columns or JSON members.
+const RESERVED: [&str; 8] = [
+    "station",
+    "partition",
+    "min",
+    "mean",
+    "max",
+    "count",
+    "stddev",
+    "histogram",
+];
+
+/// More about every station, such as its country and coordinates, from a
+/// CSV file with a header line, `station,country,lat,lon`, and then one line
+/// per station. The first column holds the station's name, whatever the
+/// header calls it; the other columns are written after the station in the
+/// JSON, CSV and Arrow outputs, empty (or `null`) for stations the file
+/// does not list. GeoJSON places stations by the `lat` and `lon` columns.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    columns: Vec<String>,
+    stations: FxHashMap<String, Vec<String>>,
+}
+
+impl Metadata {
+    /// Reads the CSV text of a metadata file. Fields are quoted as in RFC
+    /// 4180 if they hold a comma, and lines may have fewer fields than the
+    /// header, the rest being empty.
+    pub fn parse(csv: &str) -> Result<Self, String> {
+        let mut lines = csv
+            .lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let (_, header) = lines.next().ok_or("no header line")?;
+        let mut columns = fields(header).ok_or("the header line is not valid CSV")?;
+        columns.remove(0);
+        for column in &columns {
+            if RESERVED.contains(&column.as_str()) || column.parse::<Percentile>().is_ok() {
+                return Err(format!("column `{column}` clashes with one of the results"));
+            }
+        }
+        let mut stations = FxHashMap::default();
+        for (number, line) in lines {
+            let mut values = fields(line)
+                .filter(|values| values.len() <= columns.len() + 1)
+                .ok_or_else(|| {
+                    format!("line {}: `{line}` does not match the header", number + 1)
+                })?;
+            let station = values.remove(0);
+            values.resize(columns.len(), String::new());
+            if stations.insert(station.clone(), values).is_some() {
+                return Err(format!("line {}: `{station}` is listed twice", number + 1));
+            }
+        }
+        Ok(Metadata { columns, stations })
+    }
+
+    /// Names of the columns after the station's.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// The first of the columns called one of `names`, ignoring case.
+    pub(super) fn column_named(&self, names: &[&str]) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|column| names.iter().any(|name| column.eq_ignore_ascii_case(name)))
+    }
+
+    /// The values of [`Metadata::columns`] for `station`, if it is listed.
+    pub fn get(&self, station: &str) -> Option<&[String]> {
+        self.stations.get(station).map(Vec::as_slice)
+    }
+
+    /// The stations of `results` merged by their value of `column`, named by
+    /// that value; stations without one are left out. `None` if there is no
+    /// such column.
+    pub fn group_by(&self, column: &str, results: &Results) -> Option<Results> {
+        let i = self.columns.iter().position(|name| name == column)?;
+        Some(
+            results
+                .iter()
+                .filter_map(|(station, stats)| {
+                    let group = self.get(station)?[i].as_str();
+                    (!group.is_empty()).then(|| (group.to_owned(), stats.clone()))
+                })
+                .collect(),
+        )
+    }
+
+    /// The values for `station`, empty ones if it is not listed.
+    pub(super) fn values<'a>(&'a self, station: &str) -> impl Iterator<Item = &'a str> {
+        let values = self.get(station);
+        (0..self.columns.len()).map(move |i| values.map_or("", |values| values[i].as_str()))
+    }
+}
+
+/// The fields of a CSV line, unquoted.
+fn fields(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut rest = Some(line.as_bytes());
+    while let Some(line) = rest {
+        let (value, after) = field(line, b',').ok()?;
+        fields.push(String::from_utf8(value.into_owned()).ok()?);
+        rest = after;
+    }
+    Some(fields)
+}
+
+/// `value` as a JSON number if it is written as one, `null` if it is
+/// empty, and a string otherwise; `00123` stays a string, so codes with
+/// leading zeros survive.
+pub(super) fn json_value(value: &str) -> String {
+    if value.is_empty() {
+        return "null".to_owned();
+    }
+    if is_json_number(value) {
+        return value.to_owned();
+    }
+    super::json_string(value)
+}
+
+/// Whether `s` follows JSON's number grammar, `-?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?`.
+pub(super) fn is_json_number(s: &str) -> bool {
+    let digits = |s: &str| s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let s = s.strip_prefix('-').unwrap_or(s);
+    let int = digits(s);
+    if int == 0 || (int > 1 && s.starts_with('0')) {
+        return false;
+    }
+    let mut rest = &s[int..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = digits(fraction);
+        if len == 0 {
+            return false;
+        }
+        rest = &fraction[len..];
+    }
+    if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        let len = digits(exponent);
+        if len == 0 {
+            return false;
+        }
+        rest = &exponent[len..];
+    }
+    rest.is_empty()
+}
@@ -0,0 +1,253 @@
+//! [`Template`]: one output line per station, in a layout of the user's.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use super::{OutputOptions, Row};
+use crate::digest::Percentile;
+
+/// A line such as `{station}: avg {mean} (min {min}, max {max}, n={count})`,
+/// written once per station in place of an [`OutputOptions::format`].
+///
+/// Fields are `station`, `min`, `mean`, `max`, `count`, `partition` (empty
+/// outside [`write_partitioned`](super::write_partitioned)), `stddev` with
+/// the `variance` feature, and percentiles such as `p99`, which are empty
+/// unless they were aggregated. A field can be followed by a format spec as
+/// in Rust's `format!`, `{station:<20}` or `{mean:>8.2}`: an optional fill
+/// character and alignment (`<`, `^` or `>`), a width and, for temperatures,
+/// the decimal places, or for names the characters kept. `{{` and `}}` are
+/// literal braces, and `\n`, `\t`, `\r`, `\0`, `\e` (the escape character,
+/// for colours) and `\\` are unescaped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Text(String),
+    Field(Field, Spec),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Station,
+    Partition,
+    Min,
+    Mean,
+    Max,
+    Count,
+    #[cfg(feature = "variance")]
+    StdDev,
+    Percentile(Percentile),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// What follows the `:` of a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Spec {
+    fill: char,
+    align: Option<Align>,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => text.push(match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('0') => '\0',
+                    Some('e') => '\x1b',
+                    Some('\\') => '\\',
+                    Some(other) => return Err(format!("unknown escape `\\{other}`")),
+                    None => return Err("the template ends in a lone `\\`".to_owned()),
+                }),
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or("a `{` is never closed")?;
+                    let (name, spec) = rest[..end].split_once(':').unwrap_or((&rest[..end], ""));
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    pieces.push(Piece::Field(name.trim().parse()?, spec.parse()?));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => {
+                    return Err("a `}` was never opened; write `}}` for a literal one".to_owned());
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(Template { pieces })
+    }
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "station" => Field::Station,
+            "partition" => Field::Partition,
+            "min" => Field::Min,
+            "mean" => Field::Mean,
+            "max" => Field::Max,
+            "count" => Field::Count,
+            #[cfg(feature = "variance")]
+            "stddev" => Field::StdDev,
+            _ if s.starts_with('p') => Field::Percentile(s.parse()?),
+            _ => {
+                return Err(format!(
+                    "unknown field `{{{s}}}`; fields are station, min, mean, max, count, \
+                     partition, {}percentiles such as p99",
+                    if cfg!(feature = "variance") {
+                        "stddev, "
+                    } else {
+                        ""
+                    }
+                ));
+            }
+        })
+    }
+}
+
+impl FromStr for Spec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let align = |c| match c {
+            '<' => Some(Align::Left),
+            '^' => Some(Align::Center),
+            '>' => Some(Align::Right),
+            _ => None,
+        };
+        let mut spec = Spec {
+            fill: ' ',
+            align: None,
+            width: 0,
+            precision: None,
+        };
+        let mut rest = s;
+        let mut chars = s.chars();
+        let (first, second) = (chars.next(), chars.next());
+        if let (Some(fill), Some(aligned @ Some(_))) = (first, second.map(align)) {
+            spec.fill = fill;
+            spec.align = aligned;
+            rest = chars.as_str();
+        } else if let Some(aligned @ Some(_)) = first.map(align) {
+            spec.align = aligned;
+            rest = &s[1..];
+        }
+        let (width, precision) = match rest.split_once('.') {
+            Some((width, precision)) => (width, Some(precision)),
+            None => (rest, None),
+        };
+        let number = |digits: &str| {
+            digits
+                .parse::<usize>()
+                .map_err(|_| format!("`{s}` is not a format spec such as `>8.2`"))
+        };
+        if !width.is_empty() {
+            spec.width = number(width)?;
+        }
+        spec.precision = precision.map(number).transpose()?;
+        Ok(spec)
+    }
+}
+
+impl Template {
+    /// Writes one line per row, with `partition` as its `{partition}`.
+    pub(super) fn write_rows(
+        &self,
+        out: &mut impl Write,
+        rows: &[Row],
+        options: &OutputOptions,
+        partition: Option<&str>,
+    ) -> io::Result<()> {
+        let mut line = String::new();
+        for &(station, stats) in rows {
+            line.clear();
+            for piece in &self.pieces {
+                let (field, spec) = match piece {
+                    Piece::Text(text) => {
+                        line.push_str(text);
+                        continue;
+                    }
+                    Piece::Field(field, spec) => (field, spec),
+                };
+                let precision = spec.precision.unwrap_or(options.precision);
+                let temp = |celsius| options.number_to(options.unit.convert(celsius), precision);
+                let (value, numeric) = match *field {
+                    Field::Station => (truncate(station, spec.precision), false),
+                    Field::Partition => (truncate(partition.unwrap_or(""), spec.precision), false),
+                    Field::Min => (temp(stats.min()), true),
+                    Field::Mean => (temp(stats.mean()), true),
+                    Field::Max => (temp(stats.max()), true),
+                    Field::Count => (stats.count().to_string(), true),
+                    #[cfg(feature = "variance")]
+                    Field::StdDev => {
+                        let spread = options.unit.scale(stats.std_dev());
+                        (options.number_to(spread, precision), true)
+                    }
+                    Field::Percentile(percentile) => {
+                        let value = stats.quantile(percentile.fraction()).map(temp);
+                        (value.unwrap_or_default(), true)
+                    }
+                };
+                pad(&mut line, &value, spec, numeric);
+            }
+            line.push('\n');
+            out.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// The first `chars` characters of `s`, or all of it.
+fn truncate(s: &str, chars: Option<usize>) -> String {
+    match chars {
+        Some(chars) => s.chars().take(chars).collect(),
+        None => s.to_owned(),
+    }
+}
+
+/// Appends `value` padded to the spec's width; numbers go right by
+/// default and text left, as in `format!`.
+fn pad(line: &mut String, value: &str, spec: &Spec, numeric: bool) {
+    let missing = spec.width.saturating_sub(value.chars().count());
+    let default = if numeric { Align::Right } else { Align::Left };
+    let (before, after) = match spec.align.unwrap_or(default) {
+        Align::Left => (0, missing),
+        Align::Center => (missing / 2, missing - missing / 2),
+        Align::Right => (missing, 0),
+    };
+    line.extend(std::iter::repeat_n(spec.fill, before));
+    line.push_str(value);
+    line.extend(std::iter::repeat_n(spec.fill, after));
+}
@@ -0,0 +1,117 @@
+//! [`Engine::Rayon`]: the memory-mapped file cut into blocks and folded into
+//! per-thread maps by rayon, with none of the pipeline's own threads.
+//!
+//! [`Engine::Rayon`]: crate::Engine::Rayon
+
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use memmap2::Mmap;
+use rayon::ThreadPoolBuilder;
+use rayon::prelude::*;
+
+use crate::aggregator::{Aggregator, BorrowedAggregator};
+use crate::chunk::{advise_huge_pages, advise_sequential};
+use crate::parse::strip_bom;
+use crate::pipeline::{Options, next_block, offset_in};
+use crate::progress::{Progress, Stage, timed, track};
+use crate::skipped::MalformedLine;
+use crate::trace::span;
+
+/// Aggregates a memory-mapped file by folding its newline-aligned blocks
+/// into one map per rayon task and reducing those pairwise.
+///
+/// Rayon folds consecutive blocks into each map and reduces neighbouring
+/// maps in file order, so line numbers are made global in the reduce just
+/// as [`Mode::Mmap`] does across its slices.
+///
+/// [`Mode::Mmap`]: crate::Mode::Mmap
+pub(crate) fn process_rayon(mmap: &Mmap, options: &Options) -> io::Result<Aggregator> {
+    if options.huge_pages {
+        advise_huge_pages(mmap);
+    }
+    let data = strip_bom(mmap);
+    advise_sequential(mmap, data);
+    let _span = span!(DEBUG, "rayon", bytes = data.len());
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(options.threads.max(1))
+        .build()
+        .map_err(io::Error::other)?;
+
+    let mut blocks = Vec::with_capacity(data.len() / crate::STREAM_BLOCK_SIZE + 1);
+    let mut rest = data;
+    while !rest.is_empty() {
+        blocks.push(next_block(&mut rest));
+    }
+
+    let progress = options.progress.as_deref();
+    // Blocks after the first one that failed in strict mode are skipped
+    let failed_at = AtomicUsize::new(usize::MAX);
+    let part = pool.install(|| {
+        blocks
+            .into_par_iter()
+            .enumerate()
+            .fold(
+                || Part::new(options),
+                |mut part, (index, block)| {
+                    if part.error.is_some()
+                        || index > failed_at.load(Ordering::Relaxed)
+                        || options.cancelled()
+                    {
+                        return part;
+                    }
+                    part.aggregator.set_next_offset(offset_in(mmap, block));
+                    let parsed = track(progress, &mut part.aggregator, block.len(), |aggregator| {
+                        aggregator.process_chunk(block)
+                    });
+                    if let Err(err) = parsed {
+                        failed_at.fetch_min(index, Ordering::Relaxed);
+                        part.error = Some(err);
+                    }
+                    part
+                },
+            )
+            .reduce(
+                || Part::new(options),
+                |left, right| left.then(right, progress),
+            )
+    });
+
+    let Part { aggregator, error } = part;
+    if let Some(err) = error {
+        return Err(err.into());
+    }
+    Ok(timed(progress, Stage::Merge, || aggregator.into_owned()))
+}
+
+/// What a run of consecutive blocks added up to, with lines numbered from
+/// the start of the run; in strict mode, the first line that failed.
+struct Part<'a> {
+    aggregator: BorrowedAggregator<'a>,
+    error: Option<MalformedLine>,
+}
+
+impl<'a> Part<'a> {
+    fn new(options: &Options) -> Self {
+        Part {
+            aggregator: options.borrowed_aggregator(),
+            error: None,
+        }
+    }
+
+    /// Appends `next`, the run of blocks right after this one.
+    fn then(mut self, mut next: Part<'a>, progress: Option<&Progress>) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        let lines_before = self.aggregator.rows();
+        next.aggregator.shift_line_numbers(lines_before);
+        if let Some(err) = &mut next.error {
+            err.line += lines_before;
+        }
+        let Part { aggregator, error } = next;
+        timed(progress, Stage::Merge, || self.aggregator.merge(aggregator));
+        self.error = error;
+        self
+    }
+}
@@ -0,0 +1,330 @@
+use std::hash::BuildHasher;
+
+use memchr::memchr;
+use rustc_hash::FxBuildHasher;
+
+/// How lines are parsed and what happens to those that do not parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParseOptions {
+    /// Byte between the station and the temperature: `;` in the original
+    /// challenge, though tab and comma separated variants exist.
+    pub delimiter: u8,
+    /// Fail on the first malformed line instead of skipping it.
+    pub strict: bool,
+    /// How many skipped lines to keep for the report in lenient mode.
+    pub skip_samples: usize,
+    /// How the station and the temperature are laid out in a line.
+    pub format: InputFormat,
+    /// A header line to drop wherever it turns up, e.g. at the start of
+    /// every one of several CSV exports. It still counts as a row, so line
+    /// numbers stay right, but is neither aggregated nor skipped.
+    pub header: Option<Header>,
+    /// Also read temperatures written with a decimal comma, `12,3`, as some
+    /// European exports do. Lines with a decimal point still parse, and
+    /// only lines that fail to are looked at again, so files without any
+    /// pay nothing for it.
+    pub decimal_comma: bool,
+    /// The fields of a wider delimited line that hold the station and the
+    /// temperature, e.g. of `id;station;timestamp;temperature;humidity`.
+    /// Without it a line is the station, the delimiter and the temperature.
+    pub columns: Option<Columns>,
+    /// Let fields be quoted as in RFC 4180, `"Washington, D.C.";4.3`, with
+    /// `""` for a quote inside one; a field cannot span lines. Lines go
+    /// through a slower parser of their own, so only [`Aggregator`] reads
+    /// them, as with [`InputFormat::Jsonl`].
+    ///
+    /// [`Aggregator`]: crate::Aggregator
+    pub quoted: bool,
+    /// The first line of every input is a header, whatever it looks like;
+    /// without this it is only taken for one if it does not parse and its
+    /// temperature field is not a number, see [`sniff_sample`].
+    ///
+    /// [`sniff_sample`]: crate::sniff_sample
+    pub skip_header: bool,
+}
+
+/// Positions of the station and temperature fields, see
+/// [`ParseOptions::columns`], counted from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Columns {
+    pub station: usize,
+    pub temp: usize,
+}
+
+/// A fingerprint of a header line, see [`ParseOptions::header`]. Only lines
+/// that fail to parse are checked against it, so it costs nothing on the
+/// hot path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Header(u64);
+
+impl Header {
+    /// The header `line`, without its line ending.
+    pub fn new(line: &[u8]) -> Self {
+        Header(FxBuildHasher.hash_one(strip_cr(line)))
+    }
+
+    pub(crate) fn matches(self, line: &[u8]) -> bool {
+        self == Header::new(line)
+    }
+}
+
+/// The layout of input lines, see [`ParseOptions::format`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum InputFormat {
+    /// `station;temperature`, with [`ParseOptions::delimiter`] in place of
+    /// the `;`
+    #[cfg_attr(
+        feature = "clap",
+        value(help = "`station;temperature`, with --delimiter in place of the `;`")
+    )]
+    #[default]
+    Delimited,
+    /// Worked out per input from its first few kilobytes by
+    /// [`aggregate_file`], [`aggregate_reader`] and the functions built on
+    /// them: JSON Lines, or lines delimited by `;`, a tab or a comma.
+    /// Everything an [`Aggregator`] is handed directly is
+    /// read as delimited.
+    ///
+    /// [`aggregate_file`]: crate::aggregate_file
+    /// [`aggregate_reader`]: crate::aggregate_reader
+    /// [`Aggregator`]: crate::Aggregator
+    #[cfg_attr(
+        feature = "clap",
+        value(
+            help = "Worked out per input from its first few kilobytes: JSON Lines, or lines \
+                      delimited by `;`, a tab or a comma"
+        )
+    )]
+    Auto,
+    /// One JSON object per line with a `station` string and a `temp`
+    /// number, e.g. `{"station": "Hamburg", "temp": 12.0}`; other fields are
+    /// ignored. Only [`Aggregator`] reads it, so not the memory-mapped mode
+    /// or engines other than the threads one, whose aggregators borrow
+    /// station names from the input.
+    ///
+    /// [`Aggregator`]: crate::Aggregator
+    #[cfg_attr(
+        feature = "clap",
+        value(
+            help = "One JSON object per line with a `station` string and a `temp` number, \
+                      e.g. `{\"station\": \"Hamburg\", \"temp\": 12.0}`; other fields are \
+                      ignored. Not with --mmap or --engine"
+        )
+    )]
+    Jsonl,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            delimiter: b';',
+            strict: false,
+            skip_samples: 5,
+            format: InputFormat::Delimited,
+            header: None,
+            decimal_comma: false,
+            columns: None,
+            quoted: false,
+            skip_header: false,
+        }
+    }
+}
+
+/// The UTF-8 byte order mark some Windows tools write at the start of a file.
+pub(crate) const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// `data` without a leading byte order mark.
+pub(crate) fn strip_bom(data: &[u8]) -> &[u8] {
+    data.strip_prefix(BOM).unwrap_or(data)
+}
+
+/// `line` without the `\r` left over from a CRLF line ending.
+#[inline]
+pub(crate) fn strip_cr(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Splits a `station;temperature` line (without its newline) into the station
+/// bytes and the temperature in tenths of a degree, with `delimiter` in place
+/// of the `;`.
+pub fn parse_line(line: &[u8], delimiter: u8) -> Option<(&[u8], i32)> {
+    parse_split(line, memchr(delimiter, line)?)
+}
+
+/// [`parse_line`] for a line whose delimiter was already found at `pos`.
+#[inline]
+pub(crate) fn parse_split(line: &[u8], pos: usize) -> Option<(&[u8], i32)> {
+    let (city, temp_bytes) = line.split_at(pos);
+    // skip the delimiter
+    let temp = parse_temp(temp_bytes[1..].trim_ascii())?;
+    Some((city, temp))
+}
+
+/// [`parse_split`] on a line whose first delimiter, if any, is at `pos`,
+/// allowing for [`ParseOptions::decimal_comma`] and
+/// [`ParseOptions::columns`].
+#[inline]
+pub(crate) fn parse_record<'l>(
+    line: &'l [u8],
+    pos: Option<usize>,
+    options: &ParseOptions,
+) -> Option<(&'l [u8], i32)> {
+    let pos = pos?;
+    if let Some(columns) = options.columns {
+        let (city, temp) = split_columns(line, pos, options.delimiter, columns)?;
+        let temp = temp.trim_ascii();
+        let temp = parse_temp(temp).or_else(|| parse_temp_comma(temp, options.decimal_comma))?;
+        return Some((city, temp));
+    }
+    parse_split(line, pos).or_else(|| {
+        let (city, temp_bytes) = line.split_at(pos);
+        let temp = parse_temp_comma(temp_bytes[1..].trim_ascii(), options.decimal_comma)?;
+        Some((city, temp))
+    })
+}
+
+/// The station and temperature fields of a line whose first delimiter is
+/// at `first`. Only the fields up to the later of the two are looked for,
+/// one delimiter at a time, so trailing columns cost nothing.
+#[inline]
+pub(crate) fn split_columns(
+    line: &[u8],
+    first: usize,
+    delimiter: u8,
+    columns: Columns,
+) -> Option<(&[u8], &[u8])> {
+    let (mut start, mut end) = (0, first);
+    let (mut station, mut temp) = (None, None);
+    for column in 0..=columns.station.max(columns.temp) {
+        if column > 0 {
+            start = end + 1;
+            let rest = line.get(start..)?;
+            end = memchr(delimiter, rest).map_or(line.len(), |pos| start + pos);
+        }
+        let field = &line[start..end];
+        if column == columns.station {
+            station = Some(field);
+        }
+        if column == columns.temp {
+            temp = Some(field);
+        }
+    }
+    Some((station?, temp?))
+}
+
+/// [`parse_temp`] with a comma as the decimal mark, for the temperatures it
+/// turned down if `decimal_comma` is set.
+#[cold]
+pub(crate) fn parse_temp_comma(bytes: &[u8], decimal_comma: bool) -> Option<i32> {
+    if !decimal_comma {
+        return None;
+    }
+    let mut number = [0; 16];
+    let number = number.get_mut(..bytes.len())?;
+    number.copy_from_slice(bytes);
+    let mut commas = number.iter_mut().filter(|byte| **byte == b',');
+    *commas.next()? = b'.';
+    if commas.next().is_some() {
+        return None;
+    }
+    parse_temp(number)
+}
+
+/// Parses a temperature with exactly one fractional digit (`-12.3`) into
+/// tenths of a degree, without going through `str` or `f64`.
+#[inline]
+pub fn parse_temp(bytes: &[u8]) -> Option<i32> {
+    parse_fixed(bytes).or_else(|| parse_general(bytes))
+}
+
+/// The challenge's own format, `-?\d{1,2}\.\d`, with one length check and
+/// no branching on the digits: a one-digit whole part is padded to two, the
+/// digits are checked together and the sign is applied as a factor.
+#[inline]
+fn parse_fixed(bytes: &[u8]) -> Option<i32> {
+    let negative = bytes.first() == Some(&b'-');
+    let [tens, ones, dot, tenths] = match bytes[negative as usize..] {
+        [ones, dot, tenths] => [b'0', ones, dot, tenths],
+        [tens, ones, dot, tenths] => [tens, ones, dot, tenths],
+        _ => return None,
+    };
+    let [tens, ones, tenths] = [tens, ones, tenths].map(|digit| digit.wrapping_sub(b'0'));
+    let valid = (dot == b'.') & (tens < 10) & (ones < 10) & (tenths < 10);
+    let sign = 1 - 2 * negative as i32;
+    let value = tens as i32 * 100 + ones as i32 * 10 + tenths as i32;
+    valid.then_some(sign * value)
+}
+
+/// Any number of whole digits, for what [`parse_fixed`] turns down.
+#[cold]
+fn parse_general(bytes: &[u8]) -> Option<i32> {
+    let (negative, digits) = match bytes {
+        [b'-', rest @ ..] => (true, rest),
+        _ => (false, bytes),
+    };
+    let [whole @ .., b'.', frac] = digits else {
+        return None;
+    };
+    if whole.is_empty() || !frac.is_ascii_digit() {
+        return None;
+    }
+
+    let mut value: i32 = 0;
+    for &b in whole {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        value = value.checked_mul(10)?.checked_add((b - b'0') as i32)?;
+    }
+    value = value.checked_mul(10)?.checked_add((frac - b'0') as i32)?;
+
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_cr_drops_one_trailing_carriage_return() {
+        assert_eq!(strip_cr(b"Oslo;-3.4\r"), b"Oslo;-3.4");
+        assert_eq!(strip_cr(b"Oslo;-3.4"), b"Oslo;-3.4");
+        assert_eq!(strip_cr(b"Oslo;-3.4\r\r"), b"Oslo;-3.4\r");
+        assert_eq!(strip_cr(b"\r"), b"");
+        assert_eq!(strip_cr(b""), b"");
+    }
+
+    #[test]
+    fn strip_cr_keeps_a_carriage_return_inside_the_line() {
+        assert_eq!(strip_cr(b"Os\rlo;-3.4"), b"Os\rlo;-3.4");
+    }
+
+    #[test]
+    fn strip_bom_drops_a_leading_byte_order_mark() {
+        assert_eq!(strip_bom(b"\xEF\xBB\xBFOslo;-3.4\n"), b"Oslo;-3.4\n");
+        assert_eq!(strip_bom(BOM), b"");
+        assert_eq!(strip_bom(b"Oslo;-3.4\n"), b"Oslo;-3.4\n");
+    }
+
+    #[test]
+    fn strip_bom_keeps_partial_and_later_byte_order_marks() {
+        assert_eq!(strip_bom(b"\xEF\xBBOslo"), b"\xEF\xBBOslo");
+        assert_eq!(strip_bom(b"a\xEF\xBB\xBF"), b"a\xEF\xBB\xBF");
+        assert_eq!(
+            strip_bom(b"\xEF\xBB\xBF\xEF\xBB\xBFOslo"),
+            b"\xEF\xBB\xBFOslo"
+        );
+    }
+
+    #[test]
+    fn parse_line_ignores_a_carriage_return_once_stripped() {
+        let (name, temp) = parse_line(strip_cr(b"Oslo;-3.4\r"), b';').unwrap();
+        assert_eq!(name, b"Oslo");
+        assert_eq!(temp, -34);
+    }
+}
@@ -0,0 +1,94 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::pipeline::in_file;
+
+/// Partition label of files that sit outside any `key=value` directory.
+pub const ROOT_PARTITION: &str = ".";
+
+/// Walks `root` recursively and returns every data file under it, sorted by
+/// path, with the partition it belongs to (see [`partition_of`]).
+///
+/// Names starting with `.` or `_` are skipped, as Hive and Spark use them for
+/// metadata such as `_SUCCESS`. Symlinks to files are followed; symlinks to
+/// directories are not, so a loop cannot trap the walk.
+pub fn discover(root: impl AsRef<Path>) -> io::Result<Vec<(PathBuf, String)>> {
+    let root = root.as_ref();
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with(['.', '_']) {
+                continue;
+            }
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file() || fs::metadata(&path)?.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files
+        .into_iter()
+        .map(|path| {
+            let partition = partition_of(root, &path);
+            (path, partition)
+        })
+        .collect())
+}
+
+/// `path`, taken from `root` if relative, with `..` and symlinks resolved,
+/// or a [`PermissionDenied`] error if that is not inside `root`, which must
+/// be canonical itself. For servers that read the files their clients name;
+/// the error does not repeat `path`.
+///
+/// [`PermissionDenied`]: io::ErrorKind::PermissionDenied
+pub fn confine(root: &Path, path: &Path) -> io::Result<PathBuf> {
+    let canonical = fs::canonicalize(root.join(path))?;
+    if !canonical.starts_with(root) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not inside the served directory",
+        ));
+    }
+    Ok(canonical)
+}
+
+/// The files `path` names as [`confine`] resolves it against `root`: the
+/// file itself, or those [`discover`] finds in a directory, each of them
+/// inside `root` too. Errors name the path they are about.
+pub fn confined_files(root: &Path, path: &Path) -> io::Result<Vec<PathBuf>> {
+    let resolved = confine(root, path).map_err(|err| in_file(err, path))?;
+    if !resolved.is_dir() {
+        return Ok(vec![resolved]);
+    }
+    discover(&resolved)?
+        .into_iter()
+        .map(|(file, _)| confine(root, &file).map_err(|err| in_file(err, &file)))
+        .collect()
+}
+
+/// The Hive-style `key=value` directories between `root` and `file`, joined
+/// with `/` (`date=2024-01-01/region=eu`), or [`ROOT_PARTITION`] if there
+/// are none. Other directories on the way are ignored.
+pub fn partition_of(root: &Path, file: &Path) -> String {
+    let dirs = file
+        .parent()
+        .and_then(|dir| dir.strip_prefix(root).ok())
+        .unwrap_or(Path::new(""));
+    let keys: Vec<_> = dirs
+        .components()
+        .filter_map(|component| component.as_os_str().to_str())
+        .filter(|name| name.contains('='))
+        .collect();
+    if keys.is_empty() {
+        ROOT_PARTITION.to_string()
+    } else {
+        keys.join("/")
+    }
+}
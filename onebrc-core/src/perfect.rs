@@ -0,0 +1,278 @@
+//! A perfect hash over a fixed station list, for [`Options::known_stations`].
+//!
+//! [`Options::known_stations`]: crate::Options::known_stations
+
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use rustc_hash::{FxBuildHasher, FxHashSet};
+
+use crate::filter::StationFilter;
+use crate::key::StationKey;
+use crate::stats::{StatsOptions, TempStats};
+
+/// Tries per bucket before the table is made larger.
+const MAX_PILOT: u64 = 1 << 16;
+
+/// Resolves the names of a fixed station list to slots of an array without
+/// probing: names are hashed into small buckets, and every bucket has a
+/// pilot value, found once in [`StationIndex::new`], that sends each of its
+/// names to a slot no other name uses. A lookup is one hash, two array reads
+/// and one comparison, which tells names on the list from the rest.
+#[derive(Debug)]
+pub struct StationIndex {
+    pilots: Vec<u64>,
+    /// The name in every slot, if any.
+    names: Vec<Option<StationKey>>,
+    len: usize,
+}
+
+impl StationIndex {
+    /// Builds the index for `names`; duplicates count once. No pilot can
+    /// tell apart names with the same hash, so a name hashing like one
+    /// before it is left out, and goes to the usual map like names off the
+    /// list.
+    pub fn new<N: Into<Vec<u8>>>(names: impl IntoIterator<Item = N>) -> Self {
+        let names = names.into_iter().map(Into::into);
+        Self::with_hashes(names.map(|name| (hash(&name), name)))
+    }
+
+    /// [`StationIndex::new`] over names hashed already.
+    fn with_hashes(names: impl IntoIterator<Item = (u64, Vec<u8>)>) -> Self {
+        let mut seen = FxHashSet::default();
+        let names: Vec<(u64, Vec<u8>)> = names
+            .into_iter()
+            .filter(|&(hash, _)| seen.insert(hash))
+            .collect();
+        let mut slots = (2 * names.len()).next_power_of_two();
+        loop {
+            if let Some(index) = Self::build(&names, slots) {
+                return index;
+            }
+            slots *= 2;
+        }
+    }
+
+    /// The index with `slots` slots, if a pilot can be found for every bucket.
+    fn build(names: &[(u64, Vec<u8>)], slots: usize) -> Option<Self> {
+        let buckets = names.len().div_ceil(4).next_power_of_two();
+        let mut by_bucket: Vec<Vec<(u64, &[u8])>> = vec![Vec::new(); buckets];
+        for (hash, name) in names {
+            by_bucket[bucket(*hash, buckets)].push((*hash, name));
+        }
+        // Place the largest buckets first, while most slots are free
+        let mut order: Vec<usize> = (0..buckets).collect();
+        order.sort_by_key(|&bucket| std::cmp::Reverse(by_bucket[bucket].len()));
+
+        let mut pilots = vec![0; buckets];
+        let mut taken: Vec<Option<StationKey>> = vec![None; slots];
+        let mut wanted = Vec::new();
+        for bucket in order {
+            let entries = &by_bucket[bucket];
+            let pilot = (0..MAX_PILOT).find(|&pilot| {
+                wanted.clear();
+                wanted.extend(entries.iter().map(|&(hash, _)| slot(hash, pilot, slots)));
+                let free = |(i, &slot): (usize, &usize)| {
+                    taken[slot].is_none() && !wanted[..i].contains(&slot)
+                };
+                wanted.iter().enumerate().all(free)
+            })?;
+            pilots[bucket] = pilot;
+            for (&(_, name), &slot) in entries.iter().zip(&wanted) {
+                taken[slot] = Some(name.into());
+            }
+        }
+        Some(StationIndex {
+            pilots,
+            names: taken,
+            len: names.len(),
+        })
+    }
+
+    /// Number of stations in the index.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The slot of `name`, if it is on the list. Slots are below
+    /// [`StationIndex::slots`] and differ between names.
+    #[inline]
+    pub fn get(&self, name: &[u8]) -> Option<usize> {
+        let hash = hash(name);
+        let pilot = self.pilots[bucket(hash, self.pilots.len())];
+        let slot = slot(hash, pilot, self.names.len());
+        (self.names[slot].as_deref() == Some(name)).then_some(slot)
+    }
+
+    /// Number of slots, at least twice the number of stations.
+    pub fn slots(&self) -> usize {
+        self.names.len()
+    }
+}
+
+fn hash(name: &[u8]) -> u64 {
+    FxBuildHasher.hash_one(name)
+}
+
+fn bucket(hash: u64, buckets: usize) -> usize {
+    hash as usize & (buckets - 1)
+}
+
+fn slot(hash: u64, pilot: u64, slots: usize) -> usize {
+    let seed = pilot.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let mixed = (hash ^ seed).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    (mixed >> 32) as usize & (slots - 1)
+}
+
+/// Statistics for the stations of a [`StationIndex`], kept by an aggregator
+/// next to its map for the stations not on the list.
+#[derive(Debug)]
+pub(crate) struct KnownStats {
+    index: Arc<StationIndex>,
+    /// Per slot of the index.
+    stations: Vec<Known>,
+}
+
+#[derive(Debug, Clone)]
+enum Known {
+    Unseen,
+    Seen(TempStats),
+    /// Turned down by the filter.
+    Rejected,
+}
+
+impl KnownStats {
+    pub(crate) fn new(index: Arc<StationIndex>) -> Self {
+        let stations = vec![Known::Unseen; index.slots()];
+        KnownStats { index, stations }
+    }
+
+    /// Adds a reading of `city` if it is on the list, and tells whether it
+    /// was. The filter is asked once per station, on its first reading.
+    #[inline]
+    pub(crate) fn add(
+        &mut self,
+        city: &[u8],
+        temp: i32,
+        options: StatsOptions,
+        filter: Option<&StationFilter>,
+    ) -> bool {
+        let Some(slot) = self.index.get(city) else {
+            return false;
+        };
+        let known = &mut self.stations[slot];
+        match known {
+            Known::Seen(stats) => stats.update(temp),
+            Known::Rejected => {}
+            Known::Unseen if filter.is_some_and(|filter| !filter.matches(city)) => {
+                *known = Known::Rejected;
+            }
+            Known::Unseen => *known = Known::Seen(TempStats::with_options(temp, options)),
+        }
+        true
+    }
+
+    /// Folds `other`, which must be built on the same index, into `self`.
+    pub(crate) fn merge(&mut self, other: KnownStats) {
+        for (ours, theirs) in self.stations.iter_mut().zip(other.stations) {
+            match (&mut *ours, theirs) {
+                (Known::Seen(stats), Known::Seen(other)) => stats.merge(&other),
+                (Known::Unseen, theirs) => *ours = theirs,
+                _ => {}
+            }
+        }
+    }
+
+    /// The stations that had readings, and those the filter turned down.
+    pub(crate) fn into_entries(self) -> impl Iterator<Item = (StationKey, Option<TempStats>)> {
+        let index = self.index;
+        self.stations
+            .into_iter()
+            .enumerate()
+            .filter_map(move |(slot, known)| {
+                let stats = match known {
+                    Known::Unseen => return None,
+                    Known::Seen(stats) => Some(stats),
+                    Known::Rejected => None,
+                };
+                Some((index.names[slot].clone()?, stats))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|i| format!("Station {i}").into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn every_name_gets_a_slot_of_its_own() {
+        for count in [0, 1, 3, 10_000] {
+            let names = names(count);
+            let index = StationIndex::new(names.iter().chain(&names).cloned());
+            assert_eq!(index.len(), count);
+            assert!(index.slots() >= 2 * count);
+            let mut slots: Vec<usize> = names.iter().map(|name| index.get(name).unwrap()).collect();
+            assert!(slots.iter().all(|&slot| slot < index.slots()));
+            slots.sort_unstable();
+            slots.dedup();
+            assert_eq!(slots.len(), count);
+            assert_eq!(index.get(b"Station -1"), None);
+            assert_eq!(index.get(b""), None);
+        }
+    }
+
+    #[test]
+    fn known_stats_add_merge_and_filter() {
+        let index = Arc::new(StationIndex::new(["Oslo", "Lima", "Riga"]));
+        let filter = StationFilter::new().with_allowed(["Oslo", "Lima", "Bern"]);
+        let options = StatsOptions::default();
+        let (mut left, mut right) = (KnownStats::new(index.clone()), KnownStats::new(index));
+        assert!(left.add(b"Oslo", 10, options, Some(&filter)));
+        assert!(left.add(b"Riga", 10, options, Some(&filter)));
+        assert!(!left.add(b"Bern", 10, options, Some(&filter)));
+        assert!(right.add(b"Oslo", 30, options, Some(&filter)));
+        assert!(right.add(b"Lima", 20, options, Some(&filter)));
+        left.merge(right);
+        let mut entries: Vec<_> = left
+            .into_entries()
+            .map(|(name, stats)| (name.to_vec(), stats.map(|s| (s.count(), s.mean()))))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            entries,
+            [
+                (b"Lima".to_vec(), Some((1, 2.0))),
+                (b"Oslo".to_vec(), Some((2, 2.0))),
+                (b"Riga".to_vec(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn names_hashing_alike_are_left_out() {
+        // Without the check the slots would double until memory ran out
+        let names = [b"Oslo".to_vec(), b"Lima".to_vec(), b"Riga".to_vec()];
+        let index = StationIndex::with_hashes(
+            names
+                .iter()
+                .map(|name| (hash(&names[0]), name.clone()))
+                .chain([(hash(b"Bern"), b"Bern".to_vec())]),
+        );
+        assert_eq!(index.len(), 2);
+        assert!(index.slots() <= 8);
+        assert!(index.get(b"Oslo").is_some());
+        assert!(index.get(b"Bern").is_some());
+        assert_eq!(index.get(b"Lima"), None);
+        assert_eq!(index.get(b"Riga"), None);
+    }
+}
@@ -0,0 +1,1482 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+
+use memchr::{memchr, memchr_iter, memrchr};
+use memmap2::Mmap;
+
+use crate::affinity::Cores;
+use crate::aggregator::{Aggregator, BorrowedAggregator, TableAggregator};
+use crate::aliases::Aliases;
+use crate::batching::{BatchSizer, Feedback};
+#[cfg(feature = "cloud")]
+use crate::cloud::aggregate_object;
+use crate::chunk::{advise_huge_pages, advise_sequential, chunk_ranges, map_file, slice_chunks};
+use crate::columnar::{aggregate_parquet, is_parquet, read_parquet};
+use crate::compression::{Compression, is_stream, open_decoder, open_input, open_stream};
+use crate::filter::StationFilter;
+#[cfg(feature = "http")]
+use crate::http::aggregate_url;
+use crate::live::LiveResults;
+use crate::parse::{BOM, InputFormat, ParseOptions, strip_bom};
+use crate::perfect::StationIndex;
+use crate::pool::{Buffer, BufferPool};
+use crate::progress::{Counts, Progress, Stage, TimedReader, timed, track};
+use crate::ring::ring;
+use crate::sample::process_sampled;
+use crate::skipped::MalformedLine;
+use crate::sniff::{sniff_file, sniff_reader};
+#[cfg(feature = "spill")]
+use crate::spill::Spill;
+use crate::staged::process_pipelined;
+use crate::stats::StatsOptions;
+use crate::table::KeyCheck;
+use crate::trace::{current_span, event, span};
+
+/// How a file is divided between worker threads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// The calling thread reads lines and hands out batches of them.
+    #[default]
+    Batched,
+    /// Every worker opens the file and reads its own newline-aligned range.
+    Chunked,
+    /// The file is memory-mapped and workers parse borrowed slices of it.
+    /// Falls back to [`Mode::Chunked`] if the file cannot be mapped, with a
+    /// warning event under the `tracing` feature.
+    Mmap,
+    /// [`Options::io_threads`] threads do nothing but read large
+    /// newline-aligned stripes of the file into pooled buffers, which the
+    /// calling thread hands to the workers in file order.
+    Pipelined,
+}
+
+/// How input files are read. Only plain text files read in [`Mode::Batched`]
+/// or [`Mode::Chunked`] are affected; the others go through their decoder, the
+/// memory map or plain reads as before.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum IoBackend {
+    /// Blocking reads, one at a time.
+    #[default]
+    Std,
+    /// Several large reads kept in flight through io_uring into registered
+    /// buffers, so reading overlaps with parsing. Needs the `uring` feature
+    /// and Linux.
+    Uring,
+}
+
+/// What drives the worker threads for a plain text file in
+/// [`aggregate_file`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Engine {
+    /// The pipeline's own threads, dividing the file as [`Mode`] says.
+    #[cfg_attr(
+        feature = "clap",
+        value(help = "The pipeline's own threads, dividing the file as --chunked or --mmap say")
+    )]
+    #[default]
+    Threads,
+    /// A rayon fold over newline-aligned blocks of the memory-mapped file,
+    /// as a simpler baseline to compare the others against; [`Mode`] is not
+    /// used. Needs the `rayon` feature.
+    #[cfg_attr(
+        feature = "clap",
+        value(
+            help = "A rayon fold over newline-aligned blocks of the memory-mapped file, as a \
+                      simpler baseline to compare the others against. Needs the `rayon` feature"
+        )
+    )]
+    Rayon,
+    /// The pipeline's threads over the memory-mapped file as in
+    /// [`Mode::Mmap`], with per-worker maps that are open-addressing tables
+    /// with linear probing, made for a few thousand short station names.
+    #[cfg_attr(
+        feature = "clap",
+        value(
+            help = "The pipeline's threads over the memory-mapped file as with --mmap, with \
+                      open-addressing tables made for a few thousand short station names"
+        )
+    )]
+    CustomMap,
+    /// Like [`Engine::CustomMap`], but stations are told apart by a 64-bit
+    /// hash of their names alone, without comparing names. Two names with
+    /// the same hash would be counted as one station;
+    /// [`Options::verify_collisions`] checks that this never happens.
+    #[cfg_attr(
+        feature = "clap",
+        value(
+            help = "Like custom-map, but stations are told apart by a 64-bit hash of their \
+                      names alone, without comparing names; --verify-collisions checks that no \
+                      two names share one"
+        )
+    )]
+    HashOnly,
+}
+
+/// Settings for [`aggregate_file`].
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Number of worker threads.
+    pub threads: usize,
+    /// Lines per batch in [`Mode::Batched`]; with `adaptive_batch`, the
+    /// size of the first batch.
+    pub batch_size: usize,
+    /// In [`Mode::Batched`], size every batch from how full the queue is
+    /// and how long the workers took over the previous batches, instead of
+    /// always using `batch_size`.
+    pub adaptive_batch: bool,
+    /// Batches (or stream blocks) allowed in flight between the reader and
+    /// the workers. `None` means [`QUEUE_DEPTH_PER_WORKER`] per worker.
+    pub queue_depth: Option<usize>,
+    pub mode: Mode,
+    /// Reading threads in [`Mode::Pipelined`].
+    pub io_threads: usize,
+    /// Stripes each reading thread of [`Mode::Pipelined`] may have read
+    /// ahead of the workers.
+    pub read_queue_depth: usize,
+    pub engine: Engine,
+    /// With [`Engine::HashOnly`], compare the names anyway and fail if two
+    /// different ones share a hash, to check that the engine can be trusted
+    /// on some data.
+    pub verify_collisions: bool,
+    pub io_backend: IoBackend,
+    /// Open plain input files with `O_DIRECT` (Linux only), bypassing the
+    /// page cache: for measuring cold reads, or to leave the cache alone on
+    /// a shared machine. Affects the same files as `io_backend`.
+    pub direct_io: bool,
+    /// Ask for transparent huge pages behind the mapping in [`Mode::Mmap`]
+    /// (Linux only, and only a hint).
+    pub huge_pages: bool,
+    /// Pin every worker to a core of its own, and the thread that reads and
+    /// merges for them to another one, so the scheduler does not move them
+    /// between cores mid-run (Linux only). Only the cores the process is
+    /// allowed on are used, so `taskset` and cgroup limits still apply.
+    pub pin_threads: bool,
+    /// Keep every worker on one NUMA node (Linux only): the workers that
+    /// take consecutive parts of the input share a node, and their maps are
+    /// allocated there. With `pin_threads`, each also gets a core of its own
+    /// on that node.
+    pub numa: bool,
+    pub parse: ParseOptions,
+    pub stats: StatsOptions,
+    /// Only aggregate the stations this matches.
+    pub filter: Option<Arc<StationFilter>>,
+    /// Canonical names for stations spelled more than one way, see
+    /// [`Aggregator::with_aliases`].
+    pub aliases: Option<Arc<Aliases>>,
+    /// A fixed list of the stations expected in the input, resolved without
+    /// probing; stations not on it are aggregated as usual. Only used where
+    /// workers parse the memory-mapped file: [`Mode::Mmap`] and the engines
+    /// other than [`Engine::Threads`].
+    pub known_stations: Option<Arc<StationIndex>>,
+    /// Keep the workers' station maps under about this many bytes between
+    /// them by spilling them to sorted runs in the temporary directory when
+    /// they grow past their share, and merging the runs at the end; for
+    /// inputs with millions of distinct stations. Only the merged result
+    /// has to fit. Needs the `spill` feature, and [`Mode::Batched`] or
+    /// [`Mode::Pipelined`] with [`Engine::Threads`] for plain files (the
+    /// mapped modes keep their keys in the input); standard input and
+    /// compressed files work in any mode, Parquet input is not limited.
+    pub max_memory: Option<u64>,
+    /// Only aggregate this fraction of every file, above 0 and at most 1,
+    /// in evenly spread blocks, for a quick estimate of a large input: the
+    /// means are close, while the counts, minimums and maximums are only
+    /// the sample's. Needs plain files, which are memory-mapped.
+    pub sample: Option<f64>,
+    /// Parse and count the lines without aggregating them, so the results
+    /// are empty: for checking an input's lines, and timing reading and
+    /// parsing apart from the station map. Needs [`Engine::Threads`] or
+    /// [`Engine::Rayon`].
+    pub count_only: bool,
+    /// Range requests kept in flight for a URL input.
+    pub connections: usize,
+    /// Also save a URL input to this file, resuming the download if an
+    /// earlier run left part of it there.
+    pub download_to: Option<PathBuf>,
+    /// Counters bumped as each block of input is processed, for reporting
+    /// progress from another thread.
+    pub progress: Option<Arc<Progress>>,
+    /// Where the workers publish the stations aggregated so far, for
+    /// showing results from another thread before the run is done.
+    pub live: Option<Arc<LiveResults>>,
+    /// Stops the run early once set, e.g. from a signal handler: no more
+    /// input is read, what was read is still aggregated, and the partial
+    /// result is returned as if the input had ended there.
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+/// Default number of queued batches per worker thread.
+pub const QUEUE_DEPTH_PER_WORKER: usize = 2;
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            batch_size: 100_000,
+            adaptive_batch: false,
+            queue_depth: None,
+            mode: Mode::default(),
+            io_threads: 2,
+            read_queue_depth: QUEUE_DEPTH_PER_WORKER,
+            engine: Engine::default(),
+            verify_collisions: false,
+            io_backend: IoBackend::default(),
+            direct_io: false,
+            huge_pages: false,
+            pin_threads: false,
+            numa: false,
+            parse: ParseOptions::default(),
+            stats: StatsOptions::default(),
+            filter: None,
+            aliases: None,
+            known_stations: None,
+            max_memory: None,
+            sample: None,
+            count_only: false,
+            connections: 4,
+            download_to: None,
+            progress: None,
+            live: None,
+            cancel: None,
+        }
+    }
+}
+
+impl Options {
+    /// An empty aggregator set up with these options.
+    pub fn aggregator(&self) -> Aggregator {
+        Aggregator::with_options(self.parse)
+            .with_stats(self.stats)
+            .with_filter(self.filter.clone())
+            .with_aliases(self.aliases.clone())
+            .with_count_only(self.count_only)
+    }
+
+    pub(crate) fn borrowed_aggregator<'a>(&self) -> BorrowedAggregator<'a> {
+        BorrowedAggregator::with_options(self.parse)
+            .with_stats(self.stats)
+            .with_filter(self.filter.clone())
+            .with_aliases(self.aliases.clone())
+            .with_count_only(self.count_only)
+            .with_known_stations(self.known_stations.clone())
+    }
+
+    /// Whether [`Options::cancel`] has been set.
+    pub(crate) fn cancelled(&self) -> bool {
+        cancelled(self.cancel.as_deref())
+    }
+
+    /// Where to pin threads, with [`Options::pin_threads`] or
+    /// [`Options::numa`].
+    pub(crate) fn cores(&self) -> io::Result<Option<Arc<Cores>>> {
+        if !self.pin_threads && !self.numa {
+            return Ok(None);
+        }
+        let cores = Cores::allowed(self.pin_threads, self.numa)?;
+        Ok(Some(Arc::new(cores)))
+    }
+}
+
+pub(crate) fn cancelled(cancel: Option<&AtomicBool>) -> bool {
+    cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+}
+
+/// Size of the blocks the streaming reader hands to workers.
+pub const STREAM_BLOCK_SIZE: usize = 4 << 20;
+
+/// Aggregates a whole file in parallel according to `options`. A path of `-`
+/// reads standard input, an `http://` or `https://` URL is downloaded (with
+/// the `http` feature), an `s3://`, `gs://` or `az://` URL is read from the
+/// object store (with the `cloud` feature), and gzip or zstd files are decompressed on the fly;
+/// all of them go through [`aggregate_reader`] whatever the mode. Parquet
+/// files go to [`aggregate_parquet`](crate::aggregate_parquet).
+pub fn aggregate_file(path: impl AsRef<Path>, options: &Options) -> io::Result<Aggregator> {
+    let path = path.as_ref();
+    let _span = span!(INFO, "aggregate_file", path = %path.display(), mode = ?options.mode);
+    if options.sample.is_some() && !is_plain_file(path)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only plain files can be sampled, not streams, URLs, Parquet or compressed files",
+        ));
+    }
+    if is_stream(path) {
+        return aggregate_reader(open_stream(path)?, options);
+    }
+    if let Some(url) = as_url(path) {
+        return aggregate_url(url, options);
+    }
+    if let Some(url) = as_object_url(path) {
+        return aggregate_object(url, options);
+    }
+    if is_parquet(path)? {
+        return aggregate_parquet(path, options);
+    }
+    if let Some(compression) = Compression::detect(path)? {
+        // The calling thread decompresses while the pool parses
+        return aggregate_reader(open_decoder(path, compression)?, options);
+    }
+    let options = &Options {
+        parse: sniff_file(path, options.parse)?,
+        ..options.clone()
+    };
+    if let Some(fraction) = options.sample {
+        return process_sampled(path, options, fraction);
+    }
+
+    let pooled = matches!(options.mode, Mode::Batched | Mode::Pipelined);
+    if options.max_memory.is_some() && (options.engine != Engine::Threads || !pooled) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a memory limit needs the batched or pipelined mode of the threads engine",
+        ));
+    }
+    if options.engine != Engine::Threads || matches!(options.mode, Mode::Mmap) {
+        let needs = if options.parse.format == InputFormat::Jsonl {
+            Some("JSON Lines input")
+        } else {
+            options.parse.quoted.then_some("quoted input")
+        };
+        if let Some(input) = needs {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{input} needs the batched, pipelined or chunked mode of the threads engine"
+                ),
+            ));
+        }
+    }
+    if options.count_only && matches!(options.engine, Engine::CustomMap | Engine::HashOnly) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "counting lines only needs the threads or rayon engine",
+        ));
+    }
+    let mode = match options.engine {
+        Engine::Threads => options.mode,
+        Engine::Rayon => return aggregate_rayon(path, options),
+        Engine::CustomMap | Engine::HashOnly => Mode::Mmap,
+    };
+    match mode {
+        Mode::Batched => process_batched(path, options),
+        Mode::Chunked => process_chunked(path, options),
+        Mode::Pipelined => process_pipelined(path, options),
+        Mode::Mmap => match map_file(path) {
+            Ok(mmap) => {
+                let cores = options.cores()?;
+                process_mapped(&mmap, options, cores.as_deref())
+            }
+            // Only the `tracing` feature has the error to show
+            Err(_err) => {
+                event!(WARN, error = %_err, "mmap unavailable, falling back to buffered reads");
+                process_chunked(path, options)
+            }
+        },
+    }
+}
+
+/// Aggregates several files into one result.
+///
+/// With fewer files than threads, the files are processed one after another,
+/// each split across all threads as in [`aggregate_file`]. With more, every
+/// thread takes whole files from a shared list, which suits many small parts;
+/// that list is only used for the default batched reads of the threads
+/// engine (no [`Options::max_memory`] either), as any other mode, engine or
+/// I/O backend needs the whole of [`aggregate_file`] for each file.
+/// Line numbers in errors and skipped-line samples count from the start of
+/// each file, and errors name the file they come from.
+pub fn aggregate_files<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    options: &Options,
+) -> io::Result<Aggregator> {
+    let num_threads = options.threads.max(1);
+    let _span = span!(INFO, "aggregate_files", files = paths.len());
+    let batched = matches!(
+        (options.mode, options.engine, options.io_backend),
+        (Mode::Batched, Engine::Threads, IoBackend::Std)
+    ) && !options.direct_io;
+    let sequential = !batched
+        || options.max_memory.is_some()
+        || options.sample.is_some()
+        || paths.iter().any(is_url);
+    if paths.len() == 1 || paths.len() < num_threads || sequential {
+        let mut merged = options.aggregator();
+        for path in paths {
+            if options.cancelled() {
+                break;
+            }
+            let path = path.as_ref();
+            merged.merge(aggregate_file(path, options).map_err(|err| in_file(err, path))?);
+        }
+        return Ok(merged);
+    }
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let cores = options.cores()?;
+    let _pinned = cores.as_ref().map(|cores| cores.pin_coordinator());
+    let partials = thread::scope(|scope| {
+        let handles: Vec<_> = (0..num_threads)
+            .map(|index| {
+                let mut aggregator = options.aggregator();
+                let (next, failed) = (&next, &failed);
+                let progress = options.progress.as_deref();
+                let mut live = options.live.as_deref().map(LiveResults::worker);
+                let cores = cores.as_deref();
+                let parent = current_span!();
+                scope.spawn(move || -> io::Result<Aggregator> {
+                    let _span = span!(parent: &parent, DEBUG, "worker");
+                    if let Some(cores) = cores {
+                        cores.pin_worker(index, num_threads);
+                    }
+                    let pool = BufferPool::new(0, 1);
+                    while !failed.load(Ordering::Relaxed) && !options.cancelled() {
+                        let Some(path) = paths.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                            break;
+                        };
+                        let path = path.as_ref();
+                        let _file = span!(DEBUG, "file", path = %path.display());
+                        aggregator.set_next_line(1);
+                        aggregator.set_next_offset(0);
+                        let read = is_parquet(path).and_then(|parquet| {
+                            if parquet {
+                                return read_parquet(&mut aggregator, path, progress);
+                            }
+                            let (input, parse) = sniff_reader(open_input(path)?, options.parse)?;
+                            aggregator.set_parse(parse);
+                            let input = TimedReader::new(input, progress);
+                            read_blocks(input, &pool, true, |_, offset, block| {
+                                aggregator.set_next_offset(offset);
+                                track(progress, &mut aggregator, block.len(), |aggregator| {
+                                    aggregator.process_chunk(&block)
+                                })?;
+                                if let Some(live) = &mut live {
+                                    live.offer(&aggregator);
+                                }
+                                Ok(!options.cancelled())
+                            })
+                        });
+                        if let Err(err) = read {
+                            failed.store(true, Ordering::Relaxed);
+                            return Err(in_file(err, path));
+                        }
+                    }
+                    if let Some(live) = &mut live {
+                        live.publish(&aggregator);
+                    }
+                    Ok(aggregator)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Thread panicked"))
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+
+    Ok(merge_all(partials, options.progress.as_deref()))
+}
+
+#[cfg(feature = "rayon")]
+fn aggregate_rayon(path: &Path, options: &Options) -> io::Result<Aggregator> {
+    match map_file(path) {
+        Ok(mmap) => crate::parallel::process_rayon(&mmap, options),
+        Err(_err) => {
+            event!(WARN, error = %_err, "mmap unavailable, falling back to buffered reads");
+            process_chunked(path, options)
+        }
+    }
+}
+
+#[cfg(not(feature = "rayon"))]
+fn aggregate_rayon(_: &Path, _: &Options) -> io::Result<Aggregator> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the rayon engine needs the `rayon` feature",
+    ))
+}
+
+/// Whether `path` can be read at any offset: not a stream or URL, nor a
+/// Parquet or compressed file.
+fn is_plain_file(path: &Path) -> io::Result<bool> {
+    if is_stream(path) || is_url(path) {
+        return Ok(false);
+    }
+    Ok(!is_parquet(path)? && Compression::detect(path)?.is_none())
+}
+
+/// Whether `path` is a URL rather than a file: `http://` or `https://`, or
+/// an object store's such as `s3://`, `gs://` or `az://`.
+pub fn is_url(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    as_url(path).is_some() || as_object_url(path).is_some()
+}
+
+fn as_url(path: &Path) -> Option<&str> {
+    let url = path.to_str()?;
+    let scheme = url.split_once("://")?.0;
+    (scheme == "http" || scheme == "https").then_some(url)
+}
+
+#[cfg(not(feature = "http"))]
+fn aggregate_url(_: &str, _: &Options) -> io::Result<Aggregator> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "http and https inputs need the `http` feature",
+    ))
+}
+
+/// URL schemes of the object stores `aggregate_object` reads from.
+const OBJECT_SCHEMES: [&str; 8] = ["s3", "s3a", "gs", "az", "adl", "azure", "abfs", "abfss"];
+
+fn as_object_url(path: &Path) -> Option<&str> {
+    let url = path.to_str()?;
+    let scheme = url.split_once("://")?.0;
+    OBJECT_SCHEMES.contains(&scheme).then_some(url)
+}
+
+#[cfg(not(feature = "cloud"))]
+fn aggregate_object(_: &str, _: &Options) -> io::Result<Aggregator> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "object store inputs need the `cloud` feature",
+    ))
+}
+
+/// Prefixes `err` with the file it came from.
+pub(crate) fn in_file(err: io::Error, path: &Path) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {err}", path.display()))
+}
+
+/// Aggregates a stream that can only be read front to back (a pipe, a socket,
+/// a decompressor). The calling thread cuts it into newline-aligned blocks of
+/// about [`STREAM_BLOCK_SIZE`] bytes for the worker pool; with at most
+/// `queue_depth` blocks in flight, memory use does not depend on the input.
+pub fn aggregate_reader(reader: impl Read, options: &Options) -> io::Result<Aggregator> {
+    let _span = span!(INFO, "aggregate_reader");
+    let (reader, parse) = sniff_reader(reader, options.parse)?;
+    let options = &Options {
+        parse,
+        ..options.clone()
+    };
+    run_pool(options, |send, _, pool| {
+        let reader = TimedReader::new(reader, options.progress.as_deref());
+        read_blocks(reader, pool, true, |line, offset, block| {
+            Ok(send(line, offset, block))
+        })
+    })
+}
+
+/// Cuts `reader` into newline-aligned blocks of about [`STREAM_BLOCK_SIZE`]
+/// bytes and passes each to `each` with the number of its first line and its
+/// byte offset, until the input ends or `each` returns false. A byte order
+/// mark at the start of the stream is dropped if the stream is the start of
+/// the file, `at_start`. Blocks are taken from `pool`.
+fn read_blocks(
+    mut reader: impl Read,
+    pool: &BufferPool,
+    mut at_start: bool,
+    mut each: impl FnMut(u64, u64, Buffer) -> io::Result<bool>,
+) -> io::Result<()> {
+    let mut next_line = 1;
+    let mut next_offset = 0;
+    let mut carry = Vec::new();
+    loop {
+        let mut block = pool.get();
+        block.reserve(carry.len() + STREAM_BLOCK_SIZE);
+        block.append(&mut carry);
+        let mut eof = false;
+        // A line longer than a block keeps the block growing until it ends
+        let cut = loop {
+            let wanted = STREAM_BLOCK_SIZE as u64;
+            let read = reader.by_ref().take(wanted).read_to_end(&mut block)?;
+            if (read as u64) < wanted {
+                eof = true;
+                break block.len();
+            }
+            if let Some(pos) = memrchr(b'\n', &block) {
+                break pos + 1;
+            }
+        };
+        carry.extend_from_slice(&block[cut..]);
+        block.truncate(cut);
+
+        let mut offset = next_offset;
+        next_offset += block.len() as u64;
+        if at_start && block.starts_with(BOM) {
+            block.drain(..BOM.len());
+            offset += BOM.len() as u64;
+        }
+        at_start = false;
+        if block.is_empty() {
+            return Ok(());
+        }
+        let first_line = next_line;
+        next_line += memchr_iter(b'\n', &block).count() as u64;
+        if !each(first_line, offset, block)? || eof {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads lines on the calling thread and hands batches of `batch_size` lines
+/// to the worker pool, or of a size [`BatchSizer`] picks as it goes. A batch
+/// is one pooled buffer of whole lines, copied out of the reader's buffer as
+/// they are, so no line gets an allocation of its own.
+fn process_batched(path: &Path, options: &Options) -> io::Result<Aggregator> {
+    let len = fs::metadata(path)?.len();
+    let file = open_range(path, 0, len, options.io_backend, options.direct_io)?;
+    let mut reader = BufReader::new(TimedReader::new(file, options.progress.as_deref()));
+    let mut batch_size = options.batch_size.max(1);
+    let mut sizer = BatchSizer::new(batch_size);
+
+    run_pool(options, |send, feedback, pool| {
+        let mut next_line = 1;
+        let mut next_offset = 0;
+        // The batch starting at `first_line` ready to send, and its offset
+        let mut cut = |first_line, batch: Buffer| {
+            let len = batch.len() as u64;
+            let batch = without_bom(first_line, batch);
+            let offset = next_offset + len - batch.len() as u64;
+            next_offset += len;
+            (offset, batch)
+        };
+        let mut batch = pool.get();
+        let mut lines = 0;
+        loop {
+            let buffer = reader.fill_buf()?;
+            if buffer.is_empty() {
+                break;
+            }
+            // Take lines up to the end of the batch, or the whole buffer
+            let mut taken = buffer.len();
+            let mut found = 0;
+            for newline in memchr_iter(b'\n', buffer) {
+                found += 1;
+                if lines + found == batch_size {
+                    taken = newline + 1;
+                    break;
+                }
+            }
+            batch.extend_from_slice(&buffer[..taken]);
+            reader.consume(taken);
+            lines += found;
+            if lines == batch_size {
+                let bytes_per_line = batch.len() / lines;
+                let first_line = next_line;
+                next_line += lines as u64;
+                lines = 0;
+                let full = std::mem::replace(&mut batch, pool.get());
+                let (offset, full) = cut(first_line, full);
+                if !send(first_line, offset, full) {
+                    return Ok(());
+                }
+                if options.adaptive_batch {
+                    sizer.adjust(feedback);
+                    batch_size = sizer.size();
+                }
+                batch.reserve(batch_size * bytes_per_line);
+            }
+        }
+
+        // Handle remaining lines
+        if !batch.is_empty() {
+            let (offset, batch) = cut(next_line, batch);
+            send(next_line, offset, batch);
+        }
+        Ok(())
+    })
+}
+
+/// `batch` without the byte order mark it starts with if it is the start of
+/// the file.
+fn without_bom(first_line: u64, mut batch: Buffer) -> Buffer {
+    if first_line == 1 && batch.starts_with(BOM) {
+        batch.drain(..BOM.len());
+    }
+    batch
+}
+
+/// Runs `feed` on the calling thread while a pool of `options.threads`
+/// workers folds the items it produces into per-worker aggregators.
+///
+/// `feed` hands out newline-aligned blocks of whole lines through
+/// `send(first_line, offset, block)`, where `first_line` is the number of
+/// the block's first line and `offset` the byte it starts at; `send` returns false once feeding should stop
+/// because a strict-mode worker failed or the run was cancelled. The queue
+/// is bounded so the reader blocks instead of buffering the whole input.
+/// `feed` is told how the workers keep up through a [`Feedback`], and takes
+/// its blocks from a [`BufferPool`] sized to the queue, which they go back
+/// to once a worker is done with them.
+///
+/// The workers are scoped to the call, so they borrow `options` and the
+/// shared state instead of owning copies of it.
+pub(crate) fn run_pool(
+    options: &Options,
+    feed: impl FnOnce(
+        &mut dyn FnMut(u64, u64, Buffer) -> bool,
+        &Feedback,
+        &BufferPool,
+    ) -> io::Result<()>,
+) -> io::Result<Aggregator> {
+    let num_threads = options.threads.max(1);
+    let queue_depth = options
+        .queue_depth
+        .unwrap_or(num_threads * QUEUE_DEPTH_PER_WORKER)
+        .max(1);
+    let (mut producer, consumer) = ring::<(u64, u64, Buffer)>(queue_depth);
+    let failed = AtomicBool::new(false);
+    let feedback = Feedback::new(queue_depth);
+    let pool = BufferPool::new(queue_depth, num_threads);
+    let cores = options.cores()?;
+    let _pinned = cores.as_ref().map(|cores| cores.pin_coordinator());
+    let progress = options.progress.as_deref();
+    let spill = spill(options, num_threads)?;
+
+    let (fed, results) = thread::scope(|scope| {
+        // Each worker accumulates into one map for its whole lifetime and
+        // hands it back when the queue closes, so only `num_threads` maps
+        // need merging.
+        let handles: Vec<_> = (0..num_threads)
+            .map(|index| {
+                let items = consumer.clone();
+                let (failed, feedback, cores) = (&failed, &feedback, cores.as_deref());
+                let spill = spill.as_ref();
+                let mut live = options.live.as_deref().map(LiveResults::worker);
+                let mut aggregator = options.aggregator();
+                let parent = current_span!();
+                scope.spawn(move || -> Result<Aggregator, MalformedLine> {
+                    let _span = span!(parent: &parent, DEBUG, "worker");
+                    if let Some(cores) = cores {
+                        cores.pin_worker(index, num_threads);
+                    }
+                    for (first_line, offset, block) in items {
+                        if let Some(progress) = progress {
+                            progress.dequeued();
+                        }
+                        aggregator.set_next_line(first_line);
+                        aggregator.set_next_offset(offset);
+                        let (started, rows_before) = (Instant::now(), aggregator.rows());
+                        let parsed = track(progress, &mut aggregator, block.len(), |aggregator| {
+                            aggregator.process_chunk(&block)
+                        });
+                        feedback.finished(aggregator.rows() - rows_before, started.elapsed());
+                        if let Err(err) = parsed {
+                            event!(DEBUG, line = err.line, "malformed line, stopping");
+                            failed.store(true, Ordering::Relaxed);
+                            return Err(err);
+                        }
+                        if let Some(spill) = spill
+                            && !spill.offer(&mut aggregator)
+                        {
+                            failed.store(true, Ordering::Relaxed);
+                            break;
+                        }
+                        if let Some(live) = &mut live {
+                            live.offer(&aggregator);
+                        }
+                    }
+                    if let Some(live) = &mut live {
+                        live.publish(&aggregator);
+                    }
+                    event!(DEBUG, rows = aggregator.rows(), "queue closed");
+                    Ok(aggregator)
+                })
+            })
+            .collect();
+        drop(consumer);
+
+        let fed = {
+            let _span = span!(DEBUG, "reader", queue_depth);
+            let mut send = |first_line, offset, block| {
+                if failed.load(Ordering::Relaxed) || options.cancelled() {
+                    return false;
+                }
+                if let Some(progress) = progress {
+                    progress.queued();
+                }
+                feedback.set_queued(producer.len());
+                producer.push((first_line, offset, block)).is_ok()
+            };
+            feed(&mut send, &feedback, &pool)
+        };
+        drop(producer); // Workers exit once the queue drains
+        let results: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Thread panicked"))
+            .collect();
+        (fed, results)
+    });
+
+    let mut partials = Vec::with_capacity(results.len());
+    let mut first_error: Option<MalformedLine> = None;
+    for result in results {
+        match result {
+            Ok(aggregator) => partials.push(aggregator),
+            Err(err) => {
+                if first_error.as_ref().is_none_or(|first| err.line < first.line) {
+                    first_error = Some(err);
+                }
+            }
+        }
+    }
+    if let Some(err) = first_error {
+        return Err(err.into());
+    }
+    fed?;
+
+    match spill {
+        Some(spill) => spill.merge(partials, progress),
+        None => Ok(merge_all(partials, progress)),
+    }
+}
+
+/// Where the workers of a pool spill with [`Options::max_memory`].
+#[cfg(feature = "spill")]
+fn spill(options: &Options, workers: usize) -> io::Result<Option<Spill>> {
+    options
+        .max_memory
+        .map(|limit| Spill::new(limit, workers))
+        .transpose()
+}
+
+#[cfg(not(feature = "spill"))]
+fn spill(options: &Options, _: usize) -> io::Result<Option<Spill>> {
+    match options.max_memory {
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "a memory limit needs the `spill` feature",
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Without the `spill` feature there is never a spill to offer maps to.
+#[cfg(not(feature = "spill"))]
+enum Spill {}
+
+#[cfg(not(feature = "spill"))]
+impl Spill {
+    fn offer(&self, _: &mut Aggregator) -> bool {
+        match *self {}
+    }
+
+    fn merge(&self, _: Vec<Aggregator>, _: Option<&Progress>) -> io::Result<Aggregator> {
+        match *self {}
+    }
+}
+
+/// Splits the file into `num_threads` byte ranges aligned to line starts and
+/// lets every worker open, read and parse its own range.
+///
+/// Workers number lines from the start of their own range; the numbers are
+/// made global afterwards from the line counts of the preceding ranges.
+fn process_chunked(path: &Path, options: &Options) -> io::Result<Aggregator> {
+    process_ranges(path, chunk_ranges(path, options.threads.max(1))?, options)
+}
+
+/// Aggregates the given consecutive byte ranges of `path`, one worker each.
+/// Line numbers count from the start of the first range.
+pub(crate) fn process_ranges(
+    path: &Path,
+    ranges: Vec<(u64, u64)>,
+    options: &Options,
+) -> io::Result<Aggregator> {
+    let _span = span!(DEBUG, "ranges", ranges = ranges.len());
+    let cores = options.cores()?;
+    let _pinned = cores.as_ref().map(|cores| cores.pin_coordinator());
+    let workers = ranges.len();
+    let handles: Vec<_> = ranges
+        .into_iter()
+        .enumerate()
+        .map(|(index, (start, end))| {
+            let path = path.to_path_buf();
+            let (io_backend, direct_io) = (options.io_backend, options.direct_io);
+            let progress = options.progress.clone();
+            let cancel = options.cancel.clone();
+            let live = options.live.clone();
+            let cores = cores.clone();
+            let mut aggregator = options.aggregator();
+            let parent = current_span!();
+            thread::spawn(move || -> io::Result<Aggregator> {
+                let _span = span!(parent: &parent, DEBUG, "worker", start, end);
+                if let Some(cores) = cores {
+                    cores.pin_worker(index, workers);
+                }
+                let progress = progress.as_deref();
+                let mut live = live.as_deref().map(LiveResults::worker);
+                let range = open_range(&path, start, end, io_backend, direct_io)?;
+                let range = TimedReader::new(range, progress);
+                let pool = BufferPool::new(0, 1);
+                read_blocks(range, &pool, start == 0, |_, offset, block| {
+                    aggregator.set_next_offset(start + offset);
+                    track(progress, &mut aggregator, block.len(), |aggregator| {
+                        aggregator.process_chunk(&block)
+                    })?;
+                    if let Some(live) = &mut live {
+                        live.offer(&aggregator);
+                    }
+                    Ok(!cancelled(cancel.as_deref()))
+                })?;
+                if let Some(live) = &mut live {
+                    live.publish(&aggregator);
+                }
+                event!(DEBUG, rows = aggregator.rows(), "range done");
+                Ok(aggregator)
+            })
+        })
+        .collect();
+
+    let mut partials = Vec::with_capacity(handles.len());
+    let mut lines_before = 0;
+    for handle in handles {
+        match handle.join().expect("Thread panicked") {
+            Ok(mut aggregator) => {
+                aggregator.shift_line_numbers(lines_before);
+                lines_before += aggregator.rows();
+                partials.push(aggregator);
+            }
+            Err(err) => return Err(shift_malformed(err, lines_before)),
+        }
+    }
+
+    Ok(merge_all(partials, options.progress.as_deref()))
+}
+
+/// Opens the bytes `start..end` of `path` for reading through `backend`,
+/// with `O_DIRECT` if `direct`.
+fn open_range(
+    path: &Path,
+    start: u64,
+    end: u64,
+    backend: IoBackend,
+    direct: bool,
+) -> io::Result<Box<dyn Read + Send>> {
+    match backend {
+        IoBackend::Std if direct => direct_reader(path, start, end),
+        IoBackend::Std => {
+            let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(start))?;
+            Ok(Box::new(file.take(end - start)))
+        }
+        #[cfg(all(feature = "uring", target_os = "linux"))]
+        IoBackend::Uring => {
+            let reader = crate::uring::UringReader::open(path, start, end, direct)?;
+            Ok(Box::new(reader))
+        }
+        #[cfg(not(all(feature = "uring", target_os = "linux")))]
+        IoBackend::Uring => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the io_uring backend needs the `uring` feature and Linux",
+        )),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn direct_reader(path: &Path, start: u64, end: u64) -> io::Result<Box<dyn Read + Send>> {
+    let reader = crate::direct::DirectReader::open(path, start, end)?;
+    Ok(Box::new(reader))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn direct_reader(_: &Path, _: u64, _: u64) -> io::Result<Box<dyn Read + Send>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "direct I/O is only available on Linux",
+    ))
+}
+
+/// Parses a memory-mapped file, giving each worker a borrowed slice of it so
+/// no per-line buffers are allocated. Station keys stay borrowed from the
+/// mapping until the per-worker maps have been merged. Threads are pinned to
+/// `cores` if given.
+fn process_mapped(mmap: &Mmap, options: &Options, cores: Option<&Cores>) -> io::Result<Aggregator> {
+    if options.huge_pages {
+        advise_huge_pages(mmap);
+    }
+    match options.engine {
+        Engine::CustomMap | Engine::HashOnly => {
+            parse_slices::<TableAggregator>(mmap, options, cores)
+        }
+        _ => parse_slices::<BorrowedAggregator>(mmap, options, cores),
+    }
+}
+
+/// The per-worker aggregators of [`process_mapped`], whose keys borrow from
+/// the mapping.
+trait SliceAggregator<'a>: Counts + Send + Sized {
+    fn new(options: &Options) -> Self;
+    fn process_chunk(&mut self, chunk: &'a [u8]) -> Result<(), MalformedLine>;
+    fn rows(&self) -> u64;
+    fn shift_line_numbers(&mut self, base: u64);
+    fn set_next_offset(&mut self, offset: u64);
+    fn merge(&mut self, other: Self);
+    /// Fails if the aggregator found out that it cannot be trusted.
+    fn verify(&self) -> io::Result<()> {
+        Ok(())
+    }
+    fn into_owned(self) -> Aggregator;
+}
+
+impl<'a> SliceAggregator<'a> for BorrowedAggregator<'a> {
+    fn new(options: &Options) -> Self {
+        options.borrowed_aggregator()
+    }
+
+    fn process_chunk(&mut self, chunk: &'a [u8]) -> Result<(), MalformedLine> {
+        BorrowedAggregator::process_chunk(self, chunk)
+    }
+
+    fn rows(&self) -> u64 {
+        BorrowedAggregator::rows(self)
+    }
+
+    fn shift_line_numbers(&mut self, base: u64) {
+        BorrowedAggregator::shift_line_numbers(self, base);
+    }
+
+    fn set_next_offset(&mut self, offset: u64) {
+        BorrowedAggregator::set_next_offset(self, offset);
+    }
+
+    fn merge(&mut self, other: Self) {
+        BorrowedAggregator::merge(self, other);
+    }
+
+    fn into_owned(self) -> Aggregator {
+        BorrowedAggregator::into_owned(self)
+    }
+}
+
+impl<'a> SliceAggregator<'a> for TableAggregator<'a> {
+    fn new(options: &Options) -> Self {
+        TableAggregator::new(
+            options.parse,
+            options.stats,
+            options.filter.clone(),
+            options.known_stations.clone(),
+            options.aliases.clone(),
+            match options.engine {
+                Engine::HashOnly if options.verify_collisions => KeyCheck::Verify,
+                Engine::HashOnly => KeyCheck::HashOnly,
+                _ => KeyCheck::Full,
+            },
+        )
+    }
+
+    fn process_chunk(&mut self, chunk: &'a [u8]) -> Result<(), MalformedLine> {
+        TableAggregator::process_chunk(self, chunk)
+    }
+
+    fn rows(&self) -> u64 {
+        TableAggregator::rows(self)
+    }
+
+    fn shift_line_numbers(&mut self, base: u64) {
+        TableAggregator::shift_line_numbers(self, base);
+    }
+
+    fn set_next_offset(&mut self, offset: u64) {
+        TableAggregator::set_next_offset(self, offset);
+    }
+
+    fn merge(&mut self, other: Self) {
+        TableAggregator::merge(self, other);
+    }
+
+    fn verify(&self) -> io::Result<()> {
+        TableAggregator::verify(self)
+    }
+
+    fn into_owned(self) -> Aggregator {
+        TableAggregator::into_owned(self)
+    }
+}
+
+/// The workers of [`process_mapped`], one slice of the mapping and one `A`
+/// each.
+fn parse_slices<'a, A: SliceAggregator<'a>>(
+    mmap: &'a Mmap,
+    options: &Options,
+    cores: Option<&Cores>,
+) -> io::Result<Aggregator> {
+    let data = strip_bom(mmap);
+    let progress = options.progress.as_deref();
+    let _span = span!(DEBUG, "mapped", bytes = data.len());
+    let _pinned = cores.map(Cores::pin_coordinator);
+
+    let merged = thread::scope(|scope| {
+        let slices = slice_chunks(data, options.threads.max(1));
+        let workers = slices.len();
+        let handles: Vec<_> = slices
+            .into_iter()
+            .enumerate()
+            .map(|(index, mut rest)| {
+                let mut aggregator = A::new(options);
+                let parent = current_span!();
+                scope.spawn(move || -> io::Result<_> {
+                    let _span = span!(parent: &parent, DEBUG, "worker", bytes = rest.len());
+                    if let Some(cores) = cores {
+                        cores.pin_worker(index, workers);
+                    }
+                    // Read ahead from the worker's own node
+                    advise_sequential(mmap, rest);
+                    // Parse in newline-aligned blocks so progress moves during the run
+                    while !rest.is_empty() && !options.cancelled() {
+                        let block = next_block(&mut rest);
+                        aggregator.set_next_offset(offset_in(mmap, block));
+                        track(progress, &mut aggregator, block.len(), |aggregator| {
+                            aggregator.process_chunk(block)
+                        })?;
+                        aggregator.verify()?;
+                    }
+                    event!(DEBUG, rows = aggregator.rows(), "slice done");
+                    Ok(aggregator)
+                })
+            })
+            .collect();
+
+        let mut merged = A::new(options);
+        let mut lines_before = 0;
+        for handle in handles {
+            match handle.join().expect("Thread panicked") {
+                Ok(mut aggregator) => {
+                    aggregator.shift_line_numbers(lines_before);
+                    lines_before += aggregator.rows();
+                    timed(progress, Stage::Merge, || merged.merge(aggregator));
+                }
+                Err(err) => return Err(shift_malformed(err, lines_before)),
+            }
+        }
+        merged.verify()?;
+        Ok(merged)
+    })?;
+
+    Ok(timed(progress, Stage::Merge, || merged.into_owned()))
+}
+
+/// Takes the first newline-aligned block of about [`STREAM_BLOCK_SIZE`]
+/// bytes off `rest`.
+pub(crate) fn next_block<'a>(rest: &mut &'a [u8]) -> &'a [u8] {
+    let tail = rest.get(STREAM_BLOCK_SIZE..).unwrap_or_default();
+    let end = memchr(b'\n', tail).map_or(rest.len(), |pos| STREAM_BLOCK_SIZE + pos + 1);
+    let (block, after) = rest.split_at(end);
+    *rest = after;
+    block
+}
+
+/// [`Aggregator::merge_all`], timed as [`Stage::Merge`].
+/// Where `part`, a slice of `data`, starts in it.
+pub(crate) fn offset_in(data: &[u8], part: &[u8]) -> u64 {
+    (part.as_ptr() as usize - data.as_ptr() as usize) as u64
+}
+
+pub(crate) fn merge_all(partials: Vec<Aggregator>, progress: Option<&Progress>) -> Aggregator {
+    let _span = span!(DEBUG, "merge", parts = partials.len());
+    timed(progress, Stage::Merge, || Aggregator::merge_all(partials))
+}
+
+/// Makes the line number of a [`MalformedLine`] wrapped in `err` global.
+pub(crate) fn shift_malformed(err: io::Error, lines_before: u64) -> io::Error {
+    match err.downcast::<MalformedLine>() {
+        Ok(mut malformed) => {
+            malformed.line += lines_before;
+            malformed.into()
+        }
+        Err(err) => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::results::Results;
+    use crate::testing::TempFile;
+
+    const MODES: [Mode; 4] = [Mode::Batched, Mode::Chunked, Mode::Mmap, Mode::Pipelined];
+
+    /// A reader that hands out one byte per read.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((&byte, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = byte;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    /// A BOM and `len` bytes in all of CRLF lines, each of the `cr_at`
+    /// offsets (ascending, at least 32 apart) holding the `\r` of a line
+    /// ending, so a boundary right after it splits the `\r\n`.
+    fn crlf_input(cr_at: &[usize], len: usize) -> Vec<u8> {
+        let mut data = BOM.to_vec();
+        let mut line = 0;
+        for &end in cr_at.iter().chain([&(len - 2)]) {
+            // Whole lines, then one with its name padded to end at `end`
+            while data.len() + 32 < end {
+                let temp: i32 = line * 37 % 1999 - 999;
+                let sign = if temp < 0 { "-" } else { "" };
+                let (whole, tenth) = (temp.abs() / 10, temp.abs() % 10);
+                let station = line % 13;
+                data.extend_from_slice(format!("St{station};{sign}{whole}.{tenth}\r\n").as_bytes());
+                line += 1;
+            }
+            let name = "x".repeat(end - data.len() - ";1.0".len());
+            data.extend_from_slice(format!("{name};1.0\r\n").as_bytes());
+            assert_eq!(data[end], b'\r');
+        }
+        assert_eq!(data.len(), len);
+        data
+    }
+
+    /// What aggregating `input` with its line endings made LF and its BOM
+    /// dropped gives.
+    fn expected(input: &[u8]) -> Results {
+        let lf: Vec<u8> = strip_bom(input)
+            .iter()
+            .copied()
+            .filter(|&byte| byte != b'\r')
+            .collect();
+        let mut aggregator = Options::default().aggregator();
+        aggregator.process_chunk(&lf).unwrap();
+        aggregator.into_results()
+    }
+
+    fn aggregate(path: &Path, mode: Mode, threads: usize) -> Results {
+        let options = Options {
+            mode,
+            threads,
+            batch_size: 1000,
+            ..Options::default()
+        };
+        let aggregator = aggregate_file(path, &options).unwrap();
+        assert_eq!(
+            aggregator.skipped().count(),
+            0,
+            "{mode:?} on {threads} thread(s)"
+        );
+        aggregator.into_results()
+    }
+
+    #[test]
+    fn crlf_and_bom_in_every_mode() {
+        let input = crlf_input(&[100, 1000], 5000);
+        let file = TempFile::new("crlf-small", &input);
+        let expected = expected(&input);
+        for mode in MODES {
+            for threads in 1..=4 {
+                assert_eq!(
+                    aggregate(&file.0, mode, threads),
+                    expected,
+                    "{mode:?}, {threads} thread(s)"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn crlf_straddling_block_and_chunk_boundaries_in_every_mode() {
+        // The `\r` ends the 8 KiB buffer of a batched read, the first
+        // quarter and half of the file (chunk ends for 4 and 2 threads), and a
+        // block or stripe of `STREAM_BLOCK_SIZE`
+        let len = 2 * STREAM_BLOCK_SIZE;
+        let input = crlf_input(&[(8 << 10) - 1, len / 4 - 1, STREAM_BLOCK_SIZE - 1], len);
+        let file = TempFile::new("crlf-straddle", &input);
+        let expected = expected(&input);
+        for mode in MODES {
+            for threads in [1, 2, 4] {
+                assert_eq!(
+                    aggregate(&file.0, mode, threads),
+                    expected,
+                    "{mode:?}, {threads} thread(s)"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bom_straddling_chunk_boundaries_in_every_mode() {
+        // With 8 threads on so few bytes the first chunk ends inside the BOM
+        let input = b"\xEF\xBB\xBFOslo;-3.4\r\nLima;18.2\r\nOslo;1.0\r\n";
+        let file = TempFile::new("bom-straddle", input);
+        let expected = expected(input);
+        for mode in MODES {
+            for threads in 1..=8 {
+                assert_eq!(
+                    aggregate(&file.0, mode, threads),
+                    expected,
+                    "{mode:?}, {threads} thread(s)"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_lines_get_their_byte_offset_in_every_mode() {
+        // Bad lines first, at the chunk ends for 4 and 2 threads and last,
+        // after a BOM and CRLF lines
+        let lines = 2 * STREAM_BLOCK_SIZE / 10;
+        let mut input = BOM.to_vec();
+        let mut bad = Vec::new();
+        for line in 1..=lines {
+            if [1, lines / 4 + 1, lines / 2 + 1, lines].contains(&line) {
+                bad.push((line as u64, input.len() as u64));
+                input.extend_from_slice(b"oops\r\n");
+            } else {
+                input.extend_from_slice(b"Oslo;1.0\r\n");
+            }
+        }
+        let file = TempFile::new("malformed-offsets", &input);
+        let found = |aggregator: Aggregator| -> Vec<(u64, u64)> {
+            let skipped = aggregator.skipped();
+            assert_eq!(skipped.count(), bad.len() as u64);
+            skipped.samples().map(|s| (s.line, s.offset)).collect()
+        };
+        for mode in MODES {
+            for threads in [1, 2, 4] {
+                let options = Options {
+                    mode,
+                    threads,
+                    batch_size: 1000,
+                    ..Options::default()
+                };
+                let aggregator = aggregate_file(&file.0, &options).unwrap();
+                assert_eq!(found(aggregator), bad, "{mode:?}, {threads} thread(s)");
+            }
+        }
+        let options = Options {
+            threads: 2,
+            ..Options::default()
+        };
+        let aggregator = aggregate_reader(input.as_slice(), &options).unwrap();
+        assert_eq!(found(aggregator), bad, "reader");
+        let mut aggregator = Aggregator::new();
+        aggregator.process_reader(input.as_slice()).unwrap();
+        let first = aggregator.skipped().samples().next().unwrap().to_string();
+        assert!(first.starts_with("line 1 (byte 3): "), "{first}");
+        assert_eq!(found(aggregator), bad, "lines");
+    }
+
+    fn blocks(reader: impl Read) -> Vec<(u64, u64, Vec<u8>)> {
+        blocks_from(reader, true)
+    }
+
+    fn blocks_from(reader: impl Read, at_start: bool) -> Vec<(u64, u64, Vec<u8>)> {
+        let pool = BufferPool::new(2, 1);
+        let mut blocks = Vec::new();
+        read_blocks(reader, &pool, at_start, |line, offset, block| {
+            blocks.push((line, offset, block.to_vec()));
+            Ok(true)
+        })
+        .unwrap();
+        blocks
+    }
+
+    #[test]
+    fn read_blocks_drops_a_bom_split_between_reads() {
+        let input = b"\xEF\xBB\xBFOslo;-3.4\r\nLima;18.2\r\n";
+        assert_eq!(
+            blocks(Trickle(input)),
+            [(1, 3, b"Oslo;-3.4\r\nLima;18.2\r\n".to_vec())]
+        );
+    }
+
+    #[test]
+    fn read_blocks_keeps_crlf_straddling_a_block_whole() {
+        let input = crlf_input(&[STREAM_BLOCK_SIZE - 1], STREAM_BLOCK_SIZE + 100);
+        let blocks = blocks(input.as_slice());
+        assert_eq!(blocks.len(), 2);
+        let (first, second) = (&blocks[0].2, &blocks[1].2);
+        assert!(first.ends_with(b"\r\n") && !first.starts_with(BOM));
+        assert!(!second.starts_with(b"\n"));
+        assert_eq!(blocks[1].0, 1 + memchr_iter(b'\n', first).count() as u64);
+        assert_eq!(blocks[1].1, (BOM.len() + first.len()) as u64);
+        assert_eq!([first.as_slice(), second].concat(), strip_bom(&input));
+    }
+
+    #[test]
+    fn read_blocks_keeps_a_bom_after_the_first_line() {
+        let input = b"Oslo;-3.4\n\xEF\xBB\xBFLima;18.2\n";
+        assert_eq!(blocks(&input[..]), [(1, 0, input.to_vec())]);
+    }
+
+    #[test]
+    fn read_blocks_keeps_a_bom_in_the_middle_of_the_file() {
+        let input = b"\xEF\xBB\xBFLima;18.2\n";
+        assert_eq!(blocks_from(&input[..], false), [(1, 0, input.to_vec())]);
+    }
+
+    #[test]
+    fn ranges_keep_a_bom_at_the_start_of_a_later_range() {
+        let input = b"\xEF\xBB\xBFOslo;1.0\n\xEF\xBB\xBFLima;2.0\n";
+        let file = TempFile::new("bom-later-range", input);
+        let options = Options::default();
+        let ranges = process_ranges(&file.0, vec![(0, 12), (12, 24)], &options).unwrap();
+        let mut expected = Aggregator::new();
+        expected.process_chunk(&input[3..]).unwrap();
+        assert_eq!(ranges.into_results(), expected.into_results());
+    }
+
+    #[test]
+    fn many_files_are_read_in_the_mode_asked_for() {
+        let parts = [
+            &b"Oslo;-3.4\nLima;18.2\n"[..],
+            b"Oslo;1.0\r\nRiga;0.5\r\n",
+            b"Lima;-0.1",
+            b"Riga;9.9\nOslo;2.2\n",
+        ];
+        let files: Vec<_> = parts
+            .iter()
+            .enumerate()
+            .map(|(i, part)| TempFile::new(&format!("many-{i}"), part))
+            .collect();
+        let paths: Vec<_> = files.iter().map(|file| file.0.as_path()).collect();
+        let want = expected(&parts.join(&b"\n"[..]));
+        for mode in MODES {
+            let options = Options {
+                mode,
+                threads: 2,
+                ..Options::default()
+            };
+            let got = aggregate_files(&paths, &options).unwrap().into_results();
+            assert_eq!(got, want, "{mode:?}");
+        }
+        // The engine's own checks apply too
+        let options = Options {
+            engine: Engine::CustomMap,
+            count_only: true,
+            threads: 2,
+            ..Options::default()
+        };
+        let err = aggregate_files(&paths, &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn without_bom_only_strips_the_start_of_the_file() {
+        let pool = BufferPool::new(1, 1);
+        let batch = |bytes: &[u8]| {
+            let mut batch = pool.get();
+            batch.extend_from_slice(bytes);
+            batch
+        };
+        let input = b"\xEF\xBB\xBFOslo;-3.4\r\n";
+        assert_eq!(*without_bom(1, batch(input)), b"Oslo;-3.4\r\n");
+        assert_eq!(*without_bom(2, batch(input)), input);
+        assert_eq!(*without_bom(1, batch(b"Oslo;-3.4\r\n")), b"Oslo;-3.4\r\n");
+    }
+}
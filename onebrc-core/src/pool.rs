@@ -0,0 +1,64 @@
+//! Read buffers that cycle between the reader and the workers.
+
+use std::ops::{Deref, DerefMut};
+
+use crossbeam::channel::{self, Receiver, Sender};
+
+/// Buffers for blocks of input, handed out by [`BufferPool::get`] and put
+/// back when the [`Buffer`] is dropped, so buffers are only allocated (and
+/// grown) until the pipeline reaches its steady state.
+///
+/// The pool keeps at most as many buffers as can be in use at once: the
+/// queued ones, one per worker and the one being filled. A buffer dropped
+/// while the pool is full is freed.
+#[derive(Debug, Clone)]
+pub(crate) struct BufferPool {
+    free: Sender<Vec<u8>>,
+    take: Receiver<Vec<u8>>,
+}
+
+impl BufferPool {
+    /// A pool for a queue of `queue_depth` blocks between a reader and
+    /// `workers` workers.
+    pub(crate) fn new(queue_depth: usize, workers: usize) -> Self {
+        let (free, take) = channel::bounded(queue_depth + workers + 1);
+        BufferPool { free, take }
+    }
+
+    /// An empty buffer, with the capacity it had when last used.
+    pub(crate) fn get(&self) -> Buffer {
+        Buffer {
+            bytes: self.take.try_recv().unwrap_or_default(),
+            pool: self.free.clone(),
+        }
+    }
+}
+
+/// A buffer from a [`BufferPool`], which it returns to on drop.
+#[derive(Debug)]
+pub(crate) struct Buffer {
+    bytes: Vec<u8>,
+    pool: Sender<Vec<u8>>,
+}
+
+impl Deref for Buffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.bytes
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.bytes
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        let mut bytes = std::mem::take(&mut self.bytes);
+        bytes.clear();
+        let _ = self.pool.try_send(bytes);
+    }
+}
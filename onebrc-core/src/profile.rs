@@ -0,0 +1,118 @@
+use std::io::{self, Read};
+
+use memchr::memchr_iter;
+
+/// Lines at least this long share the last bucket of [`LineLengths`], so a
+/// file with no newlines cannot make it allocate without bound.
+pub const LONG_LINE: usize = 1024;
+
+/// How the line lengths of an input are spread, counted without parsing the
+/// lines, for sizing buffers and checking that generated data looks like the
+/// challenge's (at most 100 bytes of name and 5 of temperature).
+#[derive(Debug, Clone)]
+pub struct LineLengths {
+    /// Lines per length in bytes, the newline not counted.
+    counts: Vec<u64>,
+    longest: u64,
+    bytes: u64,
+    /// Bytes of every line, the newlines not counted.
+    content: u64,
+    /// Bytes of the line that the last read ended in.
+    current: u64,
+}
+
+impl Default for LineLengths {
+    fn default() -> Self {
+        LineLengths {
+            counts: vec![0; LONG_LINE + 1],
+            longest: 0,
+            bytes: 0,
+            content: 0,
+            current: 0,
+        }
+    }
+}
+
+impl LineLengths {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts the lines of `reader` to its end, a last one without a
+    /// newline included.
+    pub fn scan(&mut self, mut reader: impl Read) -> io::Result<()> {
+        let mut buf = vec![0; 1 << 20];
+        loop {
+            let read = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            let chunk = &buf[..read];
+            self.bytes += read as u64;
+            let mut start = 0;
+            for end in memchr_iter(b'\n', chunk) {
+                self.current += (end - start) as u64;
+                self.end_line();
+                start = end + 1;
+            }
+            self.current += (read - start) as u64;
+        }
+        if self.current > 0 {
+            self.end_line();
+        }
+        Ok(())
+    }
+
+    fn end_line(&mut self) {
+        self.counts[(self.current as usize).min(LONG_LINE)] += 1;
+        self.longest = self.longest.max(self.current);
+        self.content += self.current;
+        self.current = 0;
+    }
+
+    pub fn lines(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Bytes read, newlines included.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn shortest(&self) -> Option<u64> {
+        self.counts
+            .iter()
+            .position(|&count| count > 0)
+            .map(|length| length as u64)
+    }
+
+    pub fn longest(&self) -> Option<u64> {
+        (self.lines() > 0).then_some(self.longest)
+    }
+
+    /// Mean bytes per line, the newline not counted.
+    pub fn mean(&self) -> Option<f64> {
+        let lines = self.lines();
+        (lines > 0).then(|| self.content as f64 / lines as f64)
+    }
+
+    /// The length that a `q` share of the lines are no longer than, as
+    /// [`LONG_LINE`] if it is one of the long ones.
+    pub fn quantile(&self, q: f64) -> Option<u64> {
+        let lines = self.lines();
+        if lines == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * lines as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.counts
+            .iter()
+            .position(|&count| {
+                seen += count;
+                seen >= rank
+            })
+            .map(|length| length as u64)
+    }
+}
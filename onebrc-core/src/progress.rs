@@ -0,0 +1,175 @@
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+/// Counters that workers bump as they finish each block of input, so another
+/// thread can report progress while a run is under way. Attach one through
+/// [`Options::progress`](crate::Options::progress).
+#[derive(Debug, Default)]
+pub struct Progress {
+    bytes: AtomicU64,
+    rows: AtomicU64,
+    skipped: AtomicU64,
+    in_flight: AtomicU64,
+    /// Nanoseconds spent per [`Stage`], in `Stage::ALL` order.
+    stage_nanos: [AtomicU64; 3],
+    /// [`Stage::Parse`] time of every thread that has parsed, in the order
+    /// they started.
+    workers: Mutex<Vec<(ThreadId, Duration)>>,
+}
+
+/// A part of the pipeline whose time [`Progress::stage_time`] reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading (and decompressing) input, summed over the threads that read
+    Read,
+    /// Parsing and aggregating lines, summed over the workers
+    Parse,
+    /// Merging the per-worker partial results
+    Merge,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 3] = [Stage::Read, Stage::Parse, Stage::Merge];
+
+    /// Lowercase name, e.g. for metric labels.
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Read => "read",
+            Stage::Parse => "parse",
+            Stage::Merge => "merge",
+        }
+    }
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Progress::default()
+    }
+
+    /// Input bytes processed so far. For compressed input these are
+    /// decompressed bytes.
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Lines processed so far, malformed ones included.
+    pub fn rows(&self) -> u64 {
+        self.rows.load(Ordering::Relaxed)
+    }
+
+    /// Malformed lines skipped so far.
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Batches (or stream blocks) read but not yet picked up by a worker.
+    /// Always zero in the modes where workers read for themselves.
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Time spent in `stage` so far; for stages that run on several threads
+    /// at once this is the sum over them, so it can exceed the wall time.
+    pub fn stage_time(&self, stage: Stage) -> Duration {
+        Duration::from_nanos(self.stage_nanos[stage as usize].load(Ordering::Relaxed))
+    }
+
+    /// Time each worker thread has spent parsing so far, in the order the
+    /// workers took their first block; together they make up the
+    /// [`Stage::Parse`] time.
+    pub fn worker_times(&self) -> Vec<Duration> {
+        let workers = self.workers.lock().expect("progress lock");
+        workers.iter().map(|&(_, time)| time).collect()
+    }
+
+    pub(crate) fn record(&self, bytes: u64, rows: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.rows.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_skipped(&self, skipped: u64) {
+        self.skipped.fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_time(&self, stage: Stage, time: Duration) {
+        let nanos = u64::try_from(time.as_nanos()).unwrap_or(u64::MAX);
+        self.stage_nanos[stage as usize].fetch_add(nanos, Ordering::Relaxed);
+        if stage == Stage::Parse {
+            // Taken once per block, which takes far longer to parse
+            let id = thread::current().id();
+            let mut workers = self.workers.lock().expect("progress lock");
+            match workers.iter_mut().find(|(worker, _)| *worker == id) {
+                Some((_, total)) => *total += time,
+                None => workers.push((id, time)),
+            }
+        }
+    }
+
+    pub(crate) fn queued(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dequeued(&self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Line counts of an aggregator, for [`track`].
+pub(crate) trait Counts {
+    /// Lines processed and lines skipped so far.
+    fn counts(&self) -> (u64, u64);
+}
+
+/// Runs `parse` over a block of `bytes` input bytes and records in
+/// `progress` the lines it added to `aggregator`, the skipped ones among
+/// them and the time it took. Without `progress` it only runs `parse`.
+pub(crate) fn track<A: Counts, E>(
+    progress: Option<&Progress>,
+    aggregator: &mut A,
+    bytes: usize,
+    parse: impl FnOnce(&mut A) -> Result<(), E>,
+) -> Result<(), E> {
+    let Some(progress) = progress else {
+        return parse(aggregator);
+    };
+    let (rows, skipped) = aggregator.counts();
+    let start = Instant::now();
+    let parsed = parse(aggregator);
+    progress.add_time(Stage::Parse, start.elapsed());
+    let (rows_after, skipped_after) = aggregator.counts();
+    progress.record(bytes as u64, rows_after - rows);
+    progress.record_skipped(skipped_after - skipped);
+    parsed
+}
+
+/// Runs `f`, adding the time it took to `stage` in `progress` if there is one.
+pub(crate) fn timed<T>(progress: Option<&Progress>, stage: Stage, f: impl FnOnce() -> T) -> T {
+    let Some(progress) = progress else {
+        return f();
+    };
+    let start = Instant::now();
+    let value = f();
+    progress.add_time(stage, start.elapsed());
+    value
+}
+
+/// A reader whose `read` calls count towards [`Stage::Read`].
+pub(crate) struct TimedReader<'a, R> {
+    inner: R,
+    progress: Option<&'a Progress>,
+}
+
+impl<'a, R> TimedReader<'a, R> {
+    pub(crate) fn new(inner: R, progress: Option<&'a Progress>) -> Self {
+        TimedReader { inner, progress }
+    }
+}
+
+impl<R: Read> Read for TimedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        timed(self.progress, Stage::Read, || self.inner.read(buf))
+    }
+}
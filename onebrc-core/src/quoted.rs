@@ -0,0 +1,218 @@
+//! [`ParseOptions::quoted`] lines, whose fields may be RFC 4180 quoted:
+//! `"Washington, D.C.";4.3` or `"The ""Rock""";21.0`. Kept apart from the
+//! plain split so lines without quotes never pay for looking at them.
+
+use std::borrow::Cow;
+
+use memchr::{memchr, memchr_iter};
+
+use crate::parse::{Columns, ParseOptions, parse_temp, parse_temp_comma, strip_cr};
+use crate::skipped::SkipReason;
+
+/// Calls `f` for every line of `chunk` with what [`parse_quoted`] made of
+/// it, stopping at the first error. Lines ending in CRLF lose the `\r` too.
+#[inline]
+pub(crate) fn for_each_quoted<'a, E>(
+    chunk: &'a [u8],
+    options: &ParseOptions,
+    mut f: impl FnMut(&'a [u8], Result<(Cow<'a, [u8]>, i32), SkipReason>) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut record = |line: &'a [u8]| {
+        let line = strip_cr(line);
+        f(line, parse_quoted(line, options))
+    };
+    let mut start = 0;
+    for end in memchr_iter(b'\n', chunk) {
+        record(&chunk[start..end])?;
+        start = end + 1;
+    }
+    if start < chunk.len() {
+        record(&chunk[start..])?;
+    }
+    Ok(())
+}
+
+/// The station and the temperature, in tenths of a degree, of a line whose
+/// fields may be quoted. The station is borrowed from the line unless it
+/// holds doubled quotes. Without [`ParseOptions::columns`] the line must
+/// have exactly the two fields.
+pub(crate) fn parse_quoted<'l>(
+    line: &'l [u8],
+    options: &ParseOptions,
+) -> Result<(Cow<'l, [u8]>, i32), SkipReason> {
+    let columns = options.columns.unwrap_or(Columns {
+        station: 0,
+        temp: 1,
+    });
+    let (mut station, mut temp) = (None, None);
+    let mut rest = Some(line);
+    for column in 0..=columns.station.max(columns.temp) {
+        let Some(field_start) = rest else {
+            return Err(match column {
+                1 if options.columns.is_none() => SkipReason::NoDelimiter,
+                _ => SkipReason::MissingColumn,
+            });
+        };
+        let (field, after) = field(field_start, options.delimiter)?;
+        rest = after;
+        if column == columns.temp {
+            temp = Some(field.clone());
+        }
+        if column == columns.station {
+            station = Some(field);
+        }
+    }
+    if options.columns.is_none() && rest.is_some() {
+        return Err(SkipReason::BadTemperature);
+    }
+    let (station, temp) = station.zip(temp).ok_or(SkipReason::MissingColumn)?;
+    let digits = temp.trim_ascii();
+    let temp = parse_temp(digits)
+        .or_else(|| parse_temp_comma(digits, options.decimal_comma))
+        .ok_or(SkipReason::BadTemperature)?;
+    Ok((station, temp))
+}
+
+/// A field's contents, and what follows its delimiter if it has one.
+type Field<'l> = (Cow<'l, [u8]>, Option<&'l [u8]>);
+
+/// Takes the field at the start of `line`.
+#[inline]
+pub(crate) fn field(line: &[u8], delimiter: u8) -> Result<Field<'_>, SkipReason> {
+    let Some(quoted) = line.strip_prefix(b"\"") else {
+        return Ok(match memchr(delimiter, line) {
+            Some(pos) => (Cow::Borrowed(&line[..pos]), Some(&line[pos + 1..])),
+            None => (Cow::Borrowed(line), None),
+        });
+    };
+    let mut escaped = false;
+    let mut pos = 0;
+    loop {
+        pos += memchr(b'"', &quoted[pos..]).ok_or(SkipReason::BadQuoting)?;
+        if quoted.get(pos + 1) != Some(&b'"') {
+            break;
+        }
+        // A doubled quote stands for one
+        escaped = true;
+        pos += 2;
+    }
+    let (body, after) = (&quoted[..pos], &quoted[pos + 1..]);
+    let rest = match after.split_first() {
+        None => None,
+        Some((&byte, rest)) if byte == delimiter => Some(rest),
+        Some(_) => return Err(SkipReason::BadQuoting),
+    };
+    let body = match escaped {
+        false => Cow::Borrowed(body),
+        true => Cow::Owned(unescape(body)),
+    };
+    Ok((body, rest))
+}
+
+/// A quoted field's contents with every doubled quote made single.
+#[cold]
+fn unescape(body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len());
+    let mut iter = body.iter().copied();
+    while let Some(byte) = iter.next() {
+        out.push(byte);
+        if byte == b'"' {
+            iter.next();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &[u8]) -> Result<(Cow<'_, [u8]>, i32), SkipReason> {
+        parse_quoted(line, &ParseOptions::default())
+    }
+
+    #[test]
+    fn plain_and_quoted_fields() {
+        let (station, temp) = parse(b"Oslo;-3.4").unwrap();
+        assert!(matches!(station, Cow::Borrowed(b"Oslo")));
+        assert_eq!(temp, -34);
+
+        let comma = ParseOptions {
+            delimiter: b',',
+            ..ParseOptions::default()
+        };
+        let (station, temp) = parse_quoted(b"\"Washington, D.C.\",4.3", &comma).unwrap();
+        assert!(matches!(station, Cow::Borrowed(b"Washington, D.C.")));
+        assert_eq!(temp, 43);
+        assert_eq!(
+            parse(b"\"Oslo\";\"-3.4\"").unwrap(),
+            (Cow::from(&b"Oslo"[..]), -34)
+        );
+    }
+
+    #[test]
+    fn doubled_quotes_stand_for_one() {
+        let (station, temp) = parse(b"\"The \"\"Rock\"\"\";21.0").unwrap();
+        assert!(matches!(station, Cow::Owned(_)));
+        assert_eq!((&*station, temp), (&b"The \"Rock\""[..], 210));
+        assert_eq!(parse(b"\"\"\"\";1.0").unwrap().0, &b"\""[..]);
+    }
+
+    #[test]
+    fn a_quoted_decimal_comma() {
+        let options = ParseOptions {
+            decimal_comma: true,
+            ..ParseOptions::default()
+        };
+        assert_eq!(parse_quoted(b"Oslo;\"4,2\"", &options).unwrap().1, 42);
+        assert_eq!(parse(b"Oslo;\"4,2\""), Err(SkipReason::BadTemperature));
+    }
+
+    #[test]
+    fn broken_lines() {
+        assert_eq!(parse(b"\"Oslo;1.0"), Err(SkipReason::BadQuoting));
+        assert_eq!(parse(b"\"Oslo\"x;1.0"), Err(SkipReason::BadQuoting));
+        assert_eq!(parse(b"Oslo"), Err(SkipReason::NoDelimiter));
+        assert_eq!(parse(b"Oslo;1.0;2.0"), Err(SkipReason::BadTemperature));
+        assert_eq!(parse(b"Oslo;warm"), Err(SkipReason::BadTemperature));
+    }
+
+    #[test]
+    fn columns_pick_the_fields() {
+        let options = ParseOptions {
+            columns: Some(Columns {
+                station: 2,
+                temp: 0,
+            }),
+            ..ParseOptions::default()
+        };
+        let (station, temp) = parse_quoted(b"1.5;\"x;y\";\"Oslo\";extra", &options).unwrap();
+        assert_eq!((&*station, temp), (&b"Oslo"[..], 15));
+        assert_eq!(
+            parse_quoted(b"1.5;x", &options),
+            Err(SkipReason::MissingColumn)
+        );
+    }
+
+    #[test]
+    fn for_each_quoted_strips_cr_and_takes_a_last_line_without_newline() {
+        let mut lines = Vec::new();
+        let chunk = b"\"A;B\";1.0\r\nbad\n\"C\";2.0";
+        for_each_quoted(chunk, &ParseOptions::default(), |line, record| {
+            lines.push((
+                line.to_vec(),
+                record.map(|(station, temp)| (station.to_vec(), temp)),
+            ));
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+        assert_eq!(
+            lines,
+            [
+                (b"\"A;B\";1.0".to_vec(), Ok((b"A;B".to_vec(), 10))),
+                (b"bad".to_vec(), Err(SkipReason::NoDelimiter)),
+                (b"\"C\";2.0".to_vec(), Ok((b"C".to_vec(), 20))),
+            ]
+        );
+    }
+}
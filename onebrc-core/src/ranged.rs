@@ -0,0 +1,203 @@
+//! Remote files fetched several byte ranges at a time and parsed in order,
+//! whatever serves the ranges.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::thread;
+
+use crossbeam::channel::{self, Receiver, SendError, Sender};
+
+use crate::aggregator::Aggregator;
+use crate::pipeline::{Options, aggregate_reader};
+use crate::pool::{Buffer, BufferPool};
+use crate::trace::{event, span};
+
+/// Bytes asked for in one range request.
+const RANGE_SIZE: u64 = 8 << 20;
+
+/// Ranges each connection may have fetched ahead of the parser.
+const RANGES_AHEAD: usize = 2;
+
+/// Bytes at the end of an earlier partial download compared with the
+/// remote file before resuming it.
+const RESUME_CHECK: u64 = 4096;
+
+/// A remote file that can be read a byte range at a time.
+pub(crate) trait Source: Sync {
+    /// Bytes `start..end` of the file, in a buffer from `pool`.
+    fn fetch_range(&self, start: u64, end: u64, pool: &BufferPool) -> io::Result<Buffer>;
+}
+
+/// Aggregates the `total` bytes of `source`.
+///
+/// `options.connections` threads each fetch every Nth range of
+/// [`RANGE_SIZE`] bytes, at most a couple of ranges ahead, and the ranges
+/// are parsed in file order as [`aggregate_reader`] would a stream.
+///
+/// With `options.download_to`, everything fetched is also appended to that
+/// file. A file already there that is shorter than the remote one is taken
+/// for an interrupted download of it, if its last bytes match the remote
+/// file's: its bytes are parsed from disk and only the rest is fetched.
+pub(crate) fn aggregate_ranges(
+    source: &impl Source,
+    total: u64,
+    options: &Options,
+) -> io::Result<Aggregator> {
+    let mut have = 0;
+    let mut local = None;
+    let mut copy = None;
+    if let Some(path) = &options.download_to {
+        match fs::metadata(path) {
+            Ok(meta) if meta.len() > total => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} is larger than the remote file ({total} bytes), so it cannot be \
+                         a partial download of it",
+                        path.display()
+                    ),
+                ));
+            }
+            Ok(meta) => {
+                have = meta.len();
+                let mut file = File::open(path)?;
+                if !ends_like(source, &mut file, have)? {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{} does not match the start of the remote file; remove it to \
+                             download again",
+                            path.display()
+                        ),
+                    ));
+                }
+                local = Some(file);
+                event!(INFO, have, total, "resuming download");
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        copy = Some(OpenOptions::new().create(true).append(true).open(path)?);
+    }
+
+    let connections = options.connections.max(1);
+    let pool = BufferPool::new(connections * RANGES_AHEAD, connections);
+    thread::scope(|scope| {
+        let queues = (0..connections)
+            .map(|index| {
+                let (sender, receiver) = channel::bounded(RANGES_AHEAD);
+                let ranges = Ranges {
+                    source,
+                    start: have,
+                    total,
+                    first: index,
+                    step: connections,
+                };
+                let pool = &pool;
+                scope.spawn(move || {
+                    let _span = span!(DEBUG, "connection", index);
+                    // A closed queue means the run is over
+                    let _ = ranges.fetch(pool, &sender);
+                });
+                receiver
+            })
+            .collect();
+        let download = Download {
+            local,
+            queues,
+            next: 0,
+            current: pool.get(),
+            pos: 0,
+            copy,
+        };
+        aggregate_reader(download, options)
+    })
+}
+
+/// Whether the `len` bytes of `file` end like the first `len` bytes of
+/// `source`, leaving `file` at its start.
+fn ends_like(source: &impl Source, file: &mut File, len: u64) -> io::Result<bool> {
+    let start = len.saturating_sub(RESUME_CHECK);
+    if start == len {
+        return Ok(true);
+    }
+    let remote = source.fetch_range(start, len, &BufferPool::new(0, 0))?;
+    let mut local = vec![0; (len - start) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut local)?;
+    file.rewind()?;
+    Ok(local == *remote)
+}
+
+/// Every `step`th range of `source` between `start` and `total`, from
+/// range `first` on.
+struct Ranges<'a, S> {
+    source: &'a S,
+    start: u64,
+    total: u64,
+    first: usize,
+    step: usize,
+}
+
+impl<S: Source> Ranges<'_, S> {
+    /// Fetches the ranges into buffers from `pool` and queues them, until
+    /// the file ends or the queue is closed. Errors are queued too.
+    fn fetch(
+        &self,
+        pool: &BufferPool,
+        queue: &Sender<io::Result<Buffer>>,
+    ) -> Result<(), SendError<io::Result<Buffer>>> {
+        let mut at = self.start + self.first as u64 * RANGE_SIZE;
+        while at < self.total {
+            let end = (at + RANGE_SIZE).min(self.total);
+            let range = self.source.fetch_range(at, end, pool);
+            let failed = range.is_err();
+            queue.send(range)?;
+            if failed {
+                break;
+            }
+            at += self.step as u64 * RANGE_SIZE;
+        }
+        Ok(())
+    }
+}
+
+/// The remote file as one stream: the part an earlier run downloaded, then
+/// the fetched ranges in file order, one connection after the other.
+struct Download {
+    local: Option<File>,
+    queues: Vec<Receiver<io::Result<Buffer>>>,
+    next: usize,
+    current: Buffer,
+    pos: usize,
+    /// Where fetched ranges are appended, with `Options::download_to`.
+    copy: Option<File>,
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(local) = &mut self.local {
+            match local.read(buf)? {
+                0 => self.local = None,
+                read => return Ok(read),
+            }
+        }
+        while self.pos == self.current.len() {
+            // Connections close their queues once past the end of the file
+            let Ok(range) = self.queues[self.next % self.queues.len()].recv() else {
+                return Ok(0);
+            };
+            self.current = range?;
+            self.pos = 0;
+            self.next += 1;
+            if let Some(copy) = &mut self.copy {
+                copy.write_all(&self.current)?;
+            }
+        }
+        let rest = &self.current[self.pos..];
+        let read = rest.len().min(buf.len());
+        buf[..read].copy_from_slice(&rest[..read]);
+        self.pos += read;
+        Ok(read)
+    }
+}
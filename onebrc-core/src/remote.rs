@@ -0,0 +1,376 @@
+//! Aggregation spread over several machines: a coordinator hands byte ranges
+//! of its inputs to [`serve_worker`] processes over TCP and merges the
+//! partial results they send back.
+//!
+//! Workers open the input files themselves, so every worker must see them at
+//! the same paths as the coordinator (a shared or replicated filesystem),
+//! inside the directory the worker serves.
+//! Each worker holds one connection open for all of its jobs; messages are
+//! JSON, one per line.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::aggregator::{Aggregator, Snapshot};
+use crate::chunk::{chunk_ranges, split_range};
+use crate::columnar::is_parquet;
+use crate::compression::{Compression, is_stream};
+use crate::parse::ParseOptions;
+use crate::partition::confine;
+use crate::pipeline::{Options, aggregate_file, in_file, process_ranges};
+use crate::skipped::MalformedLine;
+use crate::sniff::sniff_file;
+use crate::stats::StatsOptions;
+use crate::trace::{current_span, event, span};
+
+/// Byte ranges per worker that a plain text file is cut into, so that a
+/// slow worker holds up the run by a fraction of its share at most.
+const RANGES_PER_WORKER: usize = 4;
+
+/// A piece of work sent to a worker.
+#[derive(Serialize, Deserialize)]
+struct Job {
+    path: PathBuf,
+    /// Newline-aligned byte range to aggregate, or `None` for the whole file
+    /// (compressed and Parquet files, which cannot be split).
+    range: Option<(u64, u64)>,
+    parse: ParseOptions,
+    stats: StatsOptions,
+}
+
+/// A worker's answer to a [`Job`]. Line numbers count from the start of the
+/// job's range.
+#[derive(Serialize, Deserialize)]
+enum Reply {
+    Done(Snapshot),
+    Malformed(MalformedLine),
+    Failed(String),
+}
+
+/// Answers jobs from coordinators connecting to `listener`, one thread per
+/// connection and at most `max_connections` of them, until accepting fails;
+/// connections beyond that are closed at once. Jobs bring their own parse
+/// and statistics options; the rest of `options` (threads, mode) applies to
+/// all of them. A job for a file outside `root` (a canonical path) fails
+/// without the file being opened.
+pub fn serve_worker(
+    listener: TcpListener,
+    options: &Options,
+    root: &Path,
+    max_connections: usize,
+) -> io::Result<()> {
+    let root: Arc<Path> = Arc::from(root);
+    let open = Arc::new(AtomicUsize::new(0));
+    loop {
+        let (stream, _peer) = listener.accept()?;
+        let Some(connection) = Connection::open(&open, max_connections) else {
+            event!(WARN, peer = %_peer, max_connections, "refusing coordinator, too many connected");
+            continue;
+        };
+        let options = options.clone();
+        let root = Arc::clone(&root);
+        thread::spawn(move || {
+            let _connection = connection;
+            if let Err(_err) = serve_connection(stream, &options, &root) {
+                event!(WARN, peer = %_peer, error = %_err, "coordinator connection failed");
+            }
+        });
+    }
+}
+
+/// One of the connections [`serve_worker`] counts, given back when dropped.
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    /// Counts in another connection, unless `max` are open already.
+    fn open(open: &Arc<AtomicUsize>, max: usize) -> Option<Connection> {
+        open.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+            (n < max).then_some(n + 1)
+        })
+        .ok()
+        .map(|_| Connection(Arc::clone(open)))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+fn serve_connection(stream: TcpStream, options: &Options, root: &Path) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        let mut job: Job = serde_json::from_str(&line)?;
+        let _span = span!(INFO, "job", path = %job.path.display(), range = ?job.range);
+        let options = Options {
+            parse: job.parse,
+            stats: job.stats,
+            filter: None,
+            progress: None,
+            ..options.clone()
+        };
+        let reply = match confine(root, &job.path).and_then(|path| {
+            job.path = path;
+            run_job(&job, &options)
+        }) {
+            Ok(aggregator) => Reply::Done(aggregator.snapshot()),
+            Err(err) => match err.downcast::<MalformedLine>() {
+                Ok(malformed) => Reply::Malformed(malformed),
+                Err(err) => Reply::Failed(err.to_string()),
+            },
+        };
+        send(&mut writer, &reply)?;
+        line.clear();
+    }
+    Ok(())
+}
+
+fn run_job(job: &Job, options: &Options) -> io::Result<Aggregator> {
+    match job.range {
+        Some((start, end)) => {
+            let ranges = split_range(&job.path, start, end, options.threads.max(1))?;
+            process_ranges(&job.path, ranges, options)
+        }
+        None => aggregate_file(&job.path, options),
+    }
+}
+
+/// Aggregates `paths` on the `workers` (`host:port` addresses of
+/// [`serve_worker`] processes), keeping every worker busy until all ranges
+/// are done. The result is the same as [`aggregate_files`] on one machine:
+/// line numbers count from the start of each file and `options.filter`
+/// applies, although the workers aggregate every station.
+///
+/// Stdin cannot be shared with the workers and is rejected.
+///
+/// [`aggregate_files`]: crate::aggregate_files
+pub fn aggregate_remote<P: AsRef<Path>>(
+    paths: &[P],
+    workers: &[String],
+    options: &Options,
+) -> io::Result<Aggregator> {
+    if workers.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no workers to hand the input to",
+        ));
+    }
+    let jobs = plan(paths, workers.len(), options)?;
+    let _span = span!(
+        INFO,
+        "aggregate_remote",
+        jobs = jobs.len(),
+        workers = workers.len()
+    );
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let replies = Mutex::new(Vec::with_capacity(jobs.len()));
+    thread::scope(|scope| {
+        let handles: Vec<_> = workers
+            .iter()
+            .map(|worker| {
+                let (jobs, next, failed, replies) = (&jobs, &next, &failed, &replies);
+                let progress = options.progress.as_deref();
+                let parent = current_span!();
+                scope.spawn(move || -> io::Result<()> {
+                    let _span = span!(parent: &parent, DEBUG, "worker", %worker);
+                    let run = || -> io::Result<()> {
+                        let stream = TcpStream::connect(worker)?;
+                        let mut reader = BufReader::new(stream.try_clone()?);
+                        let mut writer = BufWriter::new(stream);
+                        let mut line = String::new();
+                        while !failed.load(Ordering::Relaxed) && !options.cancelled() {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(job) = jobs.get(index) else {
+                                break;
+                            };
+                            send(&mut writer, job)?;
+                            line.clear();
+                            if reader.read_line(&mut line)? == 0 {
+                                return Err(io::ErrorKind::UnexpectedEof.into());
+                            }
+                            let reply: Reply = serde_json::from_str(&line)?;
+                            event!(DEBUG, job = index, range = ?job.range, "job answered");
+                            if let (Some(progress), Reply::Done(snapshot)) = (progress, &reply) {
+                                progress.record(job_bytes(job)?, snapshot.rows);
+                                progress.record_skipped(snapshot.skipped.count());
+                            }
+                            replies.lock().unwrap().push((index, reply));
+                        }
+                        Ok(())
+                    };
+                    run().map_err(|err| {
+                        failed.store(true, Ordering::Relaxed);
+                        io::Error::new(err.kind(), format!("worker {worker}: {err}"))
+                    })
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().expect("Thread panicked"))
+    })?;
+
+    // Merge in input order so that line numbers can be made per-file again
+    let mut replies = replies.into_inner().unwrap();
+    replies.sort_by_key(|(index, _)| *index);
+    let mut total = options.aggregator();
+    let mut lines_before = 0;
+    for (index, reply) in replies {
+        let job = &jobs[index];
+        if job.range.is_none_or(|(start, _)| start == 0) {
+            lines_before = 0;
+        }
+        match reply {
+            Reply::Done(snapshot) => {
+                let mut part = options.aggregator();
+                part.restore(snapshot);
+                part.shift_line_numbers(lines_before);
+                lines_before += part.rows();
+                total.merge(part);
+            }
+            Reply::Malformed(mut malformed) => {
+                malformed.line += lines_before;
+                return Err(in_file(malformed.into(), &job.path));
+            }
+            Reply::Failed(err) => return Err(in_file(io::Error::other(err), &job.path)),
+        }
+    }
+    if let Some(filter) = &options.filter {
+        total.retain_matching(filter);
+    }
+    Ok(total)
+}
+
+/// Cuts the plain text inputs into ranges and makes one job of every other
+/// file.
+fn plan<P: AsRef<Path>>(paths: &[P], workers: usize, options: &Options) -> io::Result<Vec<Job>> {
+    let mut jobs = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        if is_stream(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "stdin, pipes and sockets cannot be handed to remote workers",
+            ));
+        }
+        let parse = sniff_file(path, options.parse)?;
+        let job = |range| Job {
+            path: path.to_path_buf(),
+            range,
+            parse,
+            stats: options.stats,
+        };
+        if Compression::detect(path)?.is_some() || is_parquet(path)? {
+            jobs.push(job(None));
+            continue;
+        }
+        let ranges = chunk_ranges(path, workers * RANGES_PER_WORKER)?;
+        jobs.extend(ranges.into_iter().map(|range| job(Some(range))));
+    }
+    Ok(jobs)
+}
+
+/// Input bytes a job covers, for progress reporting.
+fn job_bytes(job: &Job) -> io::Result<u64> {
+    match job.range {
+        Some((start, end)) => Ok(end - start),
+        None => Ok(fs::metadata(&job.path)?.len()),
+    }
+}
+
+fn send(writer: &mut impl Write, message: &impl Serialize) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, message)?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::aggregate_files;
+    use crate::results::Results;
+    use crate::testing::TempFile;
+
+    /// `lines` readings with malformed lines at the given 1-based numbers.
+    fn input(lines: usize, bad: &[usize]) -> Vec<u8> {
+        let mut input = Vec::new();
+        for line in 1..=lines {
+            let row = match bad.contains(&line) {
+                true => "bad line\n".to_string(),
+                false => format!("St{};{}.{}\n", line % 13, line % 30, line % 10),
+            };
+            input.extend_from_slice(row.as_bytes());
+        }
+        input
+    }
+
+    /// The address of a worker serving the temporary directory.
+    fn worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let root = fs::canonicalize(std::env::temp_dir()).unwrap();
+        let options = Options {
+            threads: 2,
+            ..Options::default()
+        };
+        thread::spawn(move || serve_worker(listener, &options, &root, 4));
+        address
+    }
+
+    /// What `aggregator` found, with its samples in the order of their
+    /// numbers, which is not that of the files.
+    fn outcome(aggregator: Aggregator) -> (u64, Vec<MalformedLine>, Results) {
+        let skipped = aggregator.skipped();
+        let mut samples: Vec<_> = skipped.samples().cloned().collect();
+        samples.sort_by_key(|malformed| (malformed.line, malformed.offset));
+        (skipped.count(), samples, aggregator.into_results())
+    }
+
+    #[test]
+    fn line_numbers_count_from_the_start_of_each_file() {
+        let first = TempFile::new("remote-first", &input(400, &[1, 150, 399]));
+        let second = TempFile::new("remote-second", &input(300, &[2, 260]));
+        let paths = [&first.0, &second.0];
+        let mut options = Options::default();
+        options.parse.skip_samples = 10;
+        let worker = worker();
+        let remote = aggregate_remote(&paths, &[worker.clone(), worker], &options).unwrap();
+        let local = aggregate_files(&paths, &options).unwrap();
+        let remote = outcome(remote);
+        assert_eq!(remote, outcome(local));
+        let lines: Vec<u64> = remote.1.iter().map(|malformed| malformed.line).collect();
+        assert_eq!(lines, [1, 2, 150, 260, 399]);
+    }
+
+    #[test]
+    fn a_strict_failure_is_numbered_from_the_start_of_its_file() {
+        let first = TempFile::new("remote-strict-first", &input(400, &[]));
+        let second = TempFile::new("remote-strict-second", &input(300, &[250]));
+        let paths = [&first.0, &second.0];
+        let mut options = Options::default();
+        options.parse.strict = true;
+        let remote = aggregate_remote(&paths, &[worker()], &options).unwrap_err();
+        let local = aggregate_files(&paths, &options).unwrap_err();
+        assert!(remote.to_string().contains("line 250 "), "{remote}");
+        assert_eq!(remote.to_string(), local.to_string());
+    }
+
+    #[test]
+    fn files_outside_the_root_are_refused() {
+        let options = Options::default();
+        let err = aggregate_remote(&["/etc/hostname"], &[worker()], &options).unwrap_err();
+        assert!(err.to_string().contains("/etc/hostname"), "{err}");
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::output::Rounding;
+use crate::stats::TempStats;
+
+/// Statistic to rank stations by in [`Results::top`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Rank {
+    /// Hottest maximum first
+    #[default]
+    Max,
+    /// Coldest minimum first
+    Min,
+    /// Highest mean first
+    Mean,
+    /// Most readings first
+    Count,
+}
+
+impl Rank {
+    /// Keeps the `k` of `rows` that rank highest, best first; ties keep the
+    /// order they came in.
+    pub(crate) fn rank(self, rows: &mut Vec<(&str, &TempStats)>, k: usize) {
+        match self {
+            Rank::Max => rows.sort_by(|(_, a), (_, b)| b.max().total_cmp(&a.max())),
+            Rank::Min => rows.sort_by(|(_, a), (_, b)| a.min().total_cmp(&b.min())),
+            Rank::Mean => rows.sort_by(|(_, a), (_, b)| b.mean().total_cmp(&a.mean())),
+            Rank::Count => rows.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.count())),
+        }
+        rows.truncate(k);
+    }
+}
+
+/// What stations are ordered by in [`SortKey::sort`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum SortKey {
+    /// Station name
+    #[default]
+    Name,
+    Mean,
+    Min,
+    Max,
+    /// Number of readings
+    Count,
+}
+
+impl SortKey {
+    /// Orders `rows` by this key, lowest first or, if `descending`, highest
+    /// first. The sort is stable, so ties keep the order they came in.
+    pub fn sort(self, rows: &mut [(&str, &TempStats)], descending: bool) {
+        let compare = |a: &(&str, &TempStats), b: &(&str, &TempStats)| match self {
+            SortKey::Name => a.0.cmp(b.0),
+            SortKey::Mean => a.1.mean().total_cmp(&b.1.mean()),
+            SortKey::Min => a.1.min().total_cmp(&b.1.min()),
+            SortKey::Max => a.1.max().total_cmp(&b.1.max()),
+            SortKey::Count => a.1.count().cmp(&b.1.count()),
+        };
+        if descending {
+            rows.sort_by(|a, b| compare(b, a));
+        } else {
+            rows.sort_by(compare);
+        }
+    }
+}
+
+/// Final per-station statistics, sorted alphabetically by station name.
+///
+/// `Display` renders the challenge format: `{Abha=-23.0/18.0/59.2, ...}`.
+/// With the `serde` feature it serializes as a map from station name to
+/// [`TempStats`].
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Results {
+    stations: BTreeMap<String, TempStats>,
+}
+
+impl Results {
+    pub fn len(&self) -> usize {
+        self.stations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stations.is_empty()
+    }
+
+    pub fn get(&self, station: &str) -> Option<&TempStats> {
+        self.stations.get(station)
+    }
+
+    /// Stations in alphabetical order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &TempStats)> {
+        self.stations.iter().map(|(name, stats)| (name.as_str(), stats))
+    }
+
+    /// The `k` stations that rank highest by `by`; ties stay alphabetical.
+    pub fn top(&self, k: usize, by: Rank) -> Vec<(&str, &TempStats)> {
+        let mut ranked: Vec<_> = self.iter().collect();
+        by.rank(&mut ranked, k);
+        ranked
+    }
+}
+
+impl FromIterator<(String, TempStats)> for Results {
+    fn from_iter<I: IntoIterator<Item = (String, TempStats)>>(iter: I) -> Self {
+        let mut stations = BTreeMap::new();
+        for (name, stats) in iter {
+            stations
+                .entry(name)
+                .and_modify(|s: &mut TempStats| s.merge(&stats))
+                .or_insert(stats);
+        }
+        Results { stations }
+    }
+}
+
+impl IntoIterator for Results {
+    type Item = (String, TempStats);
+    type IntoIter = std::collections::btree_map::IntoIter<String, TempStats>;
+
+    /// Stations in alphabetical order, by value.
+    fn into_iter(self) -> Self::IntoIter {
+        self.stations.into_iter()
+    }
+}
+
+impl fmt::Display for Results {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (city, stats)) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(
+                f,
+                "{}={:.1}/{:.1}/{:.1}",
+                city,
+                Rounding::HalfUp.round(stats.min(), 1),
+                Rounding::HalfUp.round(stats.mean(), 1),
+                Rounding::HalfUp.round(stats.max(), 1)
+            )?;
+        }
+        write!(f, "}}")
+    }
+}
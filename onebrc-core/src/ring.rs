@@ -0,0 +1,313 @@
+//! The queue between the reader and the workers: a fixed ring of slots that
+//! one producer fills and any number of consumers claim with atomics.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam::utils::{Backoff, CachePadded};
+
+/// How long a side that has spun and yielded without progress sleeps before
+/// looking again, so idle workers behind a slow reader stay off the CPU.
+const IDLE_SLEEP: Duration = Duration::from_micros(50);
+
+/// A ring of `capacity` slots between one [`Producer`] and its
+/// [`Consumer`]s.
+///
+/// Every slot carries a stamp saying which position it is at and whether
+/// it is full: `2 * pos` while it waits to be written at position `pos`,
+/// `2 * pos + 1` once it holds that item, and `2 * (pos + capacity)` once a
+/// consumer took the item out. The producer is alone in writing, so it
+/// only waits for the stamp; consumers race for the head with a
+/// compare-and-swap. Nothing is allocated per item and no lock is taken.
+pub(crate) fn ring<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.max(1);
+    let ring = Arc::new(Ring {
+        slots: (0..capacity)
+            .map(|pos| Slot {
+                stamp: AtomicUsize::new(2 * pos),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect(),
+        head: CachePadded::new(AtomicUsize::new(0)),
+        tail: CachePadded::new(AtomicUsize::new(0)),
+        consumers: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+    });
+    let producer = Producer {
+        ring: Arc::clone(&ring),
+        tail: 0,
+    };
+    (producer, Consumer { ring })
+}
+
+struct Ring<T> {
+    slots: Box<[Slot<T>]>,
+    /// Next position to take from.
+    head: CachePadded<AtomicUsize>,
+    /// Next position to write to, published for [`Producer::len`].
+    tail: CachePadded<AtomicUsize>,
+    consumers: AtomicUsize,
+    closed: AtomicBool,
+}
+
+struct Slot<T> {
+    stamp: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// SAFETY: a value is only reached by the one side its slot's stamp hands
+// it to, so the ring moves values between threads like a channel does.
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn slot(&self, pos: usize) -> &Slot<T> {
+        &self.slots[pos % self.slots.len()]
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        for pos in *self.head.get_mut()..*self.tail.get_mut() {
+            let slot = &mut self.slots[pos % self.slots.len()];
+            // SAFETY: every position between head and tail was written and
+            // not taken.
+            unsafe { slot.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+/// Waits a little longer every time the other side has made no progress.
+fn wait(backoff: &Backoff) {
+    if backoff.is_completed() {
+        thread::park_timeout(IDLE_SLEEP);
+    } else {
+        backoff.snooze();
+    }
+}
+
+/// The writing end of a [`ring`]; dropping it closes the ring.
+pub(crate) struct Producer<T> {
+    ring: Arc<Ring<T>>,
+    tail: usize,
+}
+
+impl<T> Producer<T> {
+    /// Puts `value` in the next slot, waiting while the ring is full. Gives
+    /// `value` back once every consumer is gone.
+    pub(crate) fn push(&mut self, value: T) -> Result<(), T> {
+        let slot = self.ring.slot(self.tail);
+        let backoff = Backoff::new();
+        loop {
+            if self.ring.consumers.load(Ordering::Acquire) == 0 {
+                return Err(value);
+            }
+            if slot.stamp.load(Ordering::Acquire) == 2 * self.tail {
+                break;
+            }
+            wait(&backoff);
+        }
+        // SAFETY: the stamp says the slot is empty and it is this
+        // position's turn, and only the producer writes.
+        unsafe { (*slot.value.get()).write(value) };
+        slot.stamp.store(2 * self.tail + 1, Ordering::Release);
+        self.tail += 1;
+        self.ring.tail.store(self.tail, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Items waiting in the ring.
+    pub(crate) fn len(&self) -> usize {
+        self.tail - self.ring.head.load(Ordering::Relaxed).min(self.tail)
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::Release);
+    }
+}
+
+/// A reading end of a [`ring`]; clones share the items between them.
+pub(crate) struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T> Consumer<T> {
+    /// The next item, waiting while the ring is empty; `None` once the
+    /// producer is gone and the ring drained.
+    pub(crate) fn pop(&self) -> Option<T> {
+        let ring = &*self.ring;
+        let backoff = Backoff::new();
+        let mut head = ring.head.load(Ordering::Relaxed);
+        loop {
+            let slot = ring.slot(head);
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            if stamp == 2 * head + 1 {
+                let claimed = ring.head.compare_exchange_weak(
+                    head,
+                    head + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                );
+                match claimed {
+                    Ok(_) => {
+                        // SAFETY: the stamp says the slot holds this
+                        // position's item, and winning the head makes it
+                        // this consumer's alone.
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        let next = 2 * (head + ring.slots.len());
+                        slot.stamp.store(next, Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(current) => {
+                        head = current;
+                        backoff.spin();
+                    }
+                }
+            } else if stamp == 2 * head {
+                // Closing comes after the last push, so once it is seen the
+                // stamp shows whether that push filled this slot
+                if ring.closed.load(Ordering::Acquire)
+                    && slot.stamp.load(Ordering::Acquire) == 2 * head
+                {
+                    return None;
+                }
+                wait(&backoff);
+                head = ring.head.load(Ordering::Relaxed);
+            } else {
+                // Another consumer took this position
+                head = ring.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl<T> Clone for Consumer<T> {
+    fn clone(&self) -> Self {
+        self.ring.consumers.fetch_add(1, Ordering::Relaxed);
+        Consumer {
+            ring: Arc::clone(&self.ring),
+        }
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.ring.consumers.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<T> Iterator for Consumer<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts its drops in the shared counter.
+    #[derive(Debug)]
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn several_consumers_take_every_item_once() {
+        let (mut producer, consumer) = ring(4);
+        let mut taken: Vec<usize> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    let consumer = consumer.clone();
+                    scope.spawn(move || consumer.collect::<Vec<_>>())
+                })
+                .collect();
+            drop(consumer);
+            for item in 0..10_000 {
+                producer.push(item).unwrap();
+            }
+            drop(producer);
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        taken.sort_unstable();
+        assert_eq!(taken, (0..10_000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn closing_leaves_the_items_to_drain() {
+        let (mut producer, consumer) = ring(8);
+        for item in 0..3 {
+            producer.push(item).unwrap();
+        }
+        drop(producer);
+        assert_eq!(consumer.pop(), Some(0));
+        assert_eq!(consumer.clone().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(consumer.pop(), None);
+    }
+
+    #[test]
+    fn wraps_around_past_capacity() {
+        let (mut producer, consumer) = ring(3);
+        for round in 0..10 {
+            for item in 0..3 {
+                producer.push(round * 3 + item).unwrap();
+            }
+            assert_eq!(producer.len(), 3);
+            for item in 0..3 {
+                assert_eq!(consumer.pop(), Some(round * 3 + item));
+            }
+            assert_eq!(producer.len(), 0);
+        }
+        // Out of step with the capacity, too
+        for item in 0..20 {
+            producer.push(item).unwrap();
+            producer.push(item + 100).unwrap();
+            assert_eq!(consumer.pop(), Some(item));
+            assert_eq!(consumer.pop(), Some(item + 100));
+        }
+    }
+
+    #[test]
+    fn push_gives_the_item_back_without_consumers() {
+        let (mut producer, consumer) = ring(2);
+        drop(consumer);
+        assert_eq!(producer.push(7), Err(7));
+    }
+
+    #[test]
+    fn dropping_the_ring_drops_the_items_left() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let (mut producer, consumer) = ring(4);
+        let mut push = || producer.push(Counted(Arc::clone(&drops))).unwrap();
+        for _ in 0..4 {
+            push();
+        }
+        drop(consumer.pop());
+        drop(consumer.pop());
+        // These two wrap around, so the items left sit across the end
+        push();
+        push();
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+        drop(consumer.pop());
+        assert_eq!(drops.load(Ordering::Relaxed), 3);
+        drop(producer);
+        drop(consumer);
+        assert_eq!(drops.load(Ordering::Relaxed), 6);
+    }
+}
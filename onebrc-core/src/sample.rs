@@ -0,0 +1,103 @@
+//! [`Options::sample`]: a quick estimate from evenly spread blocks of a
+//! file instead of the whole of it.
+//!
+//! [`Options::sample`]: crate::Options::sample
+
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use memchr::memchr;
+
+use crate::aggregator::Aggregator;
+use crate::chunk::map_file;
+use crate::parse::strip_bom;
+use crate::pipeline::{Options, merge_all};
+use crate::progress::track;
+use crate::trace::{current_span, span};
+
+/// Size of the blocks a sample is made of: small enough that even a 1%
+/// sample of a modest file takes blocks from all over it.
+pub(crate) const SAMPLE_BLOCK_SIZE: usize = 64 << 10;
+
+/// Aggregates a `fraction` of the blocks of about [`SAMPLE_BLOCK_SIZE`]
+/// bytes that the mapped `path` divides into, spread evenly over the file.
+/// Every block is moved forward to line starts the way [`chunk_ranges`]
+/// does, and the workers take them in turn, so only the pages of the blocks
+/// taken are read. Line numbers in errors and skipped-line samples count
+/// from the start of each block.
+///
+/// [`chunk_ranges`]: crate::chunk_ranges
+pub(crate) fn process_sampled(
+    path: &Path,
+    options: &Options,
+    fraction: f64,
+) -> io::Result<Aggregator> {
+    if !(fraction > 0.0 && fraction <= 1.0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("a sample is a fraction of the input above 0 and at most 1, not {fraction}"),
+        ));
+    }
+    let mmap = map_file(path)?;
+    let data = strip_bom(&mmap);
+    let bom = mmap.len() - data.len();
+    let taken = sampled_blocks(data.len().div_ceil(SAMPLE_BLOCK_SIZE), fraction);
+    let _span = span!(DEBUG, "sampled", blocks = taken.len());
+    let progress = options.progress.as_deref();
+
+    let next = AtomicUsize::new(0);
+    let partials = thread::scope(|scope| {
+        let handles: Vec<_> = (0..options.threads.max(1))
+            .map(|_| {
+                let mut aggregator = options.aggregator();
+                let (next, taken) = (&next, &taken);
+                let parent = current_span!();
+                scope.spawn(move || -> io::Result<Aggregator> {
+                    let _span = span!(parent: &parent, DEBUG, "worker");
+                    while !options.cancelled() {
+                        let Some(&block) = taken.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                            break;
+                        };
+                        let start = line_start(data, block * SAMPLE_BLOCK_SIZE);
+                        let end = line_start(data, (block + 1) * SAMPLE_BLOCK_SIZE);
+                        let lines = &data[start..end];
+                        track(progress, &mut aggregator, lines.len(), |aggregator| {
+                            aggregator.set_next_line(1);
+                            aggregator.set_next_offset((bom + start) as u64);
+                            aggregator.process_chunk(lines)
+                        })?;
+                    }
+                    Ok(aggregator)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Thread panicked"))
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+
+    Ok(merge_all(partials, progress))
+}
+
+/// The indices of the `fraction` of `blocks` to aggregate, at least one of
+/// them, each in the middle of an equal share of the file.
+fn sampled_blocks(blocks: usize, fraction: f64) -> Vec<usize> {
+    if blocks == 0 {
+        return Vec::new();
+    }
+    let taken = ((blocks as f64 * fraction).round() as usize).clamp(1, blocks);
+    (0..taken)
+        .map(|i| ((2 * i + 1) * blocks / (2 * taken)).min(blocks - 1))
+        .collect()
+}
+
+/// The start of the first line that begins at or after `pos`.
+fn line_start(data: &[u8], pos: usize) -> usize {
+    if pos == 0 || pos >= data.len() {
+        return pos.min(data.len());
+    }
+    memchr(b'\n', &data[pos - 1..]).map_or(data.len(), |newline| pos + newline)
+}
@@ -0,0 +1,328 @@
+//! Splitting chunks into lines and finding their delimiters with SIMD,
+//! picked at runtime: AVX-512 or AVX2 on x86_64, NEON on aarch64, and the
+//! scalar memchr loop everywhere else and under Miri.
+
+use memchr::{memchr, memchr_iter};
+
+use crate::parse::strip_cr;
+
+/// Calls `f` for every line of `chunk` with the position of the first
+/// `delimiter` in it, stopping at the first error. Lines ending in CRLF
+/// lose the `\r` too.
+///
+/// The vector kernels compare a whole register of bytes against both the
+/// newline and the delimiter at once, and walk the resulting bit masks, so
+/// every byte is looked at once instead of once for the line end and again
+/// for the delimiter.
+#[inline]
+pub(crate) fn for_each_record<'a, E>(
+    chunk: &'a [u8],
+    delimiter: u8,
+    f: impl FnMut(&'a [u8], Option<usize>) -> Result<(), E>,
+) -> Result<(), E> {
+    #[cfg(all(target_arch = "x86_64", not(miri)))]
+    {
+        if std::arch::is_x86_feature_detected!("avx512bw") {
+            // SAFETY: the CPU supports AVX-512BW.
+            return unsafe { x86::split_avx512(chunk, delimiter, f) };
+        }
+        if std::arch::is_x86_feature_detected!("avx2") {
+            // SAFETY: the CPU supports AVX2.
+            return unsafe { x86::split_avx2(chunk, delimiter, f) };
+        }
+    }
+    #[cfg(all(target_arch = "aarch64", not(miri)))]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // SAFETY: the CPU supports NEON.
+            return unsafe { arm::split_neon(chunk, delimiter, f) };
+        }
+    }
+    split_scalar(chunk, delimiter, f)
+}
+
+/// The fallback: memchr for the line ends, then again for each delimiter.
+fn split_scalar<'a, E>(
+    chunk: &'a [u8],
+    delimiter: u8,
+    mut f: impl FnMut(&'a [u8], Option<usize>) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut record = |line: &'a [u8]| f(strip_cr(line), memchr(delimiter, line));
+    let mut start = 0;
+    for end in memchr_iter(b'\n', chunk) {
+        record(&chunk[start..end])?;
+        start = end + 1;
+    }
+    if start < chunk.len() {
+        record(&chunk[start..])?;
+    }
+    Ok(())
+}
+
+/// The loop shared by the kernels. `masks` compares `W` bytes against the
+/// newline and the delimiter and returns a mask for each, with `B` bits per
+/// byte (all set for a match), so `W * B` must be 64 at most.
+#[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), not(miri)))]
+#[inline(always)]
+fn split_with<'a, E, const W: usize, const B: u32>(
+    chunk: &'a [u8],
+    masks: impl Fn(&[u8; W]) -> (u64, u64),
+    mut f: impl FnMut(&'a [u8], Option<usize>) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut start = 0;
+    let mut delimiter = None;
+    let mut scan = |base: usize, (newlines, delimiters): (u64, u64)| {
+        let mut found = newlines | delimiters;
+        while found != 0 {
+            let byte = found.trailing_zeros() / B;
+            let pos = base + byte as usize;
+            if newlines >> (byte * B) & 1 == 1 {
+                let line = &chunk[start..pos];
+                f(strip_cr(line), delimiter.map(|at: usize| at - start))?;
+                start = pos + 1;
+                delimiter = None;
+            } else if delimiter.is_none() {
+                delimiter = Some(pos);
+            }
+            found &= !(u64::MAX >> (64 - B) << (byte * B));
+        }
+        Ok(())
+    };
+
+    let mut blocks = chunk.chunks_exact(W);
+    let mut base = 0;
+    for block in &mut blocks {
+        scan(base, masks(block.try_into().expect("block of W bytes")))?;
+        base += W;
+    }
+    // The tail goes through the same kernel, padded with bytes that never
+    // match and masked to its length
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let mut padded = [0; W];
+        padded[..tail.len()].copy_from_slice(tail);
+        let (newlines, delimiters) = masks(&padded);
+        let keep = u64::MAX >> (64 - tail.len() as u32 * B);
+        scan(base, (newlines & keep, delimiters & keep))?;
+    }
+    if start < chunk.len() {
+        let line = &chunk[start..];
+        f(strip_cr(line), delimiter.map(|at| at - start))?;
+    }
+    Ok(())
+}
+
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::split_with;
+
+    #[target_feature(enable = "avx512bw")]
+    pub(super) fn split_avx512<'a, E>(
+        chunk: &'a [u8],
+        delimiter: u8,
+        f: impl FnMut(&'a [u8], Option<usize>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if delimiter == 0 {
+            return super::split_scalar(chunk, delimiter, f);
+        }
+        let newline = _mm512_set1_epi8(b'\n' as i8);
+        let wanted = _mm512_set1_epi8(delimiter as i8);
+        let masks = |block: &[u8; 64]| {
+            // SAFETY: the block is 64 readable bytes.
+            let bytes = unsafe { _mm512_loadu_si512(block.as_ptr().cast()) };
+            let newlines = _mm512_cmpeq_epi8_mask(bytes, newline);
+            (newlines, _mm512_cmpeq_epi8_mask(bytes, wanted))
+        };
+        split_with::<E, 64, 1>(chunk, masks, f)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) fn split_avx2<'a, E>(
+        chunk: &'a [u8],
+        delimiter: u8,
+        f: impl FnMut(&'a [u8], Option<usize>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if delimiter == 0 {
+            return super::split_scalar(chunk, delimiter, f);
+        }
+        let newline = _mm256_set1_epi8(b'\n' as i8);
+        let wanted = _mm256_set1_epi8(delimiter as i8);
+        let masks = |block: &[u8; 32]| {
+            // SAFETY: the block is 32 readable bytes.
+            let bytes = unsafe { _mm256_loadu_si256(block.as_ptr().cast()) };
+            let mask = |eq| _mm256_movemask_epi8(eq) as u32 as u64;
+            let newlines = mask(_mm256_cmpeq_epi8(bytes, newline));
+            (newlines, mask(_mm256_cmpeq_epi8(bytes, wanted)))
+        };
+        split_with::<E, 32, 1>(chunk, masks, f)
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", not(miri)))]
+mod arm {
+    use std::arch::aarch64::*;
+
+    use super::split_with;
+
+    #[target_feature(enable = "neon")]
+    pub(super) fn split_neon<'a, E>(
+        chunk: &'a [u8],
+        delimiter: u8,
+        f: impl FnMut(&'a [u8], Option<usize>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if delimiter == 0 {
+            return super::split_scalar(chunk, delimiter, f);
+        }
+        let newline = vdupq_n_u8(b'\n');
+        let wanted = vdupq_n_u8(delimiter);
+        // NEON has no byte movemask; narrowing every 16-bit lane by four
+        // bits leaves four mask bits per byte
+        let masks = |block: &[u8; 16]| {
+            // SAFETY: the block is 16 readable bytes.
+            let bytes = unsafe { vld1q_u8(block.as_ptr()) };
+            let mask = |eq: uint8x16_t| {
+                let nibbles = vshrn_n_u16::<4>(vreinterpretq_u16_u8(eq));
+                vget_lane_u64::<0>(vreinterpret_u64_u8(nibbles))
+            };
+            let newlines = mask(vceqq_u8(bytes, newline));
+            (newlines, mask(vceqq_u8(bytes, wanted)))
+        };
+        split_with::<E, 16, 4>(chunk, masks, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Records = Vec<(Vec<u8>, Option<usize>)>;
+
+    fn run<'a>(
+        split: impl FnOnce(&mut dyn FnMut(&'a [u8], Option<usize>) -> Result<(), ()>) -> Result<(), ()>,
+    ) -> Records {
+        let mut records = Vec::new();
+        split(&mut |line, at| {
+            records.push((line.to_vec(), at));
+            Ok(())
+        })
+        .unwrap();
+        records
+    }
+
+    /// `chunk` as split by the scalar loop and by every kernel this CPU has.
+    fn by_every_kernel(chunk: &[u8], delimiter: u8) -> Vec<(&'static str, Records)> {
+        let mut runs = vec![("scalar", run(|f| split_scalar(chunk, delimiter, f)))];
+        #[cfg(all(target_arch = "x86_64", not(miri)))]
+        {
+            if std::arch::is_x86_feature_detected!("avx512bw") {
+                // SAFETY: the CPU supports AVX-512BW.
+                let records = run(|f| unsafe { x86::split_avx512(chunk, delimiter, f) });
+                runs.push(("avx512", records));
+            }
+            if std::arch::is_x86_feature_detected!("avx2") {
+                // SAFETY: the CPU supports AVX2.
+                let records = run(|f| unsafe { x86::split_avx2(chunk, delimiter, f) });
+                runs.push(("avx2", records));
+            }
+        }
+        #[cfg(all(target_arch = "aarch64", not(miri)))]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                // SAFETY: the CPU supports NEON.
+                let records = run(|f| unsafe { arm::split_neon(chunk, delimiter, f) });
+                runs.push(("neon", records));
+            }
+        }
+        runs.push(("dispatched", run(|f| for_each_record(chunk, delimiter, f))));
+        runs
+    }
+
+    fn assert_kernels_agree(chunk: &[u8], delimiter: u8) {
+        let runs = by_every_kernel(chunk, delimiter);
+        let (_, scalar) = &runs[0];
+        for (kernel, records) in &runs[1..] {
+            assert_eq!(
+                records,
+                scalar,
+                "{kernel} on {:?}",
+                String::from_utf8_lossy(chunk)
+            );
+        }
+    }
+
+    /// Lines of every length up to past the widest register, so that line
+    /// ends and delimiters fall at every offset within a block.
+    fn lines(line_end: &str) -> String {
+        (0..80)
+            .map(|n| format!("{};{}.{}{line_end}", "s".repeat(n), n % 7, n % 10))
+            .collect()
+    }
+
+    #[test]
+    fn scalar_splits_records() {
+        let records = run(|f| split_scalar(b"a;1.0\r\nbc;-2\nnone\n;3", b';', f));
+        let expected: Records = vec![
+            (b"a;1.0".to_vec(), Some(1)),
+            (b"bc;-2".to_vec(), Some(2)),
+            (b"none".to_vec(), None),
+            (b";3".to_vec(), Some(0)),
+        ];
+        assert_eq!(records, expected);
+    }
+
+    #[test]
+    fn tails_shorter_than_a_register() {
+        let input = lines("\n");
+        for len in 0..=64 {
+            assert_kernels_agree(&input.as_bytes()[..len], b';');
+        }
+    }
+
+    #[test]
+    fn lines_crossing_block_boundaries() {
+        let input = lines("\n");
+        for skip in 0..64 {
+            assert_kernels_agree(&input.as_bytes()[skip..], b';');
+        }
+    }
+
+    #[test]
+    fn crlf_line_ends() {
+        let input = lines("\r\n");
+        for skip in 0..64 {
+            assert_kernels_agree(&input.as_bytes()[skip..], b';');
+        }
+    }
+
+    #[test]
+    fn missing_final_newline() {
+        let input = lines("\n");
+        let input = input.trim_end().as_bytes();
+        for cut in 0..64 {
+            assert_kernels_agree(&input[..input.len() - cut], b';');
+        }
+    }
+
+    #[test]
+    fn delimiter_in_the_last_byte() {
+        for len in 1..=130 {
+            let mut chunk = vec![b's'; len];
+            chunk[len - 1] = b';';
+            assert_kernels_agree(&chunk, b';');
+            if len > 1 {
+                chunk[len / 2] = b'\n';
+                assert_kernels_agree(&chunk, b';');
+            }
+        }
+    }
+
+    #[test]
+    fn first_of_several_delimiters_and_other_delimiter_bytes() {
+        let input = "a;b;c\n,;x,\n\n;;\n".repeat(9);
+        for delimiter in [b';', b',', b'\t', 0] {
+            assert_kernels_agree(input.as_bytes(), delimiter);
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use memchr::memchr;
+
+use crate::parse::{ParseOptions, split_columns};
+
+/// A line that is not a valid `station;temperature` measurement, reported in
+/// strict mode and kept as a sample in lenient mode. `line` is 1-based;
+/// `offset` is the byte the line starts at in its input (0 for Parquet,
+/// which has no lines).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MalformedLine {
+    pub line: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub offset: u64,
+    pub reason: SkipReason,
+    pub content: Vec<u8>,
+}
+
+impl fmt::Display for MalformedLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {} (byte {}): malformed measurement `{}` ({})",
+            self.line,
+            self.offset,
+            String::from_utf8_lossy(&self.content),
+            self.reason
+        )
+    }
+}
+
+impl Error for MalformedLine {}
+
+impl From<MalformedLine> for io::Error {
+    fn from(err: MalformedLine) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// What is wrong with a [`MalformedLine`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SkipReason {
+    /// The line is not valid UTF-8, e.g. binary garbage or another encoding.
+    Encoding,
+    /// There is no delimiter between station and temperature.
+    NoDelimiter,
+    /// The line has fewer fields than [`ParseOptions::columns`] asks for.
+    MissingColumn,
+    /// The temperature is not a number with exactly one decimal place.
+    BadTemperature,
+    /// The temperature is well-formed but too large to aggregate.
+    OutOfRange,
+    /// A value is missing, e.g. a null in Parquet input.
+    Missing,
+    /// A JSON Lines line is not an object with a `station` and a `temp`.
+    BadJson,
+    /// A quoted field is not closed, or is followed by more than its
+    /// delimiter.
+    BadQuoting,
+}
+
+impl SkipReason {
+    /// Works out why `line` did not parse. Only called for lines that failed,
+    /// so the hot path never pays for it.
+    pub(crate) fn classify(line: &[u8], options: &ParseOptions) -> Self {
+        if std::str::from_utf8(line).is_err() {
+            return SkipReason::Encoding;
+        }
+        let Some(pos) = memchr(options.delimiter, line) else {
+            return SkipReason::NoDelimiter;
+        };
+        let temp = match options.columns {
+            None => &line[pos + 1..],
+            Some(columns) => match split_columns(line, pos, options.delimiter, columns) {
+                Some((_, temp)) => temp,
+                None => return SkipReason::MissingColumn,
+            },
+        };
+        let temp = temp.trim_ascii();
+        let digits = temp.strip_prefix(b"-").unwrap_or(temp);
+        match digits {
+            [whole @ .., b'.', frac]
+                if !whole.is_empty()
+                    && whole.iter().all(u8::is_ascii_digit)
+                    && frac.is_ascii_digit() =>
+            {
+                SkipReason::OutOfRange
+            }
+            _ => SkipReason::BadTemperature,
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SkipReason::Encoding => "not UTF-8",
+            SkipReason::NoDelimiter => "no delimiter",
+            SkipReason::MissingColumn => "too few columns",
+            SkipReason::BadTemperature => "unparseable temperature",
+            SkipReason::OutOfRange => "temperature out of range",
+            SkipReason::Missing => "missing value",
+            SkipReason::BadJson => "not a station/temp JSON object",
+            SkipReason::BadQuoting => "unbalanced quotes",
+        })
+    }
+}
+
+/// Malformed lines dropped in lenient mode: a count per [`SkipReason`] plus
+/// the first few offending lines, kept for the end-of-run report.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SkippedLines {
+    count: u64,
+    by_reason: BTreeMap<SkipReason, u64>,
+    samples: Vec<MalformedLine>,
+}
+
+impl SkippedLines {
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// How many lines were skipped for each reason that occurred.
+    pub fn by_reason(&self) -> impl Iterator<Item = (SkipReason, u64)> {
+        self.by_reason
+            .iter()
+            .map(|(reason, count)| (*reason, *count))
+    }
+
+    /// The earliest skipped lines.
+    pub fn samples(&self) -> impl Iterator<Item = &MalformedLine> {
+        self.samples.iter()
+    }
+
+    fn record(&mut self, line: u64, offset: u64, reason: SkipReason, content: &[u8], limit: usize) {
+        self.count += 1;
+        *self.by_reason.entry(reason).or_default() += 1;
+        if self.samples.len() < limit {
+            self.samples.push(MalformedLine {
+                line,
+                offset,
+                reason,
+                content: content.to_vec(),
+            });
+        }
+    }
+
+    fn merge(&mut self, other: SkippedLines, limit: usize) {
+        self.count += other.count;
+        for (reason, count) in other.by_reason {
+            *self.by_reason.entry(reason).or_default() += count;
+        }
+        self.samples.extend(other.samples);
+        self.samples.sort_by_key(|malformed| malformed.line);
+        self.samples.truncate(limit);
+    }
+
+    fn shift(&mut self, base: u64) {
+        for malformed in &mut self.samples {
+            malformed.line += base;
+        }
+    }
+}
+
+/// Line bookkeeping shared by both aggregator flavours: numbering, the row
+/// count and what to do with lines that fail to parse.
+#[derive(Debug, Clone)]
+pub(crate) struct LineLog {
+    pub(crate) options: ParseOptions,
+    next_line: u64,
+    /// Byte offset of the next chunk of input.
+    next_offset: u64,
+    /// The chunk being processed: its offset, address and length, from
+    /// which a malformed line's offset follows.
+    chunk: (u64, usize, usize),
+    rows: u64,
+    skipped: SkippedLines,
+}
+
+impl LineLog {
+    pub(crate) fn new(options: ParseOptions) -> Self {
+        LineLog {
+            options,
+            next_line: 1,
+            next_offset: 0,
+            chunk: (0, 0, 0),
+            rows: 0,
+            skipped: SkippedLines::default(),
+        }
+    }
+
+    pub(crate) fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub(crate) fn skipped(&self) -> &SkippedLines {
+        &self.skipped
+    }
+
+    pub(crate) fn set_next_line(&mut self, line: u64) {
+        self.next_line = line;
+    }
+
+    /// Sets the byte offset of the next chunk; each chunk moves it on by
+    /// its length otherwise.
+    pub(crate) fn set_next_offset(&mut self, offset: u64) {
+        self.next_offset = offset;
+        self.chunk = (offset, 0, 0);
+    }
+
+    /// Takes `len` bytes of input starting with `chunk` as the next, for
+    /// the offsets of the malformed lines in it.
+    #[inline]
+    pub(crate) fn enter_chunk(&mut self, chunk: &[u8], len: usize) {
+        self.chunk = (self.next_offset, chunk.as_ptr() as usize, len);
+        self.next_offset += len as u64;
+    }
+
+    /// Byte offset of `content`, a line of the current chunk; the chunk's
+    /// own offset for content from anywhere else.
+    fn offset_of(&self, content: &[u8]) -> u64 {
+        let (offset, start, len) = self.chunk;
+        match (content.as_ptr() as usize).checked_sub(start) {
+            Some(at) if at <= len => offset + at as u64,
+            _ => offset,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn accept(&mut self) {
+        self.rows += 1;
+        self.next_line += 1;
+    }
+
+    /// Records a malformed line, or fails with it in strict mode. The
+    /// header line is let through instead.
+    pub(crate) fn reject(
+        &mut self,
+        content: &[u8],
+        reason: SkipReason,
+    ) -> Result<(), MalformedLine> {
+        let line = self.next_line;
+        let offset = self.offset_of(content);
+        self.rows += 1;
+        self.next_line += 1;
+        if self
+            .options
+            .header
+            .is_some_and(|header| header.matches(content))
+        {
+            return Ok(());
+        }
+        if self.options.strict {
+            return Err(MalformedLine {
+                line,
+                offset,
+                reason,
+                content: content.to_vec(),
+            });
+        }
+        self.skipped
+            .record(line, offset, reason, content, self.options.skip_samples);
+        Ok(())
+    }
+
+    /// Takes over the count and skipped lines of an earlier run.
+    #[cfg(any(feature = "checkpoint", feature = "remote"))]
+    pub(crate) fn restore(&mut self, rows: u64, skipped: SkippedLines) {
+        self.rows = rows;
+        self.skipped = skipped;
+    }
+
+    pub(crate) fn merge(&mut self, other: LineLog) {
+        self.rows += other.rows;
+        self.skipped.merge(other.skipped, self.options.skip_samples);
+    }
+
+    /// Renumbers locally counted lines once the number of lines before them
+    /// is known.
+    pub(crate) fn shift(&mut self, base: u64) {
+        self.skipped.shift(base);
+    }
+}
@@ -0,0 +1,263 @@
+//! [`InputFormat::Auto`] and header lines: the layout of an input worked out
+//! from its first few kilobytes.
+
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+use memchr::{memchr, memrchr};
+
+use crate::compression::{is_stream, open_input};
+use crate::json::parse_json;
+use crate::parse::{
+    Header, InputFormat, ParseOptions, parse_record, split_columns, strip_bom, strip_cr,
+};
+use crate::quoted::parse_quoted;
+
+/// How much of an input [`sniff_sample`] is given.
+pub(crate) const SNIFF_SIZE: u64 = 16 * 1024;
+
+/// Delimiters tried after the one in the options, in order of preference.
+const DELIMITERS: [u8; 3] = [b';', b'\t', b','];
+
+/// `parse` completed from `sample`, the start of an input.
+///
+/// [`InputFormat::Auto`] is replaced by what the sample looks like: a first
+/// line that opens a JSON object makes it [`InputFormat::Jsonl`]; otherwise
+/// the lines are delimited by whichever of `parse.delimiter`, `;`, tab and
+/// comma most of the sample's lines parse with, the first of those on a
+/// tie, and a sample with no line that parses keeps `parse.delimiter`.
+/// Lines count as parsing with a decimal comma too, and any that only parse
+/// with one turn on [`ParseOptions::decimal_comma`].
+///
+/// The first line of delimited input becomes the [`ParseOptions::header`]
+/// if it does not parse and its temperature field is not a number, such as
+/// `station,temperature`, or whatever it is with [`ParseOptions::skip_header`];
+/// a first line that is a reading is an error then, as it would not be
+/// dropped.
+pub fn sniff_sample(sample: &[u8], mut parse: ParseOptions) -> io::Result<ParseOptions> {
+    let sample = strip_bom(sample);
+    // Only whole lines, unless the sample is all one line
+    let sample = memrchr(b'\n', sample).map_or(sample, |end| &sample[..end]);
+    let mut lines = sample
+        .split(|&byte| byte == b'\n')
+        .map(strip_cr)
+        .filter(|line| !line.trim_ascii().is_empty());
+    let Some(first) = lines.next() else {
+        return Ok(parse);
+    };
+    if parse.format == InputFormat::Auto {
+        parse = sniff_layout(first, lines, parse);
+    }
+    if parse.format == InputFormat::Jsonl && !parse.skip_header {
+        return Ok(parse);
+    }
+
+    let parsed = match parse.format {
+        InputFormat::Jsonl => parse_json(first).is_ok(),
+        _ if parse.quoted => parse_quoted(first, &parse).is_ok(),
+        _ => parse_record(first, memchr(parse.delimiter, first), &parse).is_some(),
+    };
+    if parse.skip_header && parsed {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the first line is a reading, not a header: {}",
+                String::from_utf8_lossy(first)
+            ),
+        ));
+    }
+    let header =
+        parse.skip_header || !parsed && !temp_field(first, &parse).is_none_or(looks_numeric);
+    if header {
+        parse.header = Some(Header::new(first));
+    }
+    Ok(parse)
+}
+
+/// The format, delimiter and decimal mark of [`InputFormat::Auto`] input
+/// whose first lines are `first` and `rest`.
+fn sniff_layout<'a>(
+    first: &'a [u8],
+    rest: impl Iterator<Item = &'a [u8]> + Clone,
+    mut parse: ParseOptions,
+) -> ParseOptions {
+    if first.trim_ascii_start().starts_with(b"{") {
+        parse.format = InputFormat::Jsonl;
+        return parse;
+    }
+    parse.format = InputFormat::Delimited;
+    let lines = || [first].into_iter().chain(rest.clone());
+    let parses = |line: &[u8], delimiter| {
+        let options = ParseOptions {
+            delimiter,
+            decimal_comma: true,
+            ..parse
+        };
+        parse_record(line, memchr(delimiter, line), &options).is_some()
+    };
+    let mut best = (0, parse.delimiter);
+    for delimiter in [parse.delimiter].into_iter().chain(DELIMITERS) {
+        let parsed = lines().filter(|line| parses(line, delimiter)).count();
+        if parsed > best.0 {
+            best = (parsed, delimiter);
+        }
+    }
+    parse.delimiter = best.1;
+    let plain = parse;
+    let parsed = |line: &[u8]| parse_record(line, memchr(plain.delimiter, line), &plain).is_some();
+    parse.decimal_comma |= lines().any(|line| !parsed(line) && parses(line, plain.delimiter));
+    parse
+}
+
+/// The field of `line` that should hold the temperature, if it has one.
+fn temp_field<'l>(line: &'l [u8], parse: &ParseOptions) -> Option<&'l [u8]> {
+    let pos = memchr(parse.delimiter, line)?;
+    match parse.columns {
+        Some(columns) => split_columns(line, pos, parse.delimiter, columns).map(|(_, temp)| temp),
+        None => Some(&line[pos + 1..]),
+    }
+}
+
+/// Whether `field` starts like a number, `12`, `-3.5`, `+.5` or `"4,2"`,
+/// even if it does not parse as a temperature.
+fn looks_numeric(field: &[u8]) -> bool {
+    let field = field.trim_ascii();
+    let field = field.strip_prefix(b"\"").unwrap_or(field);
+    let unsigned = field
+        .strip_prefix(b"-")
+        .or(field.strip_prefix(b"+"))
+        .unwrap_or(field);
+    let digits = unsigned
+        .strip_prefix(b".")
+        .or(unsigned.strip_prefix(b","))
+        .unwrap_or(unsigned);
+    digits.first().is_some_and(u8::is_ascii_digit)
+}
+
+/// [`sniff_sample`] over the start of the file at `path`, decompressed if
+/// it is compressed. Streams, which cannot be read twice, are not looked
+/// at; see [`sniff_reader`] for those.
+pub fn sniff_file(path: impl AsRef<Path>, parse: ParseOptions) -> io::Result<ParseOptions> {
+    let path = path.as_ref();
+    if !wants_sample(&parse) || is_stream(path) {
+        return Ok(parse);
+    }
+    let mut sample = Vec::new();
+    open_input(path)?
+        .take(SNIFF_SIZE)
+        .read_to_end(&mut sample)?;
+    sniff_sample(&sample, parse)
+}
+
+/// Whether [`sniff_sample`] would change `parse` for some input: JSON Lines
+/// have no header unless told to skip one.
+pub(crate) fn wants_sample(parse: &ParseOptions) -> bool {
+    parse.format != InputFormat::Jsonl || parse.skip_header
+}
+
+/// A reader with the sample [`sniff_reader`] took from it put back in front.
+pub(crate) type Sniffed<R> = io::Chain<Cursor<Vec<u8>>, R>;
+
+/// [`sniff_sample`] over the start of `reader`, which is handed back whole.
+pub(crate) fn sniff_reader<R: Read>(
+    mut reader: R,
+    parse: ParseOptions,
+) -> io::Result<(Sniffed<R>, ParseOptions)> {
+    let mut sample = Vec::new();
+    if wants_sample(&parse) {
+        // A short taste of a pipe looks like the end, so keep reading to the limit
+        (&mut reader).take(SNIFF_SIZE).read_to_end(&mut sample)?;
+    }
+    let parse = sniff_sample(&sample, parse)?;
+    Ok((Cursor::new(sample).chain(reader), parse))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auto() -> ParseOptions {
+        ParseOptions {
+            format: InputFormat::Auto,
+            ..ParseOptions::default()
+        }
+    }
+
+    #[test]
+    fn auto_finds_json_lines() {
+        let parse = sniff_sample(b" {\"station\": \"Oslo\", \"temp\": 1.0}\n", auto()).unwrap();
+        assert_eq!(parse.format, InputFormat::Jsonl);
+        assert_eq!(parse.header, None);
+    }
+
+    #[test]
+    fn auto_picks_the_delimiter_most_lines_parse_with() {
+        let parse = sniff_sample(b"Oslo\t-3.4\nLima\t18.2\nbad\n", auto()).unwrap();
+        assert_eq!(parse.format, InputFormat::Delimited);
+        assert_eq!(parse.delimiter, b'\t');
+        assert!(!parse.decimal_comma);
+
+        let parse = sniff_sample(b"Oslo,-3.4\nLima,18.2\n", auto()).unwrap();
+        assert_eq!(parse.delimiter, b',');
+
+        // Nothing parses, so the delimiter asked for stays
+        let parse = sniff_sample(b"one\ntwo\n", auto()).unwrap();
+        assert_eq!(
+            (parse.format, parse.delimiter),
+            (InputFormat::Delimited, b';')
+        );
+    }
+
+    #[test]
+    fn auto_turns_on_a_decimal_comma() {
+        let parse = sniff_sample(b"Oslo;-3,4\nLima;18,2\n", auto()).unwrap();
+        assert_eq!(parse.delimiter, b';');
+        assert!(parse.decimal_comma);
+    }
+
+    #[test]
+    fn a_first_line_without_a_number_is_the_header() {
+        let sample = b"\xEF\xBB\xBFstation;temperature\r\nOslo;-3.4\r\n";
+        let parse = sniff_sample(sample, ParseOptions::default()).unwrap();
+        assert_eq!(parse.header, Some(Header::new(b"station;temperature")));
+
+        // A broken reading is not a header
+        let parse = sniff_sample(b"Oslo;-3.4.5\nLima;18.2\n", ParseOptions::default()).unwrap();
+        assert_eq!(parse.header, None);
+        let parse = sniff_sample(b"Oslo;-3.4\n", ParseOptions::default()).unwrap();
+        assert_eq!(parse.header, None);
+    }
+
+    #[test]
+    fn skip_header_takes_any_first_line_but_a_reading() {
+        let skip = ParseOptions {
+            skip_header: true,
+            ..ParseOptions::default()
+        };
+        let parse = sniff_sample(b"id;42\nOslo;-3.4\n", skip).unwrap();
+        assert_eq!(parse.header, Some(Header::new(b"id;42")));
+
+        let err = sniff_sample(b"Oslo;-3.4\nLima;18.2\n", skip).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("Oslo;-3.4"), "{err}");
+    }
+
+    #[test]
+    fn only_whole_lines_of_the_sample_are_looked_at() {
+        // The cut-off last line would not parse with `;`
+        let parse = sniff_sample(b"Oslo,1.0\nLima,2.0\nRiga;", auto()).unwrap();
+        assert_eq!(parse.delimiter, b',');
+        assert_eq!(sniff_sample(b"", auto()).unwrap(), auto());
+    }
+
+    #[test]
+    fn sniff_reader_hands_the_input_back_whole() {
+        let input: Vec<u8> = (0..2 * SNIFF_SIZE as usize)
+            .map(|i| if i % 10 == 9 { b'\n' } else { b'7' })
+            .collect();
+        let (mut reader, _) = sniff_reader(input.as_slice(), auto()).unwrap();
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, input);
+    }
+}
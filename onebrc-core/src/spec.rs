@@ -0,0 +1,255 @@
+//! Checks that an input keeps to the rules of the challenge, which the
+//! parsers are more forgiving than: every line is a station name of 1 to
+//! 100 bytes of UTF-8, exactly one `;`, and a temperature from -99.9 to
+//! 99.9 with exactly one decimal.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+use std::thread;
+
+use memchr::{memchr, memchr_iter, memrchr};
+
+use crate::chunk::{map_file, slice_chunks};
+use crate::columnar::is_parquet;
+use crate::compression::{Compression, is_stream, open_input};
+
+/// The longest station name allowed, in bytes.
+pub const MAX_NAME_BYTES: usize = 100;
+
+/// Bytes of a broken line kept in its [`LineViolation`].
+const SAMPLE_BYTES: usize = 200;
+
+/// What is wrong with a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Violation {
+    /// No `;`, or more than one.
+    Delimiters,
+    EmptyName,
+    /// A name over [`MAX_NAME_BYTES`].
+    LongName,
+    InvalidUtf8,
+    /// Anything but an optional `-`, one or two digits, `.` and one digit.
+    Temperature,
+    /// A `\r\n` line ending rather than `\n`.
+    CarriageReturn,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Violation::Delimiters => "not exactly one `;`",
+            Violation::EmptyName => "empty station name",
+            Violation::LongName => "station name over 100 bytes",
+            Violation::InvalidUtf8 => "not UTF-8",
+            Violation::Temperature => "temperature not from -99.9 to 99.9 with one decimal",
+            Violation::CarriageReturn => "CRLF line ending",
+        })
+    }
+}
+
+/// Checks one line, without its newline.
+pub fn check_line(line: &[u8]) -> Result<(), Violation> {
+    if line.ends_with(b"\r") {
+        return Err(Violation::CarriageReturn);
+    }
+    let pos = memchr(b';', line).ok_or(Violation::Delimiters)?;
+    let (name, temperature) = (&line[..pos], &line[pos + 1..]);
+    if memchr(b';', temperature).is_some() {
+        return Err(Violation::Delimiters);
+    }
+    if name.is_empty() {
+        return Err(Violation::EmptyName);
+    }
+    if name.len() > MAX_NAME_BYTES {
+        return Err(Violation::LongName);
+    }
+    if std::str::from_utf8(name).is_err() {
+        return Err(Violation::InvalidUtf8);
+    }
+    let digits = temperature.strip_prefix(b"-").unwrap_or(temperature);
+    let valid = match digits {
+        [whole, b'.', tenth] => whole.is_ascii_digit() && tenth.is_ascii_digit(),
+        [tens, ones, b'.', tenth] => {
+            tens.is_ascii_digit() && ones.is_ascii_digit() && tenth.is_ascii_digit()
+        }
+        _ => false,
+    };
+    if !valid {
+        return Err(Violation::Temperature);
+    }
+    Ok(())
+}
+
+/// A line that broke a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineViolation {
+    /// 1-based line number.
+    pub line: u64,
+    /// Where the line starts, in bytes from the start of the input.
+    pub offset: u64,
+    pub violation: Violation,
+    /// The line as it was, or its first 200 bytes.
+    pub content: Vec<u8>,
+}
+
+/// What [`verify_file`] found.
+#[derive(Debug, Clone, Default)]
+pub struct Verification {
+    lines: u64,
+    by_violation: BTreeMap<Violation, u64>,
+    samples: Vec<LineViolation>,
+    max_samples: usize,
+}
+
+impl Verification {
+    fn new(max_samples: usize) -> Self {
+        Verification {
+            max_samples,
+            ..Verification::default()
+        }
+    }
+
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    /// Lines that broke a rule.
+    pub fn violations(&self) -> u64 {
+        self.by_violation.values().sum()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.by_violation.is_empty()
+    }
+
+    /// How many lines broke each rule, for the rules some line broke.
+    pub fn by_violation(&self) -> impl Iterator<Item = (Violation, u64)> + '_ {
+        self.by_violation
+            .iter()
+            .map(|(&violation, &count)| (violation, count))
+    }
+
+    /// The first lines that broke a rule, in input order.
+    pub fn samples(&self) -> &[LineViolation] {
+        &self.samples
+    }
+
+    /// Checks the lines of `chunk`, which starts a line `offset` bytes into
+    /// the input; a last line without a newline counts too.
+    fn check_chunk(&mut self, chunk: &[u8], offset: u64) {
+        let mut start = 0;
+        for end in memchr_iter(b'\n', chunk) {
+            self.check(&chunk[start..end], offset + start as u64);
+            start = end + 1;
+        }
+        if start < chunk.len() {
+            self.check(&chunk[start..], offset + start as u64);
+        }
+    }
+
+    fn check(&mut self, line: &[u8], offset: u64) {
+        self.lines += 1;
+        let Err(violation) = check_line(line) else {
+            return;
+        };
+        *self.by_violation.entry(violation).or_default() += 1;
+        if self.samples.len() < self.max_samples {
+            self.samples.push(LineViolation {
+                line: self.lines,
+                offset,
+                violation,
+                content: line[..line.len().min(SAMPLE_BYTES)].to_vec(),
+            });
+        }
+    }
+
+    /// Appends what was found in the input right after this one's.
+    fn append(&mut self, other: Verification) {
+        for (violation, count) in other.by_violation {
+            *self.by_violation.entry(violation).or_default() += count;
+        }
+        let room = self.max_samples.saturating_sub(self.samples.len());
+        let lines = self.lines;
+        self.samples.extend(
+            other
+                .samples
+                .into_iter()
+                .take(room)
+                .map(|sample| LineViolation {
+                    line: sample.line + lines,
+                    ..sample
+                }),
+        );
+        self.lines += other.lines;
+    }
+}
+
+/// Checks every line of the input at `path` (`-` for stdin; gzip and zstd
+/// files are decompressed), keeping the first `max_samples` lines that
+/// break a rule. A plain file is mapped and checked on `threads` threads.
+pub fn verify_file(
+    path: impl AsRef<Path>,
+    threads: usize,
+    max_samples: usize,
+) -> io::Result<Verification> {
+    let path = path.as_ref();
+    if is_stream(path) || Compression::detect(path)?.is_some() {
+        return verify_reader(open_input(path)?, max_samples);
+    }
+    if is_parquet(path)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Parquet files have no lines to verify",
+        ));
+    }
+    let mmap = map_file(path)?;
+    let data: &[u8] = &mmap;
+    let parts = thread::scope(|scope| {
+        let handles: Vec<_> = slice_chunks(data, threads.max(1))
+            .into_iter()
+            .map(|chunk| {
+                let offset = (chunk.as_ptr() as usize - data.as_ptr() as usize) as u64;
+                scope.spawn(move || {
+                    let mut part = Verification::new(max_samples);
+                    part.check_chunk(chunk, offset);
+                    part
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    let mut verification = Verification::new(max_samples);
+    for part in parts {
+        verification.append(part);
+    }
+    Ok(verification)
+}
+
+/// [`verify_file`] for input read front to back on the calling thread.
+pub fn verify_reader(mut reader: impl Read, max_samples: usize) -> io::Result<Verification> {
+    let mut verification = Verification::new(max_samples);
+    let mut buf = vec![0; 1 << 20];
+    let mut pending = Vec::new();
+    let mut offset = 0;
+    loop {
+        let read = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        pending.extend_from_slice(&buf[..read]);
+        if let Some(end) = memrchr(b'\n', &pending) {
+            verification.check_chunk(&pending[..end + 1], offset);
+            offset += end as u64 + 1;
+            pending.drain(..end + 1);
+        }
+    }
+    verification.check_chunk(&pending, offset);
+    Ok(verification)
+}
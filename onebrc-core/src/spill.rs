@@ -0,0 +1,279 @@
+//! Workers that keep their maps under [`Options::max_memory`] by spilling
+//! them to sorted runs on disk, merged back once the input is done.
+//!
+//! [`Options::max_memory`]: crate::Options::max_memory
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{self, AtomicUsize};
+use std::{env, process};
+
+use crate::aggregator::Aggregator;
+use crate::pipeline::merge_all;
+use crate::progress::{Progress, Stage, timed};
+use crate::stats::TempStats;
+use crate::trace::{event, span};
+
+/// A station and its statistics, as runs hold them.
+type Entry = (String, TempStats);
+
+/// The runs spilled by the workers of one pool, in a directory of their own
+/// under [`env::temp_dir`] that goes away with the `Spill`.
+pub(crate) struct Spill {
+    /// Bytes of map each worker may hold before spilling it.
+    budget: usize,
+    dir: PathBuf,
+    runs: Mutex<Vec<PathBuf>>,
+    /// The first spill that failed, reported by [`Spill::merge`].
+    error: Mutex<Option<io::Error>>,
+}
+
+impl Spill {
+    /// Splits `limit` bytes evenly between `workers`.
+    pub(crate) fn new(limit: u64, workers: usize) -> io::Result<Self> {
+        static SPILLS: AtomicUsize = AtomicUsize::new(0);
+        let spill = SPILLS.fetch_add(1, atomic::Ordering::Relaxed);
+        let dir = env::temp_dir().join(format!("onebrc-spill-{}-{spill}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let budget = limit / workers.max(1) as u64;
+        Ok(Spill {
+            budget: usize::try_from(budget).unwrap_or(usize::MAX),
+            dir,
+            runs: Mutex::new(Vec::new()),
+            error: Mutex::new(None),
+        })
+    }
+
+    /// Writes the stations of `aggregator` out as a run if its map is over
+    /// the worker's share of the limit. Returns false if that failed; the
+    /// error waits for [`Spill::merge`].
+    pub(crate) fn offer(&self, aggregator: &mut Aggregator) -> bool {
+        if aggregator.map_bytes() <= self.budget {
+            return true;
+        }
+        let stations = aggregator.take_sorted();
+        let _span = span!(DEBUG, "spill", stations = stations.len());
+        match self.write(&stations) {
+            Ok(path) => {
+                self.runs.lock().expect("spill runs lock").push(path);
+                true
+            }
+            Err(err) => {
+                let mut error = self.error.lock().expect("spill error lock");
+                error.get_or_insert(err);
+                false
+            }
+        }
+    }
+
+    fn write(&self, stations: &[Entry]) -> io::Result<PathBuf> {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        let run = RUNS.fetch_add(1, atomic::Ordering::Relaxed);
+        let path = self.dir.join(format!("run-{run}.jsonl"));
+        let mut out = BufWriter::new(File::create(&path)?);
+        for entry in stations {
+            serde_json::to_writer(&mut out, entry)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()?;
+        Ok(path)
+    }
+
+    /// Merges what is left in the workers' `partials` with the spilled runs.
+    /// The runs and the sorted remainders are merged by name in one pass,
+    /// so they are never all in memory at once; only the merged stations
+    /// are.
+    pub(crate) fn merge(
+        &self,
+        mut partials: Vec<Aggregator>,
+        progress: Option<&Progress>,
+    ) -> io::Result<Aggregator> {
+        if let Some(err) = self.error.lock().expect("spill error lock").take() {
+            return Err(err);
+        }
+        let runs = std::mem::take(&mut *self.runs.lock().expect("spill runs lock"));
+        if runs.is_empty() {
+            return Ok(merge_all(partials, progress));
+        }
+        event!(DEBUG, runs = runs.len(), "merging spilled runs");
+
+        let mut sources: Vec<Box<dyn Iterator<Item = io::Result<Entry>>>> = Vec::new();
+        for path in &runs {
+            let reader = BufReader::new(File::open(path)?);
+            let entries = serde_json::Deserializer::from_reader(reader)
+                .into_iter::<Entry>()
+                .map(|entry| entry.map_err(io::Error::from));
+            sources.push(Box::new(entries));
+        }
+        for partial in &mut partials {
+            sources.push(Box::new(partial.take_sorted().into_iter().map(Ok)));
+        }
+        // Only the line bookkeeping is left in the partials
+        let mut total = merge_all(partials, progress);
+        timed(progress, Stage::Merge, || {
+            merge_sorted(sources, |city, stats| total.insert_merged(city, stats))
+        })?;
+        Ok(total)
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The next entry of one source, ordered by name and then by source so the
+/// heap pops the smallest name first.
+struct Head {
+    entry: Entry,
+    source: usize,
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let ours = (&self.entry.0, self.source);
+        ours.cmp(&(&other.entry.0, other.source)).reverse()
+    }
+}
+
+/// Calls `each` once per station of `sources`, each sorted by name, with
+/// the statistics of all its entries merged, in name order.
+fn merge_sorted(
+    mut sources: Vec<Box<dyn Iterator<Item = io::Result<Entry>>>>,
+    mut each: impl FnMut(String, TempStats),
+) -> io::Result<()> {
+    let mut heads = BinaryHeap::with_capacity(sources.len());
+    let mut refill = Vec::new();
+    for (source, entries) in sources.iter_mut().enumerate() {
+        if let Some(entry) = entries.next().transpose()? {
+            heads.push(Head { entry, source });
+        }
+    }
+    while let Some(Head { entry, source }) = heads.pop() {
+        let (city, mut stats) = entry;
+        refill.push(source);
+        while let Some(next) = heads.peek()
+            && next.entry.0 == city
+        {
+            let next = heads.pop().expect("peeked");
+            stats.merge(&next.entry.1);
+            refill.push(next.source);
+        }
+        for source in refill.drain(..) {
+            if let Some(entry) = sources[source].next().transpose()? {
+                heads.push(Head { entry, source });
+            }
+        }
+        each(city, stats);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{Options, aggregate_file};
+    use crate::stats::StatsOptions;
+    use crate::testing::TempFile;
+
+    fn sorted(entries: &[(&str, i32)]) -> Box<dyn Iterator<Item = io::Result<Entry>>> {
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|&(city, temp)| Ok((city.to_string(), TempStats::new(temp))))
+            .collect();
+        Box::new(entries.into_iter())
+    }
+
+    #[test]
+    fn merge_sorted_merges_each_station_once_in_order() {
+        let sources = vec![
+            sorted(&[("Lima", 10), ("Oslo", -5)]),
+            sorted(&[]),
+            sorted(&[("Abha", 3), ("Lima", 30), ("Riga", 0)]),
+            sorted(&[("Oslo", 7)]),
+        ];
+        let mut merged = Vec::new();
+        merge_sorted(sources, |city, stats| {
+            merged.push((city, stats.min(), stats.max(), stats.count()));
+        })
+        .unwrap();
+        let expected = [
+            ("Abha", 0.3, 0.3, 1),
+            ("Lima", 1.0, 3.0, 2),
+            ("Oslo", -0.5, 0.7, 2),
+            ("Riga", 0.0, 0.0, 1),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(city, min, max, count)| (city.to_string(), min, max, count))
+            .collect();
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn merge_sorted_stops_at_a_failing_source() {
+        let failing = vec![
+            Ok(("Lima".to_string(), TempStats::new(1))),
+            Err(io::Error::other("bad run")),
+        ];
+        let sources: Vec<Box<dyn Iterator<Item = io::Result<Entry>>>> =
+            vec![sorted(&[("Abha", 1)]), Box::new(failing.into_iter())];
+        let err = merge_sorted(sources, |_, _| {}).unwrap_err();
+        assert_eq!(err.to_string(), "bad run");
+    }
+
+    #[test]
+    fn spilling_gives_the_same_results_as_not_spilling() {
+        let mut input = String::new();
+        for line in 0..20_000 {
+            let temp: i32 = line * 37 % 1999 - 999;
+            let (whole, tenth) = (temp / 10, (temp % 10).abs());
+            let sign = if temp < 0 && whole == 0 { "-" } else { "" };
+            // Some names too long to be stored inline
+            let city = if line % 7 == 0 {
+                format!("{}-{}", "long station name ".repeat(3), line % 40)
+            } else {
+                format!("station-{}", line % 1500)
+            };
+            input.push_str(&format!("{city};{sign}{whole}.{tenth}\n"));
+        }
+        let file = TempFile::new("spill-equivalence", input.as_bytes());
+        let options = Options {
+            threads: 2,
+            batch_size: 500,
+            stats: StatsOptions {
+                histogram: true,
+                ..StatsOptions::default()
+            },
+            ..Options::default()
+        };
+        let plain = aggregate_file(&file.0, &options).unwrap();
+        // A byte of map each, so every worker spills after every batch
+        let spilling = Options {
+            max_memory: Some(2),
+            ..options
+        };
+        let spilled = aggregate_file(&file.0, &spilling).unwrap();
+        assert_eq!(spilled.rows(), plain.rows());
+        assert!(spilled.into_results() == plain.into_results());
+    }
+}
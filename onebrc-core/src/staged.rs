@@ -0,0 +1,159 @@
+//! [`Mode::Pipelined`]: I/O threads that only read, the calling thread that
+//! only hands out what they read, and the usual parse workers.
+//!
+//! [`Mode::Pipelined`]: crate::Mode::Pipelined
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::thread;
+
+use crossbeam::channel::{self, Receiver, SendError, Sender};
+use memchr::memchr_iter;
+
+use crate::aggregator::Aggregator;
+use crate::parse::BOM;
+use crate::pipeline::{Options, QUEUE_DEPTH_PER_WORKER, STREAM_BLOCK_SIZE, run_pool};
+use crate::pool::{Buffer, BufferPool};
+use crate::progress::{Progress, Stage, timed};
+use crate::trace::span;
+
+/// Aggregates `path` with `options.io_threads` threads reading it in
+/// stripes of about [`STREAM_BLOCK_SIZE`] bytes, each at most
+/// `options.read_queue_depth` stripes ahead of the calling thread.
+///
+/// Readers move both ends of their stripes to line starts the way
+/// [`chunk_ranges`] does, so a stripe holds whole lines and goes to the
+/// workers as it was read. The calling thread only takes the stripes in
+/// file order, one reader after the other, and numbers their lines.
+/// Stripes are pooled, and go back to the readers once a worker is done.
+///
+/// [`chunk_ranges`]: crate::chunk_ranges
+pub(crate) fn process_pipelined(path: &Path, options: &Options) -> io::Result<Aggregator> {
+    let len = std::fs::metadata(path)?.len();
+    let readers = options.io_threads.max(1);
+    let depth = options.read_queue_depth.max(1);
+    let workers = options.threads.max(1);
+    let queue_depth = options
+        .queue_depth
+        .unwrap_or(workers * QUEUE_DEPTH_PER_WORKER);
+    // Stripes wait at the readers, in the workers' queue and at the workers
+    let pool = BufferPool::new(readers * depth + queue_depth.max(1), readers + workers);
+
+    run_pool(options, |send, _, _| {
+        thread::scope(|scope| {
+            let queues: Vec<Receiver<io::Result<Stripe>>> = (0..readers)
+                .map(|index| {
+                    let (sender, receiver) = channel::bounded(depth);
+                    let (pool, progress) = (&pool, options.progress.as_deref());
+                    scope.spawn(move || {
+                        let _span = span!(DEBUG, "io", index);
+                        let stripes = Stripes {
+                            path,
+                            len,
+                            first: index,
+                            step: readers,
+                        };
+                        // A closed queue means the run is over
+                        let _ = stripes.read(pool, progress, &sender);
+                    });
+                    receiver
+                })
+                .collect();
+
+            let mut next_line = 1;
+            for queue in queues.iter().cycle() {
+                // Readers close their queues once past the end of the file
+                let Ok(stripe) = queue.recv() else {
+                    break;
+                };
+                let Stripe {
+                    lines,
+                    offset,
+                    bytes,
+                } = stripe?;
+                if !bytes.is_empty() && !send(next_line, offset, bytes) {
+                    break;
+                }
+                next_line += lines;
+            }
+            Ok(())
+        })
+    })
+}
+
+/// A newline-aligned part of the file, the number of lines it holds and
+/// the byte it starts at.
+struct Stripe {
+    lines: u64,
+    offset: u64,
+    bytes: Buffer,
+}
+
+/// Every `step`th stripe of the file from stripe `first` on.
+struct Stripes<'a> {
+    path: &'a Path,
+    len: u64,
+    first: usize,
+    step: usize,
+}
+
+impl Stripes<'_> {
+    /// Reads the stripes into buffers from `pool` and queues them, until
+    /// the file ends or the queue is closed. Errors are queued too.
+    fn read(
+        &self,
+        pool: &BufferPool,
+        progress: Option<&Progress>,
+        queue: &Sender<io::Result<Stripe>>,
+    ) -> Result<(), SendError<io::Result<Stripe>>> {
+        let mut file = match File::open(self.path) {
+            Ok(file) => file,
+            Err(err) => return queue.send(Err(err)),
+        };
+        let mut scratch = Vec::new();
+        let block = STREAM_BLOCK_SIZE as u64;
+        let mut index = self.first as u64;
+        while index * block < self.len {
+            let stripe = timed(progress, Stage::Read, || {
+                let start = self.line_start(&mut file, index * block, &mut scratch)?;
+                let end = self.line_start(&mut file, (index + 1) * block, &mut scratch)?;
+                let mut bytes = pool.get();
+                file.seek(SeekFrom::Start(start))?;
+                (&mut file).take(end - start).read_to_end(&mut bytes)?;
+                let mut offset = start;
+                if start == 0 && bytes.starts_with(BOM) {
+                    bytes.drain(..BOM.len());
+                    offset += BOM.len() as u64;
+                }
+                let lines = memchr_iter(b'\n', &bytes).count() as u64;
+                Ok(Stripe {
+                    lines,
+                    offset,
+                    bytes,
+                })
+            });
+            let failed = stripe.is_err();
+            queue.send(stripe)?;
+            if failed {
+                break;
+            }
+            index += self.step as u64;
+        }
+        Ok(())
+    }
+
+    /// `offset` moved just past the next newline, as in [`chunk_ranges`];
+    /// the start and the end of the file stay where they are.
+    ///
+    /// [`chunk_ranges`]: crate::chunk_ranges
+    fn line_start(&self, file: &mut File, offset: u64, scratch: &mut Vec<u8>) -> io::Result<u64> {
+        if offset == 0 || offset >= self.len {
+            return Ok(offset.min(self.len));
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        scratch.clear();
+        let skipped = BufReader::new(file).read_until(b'\n', scratch)?;
+        Ok((offset + skipped as u64).min(self.len))
+    }
+}
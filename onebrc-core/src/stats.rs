@@ -0,0 +1,251 @@
+use crate::digest::TDigest;
+use crate::histogram::Histogram;
+
+/// Optional statistics gathered on top of min/mean/max. Everything here costs
+/// extra work per reading, so it is all off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StatsOptions {
+    /// Keep a [`TDigest`] per station for [`TempStats::quantile`].
+    pub percentiles: bool,
+    /// Keep a [`Histogram`] per station for [`TempStats::histogram`].
+    pub histogram: bool,
+}
+
+impl StatsOptions {
+    /// About the most the statistics these options add take on the heap,
+    /// per station.
+    #[cfg(feature = "spill")]
+    pub(crate) fn heap_bytes(self) -> usize {
+        let digest = if self.percentiles {
+            crate::digest::DIGEST_BYTES
+        } else {
+            0
+        };
+        let histogram = if self.histogram {
+            std::mem::size_of::<Histogram>()
+        } else {
+            0
+        };
+        digest + histogram
+    }
+}
+
+/// Running statistics for one station. Temperatures are kept as integer
+/// tenths of a degree and only converted to decimal when read back.
+///
+/// With the `variance` feature the stats also carry the sum of the squared
+/// readings for [`TempStats::variance`]; it costs a multiplication per
+/// reading, so it is compiled out of the default build.
+///
+/// Min, max, sum, count and sum of squares are integers, so merging them is
+/// exact and the min, mean, max and standard deviation come out the same
+/// however the input was split between workers and in whatever order the
+/// parts were merged. The percentile digest is an estimate that depends on
+/// both, so percentiles can differ between runs in the last digits.
+///
+/// With the `serde` feature the raw accumulators are serialized, so a
+/// deserialized value merges exactly like the original.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TempStats {
+    min: i32,
+    max: i32,
+    sum: i64,
+    count: u64,
+    /// Sum of the squared readings, in tenths squared; wide enough for any
+    /// `i32` reading, which a `u64` is not past a few of them.
+    #[cfg(feature = "variance")]
+    sum_squares: u128,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    digest: Option<Box<TDigest>>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    histogram: Option<Box<Histogram>>,
+}
+
+impl TempStats {
+    /// Starts the statistics from a single reading, in tenths of a degree.
+    pub fn new(temp: i32) -> Self {
+        TempStats {
+            min: temp,
+            max: temp,
+            sum: temp as i64,
+            count: 1,
+            #[cfg(feature = "variance")]
+            sum_squares: square(temp),
+            digest: None,
+            histogram: None,
+        }
+    }
+
+    /// Like [`TempStats::new`], also starting whatever `options` asks for.
+    pub fn with_options(temp: i32, options: StatsOptions) -> Self {
+        let mut stats = TempStats::new(temp);
+        if options.percentiles {
+            let mut digest = TDigest::new();
+            digest.add(temp as f64);
+            stats.digest = Some(Box::new(digest));
+        }
+        if options.histogram {
+            let mut histogram = Histogram::new();
+            histogram.add(temp);
+            stats.histogram = Some(Box::new(histogram));
+        }
+        stats
+    }
+
+    /// Adds one reading, in tenths of a degree.
+    pub fn update(&mut self, temp: i32) {
+        self.min = self.min.min(temp);
+        self.max = self.max.max(temp);
+        self.sum += temp as i64;
+        self.count += 1;
+        #[cfg(feature = "variance")]
+        {
+            self.sum_squares += square(temp);
+        }
+        if let Some(digest) = &mut self.digest {
+            digest.add(temp as f64);
+        }
+        if let Some(histogram) = &mut self.histogram {
+            histogram.add(temp);
+        }
+    }
+
+    /// Folds in statistics gathered elsewhere (another thread, another file).
+    pub fn merge(&mut self, other: &TempStats) {
+        #[cfg(feature = "variance")]
+        {
+            self.sum_squares += other.sum_squares;
+        }
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.count += other.count;
+        match (&mut self.digest, &other.digest) {
+            (Some(digest), Some(other)) => digest.merge(other),
+            (None, Some(other)) => self.digest = Some(other.clone()),
+            (_, None) => {}
+        }
+        match (&mut self.histogram, &other.histogram) {
+            (Some(histogram), Some(other)) => histogram.merge(other),
+            (None, Some(other)) => self.histogram = Some(other.clone()),
+            (_, None) => {}
+        }
+    }
+
+    pub fn min(&self) -> f64 {
+        self.min as f64 / 10.0
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max as f64 / 10.0
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / (10 * self.count) as f64
+    }
+
+    /// Number of readings seen.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Estimated temperature below which a fraction `q` (0 to 1) of the
+    /// readings fall, or `None` unless percentiles were enabled in
+    /// [`StatsOptions`].
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        self.digest.as_ref().map(|digest| digest.quantile(q) / 10.0)
+    }
+
+    /// Bucketed readings, if the histogram was enabled in [`StatsOptions`].
+    pub fn histogram(&self) -> Option<&Histogram> {
+        self.histogram.as_deref()
+    }
+
+    /// Population variance of the readings, in degrees squared.
+    #[cfg(feature = "variance")]
+    pub fn variance(&self) -> f64 {
+        // n * sum(x^2) - sum(x)^2 is worked out exactly, so the only
+        // rounding is in the division; it is never negative
+        let count = self.count as f64;
+        let sum = self.sum.unsigned_abs() as u128;
+        match (self.count as u128).checked_mul(self.sum_squares) {
+            Some(scaled) => (scaled - sum * sum) as f64 / (count * count) / 100.0,
+            // Only for readings far from any real temperature
+            None => {
+                let mean = self.sum as f64 / count;
+                (self.sum_squares as f64 / count - mean * mean).max(0.0) / 100.0
+            }
+        }
+    }
+
+    /// Population standard deviation of the readings, in degrees.
+    #[cfg(feature = "variance")]
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+}
+
+/// `temp` squared, which does not fit an `i32` or, summed, a `u64`.
+#[cfg(feature = "variance")]
+fn square(temp: i32) -> u128 {
+    let abs = temp.unsigned_abs() as u128;
+    abs * abs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats_of(temps: &[i32]) -> TempStats {
+        let mut stats = TempStats::new(temps[0]);
+        for &temp in &temps[1..] {
+            stats.update(temp);
+        }
+        stats
+    }
+
+    #[test]
+    fn readings_at_the_i32_limits() {
+        let stats = stats_of(&[i32::MAX; 5]);
+        assert_eq!(stats.max(), i32::MAX as f64 / 10.0);
+        assert_eq!(stats.mean(), i32::MAX as f64 / 10.0);
+        let stats = stats_of(&[i32::MIN, i32::MAX]);
+        assert_eq!(stats.min(), i32::MIN as f64 / 10.0);
+        assert_eq!(stats.mean(), -0.05);
+    }
+
+    #[cfg(feature = "variance")]
+    #[test]
+    fn variance_at_the_i32_limits() {
+        let same = stats_of(&[i32::MAX; 5]);
+        assert_eq!(same.variance(), 0.0);
+        let mut merged = same.clone();
+        merged.merge(&same);
+        assert_eq!(merged.variance(), 0.0);
+        assert_eq!(merged.count(), 10);
+
+        // Two readings a and b have a variance of ((a - b) / 2)^2
+        let spread = stats_of(&[i32::MIN, i32::MAX]);
+        let half = (i32::MAX as f64 - i32::MIN as f64) / 2.0 / 10.0;
+        assert_eq!(spread.std_dev(), half);
+    }
+
+    #[cfg(feature = "variance")]
+    #[test]
+    fn variance_is_exact_however_readings_are_merged() {
+        let temps: Vec<i32> = (0..1000).map(|i| (i * 37 % 1999) - 999).collect();
+        let whole = stats_of(&temps);
+        let mut merged = stats_of(&temps[..333]);
+        merged.merge(&stats_of(&temps[333..]));
+        assert_eq!(whole, merged);
+        assert_eq!(whole.variance(), merged.variance());
+    }
+}
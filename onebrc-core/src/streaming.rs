@@ -0,0 +1,98 @@
+use std::io;
+use std::mem;
+
+use memchr::memrchr;
+
+use crate::aggregator::Aggregator;
+use crate::parse::strip_bom;
+use crate::sniff::{SNIFF_SIZE, sniff_sample, wants_sample};
+
+/// An [`Aggregator`] fed an input in pieces of any size, such as the blocks
+/// of an HTTP body or a browser `ReadableStream`, which may end anywhere in
+/// a line.
+///
+/// Everything happens on the calling thread, without files or memory maps,
+/// so this is the way in on `wasm32`, where [`aggregate_file`] has no
+/// threads to run. The first 16 KiB are held back and sniffed as for
+/// [`aggregate_reader`], which completes [`InputFormat::Auto`] and finds a
+/// header line; after that, every chunk's whole lines are aggregated as it
+/// comes and only the unfinished last one is kept.
+///
+/// [`aggregate_file`]: crate::aggregate_file
+/// [`aggregate_reader`]: crate::aggregate_reader
+/// [`InputFormat::Auto`]: crate::InputFormat::Auto
+#[derive(Debug, Clone)]
+pub struct ChunkAggregator {
+    aggregator: Aggregator,
+    /// The sample until it is sniffed, then the start of a line whose end is
+    /// still to come.
+    pending: Vec<u8>,
+    sniffed: bool,
+}
+
+impl ChunkAggregator {
+    /// Feeds `aggregator`, with the parse options, stats, filter and aliases
+    /// it was set up with.
+    pub fn new(aggregator: Aggregator) -> Self {
+        ChunkAggregator {
+            aggregator,
+            pending: Vec::new(),
+            sniffed: false,
+        }
+    }
+
+    /// Aggregates the lines that `chunk` completes. Fails in strict mode on
+    /// a malformed line, or if the sample shows a header was promised but
+    /// the first line is a reading.
+    pub fn push_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        if self.sniffed {
+            return self.feed(chunk);
+        }
+        self.pending.extend_from_slice(chunk);
+        if self.pending.len() as u64 >= SNIFF_SIZE {
+            self.sniff()?;
+        }
+        Ok(())
+    }
+
+    /// Aggregates what is left, a last line without a newline included, and
+    /// hands back the aggregator.
+    pub fn finish(mut self) -> io::Result<Aggregator> {
+        if !self.sniffed {
+            self.sniff()?;
+        }
+        self.aggregator.process_chunk(&self.pending)?;
+        Ok(self.aggregator)
+    }
+
+    fn sniff(&mut self) -> io::Result<()> {
+        let parse = self.aggregator.parse_options();
+        if wants_sample(&parse) {
+            let parse = sniff_sample(&self.pending, parse)?;
+            self.aggregator.set_parse(parse);
+        }
+        self.sniffed = true;
+        let sample = mem::take(&mut self.pending);
+        let lines = strip_bom(&sample);
+        self.aggregator
+            .set_next_offset((sample.len() - lines.len()) as u64);
+        self.feed(lines)
+    }
+
+    fn feed(&mut self, chunk: &[u8]) -> io::Result<()> {
+        let Some(end) = memrchr(b'\n', chunk) else {
+            self.pending.extend_from_slice(chunk);
+            return Ok(());
+        };
+        let (lines, rest) = chunk.split_at(end + 1);
+        if self.pending.is_empty() {
+            self.aggregator.process_chunk(lines)?;
+        } else {
+            self.pending.extend_from_slice(lines);
+            self.aggregator.process_chunk(&self.pending)?;
+            self.pending.clear();
+        }
+        self.pending.extend_from_slice(rest);
+        Ok(())
+    }
+}
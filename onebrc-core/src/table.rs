@@ -0,0 +1,267 @@
+//! The open-addressing station map behind [`Engine::CustomMap`] and
+//! [`Engine::HashOnly`].
+//!
+//! [`Engine::CustomMap`]: crate::Engine::CustomMap
+//! [`Engine::HashOnly`]: crate::Engine::HashOnly
+
+use std::hash::BuildHasher;
+use std::io;
+
+use rustc_hash::FxBuildHasher;
+
+use crate::stats::TempStats;
+
+/// Slots a table starts with: the challenge allows up to 10,000 stations,
+/// which fit under the maximum load without growing.
+const INITIAL_SLOTS: usize = 1 << 14;
+
+/// A map from station names borrowed from the input to their statistics,
+/// with linear probing over one flat array of slots. Every slot keeps the
+/// 64-bit hash of its name next to the statistics, so a probe only compares
+/// names once the hashes match, and growing or merging never rehashes.
+///
+/// A station without statistics is one the filter turned down.
+#[derive(Debug)]
+pub(crate) struct StationTable<'a> {
+    /// A power of two in length, never more than three quarters full.
+    slots: Vec<Option<Slot<'a>>>,
+    len: usize,
+    check: KeyCheck,
+    /// With [`KeyCheck::Verify`], the first two names found to share a hash.
+    collision: Option<(Vec<u8>, Vec<u8>, u64)>,
+}
+
+/// How a [`StationTable`] tells station names apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KeyCheck {
+    /// Compare the names whenever the hashes match.
+    Full,
+    /// Trust the hash alone: names that share one share their statistics,
+    /// and the first of them names the station.
+    HashOnly,
+    /// Compare the names, and record it when two different ones share a
+    /// hash, which [`StationTable::verify`] then reports.
+    Verify,
+}
+
+#[derive(Debug)]
+struct Slot<'a> {
+    hash: u64,
+    key: &'a [u8],
+    stats: Option<TempStats>,
+}
+
+impl<'a> StationTable<'a> {
+    pub(crate) fn new(check: KeyCheck) -> Self {
+        StationTable {
+            slots: empty_slots(INITIAL_SLOTS),
+            len: 0,
+            check,
+            collision: None,
+        }
+    }
+
+    pub(crate) fn hash(key: &[u8]) -> u64 {
+        FxBuildHasher.hash_one(key)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The entry for `key`, whose hash is `hash`, if it is in the table.
+    #[inline]
+    pub(crate) fn get_mut(&mut self, hash: u64, key: &[u8]) -> Option<&mut Option<TempStats>> {
+        let index = self.probe(hash, key);
+        self.slots[index].as_mut().map(|slot| &mut slot.stats)
+    }
+
+    /// Adds `key`, which must not be in the table yet.
+    pub(crate) fn insert(&mut self, hash: u64, key: &'a [u8], stats: Option<TempStats>) {
+        if (self.len + 1) * 4 > self.slots.len() * 3 {
+            self.grow();
+        }
+        let index = self.probe(hash, key);
+        if self.check == KeyCheck::Verify && self.collision.is_none() {
+            self.find_collision(hash, key, index);
+        }
+        self.slots[index] = Some(Slot { hash, key, stats });
+        self.len += 1;
+    }
+
+    /// Fails if two different names were found to share a hash.
+    pub(crate) fn verify(&self) -> io::Result<()> {
+        let Some((first, second, hash)) = &self.collision else {
+            return Ok(());
+        };
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "stations `{}` and `{}` share the hash {hash:#018x}, so they cannot be told apart by hash alone",
+                String::from_utf8_lossy(first),
+                String::from_utf8_lossy(second),
+            ),
+        ))
+    }
+
+    /// Records a name with the same hash as `key` on the way from its home
+    /// slot to `empty`, where `key` is about to go.
+    #[cold]
+    fn find_collision(&mut self, hash: u64, key: &[u8], empty: usize) {
+        let mask = self.slots.len() - 1;
+        let mut index = hash as usize & mask;
+        while index != empty {
+            if let Some(slot) = &self.slots[index]
+                && slot.hash == hash
+            {
+                self.collision = Some((slot.key.to_vec(), key.to_vec(), hash));
+                return;
+            }
+            index = (index + 1) & mask;
+        }
+    }
+
+    /// Folds `other` into `self`.
+    pub(crate) fn merge(&mut self, other: StationTable<'a>) {
+        if self.collision.is_none() {
+            self.collision = other.collision;
+        }
+        for Slot { hash, key, stats } in other.slots.into_iter().flatten() {
+            match (self.get_mut(hash, key), stats) {
+                (Some(Some(ours)), Some(theirs)) => ours.merge(&theirs),
+                (Some(_), _) => {}
+                (None, stats) => self.insert(hash, key, stats),
+            }
+        }
+    }
+
+    /// The stations and their statistics, in no particular order.
+    pub(crate) fn into_entries(self) -> impl Iterator<Item = (&'a [u8], Option<TempStats>)> {
+        self.slots
+            .into_iter()
+            .flatten()
+            .map(|slot| (slot.key, slot.stats))
+    }
+
+    /// The slot holding `key`, or the empty one where it would go.
+    #[inline]
+    fn probe(&self, hash: u64, key: &[u8]) -> usize {
+        let mask = self.slots.len() - 1;
+        let mut index = hash as usize & mask;
+        while let Some(slot) = &self.slots[index] {
+            if slot.hash == hash && (self.check == KeyCheck::HashOnly || slot.key == key) {
+                break;
+            }
+            index = (index + 1) & mask;
+        }
+        index
+    }
+
+    fn grow(&mut self) {
+        let slots = empty_slots(self.slots.len() * 2);
+        let old = std::mem::replace(&mut self.slots, slots);
+        let mask = self.slots.len() - 1;
+        for slot in old.into_iter().flatten() {
+            let mut index = slot.hash as usize & mask;
+            while self.slots[index].is_some() {
+                index = (index + 1) & mask;
+            }
+            self.slots[index] = Some(slot);
+        }
+    }
+}
+
+fn empty_slots<'a>(len: usize) -> Vec<Option<Slot<'a>>> {
+    std::iter::repeat_with(|| None).take(len).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(count: usize) -> Vec<Vec<u8>> {
+        (0..count).map(|i| format!("St{i}").into_bytes()).collect()
+    }
+
+    fn count<'a>(table: &mut StationTable<'a>, key: &[u8]) -> Option<u64> {
+        let stats = table.get_mut(StationTable::hash(key), key)?;
+        Some(stats.as_ref().map_or(0, TempStats::count))
+    }
+
+    #[test]
+    fn grows_past_the_initial_slots_keeping_every_station() {
+        let names = names(INITIAL_SLOTS);
+        let mut table = StationTable::new(KeyCheck::Full);
+        for (i, name) in names.iter().enumerate() {
+            let stats = (i % 7 != 0).then(|| TempStats::new(i as i32));
+            table.insert(StationTable::hash(name), name, stats);
+        }
+        assert_eq!(table.len(), names.len());
+        assert!(table.slots.len() * 3 >= table.len() * 4);
+        for (i, name) in names.iter().enumerate() {
+            let expected = if i % 7 == 0 { 0 } else { 1 };
+            assert_eq!(count(&mut table, name), Some(expected));
+        }
+        assert_eq!(count(&mut table, b"St-1"), None);
+        assert_eq!(table.into_entries().count(), names.len());
+    }
+
+    #[test]
+    fn merge_adds_up_shared_stations() {
+        let names = names(1000);
+        let (mut left, mut right) = (
+            StationTable::new(KeyCheck::Full),
+            StationTable::new(KeyCheck::Full),
+        );
+        for name in &names[..600] {
+            left.insert(StationTable::hash(name), name, Some(TempStats::new(10)));
+        }
+        for name in &names[400..] {
+            right.insert(StationTable::hash(name), name, Some(TempStats::new(-10)));
+        }
+        // Turned down on one side, and so on both
+        let hash = StationTable::hash(&names[500]);
+        *left.get_mut(hash, &names[500]).unwrap() = None;
+        left.merge(right);
+        assert_eq!(left.len(), 1000);
+        let merged: Vec<_> = left.into_entries().collect();
+        for (name, stats) in merged {
+            let i: usize = std::str::from_utf8(&name[2..]).unwrap().parse().unwrap();
+            let expected = match i {
+                500 => None,
+                400..600 => Some((2, 0.0)),
+                0..400 => Some((1, 1.0)),
+                _ => Some((1, -1.0)),
+            };
+            assert_eq!(stats.map(|s| (s.count(), s.mean())), expected, "St{i}");
+        }
+    }
+
+    #[test]
+    fn names_sharing_a_hash() {
+        let hash = StationTable::hash(b"Oslo");
+        let mut full = StationTable::new(KeyCheck::Full);
+        full.insert(hash, b"Oslo", Some(TempStats::new(10)));
+        assert!(full.get_mut(hash, b"Lima").is_none());
+        full.insert(hash, b"Lima", Some(TempStats::new(20)));
+        assert_eq!(full.len(), 2);
+        assert!(full.verify().is_ok());
+
+        let mut hash_only = StationTable::new(KeyCheck::HashOnly);
+        hash_only.insert(hash, b"Oslo", Some(TempStats::new(10)));
+        assert!(hash_only.get_mut(hash, b"Lima").is_some());
+
+        let mut verify = StationTable::new(KeyCheck::Verify);
+        verify.insert(hash, b"Oslo", Some(TempStats::new(10)));
+        assert!(verify.verify().is_ok());
+        verify.insert(hash, b"Lima", Some(TempStats::new(20)));
+        let err = verify.verify().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("`Oslo` and `Lima`"), "{err}");
+
+        // A collision found by either side of a merge is kept
+        let mut merged = StationTable::new(KeyCheck::Verify);
+        merged.merge(verify);
+        assert!(merged.verify().is_err());
+    }
+}
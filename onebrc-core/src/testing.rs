@@ -0,0 +1,22 @@
+//! Helpers shared by the unit tests.
+
+use std::path::PathBuf;
+use std::{fs, process};
+
+/// A file in the temporary directory that is removed when dropped. Tests run
+/// at the same time, so each names its files differently.
+pub(crate) struct TempFile(pub(crate) PathBuf);
+
+impl TempFile {
+    pub(crate) fn new(name: &str, data: &[u8]) -> Self {
+        let path = std::env::temp_dir().join(format!("onebrc-{}-{name}", process::id()));
+        fs::write(&path, data).unwrap();
+        TempFile(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
@@ -0,0 +1,56 @@
+//! Spans and events for the `tracing` feature. Without the feature the
+//! macros expand to nothing, so call sites need no `cfg` of their own.
+//!
+//! Whole runs get `INFO` spans; workers, merges and per-item spans are
+//! `DEBUG`.
+
+/// Enters a span for the rest of the scope:
+/// `let _span = span!(DEBUG, "worker", start, end);`. After the level the
+/// arguments are those of `tracing::span!`; `parent:` goes first.
+macro_rules! span {
+    (parent: $parent:expr, $level:ident, $($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::span!(parent: $parent, tracing::Level::$level, $($arg)*).entered();
+        #[cfg(not(feature = "tracing"))]
+        let span = {
+            let _ = $parent;
+            $crate::trace::NoSpan
+        };
+        span
+    }};
+    ($level:ident, $($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::span!(tracing::Level::$level, $($arg)*).entered();
+        #[cfg(not(feature = "tracing"))]
+        let span = $crate::trace::NoSpan;
+        span
+    }};
+}
+
+/// The current span, to pass as `parent:` to spans on other threads.
+macro_rules! current_span {
+    () => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::Span::current();
+        #[cfg(not(feature = "tracing"))]
+        let span = $crate::trace::NoSpan;
+        span
+    }};
+}
+
+/// Emits an event: `event!(DEBUG, rows, "range done");`. After the level the
+/// arguments are those of `tracing::event!`.
+macro_rules! event {
+    ($level:ident, $($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($arg)*);
+    };
+}
+
+pub(crate) use {current_span, event, span};
+
+/// What [`span!`] and [`current_span!`] give without the feature. Not `()`,
+/// which would have Clippy object to binding it.
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone, Copy)]
+pub(crate) struct NoSpan;
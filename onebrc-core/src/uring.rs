@@ -0,0 +1,328 @@
+//! [`IoBackend::Uring`](crate::IoBackend::Uring): a reader that keeps several
+//! large reads in flight through io_uring, so the disk stays busy while the
+//! bytes already read are parsed.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use io_uring::{IoUring, opcode, types};
+
+use crate::direct::{ALIGN, AlignedBuf, open_direct, trim};
+
+/// Reads in flight at once, each into a buffer of its own.
+const BUFFERS: usize = 4;
+/// Bytes asked for by each read.
+const BUFFER_SIZE: usize = 4 << 20;
+
+struct Slot {
+    buffer: AlignedBuf,
+    /// File offset of the first byte of `buffer`.
+    offset: u64,
+    /// Bytes the read asks for, and bytes it has delivered so far.
+    wanted: usize,
+    filled: usize,
+    done: bool,
+}
+
+/// Reads the bytes `start..end` of a file front to back, with up to
+/// [`BUFFERS`] reads of [`BUFFER_SIZE`] bytes submitted ahead of the caller.
+pub(crate) struct UringReader {
+    ring: IoUring,
+    file: File,
+    path: PathBuf,
+    /// The file opened without `O_DIRECT`, for the rest of a direct read
+    /// that came back short of an aligned length.
+    buffered: Option<File>,
+    /// Whether the buffers are registered with the ring, which spares the
+    /// kernel mapping them for every read. Reads still work without.
+    registered: bool,
+    slots: Vec<Slot>,
+    /// Slots with a read submitted or completed, in file order.
+    queue: VecDeque<usize>,
+    /// The slot being copied out, how far, and where its part of the range
+    /// ends.
+    current: Option<(usize, usize, usize)>,
+    next_offset: u64,
+    start: u64,
+    end: u64,
+    /// Reads are aligned to this, which `O_DIRECT` needs.
+    align: u64,
+    in_flight: usize,
+}
+
+impl UringReader {
+    /// Reads with `O_DIRECT` if `direct`.
+    pub(crate) fn open(path: &Path, start: u64, end: u64, direct: bool) -> io::Result<Self> {
+        let (file, align) = if direct {
+            (open_direct(path)?, ALIGN)
+        } else {
+            (File::open(path)?, 1)
+        };
+        let ring = IoUring::new(BUFFERS as u32)?;
+        let mut slots: Vec<Slot> = (0..BUFFERS)
+            .map(|_| Slot {
+                buffer: AlignedBuf::new(BUFFER_SIZE),
+                offset: 0,
+                wanted: 0,
+                filled: 0,
+                done: false,
+            })
+            .collect();
+        let iovecs: Vec<libc::iovec> = slots
+            .iter_mut()
+            .map(|slot| libc::iovec {
+                iov_base: slot.buffer.as_mut_ptr().cast(),
+                iov_len: slot.buffer.len(),
+            })
+            .collect();
+        // SAFETY: the buffers are heap allocations owned by the reader, so
+        // they do not move and outlive every read (see `Drop`)
+        let registered = unsafe { ring.submitter().register_buffers(&iovecs) }.is_ok();
+
+        let mut reader = UringReader {
+            ring,
+            file,
+            path: path.to_owned(),
+            buffered: None,
+            registered,
+            slots,
+            queue: VecDeque::with_capacity(BUFFERS),
+            current: None,
+            next_offset: start / align * align,
+            start,
+            end,
+            align,
+            in_flight: 0,
+        };
+        for index in 0..BUFFERS {
+            if !reader.start(index)? {
+                break;
+            }
+        }
+        Ok(reader)
+    }
+
+    /// Submits a read of the next part of the range into slot `index`, or
+    /// returns false if the whole range has been asked for.
+    fn start(&mut self, index: usize) -> io::Result<bool> {
+        if self.next_offset >= self.end {
+            return Ok(false);
+        }
+        let wanted = (self.end - self.next_offset)
+            .next_multiple_of(self.align)
+            .min(BUFFER_SIZE as u64) as usize;
+        let slot = &mut self.slots[index];
+        slot.offset = self.next_offset;
+        slot.wanted = wanted;
+        slot.filled = 0;
+        slot.done = false;
+        self.next_offset += wanted as u64;
+        self.submit(index)?;
+        self.queue.push_back(index);
+        Ok(true)
+    }
+
+    /// Submits a read of what slot `index` still lacks.
+    fn submit(&mut self, index: usize) -> io::Result<()> {
+        let slot = &mut self.slots[index];
+        let buf = slot.buffer[slot.filled..].as_mut_ptr();
+        let len = (slot.wanted - slot.filled) as u32;
+        let offset = slot.offset + slot.filled as u64;
+        let fd = types::Fd(self.file.as_raw_fd());
+        let entry = if self.registered {
+            opcode::ReadFixed::new(fd, buf, len, index as u16)
+                .offset(offset)
+                .build()
+        } else {
+            opcode::Read::new(fd, buf, len).offset(offset).build()
+        };
+        // SAFETY: the buffer is not touched again until the read completes,
+        // and is not freed before that either (see `Drop`)
+        unsafe { self.ring.submission().push(&entry.user_data(index as u64)) }
+            .map_err(|_| io::Error::other("io_uring submission queue is full"))?;
+        self.ring.submit()?;
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Waits until the read into slot `index` has completed, resubmitting
+    /// the rest of any read that came back short.
+    fn wait(&mut self, index: usize) -> io::Result<()> {
+        while !self.slots[index].done {
+            self.ring.submit_and_wait(1)?;
+            let completed: Vec<(usize, i32)> = self
+                .ring
+                .completion()
+                .map(|entry| (entry.user_data() as usize, entry.result()))
+                .collect();
+            // Every harvested read is over, failed or not, so `Drop` must
+            // not wait for any of them
+            self.in_flight -= completed.len();
+            let mut error = None;
+            for (completed, result) in completed {
+                if result < 0 {
+                    error.get_or_insert(io::Error::from_raw_os_error(-result));
+                    continue;
+                }
+                let slot = &mut self.slots[completed];
+                let before = slot.filled;
+                slot.filled += result as usize;
+                // Nothing read means the file is shorter than it was; an
+                // aligned read may stop short at the end of the file
+                if result == 0
+                    || slot.filled == slot.wanted
+                    || slot.offset + slot.filled as u64 >= self.end
+                {
+                    slot.done = true;
+                    continue;
+                }
+                if error.is_some() {
+                    continue;
+                }
+                // A direct read must go on from an aligned offset, so the
+                // bytes past the last aligned one are read again
+                let aligned = slot.filled / self.align as usize * self.align as usize;
+                if aligned > before {
+                    slot.filled = aligned;
+                    self.submit(completed)?;
+                } else {
+                    self.read_buffered(completed)?;
+                }
+            }
+            if let Some(err) = error {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads what slot `index` still lacks without `O_DIRECT`, for a direct
+    /// read that came back short of an aligned length and so cannot go on.
+    fn read_buffered(&mut self, index: usize) -> io::Result<()> {
+        let file = match &mut self.buffered {
+            Some(file) => file,
+            buffered => buffered.insert(File::open(&self.path)?),
+        };
+        let slot = &mut self.slots[index];
+        while slot.filled < slot.wanted && slot.offset + (slot.filled as u64) < self.end {
+            let offset = slot.offset + slot.filled as u64;
+            match file.read_at(&mut slot.buffer[slot.filled..slot.wanted], offset) {
+                Ok(0) => break,
+                Ok(read) => slot.filled += read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        slot.done = true;
+        Ok(())
+    }
+}
+
+impl Read for UringReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some((index, pos, to)) = self.current {
+                if pos < to {
+                    let n = buf.len().min(to - pos);
+                    buf[..n].copy_from_slice(&self.slots[index].buffer[pos..pos + n]);
+                    self.current = Some((index, pos + n, to));
+                    return Ok(n);
+                }
+                // Drained: reuse the buffer for the next part of the range
+                self.current = None;
+                self.start(index)?;
+            }
+            let Some(index) = self.queue.pop_front() else {
+                return Ok(0);
+            };
+            self.wait(index)?;
+            let slot = &self.slots[index];
+            let (from, to) = trim(slot.offset, slot.filled, self.start, self.end);
+            self.current = Some((index, from, to));
+        }
+    }
+}
+
+impl Drop for UringReader {
+    fn drop(&mut self) {
+        // The kernel may still be writing into the buffers
+        while self.in_flight > 0 {
+            if self.ring.submit_and_wait(1).is_err() {
+                // Leak the buffers rather than free them under the kernel
+                std::mem::forget(std::mem::take(&mut self.slots));
+                break;
+            }
+            self.in_flight -= self.ring.completion().count();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFile;
+
+    /// Bytes that differ from one offset to the next, over three buffers and
+    /// a bit, so reads refill buffers and end off their alignment.
+    fn contents() -> Vec<u8> {
+        (0..3 * BUFFER_SIZE + 1234)
+            .map(|i| (i * 7 + i / 4099) as u8)
+            .collect()
+    }
+
+    /// `start..end` through a [`UringReader`], or `None` where the kernel or
+    /// the filesystem does not allow it.
+    fn read_range(path: &Path, start: u64, end: u64, direct: bool) -> Option<Vec<u8>> {
+        let mut reader = match UringReader::open(path, start, end, direct) {
+            Ok(reader) => reader,
+            Err(err) => {
+                eprintln!("skipped, io_uring unavailable (direct: {direct}): {err}");
+                return None;
+            }
+        };
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        Some(read)
+    }
+
+    fn assert_ranges_match_std(name: &str, direct: bool) {
+        let data = contents();
+        let file = TempFile::new(name, &data);
+        let len = data.len() as u64;
+        let align = ALIGN;
+        let ranges = [
+            (0, len),
+            (1, len - 1),
+            (align - 1, align + 1),
+            (align + 7, 2 * BUFFER_SIZE as u64 + 13),
+            (BUFFER_SIZE as u64 - 5, BUFFER_SIZE as u64 + 5),
+            (len - 100, len),
+            (len - 1, len),
+            (17, 17),
+            (len, len),
+        ];
+        let std_file = File::open(&file.0).unwrap();
+        for (start, end) in ranges {
+            let Some(read) = read_range(&file.0, start, end, direct) else {
+                return;
+            };
+            let mut expected = vec![0; (end - start) as usize];
+            std_file.read_exact_at(&mut expected, start).unwrap();
+            assert!(read == expected, "{start}..{end}, direct: {direct}");
+        }
+    }
+
+    #[test]
+    fn buffered_reads_match_std() {
+        assert_ranges_match_std("uring-buffered", false);
+    }
+
+    #[test]
+    fn direct_reads_match_std() {
+        assert_ranges_match_std("uring-direct", true);
+    }
+}
@@ -0,0 +1,193 @@
+//! Correctness checks: compare results against a reference answer, either a
+//! file in the challenge output format or a deliberately naive
+//! single-threaded implementation.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, BufRead};
+
+use crate::results::Results;
+
+/// Tolerance for min and max, which are exact one-decimal values.
+pub const EXACT_TOLERANCE: f64 = 1e-6;
+
+/// Tolerance for a mean compared against a value rounded to one decimal
+/// (wide enough for any rounding mode the reference may have used).
+pub const ROUNDED_MEAN_TOLERANCE: f64 = 0.1 + EXACT_TOLERANCE;
+
+/// Tolerance for a mean compared against an unrounded `f64` mean, which
+/// only differs from ours by float summation error.
+pub const UNROUNDED_MEAN_TOLERANCE: f64 = 1e-4;
+
+/// Min/mean/max of one station as plain numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.1}/{:.1}/{:.1}", self.min, self.mean, self.max)
+    }
+}
+
+/// Converts aggregator results into comparable summaries.
+pub fn summarize(results: &Results) -> BTreeMap<String, Summary> {
+    results
+        .iter()
+        .map(|(name, stats)| {
+            let summary = Summary {
+                min: stats.min(),
+                mean: stats.mean(),
+                max: stats.max(),
+            };
+            (name.to_string(), summary)
+        })
+        .collect()
+}
+
+/// Parses output in the challenge format, `{Abha=-23.0/18.0/59.2, ...}`.
+///
+/// Station names may themselves contain `, ` or `=` (`Washington, D.C.`), so
+/// an entry only ends where a `=min/mean/max` triple is followed by `, ` or
+/// the closing brace.
+pub fn parse_brc(text: &str) -> Result<BTreeMap<String, Summary>, String> {
+    let inner = text
+        .trim()
+        .strip_prefix('{')
+        .and_then(|t| t.strip_suffix('}'))
+        .ok_or("expected output wrapped in `{...}`")?;
+
+    let mut stations = BTreeMap::new();
+    let mut rest = inner;
+    while !rest.is_empty() {
+        let mut search_from = 0;
+        let (name, summary, tail) = loop {
+            let eq = rest[search_from..]
+                .find('=')
+                .map(|i| i + search_from)
+                .ok_or_else(|| format!("no `station=min/mean/max` entry in `{rest}`"))?;
+            let after = &rest[eq + 1..];
+            let (values, tail) = after.split_once(", ").unwrap_or((after, ""));
+            if let Some(summary) = parse_triple(values) {
+                break (&rest[..eq], summary, tail);
+            }
+            search_from = eq + 1;
+        };
+        stations.insert(name.to_string(), summary);
+        rest = tail;
+    }
+    Ok(stations)
+}
+
+fn parse_triple(values: &str) -> Option<Summary> {
+    let mut parts = values.split('/').map(|v| v.parse::<f64>().ok());
+    let summary = Summary {
+        min: parts.next()??,
+        mean: parts.next()??,
+        max: parts.next()??,
+    };
+    parts.next().is_none().then_some(summary)
+}
+
+/// The slowest, most obvious implementation: one thread, `String` lines and
+/// `f64` parsing. Kept independent of the fast path so it can check it.
+pub fn reference_results(
+    reader: impl BufRead,
+    delimiter: u8,
+) -> io::Result<BTreeMap<String, Summary>> {
+    let mut acc: BTreeMap<String, (f64, f64, f64, u64)> = BTreeMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        // A byte order mark can open each of several concatenated files
+        let line = line.strip_prefix('\u{feff}').unwrap_or(&line);
+        let Some((name, temp)) = line.split_once(char::from(delimiter)) else {
+            continue;
+        };
+        let Ok(temp) = temp.trim().parse::<f64>() else {
+            continue;
+        };
+        let entry = acc
+            .entry(name.to_string())
+            .or_insert((temp, temp, 0.0, 0));
+        entry.0 = entry.0.min(temp);
+        entry.1 = entry.1.max(temp);
+        entry.2 += temp;
+        entry.3 += 1;
+    }
+
+    Ok(acc
+        .into_iter()
+        .map(|(name, (min, max, sum, count))| {
+            let summary = Summary {
+                min,
+                mean: sum / count as f64,
+                max,
+            };
+            (name, summary)
+        })
+        .collect())
+}
+
+/// A difference between actual and expected results.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    /// Expected station absent from the actual results.
+    Missing(String),
+    /// Station in the actual results that was not expected.
+    Unexpected(String),
+    Differs {
+        station: String,
+        actual: Summary,
+        expected: Summary,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Missing(station) => write!(f, "{station}: missing"),
+            Mismatch::Unexpected(station) => write!(f, "{station}: unexpected station"),
+            Mismatch::Differs {
+                station,
+                actual,
+                expected,
+            } => write!(f, "{station}: got {actual}, expected {expected}"),
+        }
+    }
+}
+
+/// Lists every station whose min or max differ at all, or whose mean differs
+/// by more than `mean_tolerance`.
+pub fn compare(
+    actual: &BTreeMap<String, Summary>,
+    expected: &BTreeMap<String, Summary>,
+    mean_tolerance: f64,
+) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    for (station, exp) in expected {
+        match actual.get(station) {
+            None => mismatches.push(Mismatch::Missing(station.clone())),
+            Some(act) => {
+                let differs = (act.min - exp.min).abs() > EXACT_TOLERANCE
+                    || (act.max - exp.max).abs() > EXACT_TOLERANCE
+                    || (act.mean - exp.mean).abs() > mean_tolerance;
+                if differs {
+                    mismatches.push(Mismatch::Differs {
+                        station: station.clone(),
+                        actual: *act,
+                        expected: *exp,
+                    });
+                }
+            }
+        }
+    }
+    for station in actual.keys() {
+        if !expected.contains_key(station) {
+            mismatches.push(Mismatch::Unexpected(station.clone()));
+        }
+    }
+    mismatches
+}
@@ -0,0 +1,102 @@
+/*
+ * onebrc: per-station min, mean and max of One Billion Row Challenge
+ * measurements, from C or C++. Link with -lonebrc_ffi (built by
+ * `cargo build --release -p onebrc-ffi`).
+ *
+ *     static int print(const OnebrcStation *station, void *user_data) {
+ *         printf("%s=%.1f/%.1f/%.1f\n", station->name, station->min,
+ *                station->mean, station->max);
+ *         return 0;
+ *     }
+ *
+ *     if (onebrc_aggregate("measurements.txt", NULL, print, NULL) < 0)
+ *         fprintf(stderr, "%s\n", onebrc_last_error());
+ */
+
+#ifndef ONEBRC_H
+#define ONEBRC_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Return values of onebrc_aggregate */
+#define ONEBRC_OK 0
+/* The callback returned nonzero */
+#define ONEBRC_STOPPED 1
+/* A null or non-UTF-8 path, or options that make no sense */
+#define ONEBRC_INVALID_ARGUMENT (-1)
+/* The input could not be read or parsed */
+#define ONEBRC_IO_ERROR (-2)
+/* A bug in the library; the message says what went wrong */
+#define ONEBRC_PANIC (-3)
+
+/* Values of OnebrcOptions.format */
+#define ONEBRC_FORMAT_AUTO 0
+#define ONEBRC_FORMAT_DELIMITED 1
+#define ONEBRC_FORMAT_JSONL 2
+
+typedef struct OnebrcOptions {
+    /* Worker threads; 0 means one per core */
+    size_t threads;
+    /* One of the ONEBRC_FORMAT_* values */
+    uint32_t format;
+    /* Separates station and temperature in delimited input */
+    uint8_t delimiter;
+    bool skip_header;
+    bool decimal_comma;
+    bool quoted;
+    /* Fail on the first malformed line instead of skipping it */
+    bool strict;
+} OnebrcOptions;
+
+/* One station's results, valid only during the callback it is passed to */
+typedef struct OnebrcStation {
+    /* The name, NUL-terminated, in UTF-8 */
+    const char *name;
+    /* Bytes in name before the NUL */
+    size_t name_len;
+    /* Degrees Celsius, unrounded */
+    double min;
+    double mean;
+    double max;
+    uint64_t count;
+} OnebrcStation;
+
+/*
+ * Called once per station, in alphabetical order; returning nonzero stops
+ * the calls and makes onebrc_aggregate return ONEBRC_STOPPED.
+ */
+typedef int (*OnebrcCallback)(const OnebrcStation *station, void *user_data);
+
+/*
+ * Fills options with the defaults: one thread per core, the layout worked
+ * out from the input, and ';' as the delimiter.
+ */
+void onebrc_options_default(OnebrcOptions *options);
+
+/*
+ * Aggregates the input at path ("-" for stdin; gzip and zstd files are
+ * decompressed) with options, or the defaults if it is NULL, and passes
+ * every station to callback, which may be NULL, with user_data. Returns
+ * ONEBRC_OK, ONEBRC_STOPPED, or a negative ONEBRC_* code with the message
+ * from onebrc_last_error.
+ */
+int onebrc_aggregate(const char *path, const OnebrcOptions *options,
+                     OnebrcCallback callback, void *user_data);
+
+/*
+ * The message of the last failed onebrc_aggregate on this thread, or NULL
+ * if none has failed; valid until the next call on it.
+ */
+const char *onebrc_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* ONEBRC_H */
@@ -0,0 +1,230 @@
+//! A C ABI over onebrc-core, declared in `include/onebrc.h`, for C and C++
+//! programs that embed the engine instead of running `calculate`.
+//!
+//! [`onebrc_aggregate`] aggregates one input and hands every station to a
+//! callback; failures come back as a status code, with the message from
+//! [`onebrc_last_error`].
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+use onebrc_core::{InputFormat, Options, ParseOptions, aggregate_file};
+
+pub const ONEBRC_OK: c_int = 0;
+/// The callback returned nonzero.
+pub const ONEBRC_STOPPED: c_int = 1;
+/// A null or non-UTF-8 path, or options that make no sense.
+pub const ONEBRC_INVALID_ARGUMENT: c_int = -1;
+/// The input could not be read or parsed.
+pub const ONEBRC_IO_ERROR: c_int = -2;
+/// A bug in the library; the message says what went wrong.
+pub const ONEBRC_PANIC: c_int = -3;
+
+pub const ONEBRC_FORMAT_AUTO: u32 = 0;
+pub const ONEBRC_FORMAT_DELIMITED: u32 = 1;
+pub const ONEBRC_FORMAT_JSONL: u32 = 2;
+
+/// Settings for [`onebrc_aggregate`]; see `onebrc_options_default`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct OnebrcOptions {
+    /// Worker threads; 0 means one per core.
+    pub threads: usize,
+    /// One of the `ONEBRC_FORMAT_*` values.
+    pub format: u32,
+    /// Separates station and temperature in delimited input.
+    pub delimiter: u8,
+    pub skip_header: bool,
+    pub decimal_comma: bool,
+    pub quoted: bool,
+    /// Fail on the first malformed line instead of skipping it.
+    pub strict: bool,
+}
+
+impl Default for OnebrcOptions {
+    fn default() -> Self {
+        let parse = ParseOptions::default();
+        OnebrcOptions {
+            threads: 0,
+            format: ONEBRC_FORMAT_AUTO,
+            delimiter: parse.delimiter,
+            skip_header: parse.skip_header,
+            decimal_comma: parse.decimal_comma,
+            quoted: parse.quoted,
+            strict: parse.strict,
+        }
+    }
+}
+
+impl OnebrcOptions {
+    fn options(&self) -> io::Result<Options> {
+        let format = match self.format {
+            ONEBRC_FORMAT_AUTO => InputFormat::Auto,
+            ONEBRC_FORMAT_DELIMITED => InputFormat::Delimited,
+            ONEBRC_FORMAT_JSONL => InputFormat::Jsonl,
+            format => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{format} is not an ONEBRC_FORMAT_* value"),
+                ));
+            }
+        };
+        let defaults = Options::default();
+        Ok(Options {
+            threads: match self.threads {
+                0 => defaults.threads,
+                threads => threads,
+            },
+            parse: ParseOptions {
+                format,
+                delimiter: self.delimiter,
+                skip_header: self.skip_header,
+                decimal_comma: self.decimal_comma,
+                quoted: self.quoted,
+                strict: self.strict,
+                ..defaults.parse
+            },
+            ..defaults
+        })
+    }
+}
+
+/// One station's results, valid only during the callback it is passed to.
+#[repr(C)]
+#[derive(Debug)]
+pub struct OnebrcStation {
+    /// The name, NUL-terminated, in UTF-8.
+    pub name: *const c_char,
+    /// Bytes in `name` before the NUL.
+    pub name_len: usize,
+    /// Degrees Celsius, unrounded.
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+    pub count: u64,
+}
+
+/// Called once per station, in alphabetical order; returning nonzero stops
+/// the calls and makes [`onebrc_aggregate`] return [`ONEBRC_STOPPED`].
+pub type OnebrcCallback =
+    Option<unsafe extern "C" fn(station: *const OnebrcStation, user_data: *mut c_void) -> c_int>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Fills `options` with the defaults: one thread per core, the layout
+/// worked out from the input, and `;` as the delimiter.
+///
+/// # Safety
+///
+/// `options` is null or points to an `OnebrcOptions` that may be written.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn onebrc_options_default(options: *mut OnebrcOptions) {
+    // SAFETY: the caller hands over a writable struct, or null
+    if let Some(options) = unsafe { options.as_mut() } {
+        *options = OnebrcOptions::default();
+    }
+}
+
+/// Aggregates the input at `path` (`-` for stdin; gzip and zstd files are
+/// decompressed) with `options`, or the defaults if it is null, and passes
+/// every station to `callback` with `user_data`. Returns [`ONEBRC_OK`],
+/// [`ONEBRC_STOPPED`], or a negative `ONEBRC_*` code with the message from
+/// [`onebrc_last_error`].
+///
+/// # Safety
+///
+/// `path` is null or a NUL-terminated string, `options` is null or points
+/// to an `OnebrcOptions`, and `callback` may be called with `user_data` from
+/// the calling thread.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn onebrc_aggregate(
+    path: *const c_char,
+    options: *const OnebrcOptions,
+    callback: OnebrcCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    if path.is_null() {
+        set_last_error("the path is null".to_owned());
+        return ONEBRC_INVALID_ARGUMENT;
+    }
+    // SAFETY: the caller passes a NUL-terminated string
+    let Ok(path) = unsafe { CStr::from_ptr(path) }.to_str() else {
+        set_last_error("the path is not UTF-8".to_owned());
+        return ONEBRC_INVALID_ARGUMENT;
+    };
+    // SAFETY: the caller passes a readable struct, or null
+    let options = unsafe { options.as_ref() }.copied().unwrap_or_default();
+    let run = || aggregate(Path::new(path), &options, callback, user_data);
+    match panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(Ok(status)) => status,
+        Ok(Err(err)) => {
+            set_last_error(err.to_string());
+            match err.kind() {
+                io::ErrorKind::InvalidInput => ONEBRC_INVALID_ARGUMENT,
+                _ => ONEBRC_IO_ERROR,
+            }
+        }
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            set_last_error(format!("panicked: {message}"));
+            ONEBRC_PANIC
+        }
+    }
+}
+
+fn aggregate(
+    path: &Path,
+    options: &OnebrcOptions,
+    callback: OnebrcCallback,
+    user_data: *mut c_void,
+) -> io::Result<c_int> {
+    let results = aggregate_file(path, &options.options()?)?.into_results();
+    let Some(callback) = callback else {
+        return Ok(ONEBRC_OK);
+    };
+    let mut name = Vec::new();
+    for (station, stats) in results.iter() {
+        name.clear();
+        name.extend_from_slice(station.as_bytes());
+        name.push(0);
+        let station = OnebrcStation {
+            name: name.as_ptr().cast(),
+            name_len: station.len(),
+            min: stats.min(),
+            mean: stats.mean(),
+            max: stats.max(),
+            count: stats.count(),
+        };
+        // SAFETY: the caller vouched for the callback and its user data
+        if unsafe { callback(&station, user_data) } != 0 {
+            return Ok(ONEBRC_STOPPED);
+        }
+    }
+    Ok(ONEBRC_OK)
+}
+
+/// The message of the last failed [`onebrc_aggregate`] on this thread, or
+/// null if none has failed; valid until the next call on it.
+#[unsafe(no_mangle)]
+pub extern "C" fn onebrc_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |last| last.as_ptr())
+    })
+}
@@ -0,0 +1,5 @@
+fn main() {
+    // Leaves the Python symbols to the interpreter that loads the module,
+    // which macOS only allows when told to
+    pyo3_build_config::add_extension_module_link_args();
+}
@@ -0,0 +1,171 @@
+//! The `onebrc` Python module: [`aggregate`] runs the Rust pipeline over a
+//! file and hands back the results as a dict, so notebooks and scripts get
+//! its speed without starting `calculate` and parsing what it prints.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use onebrc_core::{
+    Aliases, InputFormat, Options, ParseOptions, Percentile, StationFilter, StatsOptions,
+    TempStats, aggregate_file,
+};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+/// Aggregates the measurements in the file at `path` (`-` for stdin; gzip
+/// and zstd files are decompressed) into a dict from each station's name to
+/// a dict of its `min`, `mean`, `max` and `count`, in degrees Celsius and
+/// unrounded. `percentiles` such as `["p50", "p99"]` add an entry each,
+/// `histogram` adds `histogram`, the counts per 0.5 degree bucket, and a
+/// build with the `variance` feature adds `stddev`.
+///
+/// `format` is `"auto"`, `"delimited"` or `"jsonl"`; `delimiter` implies
+/// `"delimited"`. `stations` and `pattern` keep only the stations named or
+/// matching the regex, and `aliases` maps other spellings of a station to
+/// the name reported. The GIL is released while the file is read.
+#[pyfunction]
+#[pyo3(signature = (
+    path,
+    *,
+    threads = None,
+    format = "auto",
+    delimiter = None,
+    skip_header = false,
+    decimal_comma = false,
+    quoted = false,
+    strict = false,
+    stations = None,
+    pattern = None,
+    aliases = None,
+    percentiles = None,
+    histogram = false,
+))]
+#[allow(clippy::too_many_arguments)]
+fn aggregate<'py>(
+    py: Python<'py>,
+    path: PathBuf,
+    threads: Option<usize>,
+    format: &str,
+    delimiter: Option<char>,
+    skip_header: bool,
+    decimal_comma: bool,
+    quoted: bool,
+    strict: bool,
+    stations: Option<Vec<String>>,
+    pattern: Option<&str>,
+    aliases: Option<HashMap<String, String>>,
+    percentiles: Option<Vec<String>>,
+    histogram: bool,
+) -> PyResult<Bound<'py, PyDict>> {
+    let invalid = |err: String| PyValueError::new_err(err);
+    let format = match (format, delimiter) {
+        ("auto", Some(_)) | ("delimited", _) => InputFormat::Delimited,
+        ("auto", None) => InputFormat::Auto,
+        ("jsonl", _) => InputFormat::Jsonl,
+        _ => {
+            return Err(invalid(format!(
+                "`{format}` is not one of \"auto\", \"delimited\" and \"jsonl\""
+            )));
+        }
+    };
+    let defaults = ParseOptions::default();
+    let delimiter = match delimiter {
+        Some(delimiter) if delimiter.is_ascii() => delimiter as u8,
+        Some(delimiter) => {
+            return Err(invalid(format!("`{delimiter}` is not an ASCII delimiter")));
+        }
+        None => defaults.delimiter,
+    };
+    let percentiles = percentiles
+        .unwrap_or_default()
+        .iter()
+        .map(|percentile| percentile.parse::<Percentile>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+
+    let mut filter = None;
+    if stations.is_some() || pattern.is_some() {
+        let mut stations_kept = StationFilter::new();
+        if let Some(pattern) = pattern {
+            stations_kept = stations_kept
+                .with_pattern(pattern)
+                .map_err(|err| invalid(err.to_string()))?;
+        }
+        if let Some(names) = stations {
+            stations_kept = stations_kept.with_allowed(names);
+        }
+        filter = Some(Arc::new(stations_kept));
+    }
+    let aliases = aliases.map(|aliases| {
+        Arc::new(
+            aliases
+                .into_iter()
+                .fold(Aliases::new(), |aliases, (alias, canonical)| {
+                    aliases.with_alias(alias, canonical)
+                }),
+        )
+    });
+
+    let defaults = Options::default();
+    let options = Options {
+        threads: threads.unwrap_or(defaults.threads).max(1),
+        parse: ParseOptions {
+            format,
+            delimiter,
+            skip_header,
+            decimal_comma,
+            quoted,
+            strict,
+            ..defaults.parse
+        },
+        stats: StatsOptions {
+            percentiles: !percentiles.is_empty(),
+            histogram,
+        },
+        filter,
+        aliases,
+        ..defaults
+    };
+    let results = py
+        .detach(|| aggregate_file(&path, &options))?
+        .into_results();
+
+    let dict = PyDict::new(py);
+    for (station, stats) in results.iter() {
+        dict.set_item(station, station_dict(py, stats, &percentiles)?)?;
+    }
+    Ok(dict)
+}
+
+/// The entries [`aggregate`] reports for one station.
+fn station_dict<'py>(
+    py: Python<'py>,
+    stats: &TempStats,
+    percentiles: &[Percentile],
+) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("min", stats.min())?;
+    dict.set_item("mean", stats.mean())?;
+    dict.set_item("max", stats.max())?;
+    dict.set_item("count", stats.count())?;
+    #[cfg(feature = "variance")]
+    dict.set_item("stddev", stats.std_dev())?;
+    for &percentile in percentiles {
+        dict.set_item(
+            percentile.to_string(),
+            stats.quantile(percentile.fraction()),
+        )?;
+    }
+    if let Some(histogram) = stats.histogram() {
+        dict.set_item("histogram", histogram.counts())?;
+    }
+    Ok(dict)
+}
+
+#[pymodule]
+fn onebrc(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(aggregate, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! onebrc-core for the browser and serverless runtimes: an [`Aggregator`]
+//! that measurements are streamed into chunk by chunk from JavaScript, as
+//! they arrive from a `fetch` body or a file picker, with no threads or
+//! memory maps involved.
+//!
+//! ```js
+//! import init, { Aggregator } from "./pkg/onebrc_wasm.js";
+//!
+//! await init();
+//! const aggregator = new Aggregator();
+//! for await (const chunk of response.body) aggregator.push_chunk(chunk);
+//! const results = JSON.parse(aggregator.finish());
+//! ```
+
+use onebrc_core::output::{Format, OutputOptions, write_results};
+use onebrc_core::{ChunkAggregator, InputFormat, ParseOptions};
+use wasm_bindgen::prelude::*;
+
+/// Aggregates measurements pushed in chunks of any size, which may split
+/// lines anywhere.
+#[wasm_bindgen]
+pub struct Aggregator {
+    /// Taken by `finish`.
+    inner: Option<ChunkAggregator>,
+}
+
+#[wasm_bindgen]
+impl Aggregator {
+    /// Reads lines delimited by `delimiter`, or without one whatever the
+    /// first 16 KiB look like: JSON Lines, or lines delimited by `;`, a tab
+    /// or a comma, and whether the first line is a header.
+    #[wasm_bindgen(constructor)]
+    pub fn new(delimiter: Option<String>) -> Result<Aggregator, JsError> {
+        let defaults = ParseOptions::default();
+        let parse = match delimiter.as_deref().map(str::as_bytes) {
+            None => ParseOptions {
+                format: InputFormat::Auto,
+                ..defaults
+            },
+            Some(&[delimiter]) => ParseOptions {
+                format: InputFormat::Delimited,
+                delimiter,
+                ..defaults
+            },
+            Some(_) => return Err(JsError::new("the delimiter must be one ASCII character")),
+        };
+        let aggregator = onebrc_core::Aggregator::with_options(parse);
+        Ok(Aggregator {
+            inner: Some(ChunkAggregator::new(aggregator)),
+        })
+    }
+
+    /// Aggregates the lines `chunk` completes; the rest of the last one is
+    /// kept for the next chunk.
+    pub fn push_chunk(&mut self, chunk: &[u8]) -> Result<(), JsError> {
+        self.inner
+            .as_mut()
+            .ok_or_else(finished)?
+            .push_chunk(chunk)
+            .map_err(|err| JsError::new(&err.to_string()))
+    }
+
+    /// Aggregates what is left and returns the results as JSON, an object
+    /// from every station to its `min`, `mean`, `max` and `count`. Nothing
+    /// can be pushed afterwards.
+    pub fn finish(&mut self) -> Result<String, JsError> {
+        let error = |err: std::io::Error| JsError::new(&err.to_string());
+        let results = self
+            .inner
+            .take()
+            .ok_or_else(finished)?
+            .finish()
+            .map_err(error)?
+            .into_results();
+        let options = OutputOptions {
+            format: Format::Json,
+            ..OutputOptions::default()
+        };
+        let mut json = Vec::new();
+        write_results(&mut json, &results, &options).map_err(error)?;
+        String::from_utf8(json).map_err(|err| JsError::new(&err.to_string()))
+    }
+}
+
+fn finished() -> JsError {
+    JsError::new("the aggregator is already finished")
+}